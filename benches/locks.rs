@@ -0,0 +1,86 @@
+//! Latency and throughput of every lock backend, for payloads from 8 bytes to 8 MiB and with
+//! writers contending on other threads. Run with `cargo bench --features semaphore`, or filter by
+//! backend, e.g. `cargo bench -- spin_lock`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use neocortex::{
+    CortexSync, FileLock, HybridLock, NoLock, RawCortex, RwLock, SpinLock, TicketLock,
+    Unsynchronized,
+};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+const SIZES: [usize; 5] = [8, 1 << 10, 64 << 10, 1 << 20, 8 << 20];
+/// Number of threads writing in the background during the contended benchmarks
+const CONTENDERS: [usize; 3] = [0, 1, 3];
+
+fn read_write<L: CortexSync>(c: &mut Criterion, backend: &str, settings: Option<&L::Settings>) {
+    let mut group = c.benchmark_group(format!("{}/read_write", backend));
+    for size in SIZES {
+        let raw: RawCortex<L> = RawCortex::new(None, size, settings).unwrap();
+        let mut buffer = vec![1u8; size];
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::new("write", size), &size, |b, _| {
+            b.iter(|| {
+                raw.as_mut_slice()
+                    .unwrap()
+                    .copy_from_slice(black_box(&buffer))
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("read", size), &size, |b, _| {
+            b.iter(|| buffer.copy_from_slice(&raw.as_slice().unwrap()))
+        });
+    }
+    group.finish();
+}
+
+fn contended<L: CortexSync + Sync>(c: &mut Criterion, backend: &str) {
+    let mut group = c.benchmark_group(format!("{}/contended", backend));
+    for contenders in CONTENDERS {
+        let raw: RawCortex<L> = RawCortex::new(None, 64, None).unwrap();
+        let stop = AtomicBool::new(false);
+        thread::scope(|scope| {
+            for _ in 0..contenders {
+                scope.spawn(|| {
+                    let attached: RawCortex<L> = RawCortex::attach(raw.key(), None).unwrap();
+                    while !stop.load(Ordering::Relaxed) {
+                        attached.as_mut_slice().unwrap()[0] += 1;
+                    }
+                });
+            }
+            group.bench_with_input(
+                BenchmarkId::new("write", contenders),
+                &contenders,
+                |b, _| b.iter(|| raw.as_mut_slice().unwrap()[1] += 1),
+            );
+            group.bench_with_input(BenchmarkId::new("read", contenders), &contenders, |b, _| {
+                b.iter(|| black_box(raw.as_slice().unwrap()[1]))
+            });
+            stop.store(true, Ordering::Relaxed);
+        });
+    }
+    group.finish();
+}
+
+fn backend<L: CortexSync + Sync>(c: &mut Criterion, name: &str) {
+    read_write::<L>(c, name, None);
+    contended::<L>(c, name);
+}
+
+fn backends(c: &mut Criterion) {
+    // Baseline without any locking, only meaningful without contention. Safe since the segment
+    // is only accessed from the benchmark thread.
+    let unsynchronized = unsafe { Unsynchronized::new() };
+    read_write::<NoLock>(c, "no_lock", Some(&unsynchronized));
+    backend::<SpinLock>(c, "spin_lock");
+    backend::<HybridLock>(c, "hybrid_lock");
+    backend::<TicketLock>(c, "ticket_lock");
+    backend::<RwLock>(c, "rw_lock");
+    backend::<FileLock>(c, "file_lock");
+    #[cfg(feature = "semaphore")]
+    backend::<neocortex::Semaphore>(c, "semaphore");
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+//! Attach to a segment holding arbitrary bytes, as if it was created by another process, and use
+//! it. Attaching must either fail with an error or hand out a `Cortex` that is safe to use, it must
+//! never panic or touch memory outside of the segment.
+//!
+//! The segment is emulated on the heap through `MemBackend`, so that every iteration is cheap, and
+//! reading past its end is caught by the address sanitizer.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use neocortex::{Cortex, MemBackend, RawCortex};
+use std::time::Duration;
+
+fn attach_as<T: Copy>(key: i32) {
+    let Ok(cortex) = Cortex::<T, MemBackend>::attach(key) else {
+        return;
+    };
+    let _ = cortex.read();
+    let _ = cortex.read_versioned();
+    let _ = cortex.read_timeout(Duration::ZERO);
+    let _ = cortex.attach_count();
+    let _ = cortex.schema_version();
+    let _ = cortex.created_at();
+    let _ = cortex.lock_holder();
+    let _ = cortex.last_beat();
+    let _ = cortex.owner_pid();
+    let _ = cortex.try_clone();
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let Ok(raw) = RawCortex::<MemBackend>::new(None, data.len(), None) else {
+        return;
+    };
+    raw.as_mut_slice().unwrap().copy_from_slice(data);
+
+    // Payloads valid for any bit pattern, since reading anything else from an untrusted segment
+    // is undefined behavior no matter what the header says
+    attach_as::<u8>(raw.key());
+    attach_as::<u64>(raw.key());
+    attach_as::<[u8; 64]>(raw.key());
+    attach_as::<[u64; 512]>(raw.key());
+});
@@ -0,0 +1,76 @@
+#ifndef NEOCORTEX_H
+#define NEOCORTEX_H
+
+/* Generated by cbindgen from src/ffi.rs, build with `--features ffi` to update */
+
+#include <stddef.h>
+#include <stdint.h>
+
+// Opaque handle to an attached segment, freed through `neocortex_destroy`
+typedef struct NeocortexHandle NeocortexHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Set the namespace mixed into every key, see `set_namespace`. Must be called before creating or
+// attaching anything. Returns 0 on success and -1 on failure.
+//
+// # Safety
+//
+// `name` must be null or a valid NUL-terminated string.
+int32_t neocortex_set_namespace(const char *name);
+
+// Create a zero-initialized segment of `size` bytes on `key`, or on a random key if `key` is 0.
+// The segment is removed when the creating handle is destroyed. Returns null on failure.
+struct NeocortexHandle *neocortex_create(int32_t key, size_t size);
+
+// Attach to an existing segment on `key`. Returns null on failure.
+struct NeocortexHandle *neocortex_attach(int32_t key);
+
+// Copy the first `len` bytes of the segment into `out` under the read lock. Returns 0 on
+// success and -1 on failure.
+//
+// # Safety
+//
+// `handle` must be null or a live handle, and `out` must be valid for writes of `len` bytes.
+int32_t neocortex_read(const struct NeocortexHandle *handle, void *out, size_t len);
+
+// Copy `len` bytes from `data` to the start of the segment under the write lock. Returns 0 on
+// success and -1 on failure.
+//
+// # Safety
+//
+// `handle` must be null or a live handle, and `data` must be valid for reads of `len` bytes.
+int32_t neocortex_write(const struct NeocortexHandle *handle, const void *data, size_t len);
+
+// Size of the segment in bytes, 0 for a null handle
+//
+// # Safety
+//
+// `handle` must be null or a live handle.
+size_t neocortex_size(const struct NeocortexHandle *handle);
+
+// Key of the segment, e.g. after creating it on a random key. 0 for a null handle.
+//
+// # Safety
+//
+// `handle` must be null or a live handle.
+int32_t neocortex_key(const struct NeocortexHandle *handle);
+
+// Detach from the segment and free the handle, removing the segment if the handle created it
+//
+// # Safety
+//
+// `handle` must be null or a live handle, which must not be used afterwards.
+void neocortex_destroy(struct NeocortexHandle *handle);
+
+// Description of the last error on the calling thread, or null if there was none. The string is
+// valid until the next failing call on the same thread.
+const char *neocortex_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* NEOCORTEX_H */
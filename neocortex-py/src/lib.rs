@@ -0,0 +1,198 @@
+// Code generated by `#[pymethods]` trips this lint on every `?` returning a `PyErr`
+#![allow(clippy::useless_conversion)]
+
+use neocortex::{CortexError, ErrorKind, RawCortex, SemaphoreSettings};
+use pyo3::{
+    create_exception,
+    exceptions::{PyOSError, PyTimeoutError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+use std::time::Duration;
+
+create_exception!(
+    neocortex,
+    NeocortexError,
+    PyOSError,
+    "Error reported by the neocortex crate"
+);
+
+fn to_py_err(err: CortexError) -> PyErr {
+    match err.kind() {
+        ErrorKind::TimedOut => PyTimeoutError::new_err(err.to_string()),
+        ErrorKind::InvalidInput | ErrorKind::TooLarge => PyValueError::new_err(err.to_string()),
+        _ => NeocortexError::new_err(err.to_string()),
+    }
+}
+
+/// Untyped shared memory segment protected by a semaphore, the same as a
+/// `RawCortex<Semaphore>` on the same key in a Rust process
+#[pyclass(frozen)]
+struct Segment {
+    raw: RawCortex<neocortex::Semaphore>,
+}
+
+#[pymethods]
+impl Segment {
+    /// Create a zero-initialized segment of `size` bytes, on a random key if `key` is None. The
+    /// segment is removed once this object is garbage collected.
+    #[staticmethod]
+    #[pyo3(signature = (size, key=None))]
+    fn create(size: usize, key: Option<i32>) -> PyResult<Self> {
+        let raw = RawCortex::new(key, size, None).map_err(to_py_err)?;
+        Ok(Self { raw })
+    }
+    /// Attach to an existing segment on `key`
+    #[staticmethod]
+    fn attach(key: i32) -> PyResult<Self> {
+        let raw = RawCortex::attach(key, None).map_err(to_py_err)?;
+        Ok(Self { raw })
+    }
+    /// Copy out the whole segment under the read lock
+    fn read<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let bytes = py
+            .allow_threads(|| self.raw.as_slice().map(|bytes| bytes.to_vec()))
+            .map_err(to_py_err)?;
+        Ok(PyBytes::new_bound(py, &bytes))
+    }
+    /// Copy `data` into the segment at `offset` under the write lock
+    #[pyo3(signature = (data, offset=0))]
+    fn write(&self, py: Python<'_>, data: &[u8], offset: usize) -> PyResult<()> {
+        let end = offset.saturating_add(data.len());
+        if end > self.raw.size() {
+            return Err(PyValueError::new_err(format!(
+                "Writing {} bytes at offset {} exceeds the segment size of {} bytes",
+                data.len(),
+                offset,
+                self.raw.size()
+            )));
+        }
+        py.allow_threads(|| {
+            let mut bytes = self.raw.as_mut_slice()?;
+            bytes[offset..end].copy_from_slice(data);
+            Ok(())
+        })
+        .map_err(to_py_err)
+    }
+    #[getter]
+    fn size(&self) -> usize {
+        self.raw.size()
+    }
+    #[getter]
+    fn key(&self) -> i32 {
+        self.raw.key()
+    }
+}
+
+/// Named semaphore, shared with `neocortex::Semaphore` in Rust processes. Usable as a context
+/// manager, which acquires a permit on entry and releases it on exit.
+#[pyclass(frozen)]
+struct Semaphore {
+    semaphore: neocortex::Semaphore,
+}
+
+#[pymethods]
+impl Semaphore {
+    /// Create a new semaphore on `key` with `value` permits, failing if one already exists
+    #[staticmethod]
+    #[pyo3(signature = (key, value=1))]
+    fn create(key: i32, value: u32) -> PyResult<Self> {
+        let settings = SemaphoreSettings {
+            initial_value: value,
+            ..Default::default()
+        };
+        let semaphore = neocortex::Semaphore::create(key, &settings).map_err(to_py_err)?;
+        Ok(Self { semaphore })
+    }
+    /// Open an existing semaphore on `key`
+    #[staticmethod]
+    fn open(key: i32) -> PyResult<Self> {
+        let semaphore = neocortex::Semaphore::open(key, None).map_err(to_py_err)?;
+        Ok(Self { semaphore })
+    }
+    /// Take a permit, blocking for up to `timeout` seconds or forever if it is None. Returns
+    /// whether a permit was taken.
+    #[pyo3(signature = (timeout=None))]
+    fn acquire(&self, py: Python<'_>, timeout: Option<f64>) -> PyResult<bool> {
+        let timeout = timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        py.allow_threads(|| {
+            let permit = match timeout {
+                Some(timeout) => self.semaphore.acquire_timeout(timeout)?,
+                None => Some(self.semaphore.acquire()?),
+            };
+            // Given back through `release`
+            Ok(permit.map(|permit| permit.forget()).is_some())
+        })
+        .map_err(to_py_err)
+    }
+    /// Take a permit if one is available without blocking, returns whether a permit was taken
+    fn try_acquire(&self) -> PyResult<bool> {
+        let permit = self.semaphore.try_acquire().map_err(to_py_err)?;
+        Ok(permit.map(|permit| permit.forget()).is_some())
+    }
+    /// Give back a permit
+    fn release(&self) -> PyResult<()> {
+        self.semaphore.release().map_err(to_py_err)
+    }
+    fn __enter__(slf: Bound<'_, Self>) -> PyResult<Bound<'_, Self>> {
+        slf.get().acquire(slf.py(), None)?;
+        Ok(slf)
+    }
+    #[pyo3(signature = (*_args))]
+    fn __exit__(&self, _args: &Bound<'_, pyo3::types::PyTuple>) -> PyResult<bool> {
+        self.release()?;
+        Ok(false)
+    }
+}
+
+/// Set the namespace mixed into every key, so that unrelated applications on the same host can't
+/// collide. Must be called before creating or opening anything, with the same value as the Rust
+/// processes sharing the segments.
+#[pyfunction]
+fn set_namespace(namespace: &str) -> PyResult<()> {
+    neocortex::set_namespace(namespace).map_err(to_py_err)
+}
+
+#[pymodule]
+#[pyo3(name = "neocortex")]
+fn neocortex_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(set_namespace, m)?)?;
+    m.add_class::<Segment>()?;
+    m.add_class::<Semaphore>()?;
+    m.add("NeocortexError", m.py().get_type_bound::<NeocortexError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Segment, Semaphore};
+    use pyo3::{types::PyBytesMethods, Python};
+
+    #[test]
+    fn segment_and_semaphore() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let key = rand::random::<i32>().abs();
+            let segment = Segment::create(8, Some(key)).unwrap();
+            let attached = Segment::attach(key).unwrap();
+            attached.write(py, b"abcd", 4).unwrap();
+            assert_eq!(segment.read(py).unwrap().as_bytes(), b"\0\0\0\0abcd");
+            assert!(attached.write(py, b"abcd", 6).is_err());
+
+            // The segment already holds the semaphore on its own key
+            let key = rand::random::<i32>().abs();
+            let semaphore = Semaphore::create(key, 1).unwrap();
+            assert!(semaphore.try_acquire().unwrap());
+            assert!(!Semaphore::open(key)
+                .unwrap()
+                .acquire(py, Some(0.01))
+                .unwrap());
+            semaphore.release().unwrap();
+            assert!(semaphore.acquire(py, None).unwrap());
+            semaphore.release().unwrap();
+        });
+    }
+}
@@ -0,0 +1,453 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    mark_for_deletion,
+    segment::Segment,
+    Cortex, CortexResult, CortexSync,
+};
+use std::{fmt, marker::PhantomData, num::NonZeroU64};
+
+/// Offsets below this are never handed out, so that zero can serve as a null pointer
+const RESERVED: usize = 8;
+
+#[derive(Debug, Clone, Copy)]
+struct ArenaState {
+    /// Id of the segment holding the allocations
+    data_id: i32,
+    capacity: usize,
+    /// Bytes handed out so far, including the reserved ones
+    used: usize,
+    /// Offset of the root allocation, zero if not set
+    root: u64,
+}
+
+/// Offset-based pointer to a `T` allocated in a `CortexArena`.
+///
+/// Stays valid in every process even though each maps the arena at a different address, so it can
+/// be stored inside the arena itself to link allocations together. `Option<ShmBox<T>>` is the same
+/// size as `ShmBox<T>`, which makes it a cheap optional link, e.g. to the children of a tree node.
+#[repr(transparent)]
+pub struct ShmBox<T> {
+    offset: NonZeroU64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> ShmBox<T> {
+    fn from_offset(offset: NonZeroU64) -> Self {
+        Self {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+    /// Offset of the allocation from the start of the arena
+    pub fn offset(&self) -> u64 {
+        self.offset.get()
+    }
+}
+
+impl<T: Copy> ShmBox<T> {
+    /// Borrow the value, valid while the lock of the arena is held
+    pub fn get<'a>(&self, arena: &'a ArenaView<'_>) -> CortexResult<&'a T> {
+        let ptr = arena.checked_ptr::<T>(self.offset(), 1)?;
+        Ok(unsafe { &*ptr })
+    }
+    /// Mutably borrow the value, valid while the write lock of the arena is held
+    pub fn get_mut<'a>(&self, arena: &'a mut ArenaView<'_>) -> CortexResult<&'a mut T> {
+        let ptr = arena.checked_ptr::<T>(self.offset(), 1)?;
+        Ok(unsafe { &mut *ptr })
+    }
+}
+
+impl<T> Clone for ShmBox<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ShmBox<T> {}
+
+impl<T> PartialEq for ShmBox<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<T> Eq for ShmBox<T> {}
+
+impl<T> fmt::Debug for ShmBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ShmBox").field(&self.offset).finish()
+    }
+}
+
+/// Offset-based growable array of `T` allocated in a `CortexArena`.
+///
+/// Growing allocates a new array in the arena and copies the elements over, the space of the
+/// previous one is not reused. When the vector itself is stored in the arena, copy it out before
+/// pushing and write it back afterwards, since the arena can't be borrowed twice.
+#[repr(C)]
+pub struct ArenaVec<T> {
+    offset: u64,
+    len: u64,
+    capacity: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<T> ArenaVec<T> {
+    /// Create an empty vector, nothing is allocated until the first push
+    pub const fn new() -> Self {
+        Self {
+            offset: 0,
+            len: 0,
+            capacity: 0,
+            _marker: PhantomData,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity as usize
+    }
+}
+
+impl<T: Copy> ArenaVec<T> {
+    /// Append an element, moving the elements to a larger array in the arena if it is full
+    pub fn push(&mut self, arena: &mut ArenaView<'_>, value: T) -> CortexResult<()> {
+        if self.len == self.capacity {
+            let capacity = self.capacity.saturating_mul(2).max(4);
+            let offset = arena.alloc_raw::<T>(capacity as usize)?;
+            if self.len > 0 {
+                let previous = arena.checked_ptr::<T>(self.offset, self.len())?;
+                let grown = arena.checked_ptr::<T>(offset, self.len())?;
+                unsafe { std::ptr::copy_nonoverlapping(previous, grown, self.len()) };
+            }
+            self.offset = offset;
+            self.capacity = capacity;
+        }
+        let ptr = arena.checked_ptr::<T>(self.offset, self.len() + 1)?;
+        unsafe { ptr.add(self.len()).write(value) };
+        self.len += 1;
+        Ok(())
+    }
+    /// Borrow the elements, valid while the lock of the arena is held
+    pub fn as_slice<'a>(&self, arena: &'a ArenaView<'_>) -> CortexResult<&'a [T]> {
+        if self.len == 0 {
+            return Ok(&[]);
+        }
+        let ptr = arena.checked_ptr::<T>(self.offset, self.len())?;
+        Ok(unsafe { std::slice::from_raw_parts(ptr, self.len()) })
+    }
+    /// Mutably borrow the elements, valid while the write lock of the arena is held
+    pub fn as_mut_slice<'a>(&self, arena: &'a mut ArenaView<'_>) -> CortexResult<&'a mut [T]> {
+        if self.len == 0 {
+            return Ok(&mut []);
+        }
+        let ptr = arena.checked_ptr::<T>(self.offset, self.len())?;
+        Ok(unsafe { std::slice::from_raw_parts_mut(ptr, self.len()) })
+    }
+}
+
+impl<T> Default for ArenaVec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Clone for ArenaVec<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for ArenaVec<T> {}
+
+impl<T> fmt::Debug for ArenaVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArenaVec")
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// Access to the contents of a `CortexArena` while holding its lock, see `CortexArena::with_read`
+/// and `CortexArena::with_write`
+pub struct ArenaView<'a> {
+    base: *mut u8,
+    capacity: usize,
+    used: usize,
+    root: u64,
+    _marker: PhantomData<&'a mut [u8]>,
+}
+
+impl ArenaView<'_> {
+    /// Pointer to `len` elements of `T` at `offset`, after checking that they lie within the
+    /// allocated part of the arena and are properly aligned
+    fn checked_ptr<T>(&self, offset: u64, len: usize) -> CortexResult<*mut T> {
+        let start = usize::try_from(offset)
+            .ok()
+            .filter(|start| *start >= RESERVED && start % std::mem::align_of::<T>() == 0);
+        let end = start.and_then(|start| {
+            len.checked_mul(std::mem::size_of::<T>())
+                .and_then(|size| start.checked_add(size))
+        });
+        match (start, end) {
+            (Some(start), Some(end)) if end <= self.used => {
+                Ok(unsafe { self.base.add(start) } as *mut T)
+            }
+            _ => Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Invalid arena offset {} for {} elements of {} bytes, {} bytes are allocated",
+                    offset,
+                    len,
+                    std::mem::size_of::<T>(),
+                    self.used
+                ),
+            )),
+        }
+    }
+    /// Reserve room for `len` elements of `T`, returning the offset
+    fn alloc_raw<T>(&mut self, len: usize) -> CortexResult<u64> {
+        let start = self.used.next_multiple_of(std::mem::align_of::<T>());
+        let end = len
+            .checked_mul(std::mem::size_of::<T>())
+            .and_then(|size| start.checked_add(size))
+            .filter(|end| *end <= self.capacity);
+        let Some(end) = end else {
+            return Err(CortexError::new_logic(
+                ErrorKind::OutOfMemory,
+                format!(
+                "Arena is out of space for {} elements of {} bytes, {} of {} bytes are allocated",
+                len,
+                std::mem::size_of::<T>(),
+                self.used,
+                self.capacity
+            ),
+            ));
+        };
+        self.used = end;
+        Ok(start as u64)
+    }
+    /// Move `value` into the arena, returning a pointer to it that is valid in every process
+    pub fn alloc<T: Copy>(&mut self, value: T) -> CortexResult<ShmBox<T>> {
+        let offset = self.alloc_raw::<T>(1)?;
+        let ptr = self.checked_ptr::<T>(offset, 1)?;
+        unsafe { ptr.write(value) };
+        let offset = NonZeroU64::new(offset).expect("Offsets below RESERVED are never allocated");
+        Ok(ShmBox::from_offset(offset))
+    }
+    /// The root allocation, meant as the entry point for other processes to start traversing from.
+    /// Must be read as the same `T` that it was set with.
+    pub fn root<T>(&self) -> Option<ShmBox<T>> {
+        NonZeroU64::new(self.root).map(ShmBox::from_offset)
+    }
+    pub fn set_root<T>(&mut self, root: Option<ShmBox<T>>) {
+        self.root = root.map_or(0, |root| root.offset());
+    }
+    /// Number of bytes allocated so far, including padding
+    pub fn used(&self) -> usize {
+        self.used
+    }
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Process-shared bump allocator, protected by `L`.
+///
+/// Allocations live in a fixed size segment and are addressed through offsets (`ShmBox` and
+/// `ArenaVec`) instead of pointers, so that linked structures such as trees and graphs can be
+/// built directly in shared memory and traversed by other processes. Memory is only reclaimed
+/// when the arena is dropped, allocations are never freed individually. Values are copied in
+/// without being dropped, so they have to be `Copy`.
+#[derive(Debug)]
+pub struct CortexArena<L: CortexSync> {
+    state: Cortex<ArenaState, L>,
+    segment: Segment<u8>,
+}
+
+unsafe impl<L: CortexSync + Send> Send for CortexArena<L> {}
+unsafe impl<L: CortexSync + Sync> Sync for CortexArena<L> {}
+
+impl<L: CortexSync> CortexArena<L> {
+    /// Create a new arena on `key` that can hold `capacity` bytes of allocations
+    pub fn new(key: i32, capacity: usize) -> CortexResult<Self> {
+        let segment = Segment::create(capacity, L::IN_PROCESS)?;
+        let state = ArenaState {
+            data_id: segment.id,
+            capacity,
+            used: RESERVED,
+            root: 0,
+        };
+        let state = Cortex::new(Some(key), state, false, None).inspect_err(|_| {
+            if let Err(err) = mark_for_deletion(segment.id) {
+                crate::trace::error!("Error cleaning up after failed create: {}", err)
+            }
+        })?;
+        Ok(Self { state, segment })
+    }
+    /// Attempt to attach to an already existing arena
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let state: Cortex<ArenaState, L> = Cortex::attach(key)?;
+        let segment = Segment::attach(state.read_field(|state| &state.data_id)?)?;
+        Ok(Self { state, segment })
+    }
+    fn view(&self, state: &ArenaState) -> ArenaView<'_> {
+        ArenaView {
+            base: self.segment.ptr,
+            capacity: state.capacity,
+            used: state.used,
+            root: state.root,
+            _marker: PhantomData,
+        }
+    }
+    /// Access the arena while holding the read lock, e.g. to traverse a structure from its root
+    pub fn with_read<R>(&self, f: impl FnOnce(&ArenaView<'_>) -> R) -> CortexResult<R> {
+        self.state.with_read(|state| f(&self.view(state)))
+    }
+    /// Access the arena while holding the write lock, to allocate and modify values
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut ArenaView<'_>) -> R) -> CortexResult<R> {
+        self.state.with_write(|state| {
+            let mut view = self.view(state);
+            let result = f(&mut view);
+            state.used = view.used;
+            state.root = view.root;
+            result
+        })
+    }
+    /// Number of bytes allocated so far, including padding
+    pub fn used(&self) -> CortexResult<usize> {
+        self.state.read_field(|state| &state.used)
+    }
+    pub fn key(&self) -> i32 {
+        self.state.key()
+    }
+}
+
+/// Remove the allocation segment along with the arena
+impl<L: CortexSync> Drop for CortexArena<L> {
+    fn drop(&mut self) {
+        if !self.state.is_owner() {
+            return;
+        }
+        if let Err(err) = mark_for_deletion(self.segment.id) {
+            crate::trace::error!("Error during mark_for_deletion in Drop: {}", err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arena::{ArenaVec, ArenaView, CortexArena, ShmBox};
+    use crate::{ErrorKind, SpinLock};
+    use std::num::NonZeroU64;
+
+    #[derive(Clone, Copy)]
+    struct Node {
+        value: u64,
+        left: Option<ShmBox<Node>>,
+        right: Option<ShmBox<Node>>,
+    }
+
+    fn insert(arena: &mut ArenaView<'_>, value: u64) {
+        let node = arena
+            .alloc(Node {
+                value,
+                left: None,
+                right: None,
+            })
+            .unwrap();
+        let Some(mut current) = arena.root::<Node>() else {
+            arena.set_root(Some(node));
+            return;
+        };
+        loop {
+            let parent = current.get_mut(arena).unwrap();
+            let child = if value < parent.value {
+                &mut parent.left
+            } else {
+                &mut parent.right
+            };
+            match child {
+                Some(next) => current = *next,
+                None => {
+                    *child = Some(node);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn in_order(arena: &ArenaView<'_>, node: Option<ShmBox<Node>>, out: &mut Vec<u64>) {
+        if let Some(node) = node {
+            let node = node.get(arena).unwrap();
+            in_order(arena, node.left, out);
+            out.push(node.value);
+            in_order(arena, node.right, out);
+        }
+    }
+
+    #[test]
+    fn tree_across_instances() {
+        let key = rand::random::<i32>().abs();
+        let arena: CortexArena<SpinLock> = CortexArena::new(key, 4096).unwrap();
+        arena
+            .with_write(|arena| {
+                for value in [5, 2, 8, 1, 9, 3] {
+                    insert(arena, value);
+                }
+            })
+            .unwrap();
+
+        let attached: CortexArena<SpinLock> = CortexArena::attach(key).unwrap();
+        let values = attached
+            .with_read(|arena| {
+                let mut values = Vec::new();
+                in_order(arena, arena.root(), &mut values);
+                values
+            })
+            .unwrap();
+        assert_eq!(values, [1, 2, 3, 5, 8, 9]);
+    }
+
+    #[test]
+    fn vec_and_exhaustion() {
+        let key = rand::random::<i32>().abs();
+        let arena: CortexArena<SpinLock> = CortexArena::new(key, 256).unwrap();
+
+        let list = arena
+            .with_write(|arena| {
+                let mut list = ArenaVec::new();
+                for value in 0..10u32 {
+                    list.push(arena, value).unwrap();
+                }
+                let list = arena.alloc(list).unwrap();
+                arena.set_root(Some(list));
+                list
+            })
+            .unwrap();
+        let values = arena
+            .with_read(|arena| list.get(arena).unwrap().as_slice(arena).unwrap().to_vec())
+            .unwrap();
+        assert_eq!(values, (0..10).collect::<Vec<_>>());
+
+        let err = arena
+            .with_write(|arena| arena.alloc([0u8; 256]))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::OutOfMemory);
+
+        // Offsets past the allocated part of the arena are rejected instead of read
+        let used = arena.used().unwrap() as u64;
+        let dangling = ShmBox::<u64>::from_offset(NonZeroU64::new(used).unwrap());
+        let err = arena
+            .with_read(|arena| dangling.get(arena).copied())
+            .unwrap();
+        assert_eq!(err.unwrap_err().kind(), ErrorKind::InvalidInput);
+    }
+}
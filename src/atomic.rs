@@ -0,0 +1,65 @@
+use std::sync::atomic::Ordering;
+
+/// Operations on a 32 bit atomic that the lock state machines are written against, so that they
+/// run on the atomics in shared memory as well as on the model checked atomics of `loom`
+pub(crate) trait Atomic32 {
+    fn load(&self, order: Ordering) -> u32;
+    fn fetch_add(&self, value: u32, order: Ordering) -> u32;
+    fn fetch_sub(&self, value: u32, order: Ordering) -> u32;
+    fn fetch_and(&self, value: u32, order: Ordering) -> u32;
+    fn compare_exchange(
+        &self,
+        current: u32,
+        new: u32,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u32, u32>;
+    fn compare_exchange_weak(
+        &self,
+        current: u32,
+        new: u32,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u32, u32>;
+}
+
+macro_rules! impl_atomic32 {
+    ($atomic:ty) => {
+        impl Atomic32 for $atomic {
+            fn load(&self, order: Ordering) -> u32 {
+                <$atomic>::load(self, order)
+            }
+            fn fetch_add(&self, value: u32, order: Ordering) -> u32 {
+                <$atomic>::fetch_add(self, value, order)
+            }
+            fn fetch_sub(&self, value: u32, order: Ordering) -> u32 {
+                <$atomic>::fetch_sub(self, value, order)
+            }
+            fn fetch_and(&self, value: u32, order: Ordering) -> u32 {
+                <$atomic>::fetch_and(self, value, order)
+            }
+            fn compare_exchange(
+                &self,
+                current: u32,
+                new: u32,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<u32, u32> {
+                <$atomic>::compare_exchange(self, current, new, success, failure)
+            }
+            fn compare_exchange_weak(
+                &self,
+                current: u32,
+                new: u32,
+                success: Ordering,
+                failure: Ordering,
+            ) -> Result<u32, u32> {
+                <$atomic>::compare_exchange_weak(self, current, new, success, failure)
+            }
+        }
+    };
+}
+
+impl_atomic32!(std::sync::atomic::AtomicU32);
+#[cfg(feature = "loom")]
+impl_atomic32!(loom::sync::atomic::AtomicU32);
@@ -0,0 +1,236 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    Cortex, CortexResult, CortexSync,
+};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "semaphore")] {
+        use crate::{namespace, semaphore::{Semaphore, SemaphoreSettings}};
+    } else {
+        use crate::wait_until;
+    }
+}
+
+/// Most participants a barrier can wait for, since the handles that arrived are stored in the
+/// segment
+pub const MAX_PARTICIPANTS: usize = 64;
+
+/// Per-process counter used to tell apart multiple handles living in the same process
+static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Debug, Clone, Copy)]
+struct BarrierState {
+    /// Tokens of the handles that arrived in the current round, the first `count` are in use
+    arrived: [u64; MAX_PARTICIPANTS],
+    count: usize,
+    n: usize,
+    generation: u64,
+}
+
+/// Process-shared barrier that blocks until `n` participants have called `wait`.
+///
+/// Every handle is one participant, so threads and processes taking part each attach their own.
+/// Arrivals are recorded per handle in a segment of shared memory protected by `L`, and calling
+/// `wait` on a handle that already arrived in the current round fails instead of counting twice.
+/// The barrier resets itself once released so it can be reused for multiple rounds.
+///
+/// With the `semaphore` feature, waiting participants block on a named semaphore that the last
+/// one to arrive posts to, otherwise they poll the segment.
+#[derive(Debug)]
+pub struct CortexBarrier<L: CortexSync> {
+    cortex: Cortex<BarrierState, L>,
+    token: u64,
+    #[cfg(feature = "semaphore")]
+    released: Semaphore,
+}
+
+impl<L: CortexSync> CortexBarrier<L> {
+    /// Create a new barrier on `key` that releases once `n` participants have arrived, where `n`
+    /// is at most `MAX_PARTICIPANTS`
+    pub fn new(key: i32, n: usize) -> CortexResult<Self> {
+        if n == 0 || n > MAX_PARTICIPANTS {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!(
+                    "A barrier needs between 1 and {} participants, got {}",
+                    MAX_PARTICIPANTS, n
+                ),
+            ));
+        }
+        let state = BarrierState {
+            arrived: [0; MAX_PARTICIPANTS],
+            count: 0,
+            n,
+            generation: 0,
+        };
+        let cortex = Cortex::new(Some(key), state, false, None)?;
+        #[cfg(feature = "semaphore")]
+        let released = Semaphore::create(
+            key,
+            &SemaphoreSettings {
+                initial_value: 0,
+                namespace: Some(release_namespace()),
+                ..Default::default()
+            },
+        )?;
+        Ok(Self {
+            cortex,
+            token: next_token(),
+            #[cfg(feature = "semaphore")]
+            released,
+        })
+    }
+    /// Attempt to attach to an already existing barrier
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let cortex = Cortex::attach(key)?;
+        #[cfg(feature = "semaphore")]
+        let released = Semaphore::open(
+            key,
+            Some(&SemaphoreSettings {
+                namespace: Some(release_namespace()),
+                ..Default::default()
+            }),
+        )?;
+        Ok(Self {
+            cortex,
+            token: next_token(),
+            #[cfg(feature = "semaphore")]
+            released,
+        })
+    }
+    /// Block until all participants have arrived. Exactly one participant per round gets `true`
+    /// back, which can be used to elect someone to do follow-up work. Fails with
+    /// `ErrorKind::InvalidInput` if this handle already arrived in the current round, e.g. from
+    /// another thread.
+    pub fn wait(&self) -> CortexResult<bool> {
+        let (generation, released) = self.cortex.with_write(|state| {
+            if state.arrived[..state.count].contains(&self.token) {
+                return Err(CortexError::new_logic(
+                    ErrorKind::InvalidInput,
+                    "This handle already arrived at the barrier in the current round",
+                ));
+            }
+            let generation = state.generation;
+            state.arrived[state.count] = self.token;
+            state.count += 1;
+            if state.count < state.n {
+                return Ok((generation, None));
+            }
+            state.count = 0;
+            state.generation = state.generation.wrapping_add(1);
+            Ok((generation, Some(state.n - 1)))
+        })??;
+        match released {
+            Some(waiting) => {
+                self.release(waiting)?;
+                Ok(true)
+            }
+            None => {
+                self.wait_for_release(generation)?;
+                Ok(false)
+            }
+        }
+    }
+    /// Wake the `waiting` participants of the round that just completed
+    #[cfg(feature = "semaphore")]
+    fn release(&self, waiting: usize) -> CortexResult<()> {
+        (0..waiting).try_for_each(|_| self.released.release())
+    }
+    #[cfg(not(feature = "semaphore"))]
+    fn release(&self, _waiting: usize) -> CortexResult<()> {
+        Ok(())
+    }
+    /// Block until the round started in `generation` is over
+    #[cfg(feature = "semaphore")]
+    fn wait_for_release(&self, generation: u64) -> CortexResult<()> {
+        loop {
+            self.released.acquire()?.forget();
+            if self.cortex.read()?.generation != generation {
+                return Ok(());
+            }
+            // Taken from a participant of the previous round that is yet to wake up
+            self.released.release()?;
+            std::thread::yield_now();
+        }
+    }
+    #[cfg(not(feature = "semaphore"))]
+    fn wait_for_release(&self, generation: u64) -> CortexResult<()> {
+        wait_until(|| Ok(self.cortex.read()?.generation != generation))
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+/// Identifies a handle across all processes attached to the barrier
+fn next_token() -> u64 {
+    let pid = unsafe { libc::getpid() };
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    ((pid as u64) << 32) | handle as u64
+}
+
+/// Prefix of the semaphore that waiting participants block on, which has to differ from the one
+/// `Semaphore` uses as the lock of the segment
+#[cfg(feature = "semaphore")]
+fn release_namespace() -> String {
+    namespace::qualify("cortex_barrier".to_string())
+}
+
+#[cfg(all(test, feature = "semaphore"))]
+mod tests {
+    use crate::barrier::{CortexBarrier, MAX_PARTICIPANTS};
+    use crate::semaphore::Semaphore;
+    use crate::ErrorKind;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn releases_all_participants() {
+        let key = rand::random::<i32>().abs();
+        let n_threads = 8;
+        let barrier: CortexBarrier<Semaphore> = CortexBarrier::new(key, n_threads).unwrap();
+
+        for _ in 0..3 {
+            let handles: Vec<_> = (0..n_threads - 1)
+                .map(|_| {
+                    thread::spawn(move || {
+                        let barrier: CortexBarrier<Semaphore> = CortexBarrier::attach(key).unwrap();
+                        barrier.wait().unwrap()
+                    })
+                })
+                .collect();
+
+            let mut leaders = barrier.wait().unwrap() as usize;
+            for handle in handles {
+                leaders += handle.join().expect("Thread panicked") as usize;
+            }
+            assert_eq!(leaders, 1);
+        }
+    }
+
+    #[test]
+    fn one_arrival_per_handle() {
+        let key = rand::random::<i32>().abs();
+        let barrier: CortexBarrier<Semaphore> = CortexBarrier::new(key, 2).unwrap();
+
+        thread::scope(|scope| {
+            let waiting = scope.spawn(|| barrier.wait().unwrap());
+            while barrier.cortex.read().unwrap().count == 0 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            // The same handle arriving again doesn't count as the second participant
+            let err = barrier.wait().unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert!(!waiting.is_finished());
+
+            let other: CortexBarrier<Semaphore> = CortexBarrier::attach(key).unwrap();
+            assert!(other.wait().unwrap());
+            assert!(!waiting.join().expect("Thread panicked"));
+        });
+
+        let other_key = rand::random::<i32>().abs();
+        let err = CortexBarrier::<Semaphore>::new(other_key, MAX_PARTICIPANTS + 1).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    mark_for_deletion,
+    segment::Segment,
+    Cortex, CortexResult, CortexSync,
+};
+
+/// Indirection header of a `CortexBlob`, stored on the key of the blob
+#[derive(Debug, Clone, Copy)]
+struct BlobState {
+    data_id: i32,
+    capacity: usize,
+    /// Number of bytes stored in the data segment, which is the compressed length if `compressed`
+    stored_len: usize,
+    /// Length of the blob as written, before compression
+    len: usize,
+    compressed: bool,
+}
+
+/// Process-shared byte blob of up to a fixed capacity, protected by `L`, e.g. for serialized
+/// snapshots that are replaced as a whole.
+///
+/// The bytes live in a separate segment of `capacity` bytes, and are copied in and out under the
+/// lock. With the `compress` feature, blobs at or above the threshold set through
+/// `CortexBlob::compress_above` are compressed with LZ4 before they are stored, which cuts the
+/// time spent copying under the lock for compressible data. Readers decompress transparently.
+#[derive(Debug)]
+pub struct CortexBlob<L: CortexSync> {
+    state: Cortex<BlobState, L>,
+    segment: Segment<u8>,
+    #[cfg(feature = "compress")]
+    compress_above: Option<usize>,
+}
+
+unsafe impl<L: CortexSync + Send> Send for CortexBlob<L> {}
+unsafe impl<L: CortexSync + Sync> Sync for CortexBlob<L> {}
+
+impl<L: CortexSync> CortexBlob<L> {
+    /// Create a new empty blob on `key` with room for `capacity` stored bytes
+    pub fn new(key: i32, capacity: usize) -> CortexResult<Self> {
+        let segment = Segment::create(capacity, L::IN_PROCESS)?;
+        let state = BlobState {
+            data_id: segment.id,
+            capacity,
+            stored_len: 0,
+            len: 0,
+            compressed: false,
+        };
+        let state = Cortex::new(Some(key), state, false, None).inspect_err(|_| {
+            if let Err(err) = mark_for_deletion(segment.id) {
+                crate::trace::error!("Error cleaning up after failed create: {}", err)
+            }
+        })?;
+        Ok(Self {
+            state,
+            segment,
+            #[cfg(feature = "compress")]
+            compress_above: None,
+        })
+    }
+    /// Attempt to attach to an already existing blob
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let state: Cortex<BlobState, L> = Cortex::attach(key)?;
+        let data_id = state.read_field(|state| &state.data_id)?;
+        Ok(Self {
+            state,
+            segment: Segment::attach(data_id)?,
+            #[cfg(feature = "compress")]
+            compress_above: None,
+        })
+    }
+    /// Compress blobs of at least `threshold` bytes written through this instance. Blobs that
+    /// don't get smaller are stored as is.
+    #[cfg(feature = "compress")]
+    pub fn compress_above(mut self, threshold: usize) -> Self {
+        self.compress_above = Some(threshold);
+        self
+    }
+    /// Replace the blob with `bytes`. Fails if the stored bytes exceed the capacity.
+    pub fn write(&self, bytes: &[u8]) -> CortexResult<()> {
+        #[cfg(feature = "compress")]
+        let compressed = self
+            .compress_above
+            .filter(|threshold| bytes.len() >= *threshold)
+            .map(|_| lz4_flex::block::compress(bytes))
+            .filter(|compressed| compressed.len() < bytes.len());
+        #[cfg(not(feature = "compress"))]
+        let compressed: Option<Vec<u8>> = None;
+
+        let stored = compressed.as_deref().unwrap_or(bytes);
+        self.state.with_write(|state| {
+            if stored.len() > state.capacity {
+                return Err(CortexError::new_logic(
+                    ErrorKind::NoSpace,
+                    format!(
+                        "Blob of {} bytes exceeds the capacity of {} bytes",
+                        stored.len(),
+                        state.capacity
+                    ),
+                ));
+            }
+            unsafe {
+                std::ptr::copy_nonoverlapping(stored.as_ptr(), self.segment.ptr, stored.len())
+            };
+            state.stored_len = stored.len();
+            state.len = bytes.len();
+            state.compressed = compressed.is_some();
+            Ok(())
+        })?
+    }
+    /// Copy out the blob, decompressing it if needed
+    pub fn read(&self) -> CortexResult<Vec<u8>> {
+        let (stored, state) = self.state.with_read(|state| {
+            let stored =
+                unsafe { std::slice::from_raw_parts(self.segment.ptr, state.stored_len) }.to_vec();
+            (stored, *state)
+        })?;
+        if !state.compressed {
+            return Ok(stored);
+        }
+        decompress(&stored, state.len)
+    }
+    /// Length of the blob as written
+    pub fn len(&self) -> CortexResult<usize> {
+        self.state.read_field(|state| &state.len)
+    }
+    pub fn is_empty(&self) -> CortexResult<bool> {
+        Ok(self.len()? == 0)
+    }
+    /// Number of bytes the blob takes up in shared memory, less than `len` if it was compressed
+    pub fn stored_len(&self) -> CortexResult<usize> {
+        self.state.read_field(|state| &state.stored_len)
+    }
+    pub fn capacity(&self) -> CortexResult<usize> {
+        self.state.read_field(|state| &state.capacity)
+    }
+    pub fn key(&self) -> i32 {
+        self.state.key()
+    }
+}
+
+#[cfg(feature = "compress")]
+fn decompress(stored: &[u8], len: usize) -> CortexResult<Vec<u8>> {
+    lz4_flex::block::decompress(stored, len).map_err(|err| {
+        CortexError::new_logic(
+            ErrorKind::Other,
+            format!("Error decompressing blob: {}", err),
+        )
+    })
+}
+
+#[cfg(not(feature = "compress"))]
+fn decompress(_stored: &[u8], _len: usize) -> CortexResult<Vec<u8>> {
+    Err(CortexError::new_logic(
+        ErrorKind::Unsupported,
+        "Blob was compressed, which requires the `compress` feature",
+    ))
+}
+
+/// Remove the data segment along with the blob
+impl<L: CortexSync> Drop for CortexBlob<L> {
+    fn drop(&mut self) {
+        if !self.state.is_owner() {
+            return;
+        }
+        if let Err(err) = mark_for_deletion(self.segment.id) {
+            crate::trace::error!("Error during mark_for_deletion in Drop: {}", err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blob::CortexBlob;
+    use crate::{ErrorKind, SpinLock};
+
+    #[test]
+    fn replace_and_read() {
+        let key = rand::random::<i32>().abs();
+        let blob: CortexBlob<SpinLock> = CortexBlob::new(key, 64).unwrap();
+        let attached: CortexBlob<SpinLock> = CortexBlob::attach(key).unwrap();
+        assert!(attached.is_empty().unwrap());
+
+        blob.write(b"first snapshot").unwrap();
+        assert_eq!(attached.read().unwrap(), b"first snapshot");
+        attached.write(b"second").unwrap();
+        assert_eq!(blob.read().unwrap(), b"second");
+
+        let err = blob.write(&[0; 65]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoSpace);
+        assert_eq!(blob.len().unwrap(), 6);
+    }
+
+    #[cfg(feature = "compress")]
+    #[test]
+    fn compressed() {
+        let key = rand::random::<i32>().abs();
+        let blob: CortexBlob<SpinLock> = CortexBlob::new(key, 1024).unwrap().compress_above(100);
+        let attached: CortexBlob<SpinLock> = CortexBlob::attach(key).unwrap();
+
+        // Larger than the capacity, but compresses well
+        let snapshot: Vec<u8> = (0..4096).map(|i| (i / 512) as u8).collect();
+        blob.write(&snapshot).unwrap();
+        assert!(attached.stored_len().unwrap() < 1024);
+        assert_eq!(attached.len().unwrap(), 4096);
+        assert_eq!(attached.read().unwrap(), snapshot);
+
+        // Below the threshold
+        blob.write(&[7; 50]).unwrap();
+        assert_eq!(attached.stored_len().unwrap(), 50);
+        assert_eq!(attached.read().unwrap(), [7; 50]);
+    }
+}
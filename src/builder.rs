@@ -1,5 +1,52 @@
-use crate::{Cortex, CortexResult, CortexSync};
+use crate::{
+    crash::{CortexError, ErrorKind},
+    Cortex, CortexResult, CortexSync, HugePages, Pod, RetryPolicy,
+};
 use std::marker::PhantomData;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// Permissions of new segments unless set through `CortexBuilder::permissions`
+pub(crate) const DEFAULT_PERMISSIONS: u32 = 0o666;
+
+/// What happens to a segment when the instance that created it is dropped, set through
+/// `CortexBuilder::drop_policy`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    /// The creator owns the segment and removes it when dropped, unless it hands ownership over
+    /// through `Cortex::transfer_ownership`
+    #[default]
+    Owner,
+    /// Keep a cross-process reference count and remove the segment once the last attached
+    /// instance is dropped, see `CortexBuilder::ref_counted`
+    RefCounted,
+    /// Leave the segment behind when the creator is dropped, so that it can be attached to later.
+    /// Another process can still take over cleanup through `Cortex::adopt`.
+    Keep,
+}
+
+/// Options collected by the builder and passed on to segment creation
+#[derive(Debug, Default, Clone)]
+pub(crate) struct CortexOptions {
+    pub(crate) force_ownership: bool,
+    pub(crate) force_ownership_reinit: bool,
+    pub(crate) attach_or_create: bool,
+    pub(crate) stale_after: Option<Duration>,
+    pub(crate) drop_policy: DropPolicy,
+    pub(crate) permissions: Option<u32>,
+    pub(crate) attach_timeout: Option<Duration>,
+    pub(crate) lock_warning: Option<Duration>,
+    pub(crate) lock_stats: bool,
+    pub(crate) retry: RetryPolicy,
+    pub(crate) huge_pages: HugePages,
+    pub(crate) lock_memory: bool,
+    /// Only ever set for a `T: Pod`, see `CortexBuilder::checksum`
+    pub(crate) checksum: bool,
+    pub(crate) schema_version: u32,
+    pub(crate) max_size: Option<usize>,
+    #[cfg(feature = "numa")]
+    pub(crate) numa_node: Option<u32>,
+}
 
 pub trait BuilderState {}
 
@@ -13,11 +60,23 @@ impl BuilderState for Initialized {}
 impl BuilderState for WithKey {}
 impl BuilderState for WithRandomKey {}
 
+/// Lock type and settings chosen through `CortexBuilder::lock`, on top of the key state `S`
+pub struct WithLock<S: KeyState, L: CortexSync> {
+    settings: Option<L::Settings>,
+    key_state: PhantomData<S>,
+}
+
+impl<S: KeyState, L: CortexSync> BuilderState for WithLock<S, L> {}
+
+/// Upgrades a value stored under an older schema version in place, see `CortexBuilder::migrate`
+type Migration<T> = Box<dyn FnOnce(u32, &mut T)>;
+
 pub struct CortexBuilder<T, S> {
     data: T,
-    force_ownership: bool,
+    options: CortexOptions,
+    migration: Option<Migration<T>>,
     key: Option<i32>,
-    state: PhantomData<S>,
+    state: S,
 }
 
 impl<T> CortexBuilder<T, Uninitialized> {
@@ -25,8 +84,9 @@ impl<T> CortexBuilder<T, Uninitialized> {
         CortexBuilder {
             data,
             key: None,
-            force_ownership: false,
-            state: PhantomData,
+            options: CortexOptions::default(),
+            migration: None,
+            state: Initialized {},
         }
     }
 }
@@ -37,8 +97,9 @@ impl<T> CortexBuilder<T, Initialized> {
         CortexBuilder {
             data: self.data,
             key: Some(key),
-            force_ownership: self.force_ownership,
-            state: PhantomData,
+            options: self.options,
+            migration: self.migration,
+            state: WithKey {},
         }
     }
     /// Attempt to generate a random key
@@ -46,8 +107,9 @@ impl<T> CortexBuilder<T, Initialized> {
         CortexBuilder {
             data: self.data,
             key: None,
-            force_ownership: self.force_ownership,
-            state: PhantomData,
+            options: self.options,
+            migration: self.migration,
+            state: WithRandomKey {},
         }
     }
 }
@@ -59,18 +121,40 @@ impl<T> CortexBuilder<T, WithKey> {
     /// to attach to that segment and set `is_owner` to `true`. Meaning this `Cortex` instance will
     /// be responsible for any cleanup.
     ///
-    /// # Safety
-    ///
-    /// Only use this option when you can guarantee that any pre-existing segment of shared memory on
-    /// the same `key` is also of the same type `T`.
+    /// Fails with `ErrorKind::TypeMismatch` if the existing segment was created for a value of a
+    /// different type, size or layout, see `force_ownership_reinit` to replace it instead.
     ///
     pub fn force_ownership(self) -> CortexBuilder<T, WithKey> {
-        CortexBuilder {
-            data: self.data,
-            key: self.key,
-            force_ownership: true,
-            state: PhantomData,
-        }
+        self.configure(|options| options.force_ownership = true)
+    }
+    ///
+    /// Like `force_ownership`, but if the existing segment was created for a value of a different
+    /// type, size or layout, it is destroyed along with its lock and recreated with the value
+    /// passed to the builder. Processes still attached to the old segment keep using it until they
+    /// attach again.
+    ///
+    pub fn force_ownership_reinit(self) -> CortexBuilder<T, WithKey> {
+        self.configure(|options| {
+            options.force_ownership = true;
+            options.force_ownership_reinit = true;
+        })
+    }
+    ///
+    /// Attach to an already existing segment on the selected `key` instead of throwing an error,
+    /// or create it if there is none.
+    ///
+    /// If the existing segment is orphaned, meaning that nobody is attached to it and the process
+    /// that created it has died, it is assumed to contain garbage and is destroyed and
+    /// reinitialized with the value passed to the builder instead.
+    ///
+    pub fn attach_or_create(self) -> CortexBuilder<T, WithKey> {
+        self.configure(|options| options.attach_or_create = true)
+    }
+    /// Also consider an existing segment without attachers orphaned if its heartbeat (see
+    /// `Cortex::beat`) is older than `max_age`, even if its creator pid still appears to be alive.
+    /// This guards against pid reuse. Only has an effect together with `attach_or_create`.
+    pub fn stale_after(self, max_age: Duration) -> CortexBuilder<T, WithKey> {
+        self.configure(|options| options.stale_after = Some(max_age))
     }
 }
 
@@ -78,22 +162,471 @@ pub trait KeyState {}
 impl KeyState for WithKey {}
 impl KeyState for WithRandomKey {}
 
-impl<T, S: KeyState> CortexBuilder<T, S> {
+impl<T, S: KeyState + BuilderState> CortexBuilder<T, S> {
+    ///
+    /// Use a cross-process reference count instead of a single owner. Every attached `Cortex`
+    /// increments the count and decrements it when dropped, the segment and its lock are removed
+    /// once the count reaches zero. This means the creator no longer has to outlive everyone else.
+    ///
+    /// Attaching is transparent, processes attaching to a ref counted segment automatically take
+    /// part in the reference count.
+    ///
+    pub fn ref_counted(self) -> CortexBuilder<T, S> {
+        self.drop_policy(DropPolicy::RefCounted)
+    }
+    /// Log a warning every `after` while blocked waiting for the lock, see
+    /// `Cortex::set_lock_warning`
+    pub fn lock_warning(self, after: Duration) -> CortexBuilder<T, S> {
+        self.configure(|options| options.lock_warning = Some(after))
+    }
+    /// Retry transient failures during creation and attachment according to `policy`, instead of
+    /// the default `RetryPolicy`
+    pub fn retry_policy(self, policy: RetryPolicy) -> CortexBuilder<T, S> {
+        self.configure(|options| options.retry = policy)
+    }
+    /// Back the segment with huge pages, see `HugePages`. The segment is rounded up to a whole
+    /// number of huge pages.
+    pub fn huge_pages(self, huge_pages: HugePages) -> CortexBuilder<T, S> {
+        self.configure(|options| options.huge_pages = huge_pages)
+    }
+    /// Pin the segment in RAM so that it is never swapped out, for latency sensitive use. Fails
+    /// unless the process has `CAP_IPC_LOCK` or a large enough `RLIMIT_MEMLOCK`.
+    pub fn lock_memory(self, lock_memory: bool) -> CortexBuilder<T, S> {
+        self.configure(|options| options.lock_memory = lock_memory)
+    }
+    /// Bind the pages of the segment to NUMA node `node`, so that processes pinned to the same
+    /// node don't pay for cross-node memory access. Only supported on Linux.
+    #[cfg(feature = "numa")]
+    pub fn numa_node(self, node: u32) -> CortexBuilder<T, S> {
+        self.configure(|options| options.numa_node = Some(node))
+    }
+    /// Application defined version of the stored value, 0 unless set. New segments store it in
+    /// their header. Attaching to a segment with an older version runs the migration registered
+    /// through `CortexBuilder::migrate`, and attaching to one with a newer version fails with
+    /// `ErrorKind::TypeMismatch`, as that segment was upgraded by a newer release.
+    pub fn schema_version(self, version: u32) -> CortexBuilder<T, S> {
+        self.configure(|options| options.schema_version = version)
+    }
+    /// Fail with `ErrorKind::TooLarge` before allocating anything if the segment, including its
+    /// header, would be larger than `bytes`. Replaces the global limit of `set_max_segment_size`
+    /// for this segment, so it can also be used to allow a segment above that limit.
+    pub fn max_size(self, bytes: usize) -> CortexBuilder<T, S> {
+        self.configure(|options| options.max_size = Some(bytes))
+    }
+    /// Upgrade the value of an existing segment with an older `schema_version` in place when
+    /// attaching. `migrate` receives the version found in the segment and runs under the write
+    /// lock, after which the segment carries the new version. Only the first process to attach
+    /// runs it. Since the value is modified in place, both versions must have the same size.
+    pub fn migrate(mut self, migrate: impl FnOnce(u32, &mut T) + 'static) -> CortexBuilder<T, S> {
+        self.migration = Some(Box::new(migrate));
+        self
+    }
+    /// Collect lock contention stats, see `Cortex::lock_stats`
+    pub fn lock_stats(self) -> CortexBuilder<T, S> {
+        self.configure(|options| options.lock_stats = true)
+    }
+    /// What happens to the segment when the creating instance is dropped, see `DropPolicy`
+    pub fn drop_policy(self, drop_policy: DropPolicy) -> CortexBuilder<T, S> {
+        self.configure(|options| options.drop_policy = drop_policy)
+    }
+    /// Access permissions of the segment, as the lower nine bits of a Unix file mode, e.g. `0o600`
+    /// to keep processes of other users out. Defaults to `0o666`. Only applies to the segment
+    /// itself, lock backends that need permissions have their own settings for it.
+    pub fn permissions(self, mode: u32) -> CortexBuilder<T, S> {
+        self.configure(|options| options.permissions = Some(mode))
+    }
+    /// Wait up to `timeout` for the segment to be created when attaching through
+    /// `CortexBuilder::attach`, instead of failing right away if it doesn't exist yet
+    pub fn attach_timeout(self, timeout: Duration) -> CortexBuilder<T, S> {
+        self.configure(|options| options.attach_timeout = Some(timeout))
+    }
+    /// Choose the lock implementation and its settings, see `CortexBuilder::create` and
+    /// `CortexBuilder::attach` for the final step
+    pub fn lock<L: CortexSync>(self, settings: L::Settings) -> CortexBuilder<T, WithLock<S, L>> {
+        self.lock_with(Some(settings))
+    }
+    /// Choose the lock implementation without passing any settings to it
+    pub fn default_lock<L: CortexSync>(self) -> CortexBuilder<T, WithLock<S, L>> {
+        self.lock_with(None)
+    }
+    fn lock_with<L: CortexSync>(
+        self,
+        settings: Option<L::Settings>,
+    ) -> CortexBuilder<T, WithLock<S, L>> {
+        CortexBuilder {
+            data: self.data,
+            key: self.key,
+            options: self.options,
+            migration: self.migration,
+            state: WithLock {
+                settings,
+                key_state: PhantomData,
+            },
+        }
+    }
     /// Attempt to construct a `Cortex` with custom lock settings that will differ depending on
-    /// your lock implementation
+    /// your lock implementation. The settings are also passed on to the lock when attaching to an
+    /// existing segment through `attach_or_create` or `force_ownership`.
     pub fn with_lock<L: CortexSync>(
         self,
         lock_settings: &L::Settings,
     ) -> CortexResult<Cortex<T, L>> {
-        Cortex::new(
-            self.key,
-            self.data,
-            self.force_ownership,
-            Some(lock_settings),
-        )
+        let cortex = Cortex::create(self.key, self.data, &self.options, Some(lock_settings))?;
+        finish(cortex, &self.options, self.migration)
     }
     /// Attempt to construct a `Cortex` without passing any lock settings
     pub fn with_default_lock<L: CortexSync>(self) -> CortexResult<Cortex<T, L>> {
-        Cortex::new(self.key, self.data, self.force_ownership, None)
+        let cortex = Cortex::create(self.key, self.data, &self.options, None)?;
+        finish(cortex, &self.options, self.migration)
+    }
+}
+
+impl<T: Pod, S: KeyState + BuilderState> CortexBuilder<T, S> {
+    /// Store a CRC32 of the data in the segment header on every write and verify it on every read,
+    /// so that reads fail with `CortexError::Corrupted` if something modified the segment without
+    /// going through the lock, e.g. a misbehaving peer in another language. The checksum covers
+    /// every byte of the data, so `T` must be `Pod`. Attaching to a segment that keeps a checksum
+    /// also has to go through this, `Cortex::attach` refuses it.
+    pub fn checksum(self) -> CortexBuilder<T, S> {
+        self.configure(|options| options.checksum = true)
+    }
+}
+
+impl<T, S: BuilderState> CortexBuilder<T, S> {
+    fn configure(mut self, f: impl FnOnce(&mut CortexOptions)) -> Self {
+        f(&mut self.options);
+        self
+    }
+}
+
+impl<T, S: KeyState, L: CortexSync> CortexBuilder<T, WithLock<S, L>> {
+    /// Attempt to create the segment with the chosen lock
+    pub fn create(self) -> CortexResult<Cortex<T, L>> {
+        let settings = self.state.settings.as_ref();
+        let cortex = Cortex::create(self.key, self.data, &self.options, settings)?;
+        finish(cortex, &self.options, self.migration)
+    }
+}
+
+impl<T, L: CortexSync> CortexBuilder<T, WithLock<WithKey, L>> {
+    /// Attempt to attach to an already existing segment on the key with the chosen lock, waiting
+    /// for it to be created if `attach_timeout` is set. The value passed to `CortexBuilder::new`
+    /// is unused, and only the options that apply to attaching are taken into account.
+    pub fn attach(self) -> CortexResult<Cortex<T, L>> {
+        let key = self.key.unwrap_or_default();
+        let settings = self.state.settings.as_ref();
+        let deadline = self
+            .options
+            .attach_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let mut retry = 0;
+        let cortex = loop {
+            match Cortex::attach_with(key, settings, self.options.checksum) {
+                Err(err) if err.kind() == ErrorKind::NotFound => match deadline {
+                    Some(deadline) if Instant::now() < deadline => {
+                        std::thread::sleep(self.options.retry.backoff(retry));
+                        retry = retry.saturating_add(1);
+                    }
+                    Some(_) => {
+                        return Err(CortexError::new_logic(
+                            ErrorKind::TimedOut,
+                            format!(
+                                "Timed out waiting for shared memory with key: {} to be created",
+                                key
+                            ),
+                        ))
+                    }
+                    None => return Err(err),
+                },
+                result => break result?,
+            }
+        };
+        finish(cortex, &self.options, self.migration)
+    }
+}
+
+/// Apply the options that are set on the instance rather than stored in the segment, and migrate
+/// attached segments with an older schema version
+fn finish<T, L: CortexSync>(
+    mut cortex: Cortex<T, L>,
+    options: &CortexOptions,
+    migration: Option<Migration<T>>,
+) -> CortexResult<Cortex<T, L>> {
+    if cortex.schema_version() != options.schema_version {
+        migrate(&cortex, options.schema_version, migration)?;
+    }
+    cortex.set_lock_warning(options.lock_warning);
+    cortex.set_lock_stats(options.lock_stats);
+    if options.drop_policy == DropPolicy::Keep && cortex.is_owner {
+        cortex.transfer_ownership()?;
+    }
+    Ok(cortex)
+}
+
+fn migrate<T, L: CortexSync>(
+    cortex: &Cortex<T, L>,
+    version: u32,
+    migration: Option<Migration<T>>,
+) -> CortexResult<()> {
+    let header = unsafe { &*cortex.header };
+    cortex.with_write(|data| {
+        // Another process might have migrated the segment while we waited for the lock
+        let found = header.schema_version.load(Ordering::Acquire);
+        if found > version {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Shared memory with key: {} has schema version {}, newer than {}",
+                    cortex.key(),
+                    found,
+                    version
+                ),
+            ));
+        }
+        if found == version {
+            return Ok(());
+        }
+        let Some(migration) = migration else {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Shared memory with key: {} has schema version {} and no migration to {} is \
+                 registered",
+                    cortex.key(),
+                    found,
+                    version
+                ),
+            ));
+        };
+        crate::trace::info!(
+            "Migrating shared memory with key: {} from schema version {} to {}",
+            cortex.key(),
+            found,
+            version
+        );
+        migration(found, data);
+        header.schema_version.store(version, Ordering::Release);
+        Ok(())
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, CortexBuilder, CortexError, DropPolicy, ErrorKind, SpinLock};
+    use std::time::Duration;
+
+    #[test]
+    fn lock_step() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new(42u64)
+            .key(key)
+            .permissions(0o600)
+            .lock::<SpinLock>(Default::default())
+            .create()
+            .unwrap();
+        let attached = CortexBuilder::new(0u64)
+            .key(key)
+            .default_lock::<SpinLock>()
+            .attach()
+            .unwrap();
+        assert_eq!(attached.read().unwrap(), 42);
+        drop(cortex);
+
+        let err = CortexBuilder::new(0u64)
+            .key(key)
+            .attach_timeout(Duration::from_millis(20))
+            .default_lock::<SpinLock>()
+            .attach()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn force_ownership_mismatch() {
+        let key = rand::random::<i32>().abs();
+        let old: Cortex<u32, SpinLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+
+        let err = CortexBuilder::new(2u64)
+            .key(key)
+            .force_ownership()
+            .with_default_lock::<SpinLock>()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        // Same size, but another type
+        let err = CortexBuilder::new(2i32)
+            .key(key)
+            .force_ownership()
+            .with_default_lock::<SpinLock>()
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+
+        let cortex = CortexBuilder::new(2u64)
+            .key(key)
+            .force_ownership_reinit()
+            .with_default_lock::<SpinLock>()
+            .unwrap();
+        assert!(cortex.is_owner());
+        assert_eq!(cortex.read().unwrap(), 2);
+        // The old segment lives on until its last user is gone
+        assert_eq!(old.read().unwrap(), 1);
+        let attached: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+        assert_eq!(attached.read().unwrap(), 2);
+    }
+
+    #[test]
+    fn keep_on_drop() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new(7u32)
+            .key(key)
+            .drop_policy(DropPolicy::Keep)
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        drop(cortex);
+
+        let mut attached = CortexBuilder::new(0u32)
+            .key(key)
+            .default_lock::<SpinLock>()
+            .attach()
+            .unwrap();
+        assert_eq!(attached.read().unwrap(), 7);
+        // Clean up after the test
+        assert!(attached.adopt());
+    }
+
+    #[test]
+    fn checksum_detects_corruption() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new([1u8; 32])
+            .key(key)
+            .checksum()
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        let attached = CortexBuilder::new([0u8; 32])
+            .key(key)
+            .checksum()
+            .default_lock::<SpinLock>()
+            .attach()
+            .unwrap();
+        attached.with_write(|bytes| bytes[0] = 2).unwrap();
+        assert_eq!(cortex.read().unwrap()[0], 2);
+
+        // Scribble over the data without going through the lock
+        unsafe { (*attached.ptr)[31] = 0xff };
+        assert!(matches!(cortex.read(), Err(CortexError::Corrupted)));
+        assert_eq!(
+            attached.with_read(|_| ()).unwrap_err().kind(),
+            ErrorKind::Corrupted
+        );
+
+        cortex.write([3; 32]).unwrap();
+        assert_eq!(attached.read().unwrap(), [3; 32]);
+    }
+
+    #[test]
+    fn checksum_requires_pod() {
+        // Same size and alignment as `[u64; 2]`, but with seven bytes of padding after `tag`
+        #[derive(Debug, Clone, Copy)]
+        #[allow(dead_code)]
+        struct Padded {
+            tag: u8,
+            value: u64,
+        }
+
+        let key = rand::random::<i32>().abs();
+        let _cortex = CortexBuilder::new([1u64; 2])
+            .key(key)
+            .checksum()
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        let err = Cortex::<Padded, SpinLock>::attach(key).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        let err = Cortex::<[u64; 2], SpinLock>::attach(key).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn schema_migration() {
+        #[derive(Debug, Clone, Copy)]
+        struct Limits {
+            max_connections: u32,
+            timeout_ms: u32,
+        }
+
+        let key = rand::random::<i32>().abs();
+        let old = Limits {
+            max_connections: 10,
+            timeout_ms: 0,
+        };
+        let cortex = CortexBuilder::new(old)
+            .key(key)
+            .schema_version(1)
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+
+        let attach = |version| CortexBuilder::new(old).key(key).schema_version(version);
+        let migrated = attach(2)
+            .migrate(|from, limits| {
+                assert_eq!(from, 1);
+                limits.timeout_ms = 500;
+            })
+            .default_lock::<SpinLock>()
+            .attach()
+            .unwrap();
+        assert_eq!(migrated.schema_version(), 2);
+        assert_eq!(cortex.read().unwrap().timeout_ms, 500);
+
+        // Older releases can no longer attach, and newer ones need a migration
+        for version in [1, 3] {
+            let err = attach(version)
+                .default_lock::<SpinLock>()
+                .attach()
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        }
+        let current = attach(2).default_lock::<SpinLock>().attach().unwrap();
+        assert_eq!(current.read().unwrap().max_connections, 10);
+    }
+
+    #[test]
+    fn max_size_rejects_before_allocating() {
+        let key = rand::random::<i32>().abs();
+        let err = CortexBuilder::new([0u8; 4096])
+            .key(key)
+            .max_size(1024)
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap_err();
+        assert!(matches!(err, CortexError::CleanSystem(_)));
+        assert_eq!(err.kind(), ErrorKind::TooLarge);
+        let err = Cortex::<[u8; 4096], SpinLock>::attach(key).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        let cortex = CortexBuilder::new([0u8; 4096])
+            .key(key)
+            .max_size(1 << 20)
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        assert_eq!(cortex.read().unwrap()[0], 0);
+    }
+
+    #[test]
+    fn lock_memory() {
+        let key = rand::random::<i32>().abs();
+        // Small enough to fit within the default RLIMIT_MEMLOCK
+        let result = CortexBuilder::new(42u64)
+            .key(key)
+            .lock_memory(true)
+            .with_default_lock::<SpinLock>();
+        match result {
+            Ok(cortex) => assert_eq!(cortex.read().unwrap(), 42),
+            Err(err) => assert!(err.to_string().contains("RLIMIT_MEMLOCK")),
+        }
     }
 }
@@ -0,0 +1,331 @@
+//! Process-wide registry of the resources owned by this process, with the `cleanup` feature, so
+//! that they can be removed all at once through `cleanup_all` or when the process is killed by a
+//! signal. Owners register what they created through `owned` and deregister it through
+//! `released`. Without the feature nothing is registered, but the returned `Registration` still
+//! tells a forked child apart from the process that took ownership.
+
+use std::ffi::CString;
+
+/// System resource owned by this process, which would be left behind if its owner never got to
+/// run `Drop`
+#[cfg_attr(not(feature = "cleanup"), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OwnedResource {
+    /// System V segment of a `Cortex` or `RawCortex`, removed with `IPC_RMID`
+    Segment { key: i32, id: i32 },
+    /// POSIX shared memory object holding the state of a lock, removed with `shm_unlink`
+    SharedMemory(CString),
+    /// Named semaphore, removed with `sem_unlink`
+    #[cfg(feature = "semaphore")]
+    Semaphore(CString),
+    /// Lock file or named pipe, removed with `unlink`
+    File(CString),
+    /// Directory of the named pipes of an `Event`, removed along with its contents
+    Directory(CString),
+}
+
+/// Entry in the registry, kept by the owner of a resource to deregister it again. Also records the
+/// process that took ownership, since a child created through `fork` inherits the owner but must
+/// not remove anything. The default value stands for a resource that isn't tracked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Registration {
+    #[cfg_attr(not(feature = "cleanup"), allow(dead_code))]
+    id: u64,
+    pid: libc::pid_t,
+}
+
+impl Registration {
+    /// Whether ownership was taken by the parent of this process before it forked
+    pub(crate) fn inherited(&self) -> bool {
+        self.pid != 0 && self.pid != unsafe { libc::getpid() }
+    }
+}
+
+#[cfg(feature = "cleanup")]
+impl OwnedResource {
+    /// Remove the resource from the system. Anything still attached to it keeps working, but it
+    /// can no longer be attached to.
+    ///
+    /// Also works on resources owned by other processes, e.g. for a supervisor that was handed
+    /// the resources of a worker through `owned_resources`.
+    pub fn remove(&self) -> crate::CortexResult<()> {
+        let (result, call) = match self {
+            OwnedResource::Segment { id, .. } => (
+                unsafe { crate::mem::shmctl(*id, libc::IPC_RMID, std::ptr::null_mut()) },
+                "shmctl",
+            ),
+            OwnedResource::SharedMemory(name) => {
+                (unsafe { libc::shm_unlink(name.as_ptr()) }, "shm_unlink")
+            }
+            #[cfg(feature = "semaphore")]
+            OwnedResource::Semaphore(name) => {
+                (unsafe { libc::sem_unlink(name.as_ptr()) }, "sem_unlink")
+            }
+            OwnedResource::File(path) => (unsafe { libc::unlink(path.as_ptr()) }, "unlink"),
+            OwnedResource::Directory(path) => {
+                use std::os::unix::ffi::OsStrExt;
+                // Takes a syscall per entry, so report the error of whichever one failed
+                std::fs::remove_dir_all(std::ffi::OsStr::from_bytes(path.to_bytes()))?;
+                return Ok(());
+            }
+        };
+        if result == -1 {
+            return Err(crate::CortexError::new_dirty(format!(
+                "Error during {} while cleaning up {:?}",
+                call, self
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cleanup")]
+mod registry {
+    use super::{OwnedResource, Registration};
+    use crate::{mem, CortexError, CortexResult};
+    use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    static OWNED: Mutex<Vec<(Registration, OwnedResource)>> = Mutex::new(Vec::new());
+    static NEXT_REGISTRATION: AtomicU64 = AtomicU64::new(1);
+    /// Write end of the pipe the signal handler forwards signals through, -1 until installed
+    static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+    /// Process the listener thread runs in, which a child created through `fork` doesn't inherit
+    static LISTENER_PID: AtomicI32 = AtomicI32::new(0);
+
+    pub(super) fn owned_resources() -> MutexGuard<'static, Vec<(Registration, OwnedResource)>> {
+        OWNED.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn owned(resource: impl FnOnce() -> OwnedResource) -> Registration {
+        let pid = unsafe { libc::getpid() };
+        let resource = resource();
+        // Heap segments disappear with the process anyway
+        if matches!(resource, OwnedResource::Segment { id, .. } if mem::is_heap(id)) {
+            return Registration { id: 0, pid };
+        }
+        let registration = Registration {
+            id: NEXT_REGISTRATION.fetch_add(1, Ordering::Relaxed),
+            pid,
+        };
+        owned_resources().push((registration, resource));
+        registration
+    }
+
+    pub(crate) fn released(registration: Registration) -> bool {
+        if registration.inherited() {
+            return false;
+        }
+        if registration.id == 0 {
+            return true;
+        }
+        let mut owned = owned_resources();
+        match owned.iter().position(|(owned, _)| *owned == registration) {
+            Some(index) => {
+                owned.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove everything in the registry, leaving it empty. Entries inherited through `fork`
+    /// belong to the parent, and are dropped without removing anything.
+    pub(crate) fn cleanup() -> CortexResult<()> {
+        let mut owned = std::mem::take(&mut *owned_resources());
+        owned.retain(|(registration, _)| !registration.inherited());
+        let mut result = Ok(());
+        for (_, resource) in &owned {
+            if let Err(err) = resource.remove() {
+                crate::trace::error!("{}", err);
+                result = result.and(Err(err));
+            }
+        }
+        crate::trace::info!("Cleaned up {} owned resources", owned.len());
+        result
+    }
+
+    /// Only does what is async-signal-safe, the cleanup itself happens on the listener thread
+    extern "C" fn on_signal(signal: libc::c_int) {
+        if unsafe { libc::getpid() } != LISTENER_PID.load(Ordering::Relaxed) {
+            // A forked child without a listener of its own, which must not wake up the one of its
+            // parent through the inherited pipe. Dies from the signal once the handler returns.
+            unsafe {
+                libc::signal(signal, libc::SIG_DFL);
+                libc::raise(signal);
+            }
+            return;
+        }
+        let errno = errno::errno();
+        let byte = signal as u8;
+        unsafe {
+            libc::write(
+                WRITE_FD.load(Ordering::Relaxed),
+                &byte as *const u8 as *const libc::c_void,
+                1,
+            )
+        };
+        errno::set_errno(errno);
+    }
+
+    /// Wait for the first forwarded signal, clean up and then die from it as if it had never
+    /// been handled
+    fn listen(read_fd: libc::c_int) {
+        let mut byte = 0u8;
+        loop {
+            match unsafe { libc::read(read_fd, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+                1 => break,
+                -1 if errno::errno().0 == libc::EINTR => continue,
+                _ => {
+                    crate::trace::error!("Error reading from the signal pipe");
+                    return;
+                }
+            }
+        }
+        let signal = byte as libc::c_int;
+        crate::trace::info!("Received signal {}, cleaning up owned resources", signal);
+        // Errors were already logged, and there is nothing else left to do about them
+        let _ = cleanup();
+        unsafe {
+            libc::signal(signal, libc::SIG_DFL);
+            libc::kill(libc::getpid(), signal);
+        }
+    }
+
+    /// Create the pipe and start the listener thread, once per process
+    fn start_listener() -> CortexResult<()> {
+        static STARTING: Mutex<()> = Mutex::new(());
+        let _starting = STARTING.lock().unwrap_or_else(PoisonError::into_inner);
+        let pid = unsafe { libc::getpid() };
+        if LISTENER_PID.load(Ordering::Relaxed) == pid {
+            return Ok(());
+        }
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+            return Err(CortexError::new_clean("Error during pipe"));
+        }
+        for fd in fds {
+            unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) };
+        }
+        let [read_fd, write_fd] = fds;
+        if let Err(err) = std::thread::Builder::new()
+            .name("neocortex-cleanup".to_string())
+            .spawn(move || listen(read_fd))
+        {
+            unsafe {
+                libc::close(read_fd);
+                libc::close(write_fd);
+            }
+            return Err(err.into());
+        }
+        WRITE_FD.store(write_fd, Ordering::Relaxed);
+        LISTENER_PID.store(pid, Ordering::Relaxed);
+        Ok(())
+    }
+
+    pub fn cleanup_on_signal(signals: &[libc::c_int]) -> CortexResult<()> {
+        start_listener()?;
+        for &signal in signals {
+            let mut action: libc::sigaction = unsafe { std::mem::zeroed() };
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            unsafe { libc::sigemptyset(&mut action.sa_mask) };
+            if unsafe { libc::sigaction(signal, &action, std::ptr::null_mut()) } == -1 {
+                return Err(CortexError::new_clean(format!(
+                    "Error during sigaction for signal: {}",
+                    signal
+                )));
+            }
+            crate::trace::trace!("Cleaning up owned resources on signal: {}", signal);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cleanup")]
+pub(crate) use registry::{owned, released};
+
+/// Resources owned by this process, in the order they were created. Ownership handed over with
+/// `transfer_ownership` or taken with `adopt` is taken into account.
+///
+/// Segments owned through `DropPolicy::RefCounted`, segments of the in-process backend and the
+/// element segments of the collections (`CortexVec`, `CortexMap` and so on) are not tracked.
+#[cfg(feature = "cleanup")]
+pub fn owned_resources() -> Vec<OwnedResource> {
+    registry::owned_resources()
+        .iter()
+        .filter(|(registration, _)| !registration.inherited())
+        .map(|(_, resource)| resource.clone())
+        .collect()
+}
+
+/// Remove every resource owned by this process, e.g. from an `atexit` or panic hook. Instances
+/// that are still alive keep working on what they have attached, but no longer remove anything
+/// when dropped.
+///
+/// Keeps going after an error, and returns the first one.
+#[cfg(feature = "cleanup")]
+pub fn cleanup_all() -> crate::CortexResult<()> {
+    registry::cleanup()
+}
+
+/// Remove the segments, lock state, semaphores and lock files owned by this process when one of
+/// `signals` arrives, and then let the signal terminate the process as it would have without a
+/// handler. Meant for daemons that are stopped with `SIGTERM`/`SIGINT`, e.g. by systemd, and would
+/// otherwise leak everything they own since `Drop` never runs.
+///
+/// The handlers replace any installed before for the same signals, and the signal can't be used
+/// for anything else afterwards. Signals that can't be caught, like `SIGKILL`, are rejected with
+/// `ErrorKind::InvalidInput`. What gets removed is what `owned_resources` returns.
+///
+/// A child created through `fork` inherits the handlers but not the thread doing the cleanup, so
+/// it is terminated by the signal without removing anything unless it calls `cleanup_on_signal`
+/// itself.
+#[cfg(feature = "cleanup")]
+pub fn cleanup_on_signal(signals: &[libc::c_int]) -> crate::CortexResult<()> {
+    registry::cleanup_on_signal(signals)
+}
+
+#[cfg(not(feature = "cleanup"))]
+pub(crate) fn owned(_resource: impl FnOnce() -> OwnedResource) -> Registration {
+    Registration {
+        id: 0,
+        pid: unsafe { libc::getpid() },
+    }
+}
+
+/// Whether the resource still has to be removed by its owner, which is `false` if it was already
+/// removed by `cleanup_all` or if ownership was inherited through `fork`
+#[cfg(not(feature = "cleanup"))]
+pub(crate) fn released(registration: Registration) -> bool {
+    !registration.inherited()
+}
+
+#[cfg(all(test, feature = "cleanup"))]
+mod tests {
+    use crate::cleanup::OwnedResource;
+    use crate::{Cortex, ErrorKind, SpinLock};
+
+    #[test]
+    fn registry_follows_ownership() {
+        let key = rand::random::<i32>().abs();
+        let mut cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+        let segment = OwnedResource::Segment { key, id: cortex.id };
+        let owned = |resource: &OwnedResource| crate::owned_resources().contains(resource);
+        assert!(owned(&segment));
+
+        cortex.transfer_ownership().unwrap();
+        assert!(!owned(&segment));
+        assert!(cortex.adopt());
+        assert!(owned(&segment));
+        drop(cortex);
+        assert!(!owned(&segment));
+    }
+
+    #[test]
+    fn uncatchable_signal() {
+        let err = crate::cleanup_on_signal(&[libc::SIGKILL]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
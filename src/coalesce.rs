@@ -0,0 +1,125 @@
+//! Rate-limited writes for producers that update faster than anyone reads, see `CoalescedWriter`.
+
+use crate::{Cortex, CortexResult, CortexSync};
+use std::time::{Duration, Instant};
+
+/// Write side of a cortex that commits at most once per `interval`, e.g. a 10kHz producer feeding
+/// a 30Hz consumer without taking the write lock 10000 times a second.
+///
+/// `write_coalesced` keeps the value in the writer, replacing whatever was pending, and only
+/// commits it to shared memory once `interval` has passed since the last commit. Values replaced
+/// before they were committed are never seen by readers. A pending value is only committed by a
+/// later write, `flush`, or dropping the writer, so a producer that goes quiet should call `flush`
+/// to publish its last value.
+///
+/// ```ignore
+/// let mut writer = cortex.coalesced(Duration::from_secs(1) / 30);
+/// for sample in samples {
+///     writer.write_coalesced(sample)?;
+/// }
+/// writer.flush()?;
+/// ```
+#[derive(Debug)]
+pub struct CoalescedWriter<'a, T, L: CortexSync> {
+    cortex: &'a Cortex<T, L>,
+    interval: Duration,
+    pending: Option<T>,
+    last_commit: Option<Instant>,
+}
+
+impl<T, L: CortexSync> Cortex<T, L> {
+    /// Writer committing to this cortex at most once per `interval`, see `CoalescedWriter`
+    pub fn coalesced(&self, interval: Duration) -> CoalescedWriter<'_, T, L> {
+        CoalescedWriter {
+            cortex: self,
+            interval,
+            pending: None,
+            last_commit: None,
+        }
+    }
+}
+
+impl<T, L: CortexSync> CoalescedWriter<'_, T, L> {
+    /// Set `data` as the value to commit, committing it right away if `interval` has passed since
+    /// the last commit. Returns whether it was committed.
+    pub fn write_coalesced(&mut self, data: T) -> CortexResult<bool> {
+        self.pending = Some(data);
+        let due = self
+            .last_commit
+            .is_none_or(|last_commit| last_commit.elapsed() >= self.interval);
+        if !due {
+            return Ok(false);
+        }
+        self.flush()
+    }
+    /// Commit the pending value regardless of `interval`, returning whether there was one. The
+    /// value stays pending if the write fails.
+    pub fn flush(&mut self) -> CortexResult<bool> {
+        let Some(data) = self.pending.take() else {
+            return Ok(false);
+        };
+        // Only moved into the segment once the lock is held, so it stays pending if that fails
+        let mut data = Some(data);
+        let result = self.cortex.with_write(|value| {
+            if let Some(data) = data.take() {
+                *value = data;
+            }
+        });
+        if let Err(err) = result {
+            self.pending = data;
+            return Err(err);
+        }
+        self.last_commit = Some(Instant::now());
+        Ok(true)
+    }
+    /// Whether a value is waiting to be committed
+    pub fn has_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+impl<T, L: CortexSync> Drop for CoalescedWriter<'_, T, L> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            crate::trace::warning!("Failed to flush coalesced write on drop: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, SpinLock};
+    use std::time::Duration;
+
+    #[test]
+    fn coalesced_writes() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u32, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        let attached: Cortex<u32, SpinLock> = Cortex::attach(key).unwrap();
+        let mut writer = cortex.coalesced(Duration::from_millis(50));
+
+        // The first write commits right away, the following ones within the interval don't
+        assert!(writer.write_coalesced(1).unwrap());
+        for value in 2..=100 {
+            assert!(!writer.write_coalesced(value).unwrap());
+        }
+        assert_eq!(attached.read().unwrap(), 1);
+        assert!(writer.has_pending());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(writer.write_coalesced(101).unwrap());
+        assert_eq!(attached.read().unwrap(), 101);
+        assert!(!writer.flush().unwrap());
+
+        assert!(!writer.write_coalesced(102).unwrap());
+        assert!(writer.flush().unwrap());
+        assert_eq!(attached.read().unwrap(), 102);
+
+        writer.write_coalesced(103).unwrap();
+        drop(writer);
+        assert_eq!(attached.read().unwrap(), 103);
+    }
+}
@@ -0,0 +1,354 @@
+//! Conformance checks for implementations of `CortexSync`, so that third-party lock backends can
+//! be validated against the same expectations as the built-in ones. Only available with the
+//! `testing` crate feature. Every check panics with a description of the violation.
+//!
+//! Call `check::<MyLock>(None)` from a test of the backend, use `cortex_sync_tests!` to generate
+//! a separate test per check, or call the individual checks.
+//!
+//! Checks that need several processes fork the test process. Locks with `CortexSync::IN_PROCESS`
+//! set skip them.
+
+use crate::{random_key, Cortex, CortexResult, CortexSync, ErrorKind};
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const THREADS: u64 = 4;
+const ITERATIONS: u64 = 250;
+
+/// Run every check against `L`, passing `settings` to every lock that is created or attached
+pub fn check<L>(settings: Option<&L::Settings>)
+where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    attach_before_create::<L>(settings);
+    mutual_exclusion::<L>(settings);
+    mutual_exclusion_across_processes::<L>(settings);
+    exclusive_write::<L>(settings);
+    shared_read_excludes_writers::<L>(settings);
+    timed_wait::<L>(settings);
+    release_after_panic::<L>(settings);
+}
+
+/// Generate a test per conformance check for a lock backend, in a module named `$name`. Lock
+/// settings can be passed as a third argument, which is evaluated once per test.
+///
+/// ```ignore
+/// neocortex::cortex_sync_tests!(my_lock_conformance, MyLock);
+/// neocortex::cortex_sync_tests!(my_lock_with_settings, MyLock, MySettings::default());
+/// ```
+#[macro_export]
+macro_rules! cortex_sync_tests {
+    ($name:ident, $lock:ty) => {
+        $crate::cortex_sync_tests!(@tests $name, $lock, None);
+    };
+    ($name:ident, $lock:ty, $settings:expr) => {
+        $crate::cortex_sync_tests!(@tests $name, $lock, Some(&$settings));
+    };
+    (@tests $name:ident, $lock:ty, $settings:expr) => {
+        #[cfg(test)]
+        mod $name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $crate::cortex_sync_tests!(@test $lock, $settings,
+                attach_before_create,
+                mutual_exclusion,
+                mutual_exclusion_across_processes,
+                exclusive_write,
+                shared_read_excludes_writers,
+                timed_wait,
+                release_after_panic
+            );
+        }
+    };
+    (@test $lock:ty, $settings:expr, $($check:ident),*) => {
+        $(
+            #[test]
+            fn $check() {
+                $crate::conformance::$check::<$lock>($settings);
+            }
+        )*
+    };
+}
+
+fn create<L: CortexSync>(settings: Option<&L::Settings>) -> Cortex<u64, L> {
+    Cortex::new(Some(random_key()), 0, false, settings).expect("Failed to create the lock")
+}
+
+fn attach<L: CortexSync>(key: i32, settings: Option<&L::Settings>) -> Cortex<u64, L> {
+    match settings {
+        Some(settings) => Cortex::attach_with_lock(key, settings),
+        None => Cortex::attach(key),
+    }
+    .expect("Failed to attach to the lock")
+}
+
+/// `Ok(None)` for non-blocking and timed acquisitions the lock doesn't support
+fn optional(result: CortexResult<bool>) -> Option<bool> {
+    match result {
+        Ok(acquired) => Some(acquired),
+        Err(err) if err.kind() == ErrorKind::Unsupported => None,
+        Err(err) => panic!("Acquiring the lock failed: {}", err),
+    }
+}
+
+/// Writers on several threads, each through its own attached instance, never lose an update
+pub fn mutual_exclusion<L>(settings: Option<&L::Settings>)
+where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    let cortex = create::<L>(settings);
+    let key = cortex.key();
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                let attached = attach::<L>(key, settings);
+                for _ in 0..ITERATIONS {
+                    attached.with_write(|value| *value += 1).unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(
+        cortex.read().unwrap(),
+        THREADS * ITERATIONS,
+        "Lost updates, the write lock doesn't exclude other writers"
+    );
+}
+
+/// While another thread holds `hold`, `attempt` on another instance must fail. Afterwards it must
+/// succeed, unless the lock doesn't support it.
+fn excluded_while_held<L>(
+    settings: Option<&L::Settings>,
+    hold: impl Fn(&L) -> CortexResult<()> + Sync,
+    release: impl Fn(&L) -> CortexResult<()> + Sync,
+    description: &str,
+) where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    let cortex = create::<L>(settings);
+    let key = cortex.key();
+    let attached = attach::<L>(key, settings);
+    let (held_tx, held_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        let (hold, release) = (&hold, &release);
+        // Held on another thread, since reentrant locks let the holding thread in again
+        scope.spawn(move || {
+            let holder = attach::<L>(key, settings);
+            hold(holder.lock_backend()).unwrap();
+            held_tx.send(()).unwrap();
+            done_rx.recv().unwrap();
+            release(holder.lock_backend()).unwrap();
+        });
+        held_rx.recv().unwrap();
+        let lock = attached.lock_backend();
+        let attempts = [
+            optional(lock.try_write_lock()),
+            optional(lock.timed_write_lock(Duration::from_millis(10))),
+        ];
+        done_tx.send(()).unwrap();
+        for acquired in attempts.into_iter().flatten() {
+            assert!(!acquired, "Acquired the write lock while {}", description);
+        }
+    });
+    if let Some(acquired) = optional(attached.lock_backend().try_write_lock()) {
+        assert!(
+            acquired,
+            "The write lock wasn't released after {}",
+            description
+        );
+        attached.lock_backend().release_write().unwrap();
+    }
+}
+
+/// Attaching to a lock that was never created fails with `ErrorKind::NotFound`
+pub fn attach_before_create<L: CortexSync>(settings: Option<&L::Settings>) {
+    match L::attach(random_key(), settings) {
+        Ok(_) => panic!("Attached to a lock that was never created"),
+        Err(err) => assert_eq!(
+            err.kind(),
+            ErrorKind::NotFound,
+            "Attaching to a lock that was never created failed with: {}",
+            err
+        ),
+    }
+}
+
+/// Writers in several forked processes, each attaching on its own, never lose an update
+pub fn mutual_exclusion_across_processes<L>(settings: Option<&L::Settings>)
+where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    if L::IN_PROCESS {
+        return;
+    }
+    let cortex = create::<L>(settings);
+    let children: Vec<_> = (0..THREADS)
+        .map(|_| {
+            fork(|| {
+                let attached = attach::<L>(cortex.key(), settings);
+                for _ in 0..ITERATIONS {
+                    attached.with_write(|value| *value += 1).unwrap();
+                }
+            })
+        })
+        .collect();
+    for child in children {
+        wait(child);
+    }
+    assert_eq!(
+        cortex.read().unwrap(),
+        THREADS * ITERATIONS,
+        "Lost updates, the write lock doesn't exclude writers in other processes"
+    );
+}
+
+/// Run `f` in a forked child process, which exits with 1 if `f` panics
+fn fork(f: impl FnOnce()) -> libc::pid_t {
+    match unsafe { libc::fork() } {
+        -1 => panic!("Failed to fork: {}", std::io::Error::last_os_error()),
+        0 => {
+            let code = match std::panic::catch_unwind(AssertUnwindSafe(f)) {
+                Ok(()) => 0,
+                Err(_) => 1,
+            };
+            // Skip destructors and atexit handlers inherited from the test process
+            unsafe { libc::_exit(code) }
+        }
+        pid => pid,
+    }
+}
+
+fn wait(child: libc::pid_t) {
+    let mut status = 0;
+    if unsafe { libc::waitpid(child, &mut status, 0) } == -1 {
+        panic!(
+            "Failed to wait for child: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    assert!(
+        libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0,
+        "Child process failed with status: {}",
+        status
+    );
+}
+
+/// The write lock can't be taken while another instance holds it
+pub fn exclusive_write<L>(settings: Option<&L::Settings>)
+where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    excluded_while_held::<L>(
+        settings,
+        L::write_lock,
+        L::release_write,
+        "another instance held the write lock",
+    );
+}
+
+/// The write lock can't be taken while another instance holds the read lock
+pub fn shared_read_excludes_writers<L>(settings: Option<&L::Settings>)
+where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    excluded_while_held::<L>(
+        settings,
+        L::read_lock,
+        L::release_read,
+        "another instance held the read lock",
+    );
+}
+
+/// A timed acquisition gives up once the timeout expires, but not before
+pub fn timed_wait<L>(settings: Option<&L::Settings>)
+where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    let timeout = Duration::from_millis(50);
+    let cortex = create::<L>(settings);
+    let key = cortex.key();
+    let attached = attach::<L>(key, settings);
+    let (held_tx, held_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            let holder = attach::<L>(key, settings);
+            holder.lock_backend().write_lock().unwrap();
+            held_tx.send(()).unwrap();
+            done_rx.recv().unwrap();
+            holder.lock_backend().release_write().unwrap();
+        });
+        held_rx.recv().unwrap();
+        let start = Instant::now();
+        let acquired = optional(attached.lock_backend().timed_write_lock(timeout));
+        let waited = start.elapsed();
+        done_tx.send(()).unwrap();
+        if let Some(acquired) = acquired {
+            assert!(!acquired, "Timed wait acquired a lock held elsewhere");
+            assert!(
+                waited >= timeout,
+                "Timed wait gave up after {:?}, before the timeout of {:?}",
+                waited,
+                timeout
+            );
+            assert!(
+                waited < timeout * 20,
+                "Timed wait took {:?} with a timeout of {:?}",
+                waited,
+                timeout
+            );
+        }
+    });
+}
+
+/// A writer panicking under the lock releases it, so that other instances can still write
+pub fn release_after_panic<L>(settings: Option<&L::Settings>)
+where
+    L: CortexSync,
+    L::Settings: Sync,
+{
+    let cortex = create::<L>(settings);
+    let key = cortex.key();
+    let attached = attach::<L>(key, settings);
+    thread::scope(|scope| {
+        let panicked = scope
+            .spawn(|| {
+                let writer = attach::<L>(key, settings);
+                writer.with_write(|_| panic!("Panicking under the write lock"))
+            })
+            .join();
+        assert!(panicked.is_err());
+    });
+    // Overwriting clears the poison left behind by the panic
+    match optional(attached.write_timeout(1, Duration::from_secs(5))) {
+        Some(written) => assert!(written, "The write lock wasn't released after a panic"),
+        None => attached.write(1).unwrap(),
+    }
+    assert_eq!(attached.read().unwrap(), 1);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{FileLock, HybridLock, MemBackend, ReentrantLock, RwLock, SpinLock, TicketLock};
+
+    crate::cortex_sync_tests!(spin_lock, SpinLock);
+    crate::cortex_sync_tests!(hybrid_lock, HybridLock);
+    crate::cortex_sync_tests!(ticket_lock, TicketLock);
+    crate::cortex_sync_tests!(rw_lock, RwLock);
+    crate::cortex_sync_tests!(reentrant_lock, ReentrantLock);
+    crate::cortex_sync_tests!(file_lock, FileLock, crate::FileLockSettings::default());
+    crate::cortex_sync_tests!(mem_backend, MemBackend);
+    #[cfg(feature = "semaphore")]
+    crate::cortex_sync_tests!(semaphore, crate::Semaphore);
+}
@@ -0,0 +1,416 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    no_lock,
+    rwlock::word,
+    spin::Backoff,
+    CortexResult, NoLock, Pod, RawCortex,
+};
+use std::mem::{align_of, size_of, MaybeUninit};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Stored last once the directory and the initial values are written, like `LAYOUT_MAGIC`
+const STRUCT_MAGIC: u32 = 0x4e43_5354;
+/// Longest field name in bytes
+const MAX_NAME: usize = 32;
+/// Fields start on their own cache line, so that their locks don't contend through false sharing
+const FIELD_ALIGN: usize = 64;
+
+/// Start of the segment, followed by `count` entries
+#[repr(C)]
+struct Directory {
+    magic: AtomicU32,
+    count: u32,
+}
+
+/// Position of a field in the segment. The lock word of the field is stored at `offset`, followed
+/// by the value at the next multiple of its alignment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct FieldEntry {
+    /// Name of the field, padded with zeroes
+    name: [u8; MAX_NAME],
+    offset: u64,
+    size: u64,
+    align: u64,
+}
+
+impl FieldEntry {
+    fn name(&self) -> &str {
+        let len = self
+            .name
+            .iter()
+            .position(|byte| *byte == 0)
+            .unwrap_or(MAX_NAME);
+        std::str::from_utf8(&self.name[..len]).unwrap_or_default()
+    }
+    fn data_offset(&self) -> usize {
+        self.offset as usize + (self.align as usize).max(size_of::<AtomicU32>())
+    }
+}
+
+/// A declared field along with its initial value
+struct FieldSpec {
+    name: String,
+    size: usize,
+    align: usize,
+    init: Vec<MaybeUninit<u8>>,
+}
+
+fn round_up(offset: usize, align: usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+/// Declares the fields of a `CortexStruct`, see `CortexStruct::builder`
+pub struct CortexStructBuilder {
+    fields: Vec<FieldSpec>,
+}
+
+impl CortexStructBuilder {
+    /// Add a field named `name` holding a `T`, initialized to `value` when the segment is created.
+    /// The value is ignored when attaching, but the type has to match the one the segment was
+    /// created with.
+    pub fn field<T: Pod + Send + Sync>(mut self, name: &str, value: T) -> Self {
+        let mut init = vec![MaybeUninit::uninit(); size_of::<T>()];
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                &value as *const T as *const MaybeUninit<u8>,
+                init.as_mut_ptr(),
+                size_of::<T>(),
+            )
+        };
+        self.fields.push(FieldSpec {
+            name: name.to_string(),
+            size: size_of::<T>(),
+            align: align_of::<T>(),
+            init,
+        });
+        self
+    }
+    /// Place the fields one after the other, returning their entries and the size of the segment
+    fn layout(&self) -> CortexResult<(Vec<FieldEntry>, usize)> {
+        let mut offset = size_of::<Directory>() + self.fields.len() * size_of::<FieldEntry>();
+        let mut entries: Vec<FieldEntry> = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            let name = field.name.as_bytes();
+            if name.is_empty() || name.len() > MAX_NAME || name.contains(&0) {
+                return Err(CortexError::new_logic(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid field name: {:?}, it must be between 1 and {} bytes without NUL",
+                        field.name, MAX_NAME
+                    ),
+                ));
+            }
+            if entries.iter().any(|entry| entry.name() == field.name) {
+                return Err(CortexError::new_logic(
+                    ErrorKind::InvalidInput,
+                    format!("Field {:?} is declared more than once", field.name),
+                ));
+            }
+            let mut entry = FieldEntry {
+                name: [0; MAX_NAME],
+                offset: round_up(offset, field.align.max(FIELD_ALIGN)) as u64,
+                size: field.size as u64,
+                align: field.align as u64,
+            };
+            entry.name[..name.len()].copy_from_slice(name);
+            offset = entry.data_offset() + field.size;
+            entries.push(entry);
+        }
+        Ok((entries, offset))
+    }
+    /// Create the segment on `key` with every field set to its initial value
+    pub fn create(self, key: i32) -> CortexResult<CortexStruct> {
+        let (fields, size) = self.layout()?;
+        let raw: RawCortex<NoLock> = RawCortex::new(Some(key), size, Some(no_lock::INTERNAL))?;
+        let base = raw.as_ptr();
+        unsafe {
+            let entries = base.add(size_of::<Directory>()) as *mut FieldEntry;
+            std::ptr::copy_nonoverlapping(fields.as_ptr(), entries, fields.len());
+            for (entry, spec) in fields.iter().zip(&self.fields) {
+                std::ptr::copy_nonoverlapping(
+                    spec.init.as_ptr(),
+                    base.add(entry.data_offset()) as *mut MaybeUninit<u8>,
+                    spec.size,
+                );
+            }
+            let directory = base as *mut Directory;
+            std::ptr::addr_of_mut!((*directory).count).write(fields.len() as u32);
+            // Pairs with the acquire load in `attach`, making the fields visible
+            (*directory).magic.store(STRUCT_MAGIC, Ordering::Release);
+        }
+        crate::trace::trace!(
+            "Created struct with {} fields on key: {}",
+            fields.len(),
+            key
+        );
+        Ok(CortexStruct { raw, fields })
+    }
+    /// Attach to the segment on `key`, which must have been created with the same fields, in the
+    /// same order and of the same types
+    pub fn attach(self, key: i32) -> CortexResult<CortexStruct> {
+        let (fields, size) = self.layout()?;
+        let raw: RawCortex<NoLock> = RawCortex::attach(key, Some(no_lock::INTERNAL))?;
+        if raw.size() < size_of::<Directory>() {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!("Segment with key: {} is too small to hold a struct", key),
+            ));
+        }
+        let directory = unsafe { &*(raw.as_ptr() as *const Directory) };
+        match directory.magic.load(Ordering::Acquire) {
+            STRUCT_MAGIC => {}
+            0 => {
+                return Err(CortexError::new_logic(
+                    ErrorKind::NotFound,
+                    format!("Struct with key: {} is not initialized yet", key),
+                ))
+            }
+            magic => {
+                return Err(CortexError::new_logic(
+                    ErrorKind::TypeMismatch,
+                    format!(
+                        "Segment with key: {} has magic {:#x}, expected a struct",
+                        key, magic
+                    ),
+                ))
+            }
+        }
+        // Only read the entries once it is clear that there are as many as expected
+        let matches = raw.size() >= size
+            && directory.count as usize == fields.len()
+            && unsafe {
+                let entries = raw.as_ptr().add(size_of::<Directory>()) as *const FieldEntry;
+                std::slice::from_raw_parts(entries, fields.len()) == fields.as_slice()
+            };
+        if !matches {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Field mismatch for key: {}, the struct wasn't created with the fields: {:?}",
+                    key,
+                    fields.iter().map(FieldEntry::name).collect::<Vec<_>>()
+                ),
+            ));
+        }
+        Ok(CortexStruct { raw, fields })
+    }
+}
+
+/// Lock of a single field, released when dropped
+struct FieldLock<'a> {
+    state: &'a AtomicU32,
+    write: bool,
+}
+
+impl<'a> FieldLock<'a> {
+    fn acquire(state: &'a AtomicU32, write: bool) -> Self {
+        let try_acquire = if write {
+            word::try_write
+        } else {
+            word::try_read
+        };
+        let mut backoff = Backoff::new();
+        while !try_acquire(state) {
+            backoff.snooze();
+        }
+        Self { state, write }
+    }
+}
+
+impl Drop for FieldLock<'_> {
+    fn drop(&mut self) {
+        match self.write {
+            true => word::release_write(self.state),
+            false => word::release_read(self.state),
+        }
+    }
+}
+
+/// Several named values packed into a single segment, each protected by its own lock.
+///
+/// Useful when a process shares many independently updated values, where giving each one its
+/// own `Cortex` would take a segment and a lock object apiece and run into system limits. The
+/// fields are declared through `CortexStruct::builder`, and every process has to declare the
+/// same fields in the same order to attach. Values are looked up by name, and accessing a field
+/// with a type of a different size or alignment than it was declared with fails with
+/// `ErrorKind::TypeMismatch`. Since a field can be read as any type of the same size and
+/// alignment, values have to be `Pod`, for which every bit pattern is valid.
+///
+/// Each field has a reader-writer spin lock on its own cache line, so writers of different fields
+/// never contend. A process that dies while holding the lock of a field leaves that field locked
+/// forever.
+#[derive(Debug)]
+pub struct CortexStruct {
+    raw: RawCortex<NoLock>,
+    fields: Vec<FieldEntry>,
+}
+
+impl CortexStruct {
+    /// Start declaring the fields of a struct, then `create` or `attach` it
+    pub fn builder() -> CortexStructBuilder {
+        CortexStructBuilder { fields: Vec::new() }
+    }
+    /// The lock and value of the field `name`, checking that it holds a `T`
+    fn field<T: Pod>(&self, name: &str) -> CortexResult<(&AtomicU32, *mut T)> {
+        let Some(entry) = self.fields.iter().find(|entry| entry.name() == name) else {
+            return Err(CortexError::new_logic(
+                ErrorKind::NotFound,
+                format!(
+                    "No field named {:?} in struct with key: {}",
+                    name,
+                    self.key()
+                ),
+            ));
+        };
+        if entry.size != size_of::<T>() as u64 || entry.align != align_of::<T>() as u64 {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                "Type mismatch for field {:?}, it holds {} bytes aligned to {} but a type of {} \
+                 bytes aligned to {} was requested",
+                name,
+                entry.size,
+                entry.align,
+                size_of::<T>(),
+                align_of::<T>()
+            ),
+            ));
+        }
+        let base = self.raw.as_ptr();
+        unsafe {
+            let state = &*(base.add(entry.offset as usize) as *const AtomicU32);
+            Ok((state, base.add(entry.data_offset()) as *mut T))
+        }
+    }
+    /// Read the field `name`
+    pub fn read<T: Pod + Send + Sync>(&self, name: &str) -> CortexResult<T> {
+        self.with_read(name, |value: &T| *value)
+    }
+    /// Overwrite the field `name`
+    pub fn write<T: Pod + Send + Sync>(&self, name: &str, value: T) -> CortexResult<()> {
+        self.with_write(name, |current: &mut T| *current = value)
+    }
+    /// Access the field `name` in place while holding its read lock
+    pub fn with_read<T: Pod + Send + Sync, R>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&T) -> R,
+    ) -> CortexResult<R> {
+        let (state, ptr) = self.field::<T>(name)?;
+        let _lock = FieldLock::acquire(state, false);
+        Ok(f(unsafe { &*ptr }))
+    }
+    /// Modify the field `name` in place while holding its write lock
+    pub fn with_write<T: Pod + Send + Sync, R>(
+        &self,
+        name: &str,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> CortexResult<R> {
+        let (state, ptr) = self.field::<T>(name)?;
+        let _lock = FieldLock::acquire(state, true);
+        Ok(f(unsafe { &mut *ptr }))
+    }
+    /// Names of the fields, in the order they were declared
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(FieldEntry::name)
+    }
+    pub fn key(&self) -> i32 {
+        self.raw.key()
+    }
+    /// Whether this instance created the segment and removes it when dropped
+    pub fn is_owner(&self) -> bool {
+        self.raw.is_owner()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cortex_struct::{CortexStruct, CortexStructBuilder};
+    use crate::ErrorKind;
+    use std::thread;
+
+    fn fields() -> CortexStructBuilder {
+        CortexStruct::builder()
+            .field("flag", 1u8)
+            .field("counter", 0u64)
+            .field("samples", [0.5f32; 16])
+    }
+
+    #[test]
+    fn independent_fields() {
+        let key = rand::random::<i32>().abs();
+        let cortex = fields().create(key).unwrap();
+        let attached = fields().attach(key).unwrap();
+        assert!(cortex.is_owner() && !attached.is_owner());
+        assert_eq!(
+            attached.names().collect::<Vec<_>>(),
+            ["flag", "counter", "samples"]
+        );
+        assert_eq!(attached.read::<u8>("flag").unwrap(), 1);
+        assert_eq!(attached.read::<[f32; 16]>("samples").unwrap(), [0.5; 16]);
+
+        // Holding the lock of one field doesn't block the others
+        cortex
+            .with_write("counter", |counter: &mut u64| {
+                attached.write("flag", 2u8).unwrap();
+                *counter = 5;
+            })
+            .unwrap();
+        assert_eq!(attached.read::<u8>("flag").unwrap(), 2);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached = fields().attach(key).unwrap();
+                    for _ in 0..1000 {
+                        attached
+                            .with_write("counter", |counter: &mut u64| *counter += 1)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(cortex.read::<u64>("counter").unwrap(), 4005);
+    }
+
+    #[test]
+    fn mismatches() {
+        let key = rand::random::<i32>().abs();
+        let cortex = fields().create(key).unwrap();
+        let kind = |err: crate::CortexError| err.kind();
+
+        assert_eq!(
+            kind(cortex.read::<u32>("counter").unwrap_err()),
+            ErrorKind::TypeMismatch
+        );
+        assert_eq!(
+            kind(cortex.read::<u64>("missing").unwrap_err()),
+            ErrorKind::NotFound
+        );
+        let reordered = CortexStruct::builder()
+            .field("counter", 0u64)
+            .field("flag", 1u8)
+            .field("samples", [0.5f32; 16]);
+        assert_eq!(
+            kind(reordered.attach(key).unwrap_err()),
+            ErrorKind::TypeMismatch
+        );
+        let fewer = CortexStruct::builder().field("flag", 1u8);
+        assert_eq!(
+            kind(fewer.attach(key).unwrap_err()),
+            ErrorKind::TypeMismatch
+        );
+
+        let duplicate = fields().field("flag", 0u8);
+        assert_eq!(
+            kind(duplicate.create(key).unwrap_err()),
+            ErrorKind::InvalidInput
+        );
+        let long = CortexStruct::builder().field(&"x".repeat(33), 0u8);
+        assert_eq!(kind(long.create(key).unwrap_err()), ErrorKind::InvalidInput);
+    }
+}
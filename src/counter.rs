@@ -0,0 +1,133 @@
+use crate::{no_lock, process_alive, Cortex, CortexResult, DropPolicy, NoLock};
+use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
+
+/// Number of shards, processes beyond this share shards with each other
+const SHARDS: usize = 64;
+
+/// Padded to a cache line, so that increments in different processes never touch the same line
+#[repr(C, align(64))]
+struct Shard {
+    /// Pid of the process the shard belongs to, zero if unclaimed
+    owner_pid: AtomicI32,
+    value: AtomicU64,
+}
+
+struct CounterState {
+    shards: [Shard; SHARDS],
+}
+
+/// Process-shared counter for high-frequency metrics.
+///
+/// Every attached process is given its own shard to increment, so increments never contend
+/// across processes and only `sum` has to visit every shard. Shards of processes that have died
+/// are taken over by new ones, keeping their counts. Once more than 64 processes are attached at
+/// the same time, the remaining ones share shards, which is still correct but slower.
+pub struct ShardedCounter {
+    cortex: Cortex<CounterState, NoLock>,
+    shard: usize,
+}
+
+impl ShardedCounter {
+    /// Attach to the counter on `key`, creating it if it doesn't exist yet, and claim a shard for
+    /// the current process
+    pub fn new(key: i32) -> CortexResult<Self> {
+        let cortex =
+            Cortex::attach_or_create_with(key, DropPolicy::Owner, Some(no_lock::INTERNAL), || {
+                CounterState {
+                    shards: std::array::from_fn(|_| Shard {
+                        owner_pid: AtomicI32::new(0),
+                        value: AtomicU64::new(0),
+                    }),
+                }
+            })?;
+        let shard = Self::claim(unsafe { &*cortex.ptr });
+        Ok(Self { cortex, shard })
+    }
+    /// Find the shard of the current process, claiming a free one or one of a dead process if it
+    /// has none yet
+    fn claim(state: &CounterState) -> usize {
+        let pid = unsafe { libc::getpid() };
+        if let Some(shard) = state
+            .shards
+            .iter()
+            .position(|shard| shard.owner_pid.load(Ordering::Relaxed) == pid)
+        {
+            return shard;
+        }
+        for (index, shard) in state.shards.iter().enumerate() {
+            let owner = shard.owner_pid.load(Ordering::Relaxed);
+            if (owner == 0 || !process_alive(owner))
+                && shard
+                    .owner_pid
+                    .compare_exchange(owner, pid, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return index;
+            }
+        }
+        crate::trace::warning!("All counter shards are taken, sharing one with another process");
+        pid as usize % SHARDS
+    }
+    fn state(&self) -> &CounterState {
+        unsafe { &*self.cortex.ptr }
+    }
+    pub fn add(&self, value: u64) {
+        self.state().shards[self.shard]
+            .value
+            .fetch_add(value, Ordering::Relaxed);
+    }
+    pub fn increment(&self) {
+        self.add(1)
+    }
+    /// Total of all shards. Increments made concurrently may or may not be included.
+    pub fn sum(&self) -> u64 {
+        self.state()
+            .shards
+            .iter()
+            .map(|shard| shard.value.load(Ordering::Relaxed))
+            .fold(0, u64::wrapping_add)
+    }
+    /// Reset all shards to zero, returning the total they held. Increments made concurrently are
+    /// either included in the total or kept, never lost.
+    pub fn reset(&self) -> u64 {
+        self.state()
+            .shards
+            .iter()
+            .map(|shard| shard.value.swap(0, Ordering::Relaxed))
+            .fold(0, u64::wrapping_add)
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::counter::ShardedCounter;
+    use std::thread;
+
+    #[test]
+    fn sums_all_shards() {
+        let key = rand::random::<i32>().abs();
+        let counter = ShardedCounter::new(key).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached = ShardedCounter::new(key).unwrap();
+                    for _ in 0..1000 {
+                        attached.increment();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        counter.add(5);
+
+        assert_eq!(counter.sum(), 8005);
+        assert_eq!(counter.reset(), 8005);
+        assert_eq!(counter.sum(), 0);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{error::Error, fmt::Display};
+use std::{error::Error, ffi::NulError, fmt::Display};
 
 #[derive(Debug)]
 pub enum CortexError {
@@ -7,42 +7,315 @@ pub enum CortexError {
     /// Unexpected system error occured, and memory cleanup may not have executed properly.
     /// Upon receiving this error, manual intervention might be necessary.
     DirtySystem(InnerError),
+    /// A writer died or panicked halfway through a write, so the data might be partially written.
+    /// Use `Cortex::clear_poison` to accept the data as is, or overwrite it with `Cortex::write`.
+    Poisoned,
+    /// The data doesn't match the checksum stored by the last write, meaning that something
+    /// modified the segment without going through the lock, see `CortexBuilder::checksum`.
+    /// Overwrite the data with `Cortex::write` to recover. Also returned when an encrypted value
+    /// fails authentication, due to tampering or a wrong key.
+    Corrupted,
+}
+
+/// General category of a `CortexError`, mostly derived from the OS error code
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Something already exists on the key, e.g. when creating a segment without attaching
+    AlreadyExists,
+    /// There is no segment or lock on the key
+    NotFound,
+    PermissionDenied,
+    /// A system limit on the number or total size of segments was reached
+    NoSpace,
+    OutOfMemory,
+    /// The operation would have blocked
+    WouldBlock,
+    TimedOut,
+    Interrupted,
+    /// The process holding a lock died while holding it
+    OwnerDied,
+    /// The existing segment holds another type than the requested one, or has an incompatible
+    /// layout version
+    TypeMismatch,
+    InvalidInput,
+    /// The segment would exceed the limit set through `set_max_segment_size` or
+    /// `CortexBuilder::max_size`
+    TooLarge,
+    /// The operation is not supported by the lock or the platform
+    Unsupported,
+    /// See `CortexError::Poisoned`
+    Poisoned,
+    /// See `CortexError::Corrupted`
+    Corrupted,
+    Other,
+}
+
+impl ErrorKind {
+    fn from_errno(errno: i32) -> Self {
+        match errno {
+            libc::EEXIST => ErrorKind::AlreadyExists,
+            libc::ENOENT | libc::EIDRM => ErrorKind::NotFound,
+            libc::EACCES | libc::EPERM => ErrorKind::PermissionDenied,
+            libc::ENOSPC | libc::EMFILE | libc::ENFILE => ErrorKind::NoSpace,
+            libc::ENOMEM => ErrorKind::OutOfMemory,
+            libc::EAGAIN => ErrorKind::WouldBlock,
+            libc::ETIMEDOUT => ErrorKind::TimedOut,
+            libc::EINTR => ErrorKind::Interrupted,
+            libc::EOWNERDEAD => ErrorKind::OwnerDied,
+            libc::EINVAL | libc::ENAMETOOLONG => ErrorKind::InvalidInput,
+            libc::ENOSYS | libc::ENOTSUP => ErrorKind::Unsupported,
+            _ => ErrorKind::Other,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct InnerError {
-    os_error: String,
+    /// Only set for errors caused by a failing system call
+    os_error: Option<std::io::Error>,
+    kind: ErrorKind,
     message: String,
+    segment: Option<SegmentInfo>,
+}
+
+/// `IPC_STAT` info of the segment that was in the way, attached to errors about an existing
+/// segment, see `CortexError::segment_info`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SegmentInfo {
+    /// System V id of the segment, as listed by `ipcs -m`
+    pub id: i32,
+    /// Size of the segment in bytes
+    pub size: usize,
+    /// User owning the segment
+    pub owner_uid: u32,
+    /// Process that created the segment, which may no longer be alive
+    pub creator_pid: i32,
+    /// Number of processes currently attached to the segment
+    pub attached: usize,
+}
+
+impl SegmentInfo {
+    pub(crate) fn from_stat(id: i32, info: &libc::shmid_ds) -> Self {
+        Self {
+            id,
+            size: info.shm_segsz,
+            owner_uid: info.shm_perm.uid,
+            creator_pid: info.shm_cpid,
+            attached: info.shm_nattch as usize,
+        }
+    }
+}
+
+impl Display for SegmentInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "segment {} of {} bytes owned by uid {}, created by pid {}, {} attached",
+            self.id, self.size, self.owner_uid, self.creator_pid, self.attached
+        )
+    }
 }
 
 impl Display for CortexError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            CortexError::CleanSystem(err) => {
-                write!(f, "{}. OS Error: {}", err.message, err.os_error)
+            CortexError::CleanSystem(err) | CortexError::DirtySystem(err) => {
+                write!(f, "{}", err.message)?;
+                if let Some(os_error) = &err.os_error {
+                    write!(f, ". OS Error: {}", os_error)?;
+                }
+                if let Some(segment) = &err.segment {
+                    write!(f, ". Existing {}", segment)?;
+                }
+                Ok(())
             }
-            CortexError::DirtySystem(err) => {
-                write!(f, "{}. OS Error: {}", err.message, err.os_error)
+            CortexError::Poisoned => {
+                write!(f, "Shared memory is poisoned by an interrupted write")
+            }
+            CortexError::Corrupted => {
+                write!(
+                    f,
+                    "Shared memory doesn't match the checksum of the last write"
+                )
             }
         }
     }
 }
 
 impl CortexError {
+    /// Error for a system call that just failed, capturing `errno`
     fn new_inner_error(message: impl ToString) -> InnerError {
+        let os_error = std::io::Error::last_os_error();
         InnerError {
-            os_error: std::io::Error::last_os_error().to_string(),
+            kind: ErrorKind::from_errno(os_error.raw_os_error().unwrap_or(0)),
+            os_error: Some(os_error),
             message: message.to_string(),
+            segment: None,
         }
     }
+    /// A system call failed, but all resources were cleaned up. Only call right after the failing
+    /// call, since `errno` is captured as the cause.
     pub(super) fn new_clean(message: impl ToString) -> Self {
         let inner = Self::new_inner_error(message);
         Self::CleanSystem(inner)
     }
+    /// A system call failed and resources may have been left behind, see `new_clean`
     pub(super) fn new_dirty(message: impl ToString) -> Self {
         let inner = Self::new_inner_error(message);
         Self::DirtySystem(inner)
     }
+    /// Error detected by the crate itself, without an OS error behind it
+    pub(super) fn new_logic(kind: ErrorKind, message: impl ToString) -> Self {
+        Self::CleanSystem(InnerError {
+            os_error: None,
+            kind,
+            message: message.to_string(),
+            segment: None,
+        })
+    }
+    /// Attach the info of the existing segment the error is about
+    pub(super) fn with_segment(mut self, segment: SegmentInfo) -> Self {
+        if let CortexError::CleanSystem(inner) | CortexError::DirtySystem(inner) = &mut self {
+            inner.segment = Some(segment);
+        }
+        self
+    }
+    /// General category of the error, for matching without parsing the message
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            CortexError::CleanSystem(inner) | CortexError::DirtySystem(inner) => inner.kind,
+            CortexError::Poisoned => ErrorKind::Poisoned,
+            CortexError::Corrupted => ErrorKind::Corrupted,
+        }
+    }
+    /// The OS error code (`errno`) at the time the error occurred, if there was one
+    pub fn raw_os_error(&self) -> Option<i32> {
+        match self {
+            CortexError::CleanSystem(inner) | CortexError::DirtySystem(inner) => inner
+                .os_error
+                .as_ref()
+                .and_then(std::io::Error::raw_os_error)
+                .filter(|errno| *errno != 0),
+            CortexError::Poisoned | CortexError::Corrupted => None,
+        }
+    }
+    /// Info of the existing segment that caused the error, e.g. when creating a segment on a key
+    /// that is taken or attaching to a segment of the wrong size
+    pub fn segment_info(&self) -> Option<&SegmentInfo> {
+        match self {
+            CortexError::CleanSystem(inner) | CortexError::DirtySystem(inner) => {
+                inner.segment.as_ref()
+            }
+            CortexError::Poisoned | CortexError::Corrupted => None,
+        }
+    }
+}
+
+impl Error for CortexError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CortexError::CleanSystem(inner) | CortexError::DirtySystem(inner) => inner
+                .os_error
+                .as_ref()
+                .map(|err| err as &(dyn Error + 'static)),
+            CortexError::Poisoned | CortexError::Corrupted => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CortexError {
+    fn from(err: std::io::Error) -> Self {
+        let kind = match err.raw_os_error() {
+            Some(errno) => ErrorKind::from_errno(errno),
+            None => ErrorKind::Other,
+        };
+        Self::CleanSystem(InnerError {
+            message: "I/O error".to_string(),
+            os_error: Some(err),
+            kind,
+            segment: None,
+        })
+    }
+}
+
+impl From<NulError> for CortexError {
+    fn from(err: NulError) -> Self {
+        Self::CleanSystem(InnerError {
+            message: "CString NulError".to_string(),
+            os_error: Some(std::io::Error::new(std::io::ErrorKind::InvalidInput, err)),
+            kind: ErrorKind::InvalidInput,
+            segment: None,
+        })
+    }
 }
 
-impl Error for CortexError {}
+#[cfg(test)]
+mod tests {
+    use crate::crash::ErrorKind;
+    use crate::{Cortex, CortexError, NoLock};
+    use std::error::Error;
+    use std::ffi::CString;
+
+    #[test]
+    fn kind_from_errno() {
+        let key = rand::random::<i32>().abs();
+        let err = unsafe { Cortex::<u64, NoLock>::attach_unsynchronized(key) }.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+
+        let _cortex: Cortex<u64, NoLock> =
+            unsafe { Cortex::new_unsynchronized(Some(key), 0) }.unwrap();
+        let err = unsafe { Cortex::<u64, NoLock>::new_unsynchronized(Some(key), 0) }.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn existing_segment_info() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, NoLock> =
+            unsafe { Cortex::new_unsynchronized(Some(key), 0) }.unwrap();
+
+        let err = unsafe { Cortex::<[u8; 128], NoLock>::new_unsynchronized(Some(key), [0; 128]) }
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        let segment = err.segment_info().unwrap();
+        assert_eq!(segment.id, cortex.id());
+        assert_eq!(segment.size, crate::header::segment_size::<u64>());
+        assert_eq!(segment.creator_pid, std::process::id() as i32);
+        assert_eq!(segment.attached, 1);
+        assert!(err.to_string().contains("Size mismatch"));
+
+        let err = unsafe { Cortex::<[u8; 128], NoLock>::attach_unsynchronized(key) }.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert_eq!(err.segment_info().unwrap().attached, 1);
+    }
+
+    #[test]
+    fn conversions() {
+        let err: CortexError = CString::new("cortex\0").unwrap_err().into();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(err.raw_os_error(), None);
+        assert!(err.source().is_some());
+
+        let err: CortexError = std::io::Error::from_raw_os_error(libc::EACCES).into();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        assert_eq!(err.raw_os_error(), Some(libc::EACCES));
+    }
+
+    #[test]
+    fn logic_errors_without_errno() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<[u8; 16], NoLock> =
+            unsafe { Cortex::new_unsynchronized(Some(key), [0; 16]) }.unwrap();
+
+        // A stale errno from an unrelated call isn't picked up
+        errno::set_errno(errno::Errno(libc::EACCES));
+        let err = cortex.read_bytes(8, 16).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(err.raw_os_error(), None);
+        assert!(err.source().is_none());
+        assert!(!err.to_string().contains("OS Error"));
+    }
+}
@@ -0,0 +1,154 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Identifies who holds the lock of a `Cortex`, see `Cortex::lock_holder`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockHolderInfo {
+    pub pid: i32,
+    /// Kernel thread id on Linux, a process-local counter on other platforms
+    pub thread_id: u32,
+    pub acquired_at: SystemTime,
+}
+
+/// Lock contention stats of a single `Cortex`, see `Cortex::lock_stats`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockStats {
+    /// Number of successful lock acquisitions
+    pub acquisitions: u64,
+    /// Number of acquisitions where the lock was held elsewhere at the first attempt
+    pub contended: u64,
+    /// Total time spent waiting for the lock across all acquisitions
+    pub total_wait: Duration,
+    /// Longest time the write lock was held
+    pub max_hold: Duration,
+}
+
+/// Collects `LockStats` with atomics, so it can be updated through a shared reference
+#[derive(Debug)]
+pub(crate) struct LockStatsCollector {
+    epoch: Instant,
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_nanos: AtomicU64,
+    max_hold_nanos: AtomicU64,
+    /// When the write lock was acquired in nanoseconds since `epoch`, plus one so that zero can
+    /// mean that it is not held
+    write_acquired: AtomicU64,
+}
+
+impl LockStatsCollector {
+    pub(crate) fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+            acquisitions: AtomicU64::new(0),
+            contended: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+            max_hold_nanos: AtomicU64::new(0),
+            write_acquired: AtomicU64::new(0),
+        }
+    }
+    fn now(&self) -> u64 {
+        self.epoch.elapsed().as_nanos() as u64
+    }
+    pub(crate) fn record_acquisition(&self, waited: Duration, contended: bool) {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if contended {
+            self.contended.fetch_add(1, Ordering::Relaxed);
+        }
+        self.wait_nanos
+            .fetch_add(waited.as_nanos() as u64, Ordering::Relaxed);
+    }
+    pub(crate) fn record_write_acquired(&self) {
+        self.write_acquired.store(self.now() + 1, Ordering::Relaxed);
+    }
+    pub(crate) fn record_write_released(&self) {
+        let acquired = self.write_acquired.swap(0, Ordering::Relaxed);
+        if acquired == 0 {
+            return;
+        }
+        let held = self.now().saturating_sub(acquired - 1);
+        self.max_hold_nanos.fetch_max(held, Ordering::Relaxed);
+    }
+    pub(crate) fn snapshot(&self) -> LockStats {
+        LockStats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            total_wait: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+            max_hold: Duration::from_nanos(self.max_hold_nanos.load(Ordering::Relaxed)),
+        }
+    }
+    pub(crate) fn reset(&self) {
+        self.acquisitions.store(0, Ordering::Relaxed);
+        self.contended.store(0, Ordering::Relaxed);
+        self.wait_nanos.store(0, Ordering::Relaxed);
+        self.max_hold_nanos.store(0, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{thread_id, Cortex, CortexSync, SpinLock};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lock_holder() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        assert_eq!(cortex.lock_holder(), None);
+
+        let holder = cortex
+            .with_write(|_| cortex.lock_holder())
+            .unwrap()
+            .unwrap();
+        assert_eq!(holder.pid, unsafe { libc::getpid() });
+        assert_eq!(holder.thread_id, thread_id());
+        assert_eq!(cortex.lock_holder(), None);
+    }
+
+    #[test]
+    fn lock_warning() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        let mut attached: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+        attached.set_lock_warning(Some(Duration::from_millis(5)));
+
+        cortex.lock.write_lock().unwrap();
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| attached.write(1));
+            thread::sleep(Duration::from_millis(30));
+            cortex.lock.release().unwrap();
+            // Keeps waiting after warning instead of giving up
+            waiter.join().unwrap().unwrap();
+        });
+        assert_eq!(cortex.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn lock_stats() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        let mut attached: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+        assert_eq!(attached.lock_stats(), None);
+        attached.set_lock_stats(true);
+
+        attached.write(1).unwrap();
+        assert_eq!(attached.read().unwrap(), 1);
+        cortex.lock.write_lock().unwrap();
+        assert_eq!(attached.try_read().unwrap(), None);
+        thread::scope(|scope| {
+            let waiter = scope.spawn(|| attached.read());
+            thread::sleep(Duration::from_millis(20));
+            cortex.lock.release().unwrap();
+            waiter.join().unwrap().unwrap();
+        });
+
+        let stats = attached.lock_stats().unwrap();
+        assert_eq!(stats.acquisitions, 3);
+        assert_eq!(stats.contended, 1);
+        assert!(stats.total_wait >= Duration::from_millis(20));
+
+        attached.reset_lock_stats();
+        assert_eq!(attached.lock_stats().unwrap().acquisitions, 0);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    header::{segment_size, Header},
+    unix_millis, Cortex, CortexResult, CortexSync,
+};
+use std::io::Write;
+use std::mem::{size_of, MaybeUninit};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+
+/// Write the bytes of a segment to `path`, replacing it atomically so that a crash never leaves
+/// a partial dump behind
+pub(crate) fn write_dump(path: &Path, segment: &[u8]) -> CortexResult<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(segment)?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Read a dump written by `write_dump`, checking that its header matches a segment holding a `T`.
+/// Returns the header and all bytes of the dump.
+pub(crate) fn read_dump<T>(path: &Path, key: i32) -> CortexResult<(Header, Vec<u8>)> {
+    let bytes = std::fs::read(path)?;
+    if bytes.len() != segment_size::<T>() {
+        return Err(CortexError::new_logic(
+            ErrorKind::TypeMismatch,
+            format!(
+                "Dump {} is {} bytes, but a segment holding the type is {} bytes",
+                path.display(),
+                bytes.len(),
+                segment_size::<T>()
+            ),
+        ));
+    }
+    // Every field of the header is a plain integer, so any bytes make a valid one
+    let mut header = MaybeUninit::<Header>::uninit();
+    let header = unsafe {
+        std::ptr::copy_nonoverlapping(
+            bytes.as_ptr(),
+            header.as_mut_ptr() as *mut u8,
+            size_of::<Header>(),
+        );
+        header.assume_init()
+    };
+    header.validate::<T>(key)?;
+    Ok((header, bytes))
+}
+
+/// Writes a dump of a segment if the thread panics while it is alive, see
+/// `Cortex::crash_dump_on_panic`
+pub struct CrashDump<'a, T, L: CortexSync> {
+    cortex: &'a Cortex<T, L>,
+    dir: PathBuf,
+}
+
+impl<'a, T, L: CortexSync> CrashDump<'a, T, L> {
+    pub(crate) fn new(cortex: &'a Cortex<T, L>, dir: PathBuf) -> Self {
+        Self { cortex, dir }
+    }
+    /// Write the segment and a description of its lock state into `dir`, returning the path of
+    /// the segment dump
+    fn write(&self) -> CortexResult<PathBuf> {
+        std::fs::create_dir_all(&self.dir)?;
+        let name = format!(
+            "cortex_{}_{}_{}",
+            self.cortex.key(),
+            unix_millis(),
+            std::process::id()
+        );
+        let path = self.dir.join(format!("{}.bin", name));
+
+        // Without taking the lock, since the panicking thread might hold it
+        let header = unsafe { &*self.cortex.header };
+        let segment = unsafe {
+            std::slice::from_raw_parts(self.cortex.header as *const u8, segment_size::<T>())
+        };
+        write_dump(&path, segment)?;
+
+        let generation = header.generation.load(Ordering::Acquire);
+        let holder = match self.cortex.lock_holder() {
+            Some(holder) => format!(
+                "pid {} thread {} since {:?}",
+                holder.pid, holder.thread_id, holder.acquired_at
+            ),
+            None => "none".to_string(),
+        };
+        let state = format!(
+            "key: {}\nid: {}\npid: {}\nowner pid: {}\ngeneration: {}\nwrite in progress: {}\n\
+             lock holder: {}\n",
+            self.cortex.key(),
+            self.cortex.id(),
+            std::process::id(),
+            header.owner_pid.load(Ordering::Relaxed),
+            generation,
+            generation % 2 == 1,
+            holder
+        );
+        std::fs::write(self.dir.join(format!("{}.txt", name)), state)?;
+        Ok(path)
+    }
+}
+
+impl<T, L: CortexSync> Drop for CrashDump<'_, T, L> {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            return;
+        }
+        match self.write() {
+            Ok(path) => {
+                crate::trace::error!("Panicked, dumped shared memory to {}", path.display())
+            }
+            Err(err) => crate::trace::error!("Error writing crash dump in Drop: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, CortexBuilder, CortexError, ErrorKind, SpinLock};
+
+    #[test]
+    fn fork_snapshot() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new([1u64; 4])
+            .key(key)
+            .checksum()
+            .schema_version(3)
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        let snapshot = cortex.fork_snapshot().unwrap();
+        assert_ne!(snapshot.key(), key);
+        assert!(snapshot.is_owner());
+        assert_eq!(snapshot.schema_version(), 3);
+
+        // The copy is independent of the original
+        cortex.write([2; 4]).unwrap();
+        let attached = CortexBuilder::new([0u64; 4])
+            .key(snapshot.key())
+            .checksum()
+            .schema_version(3)
+            .default_lock::<SpinLock>()
+            .attach()
+            .unwrap();
+        assert_eq!(attached.read().unwrap(), [1; 4]);
+        snapshot.write([3; 4]).unwrap();
+        assert_eq!(cortex.read().unwrap(), [2; 4]);
+
+        // Removed along with its owner
+        let snapshot_key = snapshot.key();
+        drop(attached);
+        drop(snapshot);
+        assert!(Cortex::<[u64; 4], SpinLock>::attach(snapshot_key).is_err());
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let key = rand::random::<i32>().abs();
+        let path = std::env::temp_dir().join(format!("cortex_dump_{}", key));
+        let cortex: Cortex<[u64; 4], SpinLock> =
+            Cortex::new(Some(key), [1, 2, 3, 4], false, None).unwrap();
+        cortex.snapshot_to(&path).unwrap();
+
+        cortex.write([0; 4]).unwrap();
+        cortex.restore_from(&path).unwrap();
+        assert_eq!(cortex.read().unwrap(), [1, 2, 3, 4]);
+
+        // Restoring into a segment of another type is refused
+        let other_key = rand::random::<i32>().abs();
+        let other: Cortex<[u32; 3], SpinLock> =
+            Cortex::new(Some(other_key), [0; 3], false, None).unwrap();
+        let err = other.restore_from(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn restore_verifies_checksum() {
+        let key = rand::random::<i32>().abs();
+        let path = std::env::temp_dir().join(format!("cortex_dump_{}", key));
+        let cortex = CortexBuilder::new([1u64; 4])
+            .key(key)
+            .checksum()
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        cortex.snapshot_to(&path).unwrap();
+        cortex.write([2; 4]).unwrap();
+
+        // Flip a single byte of the payload
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+        let err = cortex.restore_from(&path).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Corrupted);
+        assert_eq!(cortex.read().unwrap(), [2; 4]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn dump_on_panic() {
+        let key = rand::random::<i32>().abs();
+        let dir = std::env::temp_dir().join(format!("cortex_crash_{}", key));
+        let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 42, false, None).unwrap();
+
+        drop(cortex.crash_dump_on_panic(&dir));
+        assert!(!dir.exists());
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _dump = cortex.crash_dump_on_panic(&dir);
+            cortex
+                .with_write(|_| panic!("Crash while writing"))
+                .unwrap();
+        }));
+        assert!(result.is_err());
+
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        assert_eq!(files.len(), 2);
+        let state = std::fs::read_to_string(&files[1]).unwrap();
+        assert!(state.contains("write in progress: true"));
+
+        // The dump was taken during the write, so it only restores as poisoned data
+        cortex.write(0).unwrap();
+        let err = cortex.restore_from(&files[0]).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Poisoned);
+        assert_eq!(cortex.read().unwrap(), 0);
+        cortex.restore_poisoned_from(&files[0]).unwrap();
+        assert!(matches!(cortex.read(), Err(CortexError::Poisoned)));
+        assert!(cortex.clear_poison().unwrap());
+        assert_eq!(cortex.read().unwrap(), 42);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{crash::CortexError, Cortex, CortexResult, CortexSync, Pod};
+use chacha20poly1305::{
+    aead::{AeadCore, AeadInPlace, KeyInit, OsRng},
+    Key, Tag, XChaCha20Poly1305, XNonce,
+};
+use std::mem::{size_of, MaybeUninit};
+
+/// Ciphertext of a `T` together with what is needed to decrypt it
+struct Sealed<T> {
+    nonce: [u8; 24],
+    tag: [u8; 16],
+    ciphertext: MaybeUninit<T>,
+}
+
+impl<T: Copy> Clone for Sealed<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Copy> Copy for Sealed<T> {}
+
+/// Shared memory holding an encrypted `T`, for values such as credentials that other users on
+/// the same host must not be able to read, even if they can attach to the segment.
+///
+/// The value is encrypted with XChaCha20-Poly1305 under a 32 byte key that every process passes
+/// in, and a fresh random nonce is stored next to the ciphertext on every write. Reads verify the
+/// authentication tag, so tampering or a wrong key fails with `CortexError::Corrupted` instead of
+/// returning garbage. Values are encrypted and decrypted outside of the lock, which is only held to
+/// copy the ciphertext. `T` has to be `Pod`, since every byte of it is encrypted and the decrypted
+/// bytes are turned back into a `T`.
+pub struct EncryptedCortex<T: Pod, L: CortexSync> {
+    cortex: Cortex<Sealed<T>, L>,
+    cipher: XChaCha20Poly1305,
+}
+
+impl<T: Pod, L: CortexSync> EncryptedCortex<T, L> {
+    /// Create a new segment on `key` holding `data` encrypted under `encryption_key`
+    pub fn new(key: i32, data: T, encryption_key: &[u8; 32]) -> CortexResult<Self> {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(encryption_key));
+        let sealed = seal(&cipher, key, data);
+        let cortex = Cortex::new(Some(key), sealed, false, None)?;
+        Ok(Self { cortex, cipher })
+    }
+    /// Attempt to attach to an already existing segment, `encryption_key` has to be the key it
+    /// was created with
+    pub fn attach(key: i32, encryption_key: &[u8; 32]) -> CortexResult<Self> {
+        let cipher = XChaCha20Poly1305::new(Key::from_slice(encryption_key));
+        let cortex = Cortex::attach(key)?;
+        Ok(Self { cortex, cipher })
+    }
+    /// Decrypt the current value
+    pub fn read(&self) -> CortexResult<T> {
+        let sealed = self.cortex.read()?;
+        open(&self.cipher, self.key(), sealed)
+    }
+    /// Encrypt `data` under a fresh nonce and store it
+    pub fn write(&self, data: T) -> CortexResult<()> {
+        self.cortex.write(seal(&self.cipher, self.key(), data))
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+/// The segment key is authenticated along with the value, so ciphertext can't be copied over to
+/// another segment encrypted under the same key
+fn seal<T: Pod>(cipher: &XChaCha20Poly1305, key: i32, data: T) -> Sealed<T> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut ciphertext = MaybeUninit::new(data);
+    let bytes = unsafe {
+        std::slice::from_raw_parts_mut(ciphertext.as_mut_ptr() as *mut u8, size_of::<T>())
+    };
+    let tag = cipher
+        .encrypt_in_place_detached(&nonce, &key.to_ne_bytes(), bytes)
+        .expect("Payload exceeds the XChaCha20-Poly1305 size limit");
+    Sealed {
+        nonce: nonce.into(),
+        tag: tag.into(),
+        ciphertext,
+    }
+}
+
+fn open<T: Pod>(cipher: &XChaCha20Poly1305, key: i32, mut sealed: Sealed<T>) -> CortexResult<T> {
+    let bytes = unsafe {
+        std::slice::from_raw_parts_mut(sealed.ciphertext.as_mut_ptr() as *mut u8, size_of::<T>())
+    };
+    cipher
+        .decrypt_in_place_detached(
+            XNonce::from_slice(&sealed.nonce),
+            &key.to_ne_bytes(),
+            bytes,
+            Tag::from_slice(&sealed.tag),
+        )
+        .map_err(|_| CortexError::Corrupted)?;
+    Ok(unsafe { sealed.ciphertext.assume_init() })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::encrypted::{EncryptedCortex, Sealed};
+    use crate::{CortexError, SpinLock};
+    use std::mem::size_of;
+
+    #[test]
+    fn round_trip() {
+        let key = rand::random::<i32>().abs();
+        let secret: [u8; 32] = rand::random();
+        let cortex: EncryptedCortex<[u8; 16], SpinLock> =
+            EncryptedCortex::new(key, *b"hunter2 password", &secret).unwrap();
+        let attached: EncryptedCortex<[u8; 16], SpinLock> =
+            EncryptedCortex::attach(key, &secret).unwrap();
+        assert_eq!(&attached.read().unwrap(), b"hunter2 password");
+        attached.write(*b"correct horse 42").unwrap();
+        assert_eq!(&cortex.read().unwrap(), b"correct horse 42");
+
+        // The plaintext never appears in the segment
+        let bytes = cortex
+            .cortex
+            .with_read(|sealed| {
+                let sealed = sealed as *const Sealed<[u8; 16]> as *const u8;
+                unsafe { std::slice::from_raw_parts(sealed, size_of::<Sealed<[u8; 16]>>()) }
+                    .to_vec()
+            })
+            .unwrap();
+        assert!(!bytes
+            .windows(16)
+            .any(|window| window == b"correct horse 42"));
+
+        let wrong: EncryptedCortex<[u8; 16], SpinLock> =
+            EncryptedCortex::attach(key, &rand::random()).unwrap();
+        assert!(matches!(wrong.read(), Err(CortexError::Corrupted)));
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+/// Numeric types that can be stored in a fixed little-endian byte order, see `Le`
+pub trait LittleEndian: Copy {
+    /// Byte array of the same size as `Self`
+    type Bytes: Copy + Eq + Hash;
+
+    fn to_le_bytes(self) -> Self::Bytes;
+    fn from_le_bytes(bytes: Self::Bytes) -> Self;
+}
+
+macro_rules! impl_little_endian {
+    ($($ty:ty),*) => {
+        $(
+            impl LittleEndian for $ty {
+                type Bytes = [u8; std::mem::size_of::<$ty>()];
+
+                fn to_le_bytes(self) -> Self::Bytes {
+                    <$ty>::to_le_bytes(self)
+                }
+                fn from_le_bytes(bytes: Self::Bytes) -> Self {
+                    <$ty>::from_le_bytes(bytes)
+                }
+            }
+        )*
+    };
+}
+
+impl_little_endian!(u16, u32, u64, u128, i16, i32, i64, i128, f32, f64);
+
+/// Number stored little-endian regardless of the byte order of the host, converted on access.
+///
+/// Shared memory on a single host never needs this, but payloads that end up in a file or are
+/// sent to another machine can be read by hosts with a different byte order. Use `Le` for every
+/// numeric field of such a payload, together with `#[repr(C)]` on the containing type. A `Le` is
+/// stored as a plain byte array, so it has an alignment of 1 and never introduces padding.
+#[repr(transparent)]
+pub struct Le<T: LittleEndian>(T::Bytes);
+
+impl<T: LittleEndian> Le<T> {
+    pub fn new(value: T) -> Self {
+        Self(value.to_le_bytes())
+    }
+    /// Convert to the byte order of the host
+    pub fn get(self) -> T {
+        T::from_le_bytes(self.0)
+    }
+    pub fn set(&mut self, value: T) {
+        self.0 = value.to_le_bytes();
+    }
+    /// The stored little-endian bytes
+    pub fn to_bytes(self) -> T::Bytes {
+        self.0
+    }
+    pub fn from_bytes(bytes: T::Bytes) -> Self {
+        Self(bytes)
+    }
+}
+
+impl<T: LittleEndian> Clone for Le<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: LittleEndian> Copy for Le<T> {}
+
+impl<T: LittleEndian> PartialEq for Le<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: LittleEndian> Eq for Le<T> {}
+
+impl<T: LittleEndian> Hash for Le<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+impl<T: LittleEndian + Default> Default for Le<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: LittleEndian + fmt::Debug> fmt::Debug for Le<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
+
+impl<T: LittleEndian> From<T> for Le<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::endian::Le;
+    use crate::{Cortex, SpinLock};
+
+    #[derive(Clone, Copy, Default)]
+    #[repr(C)]
+    struct Quote {
+        price: Le<f64>,
+        volume: Le<u32>,
+        venue: u8,
+    }
+
+    #[test]
+    fn fixed_byte_order() {
+        let volume = Le::new(0x0102_0304u32);
+        assert_eq!(volume.to_bytes(), [4, 3, 2, 1]);
+        assert_eq!(volume.get(), 0x0102_0304);
+        // Byte arrays never add padding
+        assert_eq!(std::mem::size_of::<Quote>(), 13);
+
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<Quote, SpinLock> =
+            Cortex::new(Some(key), Quote::default(), false, None).unwrap();
+        cortex
+            .with_write(|quote| {
+                quote.price.set(101.5);
+                quote.volume = volume;
+            })
+            .unwrap();
+        let quote = cortex.read().unwrap();
+        assert_eq!(
+            (quote.price.get(), quote.volume.get(), quote.venue),
+            (101.5, 0x0102_0304, 0)
+        );
+    }
+}
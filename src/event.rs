@@ -0,0 +1,497 @@
+use crate::{
+    cleanup::{self, OwnedResource, Registration},
+    crash::CortexError,
+    namespace, CortexResult,
+};
+use std::ffi::CString;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+/// Per-process counter telling apart the pipes of multiple handles living in the same process
+static NEXT_PIPE: AtomicU32 = AtomicU32::new(1);
+
+fn get_dir(key: i32) -> PathBuf {
+    std::env::temp_dir().join(namespace::qualify(format!("cortex_event_{}", key)))
+}
+
+fn c_path(path: &Path) -> CortexResult<CString> {
+    Ok(CString::new(path.as_os_str().as_bytes())?)
+}
+
+/// Open a FIFO in non-blocking read/write mode, which means opening never blocks waiting for a
+/// peer and writing to it never fails because nobody is listening
+fn open_fifo(path: &CString) -> CortexResult<RawFd> {
+    let fd = unsafe {
+        libc::open(
+            path.as_ptr(),
+            libc::O_RDWR | libc::O_NONBLOCK | libc::O_CLOEXEC,
+        )
+    };
+    if fd == -1 {
+        return Err(CortexError::new_clean(format!(
+            "Error during open for event: {:?}",
+            path
+        )));
+    }
+    Ok(fd)
+}
+
+/// Create the pipe of a new handle in the directory of the event. It is created under a hidden
+/// name and only renamed once it is open, so notifiers never mistake it for the pipe of a handle
+/// that has gone away.
+fn create_pipe(dir: &Path) -> CortexResult<(CString, RawFd)> {
+    let name = format!(
+        "{}_{}",
+        unsafe { libc::getpid() },
+        NEXT_PIPE.fetch_add(1, Ordering::Relaxed)
+    );
+    let hidden = c_path(&dir.join(format!(".{}", name)))?;
+    let path = c_path(&dir.join(name))?;
+    if unsafe { libc::mkfifo(hidden.as_ptr(), 0o600) } == -1 {
+        return Err(CortexError::new_clean(format!(
+            "Error during mkfifo for event: {:?}",
+            dir
+        )));
+    }
+    let fd = open_fifo(&hidden).and_then(|fd| {
+        if unsafe { libc::rename(hidden.as_ptr(), path.as_ptr()) } == -1 {
+            let err = CortexError::new_clean(format!("Error during rename for event: {:?}", dir));
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        Ok(fd)
+    });
+    if fd.is_err() {
+        unsafe { libc::unlink(hidden.as_ptr()) };
+    }
+    Ok((path, fd?))
+}
+
+#[derive(Debug)]
+enum EventKind {
+    /// Directory holding a named FIFO per handle, which any process can join by key. `fifo` is
+    /// the one of this handle, created by process `pid`.
+    Named {
+        dir: PathBuf,
+        fifo: CString,
+        pid: libc::pid_t,
+    },
+    /// Linux eventfd, shared with child processes through `fork`
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    EventFd,
+    /// Anonymous pipe, shared with child processes through `fork`
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    Pipe { write_fd: RawFd },
+}
+
+/// Cross-process notification channel, meant to be paired with a `Cortex` on the same key so that
+/// a writer can signal readers that new data is available.
+///
+/// The exposed file descriptor becomes readable whenever a notification is pending, which means it
+/// can be registered in `poll`/`epoll` or wrapped in something like `tokio::io::unix::AsyncFd`.
+/// Multiple notifications sent before a reader wakes up are coalesced into one. Event loops that
+/// take an `AsFd` source, e.g. calloop's `Generic`, can own the event directly, and with crate
+/// feature "mio" it is a `mio::event::Source`. Either way, call `try_wait` when it becomes readable
+/// to consume the notifications, which also rearms edge-triggered registrations.
+///
+/// Named events are broadcast: every handle on the key, created through `new` or `attach`, gets
+/// its own pipe, and `notify` wakes all of them except the notifying handle itself. Handles only
+/// see notifications sent after they were created. An anonymous event is a single `eventfd` (or
+/// pipe) shared by every process that inherited it, so each notification wakes whichever of them
+/// consumes it first.
+#[derive(Debug)]
+pub struct Event {
+    fd: RawFd,
+    kind: EventKind,
+    is_owner: bool,
+    registration: Registration,
+}
+
+impl Event {
+    /// Create a new named event on `key`, failing if one already exists
+    pub fn new(key: i32) -> CortexResult<Self> {
+        let dir = get_dir(key);
+        let path = c_path(&dir)?;
+        if unsafe { libc::mkdir(path.as_ptr(), 0o700) } == -1 {
+            return Err(CortexError::new_clean(format!(
+                "Error during mkdir for event with key: {}",
+                key
+            )));
+        }
+        let (fifo, fd) = match create_pipe(&dir) {
+            Ok(joined) => joined,
+            Err(err) => {
+                if unsafe { libc::rmdir(path.as_ptr()) } == -1 {
+                    return Err(CortexError::new_dirty(format!(
+                        "Error during rmdir for event with key: {}",
+                        key
+                    )));
+                }
+                return Err(err);
+            }
+        };
+        crate::trace::trace!("Created event for key: {}", key);
+        Ok(Self {
+            fd,
+            registration: cleanup::owned(|| OwnedResource::Directory(path)),
+            kind: EventKind::Named {
+                dir,
+                fifo,
+                pid: unsafe { libc::getpid() },
+            },
+            is_owner: true,
+        })
+    }
+    /// Attempt to attach to an already existing named event
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let dir = get_dir(key);
+        let (fifo, fd) = create_pipe(&dir)?;
+        Ok(Self {
+            fd,
+            kind: EventKind::Named {
+                dir,
+                fifo,
+                pid: unsafe { libc::getpid() },
+            },
+            is_owner: false,
+            registration: Registration::default(),
+        })
+    }
+    /// Create an unnamed event backed by `eventfd` (or a pipe on platforms without it). Since it
+    /// has no name it can only be shared with child processes created through `fork`.
+    pub fn anonymous() -> CortexResult<Self> {
+        cfg_if::cfg_if! {
+            if #[cfg(any(target_os = "linux", target_os = "android"))] {
+                let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+                if fd == -1 {
+                    return Err(CortexError::new_clean("Error during eventfd"));
+                }
+                Ok(Self {
+                    fd,
+                    kind: EventKind::EventFd,
+                    is_owner: true,
+                    registration: Registration::default(),
+                })
+            } else {
+                let mut fds = [0; 2];
+                if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+                    return Err(CortexError::new_clean("Error during pipe"));
+                }
+                for fd in fds {
+                    unsafe {
+                        libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK);
+                        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                    }
+                }
+                Ok(Self {
+                    fd: fds[0],
+                    kind: EventKind::Pipe { write_fd: fds[1] },
+                    is_owner: true,
+                    registration: Registration::default(),
+                })
+            }
+        }
+    }
+    /// Wake up anyone waiting on this event
+    pub fn notify(&self) -> CortexResult<()> {
+        match &self.kind {
+            EventKind::Named { dir, fifo, .. } => self.notify_named(dir, fifo),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            EventKind::EventFd => write_one(self.fd, std::mem::size_of::<u64>()),
+            #[cfg(not(any(target_os = "linux", target_os = "android")))]
+            EventKind::Pipe { write_fd } => write_one(*write_fd, 1),
+        }
+    }
+    /// Write to the pipe of every other handle on the key, removing the pipes of handles that went
+    /// away without cleaning up after themselves
+    fn notify_named(&self, dir: &Path, own: &CString) -> CortexResult<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let name = entry?.file_name();
+            if name.as_bytes().starts_with(b".") {
+                continue;
+            }
+            let path = c_path(&dir.join(name))?;
+            if path == *own {
+                continue;
+            }
+            let fd = unsafe { libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_NONBLOCK) };
+            if fd == -1 {
+                match errno::errno().0 {
+                    // Nobody has the pipe open anymore, so its process died
+                    libc::ENXIO => unsafe {
+                        libc::unlink(path.as_ptr());
+                    },
+                    // Its handle was dropped in between
+                    libc::ENOENT => {}
+                    _ => {
+                        return Err(CortexError::new_clean(format!(
+                            "Error during open for event: {:?}",
+                            path
+                        )))
+                    }
+                }
+                continue;
+            }
+            let written = write_one(fd, 1);
+            unsafe { libc::close(fd) };
+            written?;
+        }
+        Ok(())
+    }
+    /// Consume any pending notifications without blocking, returns `true` if there were any
+    pub fn try_wait(&self) -> CortexResult<bool> {
+        let mut buf = [0u8; 64];
+        let mut notified = false;
+        loop {
+            let read = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, 64) };
+            if read > 0 {
+                notified = true;
+                continue;
+            }
+            if read == -1 && errno::errno().0 != libc::EAGAIN {
+                return Err(CortexError::new_clean("Error during read for event"));
+            }
+            return Ok(notified);
+        }
+    }
+    /// Block until a notification arrives
+    pub fn wait(&self) -> CortexResult<()> {
+        // Another process sharing an anonymous event may consume the notification first
+        while !(self.poll(None)? && self.try_wait()?) {}
+        Ok(())
+    }
+    /// Block until a notification arrives or the timeout expires, returns `false` on timeout
+    pub fn wait_timeout(&self, timeout: Duration) -> CortexResult<bool> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !self.poll(Some(remaining))? {
+                return Ok(false);
+            }
+            if self.try_wait()? {
+                return Ok(true);
+            }
+        }
+    }
+    /// Wait for the descriptor to become readable, returns `false` once `timeout` expired. Waits
+    /// interrupted by a signal are resumed with the time that is left.
+    fn poll(&self, timeout: Option<Duration>) -> CortexResult<bool> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let timeout = match deadline {
+                // Rounded up, so that the deadline has passed once poll times out
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_nanos()
+                    .div_ceil(1_000_000)
+                    .min(libc::c_int::MAX as u128) as libc::c_int,
+                None => -1,
+            };
+            let mut pollfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            match unsafe { libc::poll(&mut pollfd, 1, timeout) } {
+                -1 if errno::errno().0 == libc::EINTR => continue,
+                -1 => return Err(CortexError::new_clean("Error during poll for event")),
+                0 => return Ok(false),
+                _ => return Ok(true),
+            }
+        }
+    }
+}
+
+/// Write a notification of `len` bytes to `fd`. A full buffer means there is already a pending
+/// notification.
+fn write_one(fd: RawFd, len: usize) -> CortexResult<()> {
+    let value: u64 = 1;
+    let written = unsafe { libc::write(fd, &value as *const u64 as *const libc::c_void, len) };
+    if written == -1 && errno::errno().0 != libc::EAGAIN {
+        return Err(CortexError::new_clean("Error during write for event"));
+    }
+    Ok(())
+}
+
+impl AsRawFd for Event {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd
+    }
+}
+
+impl AsFd for Event {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // The descriptor stays open until the event is dropped
+        unsafe { BorrowedFd::borrow_raw(self.fd) }
+    }
+}
+
+/// Registers the readable end, so an event can be passed to `mio::Registry::register` as is
+#[cfg(feature = "mio")]
+impl mio::event::Source for Event {
+    fn register(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.fd).register(registry, token, interests)
+    }
+    fn reregister(
+        &mut self,
+        registry: &mio::Registry,
+        token: mio::Token,
+        interests: mio::Interest,
+    ) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.fd).reregister(registry, token, interests)
+    }
+    fn deregister(&mut self, registry: &mio::Registry) -> std::io::Result<()> {
+        mio::unix::SourceFd(&self.fd).deregister(registry)
+    }
+}
+
+impl Drop for Event {
+    fn drop(&mut self) {
+        if unsafe { libc::close(self.fd) } == -1 {
+            crate::trace::error!("Error during close of event");
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        if let EventKind::Pipe { write_fd } = &self.kind {
+            if unsafe { libc::close(*write_fd) } == -1 {
+                crate::trace::error!("Error during close of event");
+            }
+        }
+        let EventKind::Named { dir, fifo, pid } = &self.kind else {
+            return;
+        };
+        // The pipe still belongs to the parent of a handle inherited through `fork`
+        if *pid != unsafe { libc::getpid() } {
+            return;
+        }
+        // Already gone if the owner removed the event first
+        if unsafe { libc::unlink(fifo.as_ptr()) } == -1 && errno::errno().0 != libc::ENOENT {
+            crate::trace::error!("Error during unlink of event: {:?}", fifo);
+        }
+        if !self.is_owner || !cleanup::released(self.registration) {
+            return;
+        }
+        if let Err(err) = std::fs::remove_dir_all(dir) {
+            crate::trace::error!("Error removing event directory {:?}: {}", dir, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::event::{c_path, get_dir, Event};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn notify_attached() {
+        let key = rand::random::<i32>().abs();
+        let event = Event::new(key).unwrap();
+        let attached = Event::attach(key).unwrap();
+
+        assert!(!attached.try_wait().unwrap());
+        event.notify().unwrap();
+        event.notify().unwrap();
+        assert!(attached.wait_timeout(Duration::from_millis(100)).unwrap());
+        assert!(!attached.try_wait().unwrap());
+    }
+
+    #[test]
+    fn notify_every_reader() {
+        let key = rand::random::<i32>().abs();
+        let event = Event::new(key).unwrap();
+        let readers: Vec<_> = (0..3).map(|_| Event::attach(key).unwrap()).collect();
+
+        readers[0].notify().unwrap();
+        // The notifying handle doesn't wake itself
+        assert!(!readers[0].try_wait().unwrap());
+        for reader in &readers[1..] {
+            assert!(reader.wait_timeout(Duration::from_millis(100)).unwrap());
+        }
+        assert!(event.try_wait().unwrap());
+
+        // Dropped readers leave nothing behind, and a pipe without a reader is removed
+        drop(readers);
+        let dir = get_dir(key);
+        let stale = c_path(&dir.join("1_1")).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(stale.as_ptr(), 0o600) }, 0);
+        let attached = Event::attach(key).unwrap();
+        attached.notify().unwrap();
+        assert!(event.try_wait().unwrap());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+        drop(event);
+        assert!(!dir.exists());
+        assert!(Event::attach(key).is_err());
+    }
+
+    extern "C" fn ignore_signal(_: libc::c_int) {}
+
+    #[test]
+    fn wait_timeout_resumes_after_signal() {
+        use std::os::unix::thread::JoinHandleExt;
+
+        let key = rand::random::<i32>().abs();
+        let _event = Event::new(key).unwrap();
+        let waiter = std::thread::spawn(move || {
+            let attached = Event::attach(key).unwrap();
+            let start = Instant::now();
+            let notified = attached.wait_timeout(Duration::from_millis(200)).unwrap();
+            (notified, start.elapsed())
+        });
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore_signal as *const () as usize;
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+            std::thread::sleep(Duration::from_millis(50));
+            libc::pthread_kill(waiter.as_pthread_t(), libc::SIGUSR1);
+        }
+        let (notified, elapsed) = waiter.join().unwrap();
+        assert!(!notified);
+        assert!(elapsed >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn notify_anonymous() {
+        let event = Event::anonymous().unwrap();
+        assert!(!event.wait_timeout(Duration::from_millis(10)).unwrap());
+        event.notify().unwrap();
+        event.wait().unwrap();
+    }
+
+    #[cfg(feature = "mio")]
+    #[test]
+    fn mio_registration() {
+        use mio::{Events, Interest, Poll, Token};
+
+        let key = rand::random::<i32>().abs();
+        let event = Event::new(key).unwrap();
+        let mut attached = Event::attach(key).unwrap();
+        let mut poll = Poll::new().unwrap();
+        poll.registry()
+            .register(&mut attached, Token(7), Interest::READABLE)
+            .unwrap();
+        let mut events = Events::with_capacity(4);
+
+        // Edge-triggered, so each round of notifications is reported once try_wait drained the
+        // previous one
+        for _ in 0..2 {
+            event.notify().unwrap();
+            poll.poll(&mut events, Some(Duration::from_secs(5)))
+                .unwrap();
+            let tokens: Vec<_> = events.iter().map(|event| event.token()).collect();
+            assert_eq!(tokens, [Token(7)]);
+            assert!(attached.try_wait().unwrap());
+        }
+        poll.poll(&mut events, Some(Duration::from_millis(10)))
+            .unwrap();
+        assert!(events.is_empty());
+        poll.registry().deregister(&mut attached).unwrap();
+    }
+}
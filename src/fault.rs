@@ -0,0 +1,335 @@
+use crate::{crash::CortexError, CortexResult, CortexSync};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+/// Operation of a `FaultyLock` that can be programmed to fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Fault {
+    /// Creating the lock fails with `ENOSPC`, which fails creating the `Cortex`
+    Create,
+    /// Attaching to the lock fails with `ENOENT`, which fails attaching to the `Cortex`
+    Attach,
+    /// Acquiring the lock fails with `EINTR`, like an interrupted `sem_wait`. Applies to every
+    /// way of acquiring it, including `try_` and timed acquisitions and upgrades.
+    Lock,
+    /// Releasing the lock fails with `EINVAL`, like a failed `sem_post`. The lock is released
+    /// anyway, so that a test doesn't deadlock on it.
+    Release,
+    /// `Cortex::write` and its `try_` and timed variants write only the first half of the data
+    /// and return `CortexError::Poisoned`, leaving the data poisoned as if the writer had crashed
+    /// halfway through
+    PartialWrite,
+}
+
+impl Fault {
+    fn errno(self) -> i32 {
+        match self {
+            Fault::Create => libc::ENOSPC,
+            Fault::Attach => libc::ENOENT,
+            Fault::Lock => libc::EINTR,
+            Fault::Release => libc::EINVAL,
+            Fault::PartialWrite => 0,
+        }
+    }
+}
+
+/// When a programmed `Fault` is injected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    /// Every time
+    Always,
+    /// The next `n` times, then never again
+    Times(u32),
+    /// Only the `n`th time, counting from 1
+    Nth(u32),
+    /// Every time with the given probability between 0 and 1, drawn from the seeded generator of
+    /// the `FaultPlan`
+    Probability(f64),
+}
+
+#[derive(Debug)]
+struct Rule {
+    trigger: Trigger,
+    calls: u32,
+}
+
+#[derive(Debug)]
+struct PlanState {
+    rules: HashMap<Fault, Rule>,
+    injected: HashMap<Fault, u32>,
+    /// State of the xorshift generator behind `Trigger::Probability`
+    rng: u64,
+}
+
+/// Faults to inject into every `FaultyLock` created from it, shared between clones so that a test
+/// can reprogram it while the locks are in use. Faults are only injected into the process that
+/// holds the plan.
+#[derive(Debug, Clone)]
+pub struct FaultPlan {
+    state: Arc<Mutex<PlanState>>,
+}
+
+impl Default for FaultPlan {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FaultPlan {
+    /// Plan without any faults, with a fixed seed so that probabilistic faults are reproducible
+    pub fn new() -> Self {
+        Self::with_seed(0x2545_f491_4f6c_dd1d)
+    }
+    /// Plan without any faults, seeding the generator for `Trigger::Probability` with `seed`
+    pub fn with_seed(seed: u64) -> Self {
+        let state = PlanState {
+            rules: HashMap::new(),
+            injected: HashMap::new(),
+            // Xorshift gets stuck on zero
+            rng: seed.max(1),
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+    fn state(&self) -> MutexGuard<'_, PlanState> {
+        // A panicking test shouldn't take down the plan of every other lock
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+    /// Inject `fault` whenever `trigger` fires, replacing anything programmed for it before
+    pub fn fail(&self, fault: Fault, trigger: Trigger) {
+        let rule = Rule { trigger, calls: 0 };
+        self.state().rules.insert(fault, rule);
+    }
+    /// Stop injecting `fault`
+    pub fn clear(&self, fault: Fault) {
+        self.state().rules.remove(&fault);
+    }
+    /// Number of times `fault` was injected so far
+    pub fn injected(&self, fault: Fault) -> u32 {
+        self.state().injected.get(&fault).copied().unwrap_or(0)
+    }
+    /// Whether to inject `fault` into the current call
+    fn should_fail(&self, fault: Fault) -> bool {
+        let mut state = self.state();
+        let state = &mut *state;
+        let Some(rule) = state.rules.get_mut(&fault) else {
+            return false;
+        };
+        rule.calls = rule.calls.saturating_add(1);
+        let fail = match rule.trigger {
+            Trigger::Always => true,
+            Trigger::Times(n) => rule.calls <= n,
+            Trigger::Nth(n) => rule.calls == n,
+            Trigger::Probability(probability) => {
+                state.rng ^= state.rng << 13;
+                state.rng ^= state.rng >> 7;
+                state.rng ^= state.rng << 17;
+                ((state.rng >> 11) as f64 / (1u64 << 53) as f64) < probability
+            }
+        };
+        if fail {
+            *state.injected.entry(fault).or_default() += 1;
+        }
+        fail
+    }
+    /// Return the error for `fault` if it should be injected into the current call
+    fn check(&self, fault: Fault, key: i32) -> CortexResult<()> {
+        if !self.should_fail(fault) {
+            return Ok(());
+        }
+        crate::trace::warning!("Injecting {:?} fault for key: {}", fault, key);
+        // Fail with a real errno, so that the error looks like one from the system
+        errno::set_errno(errno::Errno(fault.errno()));
+        Err(CortexError::new_clean(format!(
+            "Injected {:?} fault for key: {}",
+            fault, key
+        )))
+    }
+}
+
+pub struct FaultSettings<S> {
+    /// Faults to inject, clone it before passing it in to keep programming it
+    pub plan: FaultPlan,
+    /// Settings of the wrapped lock
+    pub lock: Option<S>,
+}
+
+impl<S> FaultSettings<S> {
+    pub fn new(plan: FaultPlan) -> Self {
+        Self { plan, lock: None }
+    }
+}
+
+/// Lock that wraps `L` and injects the faults programmed in a `FaultPlan`, to test how an
+/// application recovers from failures of the system, e.g. a failing `sem_wait` or a writer dying
+/// halfway through a write. Only available with the `testing` crate feature.
+///
+/// Pass the plan through `FaultSettings` when creating or attaching. A `FaultyLock` created
+/// without settings never injects anything.
+#[derive(Debug)]
+pub struct FaultyLock<L: CortexSync> {
+    key: i32,
+    plan: FaultPlan,
+    inner: L,
+}
+
+impl<L: CortexSync> FaultyLock<L> {
+    /// Plan of the faults injected into this lock
+    pub fn plan(&self) -> &FaultPlan {
+        &self.plan
+    }
+    fn acquire<R>(&self, acquire: impl FnOnce(&L) -> CortexResult<R>) -> CortexResult<R> {
+        self.plan.check(Fault::Lock, self.key)?;
+        acquire(&self.inner)
+    }
+    fn release_with(&self, release: impl FnOnce(&L) -> CortexResult<()>) -> CortexResult<()> {
+        release(&self.inner)?;
+        self.plan.check(Fault::Release, self.key)
+    }
+}
+
+impl<L: CortexSync> CortexSync for FaultyLock<L> {
+    type Settings = FaultSettings<L::Settings>;
+    const IN_PROCESS: bool = L::IN_PROCESS;
+
+    fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let plan = settings.map_or_else(FaultPlan::new, |settings| settings.plan.clone());
+        plan.check(Fault::Create, cortex_key)?;
+        let inner = L::new(
+            cortex_key,
+            settings.and_then(|settings| settings.lock.as_ref()),
+        )?;
+        Ok(Self {
+            key: cortex_key,
+            plan,
+            inner,
+        })
+    }
+    fn attach(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let plan = settings.map_or_else(FaultPlan::new, |settings| settings.plan.clone());
+        plan.check(Fault::Attach, cortex_key)?;
+        let inner = L::attach(
+            cortex_key,
+            settings.and_then(|settings| settings.lock.as_ref()),
+        )?;
+        Ok(Self {
+            key: cortex_key,
+            plan,
+            inner,
+        })
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        Ok(Self {
+            key: self.key,
+            plan: self.plan.clone(),
+            inner: self.inner.try_clone()?,
+        })
+    }
+    fn force_ownership(&mut self) {
+        self.inner.force_ownership()
+    }
+    fn relinquish_ownership(&mut self) {
+        self.inner.relinquish_ownership()
+    }
+    fn after_fork_child(&mut self) -> CortexResult<()> {
+        self.inner.after_fork_child()
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.acquire(L::read_lock)
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.acquire(L::write_lock)
+    }
+    fn release(&self) -> CortexResult<()> {
+        self.release_with(L::release)
+    }
+    fn release_read(&self) -> CortexResult<()> {
+        self.release_with(L::release_read)
+    }
+    fn release_write(&self) -> CortexResult<()> {
+        self.release_with(L::release_write)
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        self.acquire(L::try_read_lock)
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        self.acquire(L::try_write_lock)
+    }
+    fn timed_read_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        self.acquire(|inner| inner.timed_read_lock(timeout))
+    }
+    fn timed_write_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        self.acquire(|inner| inner.timed_write_lock(timeout))
+    }
+    fn upgradable_read_lock(&self) -> CortexResult<()> {
+        self.acquire(L::upgradable_read_lock)
+    }
+    fn try_upgradable_read_lock(&self) -> CortexResult<bool> {
+        self.acquire(L::try_upgradable_read_lock)
+    }
+    fn timed_upgradable_read_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        self.acquire(|inner| inner.timed_upgradable_read_lock(timeout))
+    }
+    fn upgrade(&self) -> CortexResult<()> {
+        self.acquire(L::upgrade)
+    }
+    fn release_upgradable(&self) -> CortexResult<()> {
+        self.release_with(L::release_upgradable)
+    }
+    fn tear_write(&self) -> bool {
+        self.plan.should_fail(Fault::PartialWrite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fault::{Fault, FaultPlan, FaultSettings, FaultyLock, Trigger};
+    use crate::{Cortex, CortexError, ErrorKind, SpinLock};
+
+    #[test]
+    fn injected_faults() {
+        let key = rand::random::<i32>().abs();
+        let plan = FaultPlan::new();
+        let settings = FaultSettings::new(plan.clone());
+        let cortex: Cortex<u64, FaultyLock<SpinLock>> =
+            Cortex::new(Some(key), 1, false, Some(&settings)).unwrap();
+
+        plan.fail(Fault::Lock, Trigger::Nth(2));
+        assert_eq!(cortex.read().unwrap(), 1);
+        let err = cortex.read().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+        assert_eq!(err.raw_os_error(), Some(libc::EINTR));
+        assert_eq!(cortex.read().unwrap(), 1);
+        assert_eq!(plan.injected(Fault::Lock), 1);
+
+        plan.fail(Fault::Attach, Trigger::Times(1));
+        let attach = || Cortex::<u64, FaultyLock<SpinLock>>::attach_with_lock(key, &settings);
+        assert_eq!(attach().err().unwrap().kind(), ErrorKind::NotFound);
+        let attached = attach().unwrap();
+
+        plan.fail(Fault::PartialWrite, Trigger::Always);
+        assert!(matches!(cortex.write(u64::MAX), Err(CortexError::Poisoned)));
+        assert!(matches!(attached.read(), Err(CortexError::Poisoned)));
+        plan.clear(Fault::PartialWrite);
+        cortex.write(2).unwrap();
+        assert_eq!(attached.read().unwrap(), 2);
+    }
+
+    #[test]
+    fn probability_is_reproducible() {
+        let pattern = |seed| {
+            let plan = FaultPlan::with_seed(seed);
+            plan.fail(Fault::Lock, Trigger::Probability(0.5));
+            (0..64)
+                .map(|_| plan.should_fail(Fault::Lock))
+                .collect::<Vec<_>>()
+        };
+        let first = pattern(7);
+        assert_eq!(first, pattern(7));
+        let failures = first.iter().filter(|fail| **fail).count();
+        assert!((16..48).contains(&failures));
+    }
+}
@@ -0,0 +1,207 @@
+//! C interface for processes written in C or C++, see `include/neocortex.h`.
+//!
+//! Segments are untyped byte buffers protected by a `Semaphore`, the same as a
+//! `RawCortex<Semaphore>` on the same key, so Rust and C processes can share a segment with
+//! identical locking. Functions that can fail return a null handle or `-1`, after which
+//! `neocortex_last_error` describes what went wrong.
+
+use crate::{
+    crash::{CortexError, ErrorKind},
+    set_namespace, CortexResult, RawCortex, Semaphore,
+};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Store the error of a failed call for `neocortex_last_error`
+fn report<R>(result: CortexResult<R>) -> Option<R> {
+    result
+        .inspect_err(|err| {
+            let message = CString::new(err.to_string().replace('\0', " ")).unwrap_or_default();
+            LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+        })
+        .ok()
+}
+
+fn handle_ref<'a>(handle: *const NeocortexHandle) -> CortexResult<&'a NeocortexHandle> {
+    unsafe { handle.as_ref() }
+        .ok_or_else(|| CortexError::new_logic(ErrorKind::InvalidInput, "Null handle"))
+}
+
+fn check_len(handle: &NeocortexHandle, len: usize) -> CortexResult<()> {
+    if len > handle.raw.size() {
+        return Err(CortexError::new_logic(
+            ErrorKind::InvalidInput,
+            format!(
+                "Length {} exceeds the segment size of {} bytes",
+                len,
+                handle.raw.size()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Opaque handle to an attached segment, freed through `neocortex_destroy`
+pub struct NeocortexHandle {
+    raw: RawCortex<Semaphore>,
+}
+
+/// Set the namespace mixed into every key, see `set_namespace`. Must be called before creating or
+/// attaching anything. Returns 0 on success and -1 on failure.
+///
+/// # Safety
+///
+/// `name` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn neocortex_set_namespace(name: *const c_char) -> i32 {
+    let result = if name.is_null() {
+        Err(CortexError::new_logic(
+            ErrorKind::InvalidInput,
+            "Null namespace",
+        ))
+    } else {
+        let name = unsafe { CStr::from_ptr(name) };
+        set_namespace(name.to_string_lossy())
+    };
+    report(result).map_or(-1, |_| 0)
+}
+
+/// Create a zero-initialized segment of `size` bytes on `key`, or on a random key if `key` is 0.
+/// The segment is removed when the creating handle is destroyed. Returns null on failure.
+#[no_mangle]
+pub extern "C" fn neocortex_create(key: i32, size: usize) -> *mut NeocortexHandle {
+    let key = (key != 0).then_some(key);
+    match report(RawCortex::new(key, size, None)) {
+        Some(raw) => Box::into_raw(Box::new(NeocortexHandle { raw })),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Attach to an existing segment on `key`. Returns null on failure.
+#[no_mangle]
+pub extern "C" fn neocortex_attach(key: i32) -> *mut NeocortexHandle {
+    match report(RawCortex::attach(key, None)) {
+        Some(raw) => Box::into_raw(Box::new(NeocortexHandle { raw })),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// Copy the first `len` bytes of the segment into `out` under the read lock. Returns 0 on
+/// success and -1 on failure.
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle, and `out` must be valid for writes of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn neocortex_read(
+    handle: *const NeocortexHandle,
+    out: *mut c_void,
+    len: usize,
+) -> i32 {
+    let result = handle_ref(handle).and_then(|handle| {
+        check_len(handle, len)?;
+        let bytes = handle.raw.as_slice()?;
+        unsafe { std::ptr::copy_nonoverlapping(bytes.as_ptr(), out as *mut u8, len) };
+        Ok(())
+    });
+    report(result).map_or(-1, |_| 0)
+}
+
+/// Copy `len` bytes from `data` to the start of the segment under the write lock. Returns 0 on
+/// success and -1 on failure.
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle, and `data` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn neocortex_write(
+    handle: *const NeocortexHandle,
+    data: *const c_void,
+    len: usize,
+) -> i32 {
+    let result = handle_ref(handle).and_then(|handle| {
+        check_len(handle, len)?;
+        let mut bytes = handle.raw.as_mut_slice()?;
+        unsafe { std::ptr::copy_nonoverlapping(data as *const u8, bytes.as_mut_ptr(), len) };
+        Ok(())
+    });
+    report(result).map_or(-1, |_| 0)
+}
+
+/// Size of the segment in bytes, 0 for a null handle
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn neocortex_size(handle: *const NeocortexHandle) -> usize {
+    report(handle_ref(handle)).map_or(0, |handle| handle.raw.size())
+}
+
+/// Key of the segment, e.g. after creating it on a random key. 0 for a null handle.
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn neocortex_key(handle: *const NeocortexHandle) -> i32 {
+    report(handle_ref(handle)).map_or(0, |handle| handle.raw.key())
+}
+
+/// Detach from the segment and free the handle, removing the segment if the handle created it
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle, which must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn neocortex_destroy(handle: *mut NeocortexHandle) {
+    if !handle.is_null() {
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
+
+/// Description of the last error on the calling thread, or null if there was none. The string is
+/// valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn neocortex_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ffi::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn round_trip() {
+        let created = neocortex_create(0, 16);
+        assert!(!created.is_null());
+        unsafe {
+            let attached = neocortex_attach(neocortex_key(created));
+            assert!(!attached.is_null());
+            assert_eq!(neocortex_size(attached), 16);
+
+            let value = 42u64.to_ne_bytes();
+            assert_eq!(neocortex_write(created, value.as_ptr() as _, 8), 0);
+            let mut out = [0u8; 8];
+            assert_eq!(neocortex_read(attached, out.as_mut_ptr() as _, 8), 0);
+            assert_eq!(out, value);
+
+            let mut too_long = [0u8; 32];
+            assert_eq!(neocortex_read(attached, too_long.as_mut_ptr() as _, 32), -1);
+            let error = CStr::from_ptr(neocortex_last_error()).to_string_lossy();
+            assert!(error.contains("exceeds the segment size"));
+
+            neocortex_destroy(attached);
+            neocortex_destroy(created);
+        }
+    }
+}
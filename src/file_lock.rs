@@ -0,0 +1,266 @@
+use crate::{
+    cleanup::{self, OwnedResource, Registration},
+    crash::CortexError,
+    namespace, CortexResult, CortexSync,
+};
+use std::ffi::CString;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex, MutexGuard};
+
+fn get_path(settings: Option<&FileLockSettings>, cortex_key: i32) -> CortexResult<CString> {
+    let directory = match settings {
+        Some(settings) => settings.directory.clone(),
+        None => std::env::temp_dir(),
+    };
+    let path = directory.join(namespace::qualify(format!("cortex_lock_{}", cortex_key)));
+    Ok(CString::new(path.to_string_lossy().as_bytes())?)
+}
+
+pub struct FileLockSettings {
+    /// Directory to place the lock file in, defaults to the system temp directory. Must be the same
+    /// for every process attaching to the same key.
+    pub directory: PathBuf,
+}
+
+impl Default for FileLockSettings {
+    fn default() -> Self {
+        Self {
+            directory: std::env::temp_dir(),
+        }
+    }
+}
+
+/// Lock state within the current process, since `flock` doesn't provide any exclusion between
+/// threads sharing the same file descriptor
+#[derive(Debug, Default)]
+struct LocalState {
+    readers: usize,
+    writer: bool,
+}
+
+/// Lock based on `flock` on a lock file, where readers take a shared lock and writers take an
+/// exclusive one.
+///
+/// Slower than the other backends since every uncontended acquisition is a syscall, but the
+/// kernel automatically releases the lock if the process holding it dies, which gives crash-safety
+/// without any recovery logic. Also works on platforms where named semaphores are unavailable.
+///
+/// The lock belongs to the open lock file, which a child created through `fork` shares with its
+/// parent, so the child has to call `Cortex::after_fork_child` before locking.
+#[derive(Debug)]
+pub struct FileLock {
+    fd: libc::c_int,
+    path: CString,
+    is_owner: bool,
+    registration: Registration,
+    local: Mutex<LocalState>,
+    released: Condvar,
+}
+
+impl FileLock {
+    fn open(path: CString, flags: libc::c_int, is_owner: bool) -> CortexResult<Self> {
+        let fd =
+            unsafe { libc::open(path.as_ptr(), flags | libc::O_RDWR | libc::O_CLOEXEC, 0o600) };
+        if fd == -1 {
+            return Err(CortexError::new_clean(format!(
+                "Error during open for lock file: {:?}",
+                path
+            )));
+        }
+        let registration = match is_owner {
+            true => cleanup::owned(|| OwnedResource::File(path.clone())),
+            false => Registration::default(),
+        };
+        Ok(Self {
+            fd,
+            path,
+            is_owner,
+            registration,
+            local: Mutex::new(LocalState::default()),
+            released: Condvar::new(),
+        })
+    }
+    fn local(&self) -> MutexGuard<'_, LocalState> {
+        // The state is only ever updated after the flock call succeeded, so it stays consistent
+        self.local
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    /// Returns `false` only for non-blocking operations that would have blocked
+    fn flock(&self, operation: libc::c_int) -> CortexResult<bool> {
+        loop {
+            if unsafe { libc::flock(self.fd, operation) } == 0 {
+                return Ok(true);
+            }
+            match errno::errno().0 {
+                libc::EINTR => continue,
+                libc::EWOULDBLOCK => return Ok(false),
+                _ => return Err(CortexError::new_clean("Error during flock")),
+            }
+        }
+    }
+    fn lock_shared(&self, blocking: bool) -> CortexResult<bool> {
+        let mut local = self.local();
+        while local.writer {
+            if !blocking {
+                return Ok(false);
+            }
+            local = self
+                .released
+                .wait(local)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        if local.readers == 0 {
+            let operation = if blocking {
+                libc::LOCK_SH
+            } else {
+                libc::LOCK_SH | libc::LOCK_NB
+            };
+            if !self.flock(operation)? {
+                return Ok(false);
+            }
+        }
+        local.readers += 1;
+        Ok(true)
+    }
+    fn lock_exclusive(&self, blocking: bool) -> CortexResult<bool> {
+        let mut local = self.local();
+        while local.writer || local.readers > 0 {
+            if !blocking {
+                return Ok(false);
+            }
+            local = self
+                .released
+                .wait(local)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        let operation = if blocking {
+            libc::LOCK_EX
+        } else {
+            libc::LOCK_EX | libc::LOCK_NB
+        };
+        if !self.flock(operation)? {
+            return Ok(false);
+        }
+        local.writer = true;
+        Ok(true)
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        crate::trace::trace!("Dropping file lock: {:?}", self.path);
+
+        if unsafe { libc::close(self.fd) } == -1 {
+            crate::trace::error!("Error during close of lock file");
+        }
+        if !self.is_owner || !cleanup::released(self.registration) {
+            return;
+        }
+        if unsafe { libc::unlink(self.path.as_ptr()) } == -1 {
+            crate::trace::error!("Error during unlink of lock file");
+        }
+    }
+}
+
+impl CortexSync for FileLock {
+    type Settings = FileLockSettings;
+
+    fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let path = get_path(settings, cortex_key)?;
+        Self::open(path, libc::O_CREAT | libc::O_EXCL, true)
+    }
+    fn attach(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let path = get_path(settings, cortex_key)?;
+        Self::open(path, 0, false)
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        Self::open(self.path.clone(), 0, false)
+    }
+    fn force_ownership(&mut self) {
+        if !self.is_owner {
+            self.registration = cleanup::owned(|| OwnedResource::File(self.path.clone()));
+        }
+        self.is_owner = true
+    }
+    fn relinquish_ownership(&mut self) {
+        if self.is_owner {
+            cleanup::released(self.registration);
+            self.registration = Registration::default();
+        }
+        self.is_owner = false
+    }
+    fn after_fork_child(&mut self) -> CortexResult<()> {
+        self.relinquish_ownership();
+        // The lock belongs to the open file description, which is shared with the parent, and the
+        // local state might show a lock held by one of its threads
+        *self = Self::open(self.path.clone(), 0, false)?;
+        Ok(())
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.lock_shared(true).map(|_| ())
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.lock_exclusive(true).map(|_| ())
+    }
+    fn release(&self) -> CortexResult<()> {
+        let mut local = self.local();
+        if local.writer {
+            local.writer = false;
+        } else {
+            local.readers = local.readers.saturating_sub(1);
+        }
+        if local.readers == 0 {
+            self.flock(libc::LOCK_UN)?;
+        }
+        self.released.notify_all();
+        Ok(())
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        self.lock_shared(false)
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        self.lock_exclusive(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file_lock::FileLock;
+    use crate::{Cortex, CortexSync};
+    use std::thread;
+
+    #[test]
+    fn mutual_exclusion() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, FileLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached: Cortex<u64, FileLock> = Cortex::attach(key).unwrap();
+                    for _ in 0..200 {
+                        attached.with_write(|value| *value += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(cortex.read().unwrap(), 800);
+    }
+
+    #[test]
+    fn shared_readers_exclusive_writer() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, FileLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        let attached: Cortex<u64, FileLock> = Cortex::attach(key).unwrap();
+
+        cortex.lock.read_lock().unwrap();
+        assert_eq!(attached.try_read().unwrap(), Some(0));
+        assert!(!attached.try_write(1).unwrap());
+        cortex.lock.release_read().unwrap();
+        assert!(attached.try_write(1).unwrap());
+    }
+}
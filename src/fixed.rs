@@ -0,0 +1,291 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    CortexResult,
+};
+use std::{
+    fmt,
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+};
+
+fn capacity_error(len: usize, capacity: usize) -> CortexError {
+    CortexError::new_logic(
+        ErrorKind::InvalidInput,
+        format!("{} elements exceed the fixed capacity of {}", len, capacity),
+    )
+}
+
+/// String of up to `N` bytes stored inline, for use inside shared memory.
+///
+/// Unlike `String` it holds no pointers into the heap of the process that created it, so it can
+/// be read by any process attached to the same segment.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ShmString<const N: usize> {
+    len: usize,
+    bytes: [u8; N],
+}
+
+impl<const N: usize> ShmString<N> {
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            bytes: [0; N],
+        }
+    }
+    /// Append `string`, fails without modifying anything if it doesn't fit
+    pub fn push_str(&mut self, string: &str) -> CortexResult<()> {
+        let start = self.len();
+        let len = start + string.len();
+        if len > N {
+            return Err(capacity_error(len, N));
+        }
+        self.bytes[start..len].copy_from_slice(string.as_bytes());
+        self.len = len;
+        Ok(())
+    }
+    pub fn as_str(&self) -> &str {
+        // Another process might have left anything behind, so never trust the contents blindly
+        let bytes = &self.bytes[..self.len.min(N)];
+        match std::str::from_utf8(bytes) {
+            Ok(string) => string,
+            Err(err) => std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap_or_default(),
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.len.min(N)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> TryFrom<&str> for ShmString<N> {
+    type Error = CortexError;
+
+    fn try_from(string: &str) -> CortexResult<Self> {
+        let mut shm_string = Self::new();
+        shm_string.push_str(string)?;
+        Ok(shm_string)
+    }
+}
+
+impl<const N: usize> Default for ShmString<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for ShmString<N> {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl<const N: usize> PartialEq for ShmString<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl<const N: usize> Eq for ShmString<N> {}
+
+impl<const N: usize> PartialEq<str> for ShmString<N> {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl<const N: usize> PartialEq<&str> for ShmString<N> {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl<const N: usize> std::hash::Hash for ShmString<N> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl<const N: usize> fmt::Debug for ShmString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> fmt::Display for ShmString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+/// Vector of up to `N` elements stored inline, for use inside shared memory.
+///
+/// Unlike `Vec` it holds no pointers into the heap of the process that created it, so it can be
+/// read by any process attached to the same segment. Elements are never dropped, so they have to
+/// be `Copy`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ShmVec<T: Copy, const N: usize> {
+    len: usize,
+    items: [MaybeUninit<T>; N],
+}
+
+impl<T: Copy, const N: usize> ShmVec<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            len: 0,
+            items: [MaybeUninit::uninit(); N],
+        }
+    }
+    /// Append an element, fails if the vector is full
+    pub fn push(&mut self, value: T) -> CortexResult<()> {
+        let len = self.len();
+        if len == N {
+            return Err(capacity_error(N + 1, N));
+        }
+        self.items[len].write(value);
+        self.len += 1;
+        Ok(())
+    }
+    /// Append all elements of `values`, fails without modifying anything if they don't fit
+    pub fn extend_from_slice(&mut self, values: &[T]) -> CortexResult<()> {
+        let start = self.len();
+        let len = start + values.len();
+        if len > N {
+            return Err(capacity_error(len, N));
+        }
+        for (item, value) in self.items[start..len].iter_mut().zip(values) {
+            item.write(*value);
+        }
+        self.len = len;
+        Ok(())
+    }
+    /// Remove the last element and return it, `None` if the vector is empty
+    pub fn pop(&mut self) -> Option<T> {
+        let len = self.len().checked_sub(1)?;
+        self.len = len;
+        Some(unsafe { self.items[len].assume_init() })
+    }
+    pub fn as_slice(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.items.as_ptr() as *const T, self.len()) }
+    }
+    pub fn as_mut_slice(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.items.as_mut_ptr() as *mut T, self.len()) }
+    }
+    pub fn len(&self) -> usize {
+        // Another process might have left anything behind, so never trust the length blindly
+        self.len.min(N)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl<T: Copy, const N: usize> TryFrom<&[T]> for ShmVec<T, N> {
+    type Error = CortexError;
+
+    fn try_from(values: &[T]) -> CortexResult<Self> {
+        let mut vec = Self::new();
+        vec.extend_from_slice(values)?;
+        Ok(vec)
+    }
+}
+
+impl<T: Copy, const N: usize> Default for ShmVec<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy, const N: usize> Deref for ShmVec<T, N> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T: Copy, const N: usize> DerefMut for ShmVec<T, N> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+impl<T: Copy + PartialEq, const N: usize> PartialEq for ShmVec<T, N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl<T: Copy + Eq, const N: usize> Eq for ShmVec<T, N> {}
+
+impl<T: Copy + fmt::Debug, const N: usize> fmt::Debug for ShmVec<T, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixed::{ShmString, ShmVec};
+    use crate::{Cortex, ErrorKind, NoLock};
+
+    #[derive(Clone, Copy)]
+    struct Session {
+        user: ShmString<16>,
+        scores: ShmVec<u32, 4>,
+    }
+
+    #[test]
+    fn shared_between_instances() {
+        let key = rand::random::<i32>().abs();
+        let session = Session {
+            user: "jacob".try_into().unwrap(),
+            scores: [1, 2].as_slice().try_into().unwrap(),
+        };
+        let cortex: Cortex<_, NoLock> =
+            unsafe { Cortex::new_unsynchronized(Some(key), session) }.unwrap();
+        cortex
+            .with_write(|session| session.scores.push(3).unwrap())
+            .unwrap();
+
+        let attached: Cortex<Session, NoLock> =
+            unsafe { Cortex::attach_unsynchronized(key) }.unwrap();
+        let session = attached.read().unwrap();
+        assert_eq!(session.user, "jacob");
+        assert_eq!(*session.scores, [1, 2, 3]);
+    }
+
+    #[test]
+    fn fixed_capacity() {
+        let mut string = ShmString::<4>::try_from("abc").unwrap();
+        let err = string.push_str("de").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(string, "abc");
+        assert!(ShmString::<2>::try_from("åå").is_err());
+
+        let mut vec = ShmVec::<u8, 2>::new();
+        vec.push(1).unwrap();
+        vec.push(2).unwrap();
+        assert!(vec.push(3).is_err());
+        assert_eq!(vec.pop(), Some(2));
+        assert!(vec.extend_from_slice(&[3, 4]).is_err());
+        assert_eq!(*vec, [1]);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::{Cortex, CortexResult, CortexSync};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+/// Read access that can be atomically upgraded to write access, see `Cortex::read_upgradable`.
+/// The lock is released when dropped.
+pub struct UpgradableReadGuard<'a, T, L: CortexSync> {
+    cortex: &'a Cortex<T, L>,
+    /// Locks are released by the thread that acquired them, e.g. for `ReentrantLock`
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T, L: CortexSync> UpgradableReadGuard<'a, T, L> {
+    /// Wrap an upgradable read lock that is already held
+    pub(crate) fn new(cortex: &'a Cortex<T, L>) -> CortexResult<Self> {
+        if let Err(err) = cortex.check_data() {
+            cortex.release_upgradable()?;
+            return Err(err);
+        }
+        Ok(Self {
+            cortex,
+            _not_send: PhantomData,
+        })
+    }
+    /// Upgrade to write access, blocking until all other readers are gone. No other writer can
+    /// get in between, so anything observed through this guard still holds afterwards.
+    pub fn upgrade(self) -> CortexResult<WriteGuard<'a, T, L>> {
+        let cortex = self.cortex;
+        // Ownership of the lock moves on to the write guard
+        std::mem::forget(self);
+        if let Err(err) = cortex.lock.upgrade() {
+            cortex.release_upgradable()?;
+            return Err(err);
+        }
+        cortex.mark_write_holder();
+        if let Err(err) = cortex.check_writable() {
+            cortex.release_write()?;
+            return Err(err);
+        }
+        WriteGuard::new(cortex)
+    }
+}
+
+impl<T, L: CortexSync> Deref for UpgradableReadGuard<'_, T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.cortex.ptr }
+    }
+}
+
+impl<T, L: CortexSync> Drop for UpgradableReadGuard<'_, T, L> {
+    fn drop(&mut self) {
+        if let Err(err) = self.cortex.release_upgradable() {
+            crate::trace::error!("Error releasing upgradable read lock in Drop: {}", err);
+        }
+    }
+}
+
+/// Exclusive write access to the data of a `Cortex`. The lock is released when dropped.
+///
+/// If the guard is dropped while panicking, the data is marked as poisoned since the write might
+/// not have completed.
+pub struct WriteGuard<'a, T, L: CortexSync> {
+    cortex: &'a Cortex<T, L>,
+    /// Locks are released by the thread that acquired them, e.g. for `ReentrantLock`
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a, T, L: CortexSync> WriteGuard<'a, T, L> {
+    /// Wrap a write lock that is already held
+    pub(crate) fn new(cortex: &'a Cortex<T, L>) -> CortexResult<Self> {
+        if let Err(err) = cortex.check_data() {
+            cortex.release_write()?;
+            return Err(err);
+        }
+        cortex.begin_write();
+        Ok(Self {
+            cortex,
+            _not_send: PhantomData,
+        })
+    }
+}
+
+impl<T, L: CortexSync> Deref for WriteGuard<'_, T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.cortex.ptr }
+    }
+}
+
+impl<T, L: CortexSync> DerefMut for WriteGuard<'_, T, L> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cortex.ptr }
+    }
+}
+
+impl<T, L: CortexSync> Drop for WriteGuard<'_, T, L> {
+    fn drop(&mut self) {
+        // Leave the generation odd when panicking, which poisons the data
+        if !std::thread::panicking() {
+            self.cortex.end_write();
+        }
+        if let Err(err) = self.cortex.release_write() {
+            crate::trace::error!("Error releasing write lock in Drop: {}", err);
+        }
+    }
+}
+
+/// Shared read access to the data of a `Cortex`, holding an `Arc` of it instead of borrowing it,
+/// see `Cortex::read_owned`. The lock is released when dropped. Unlike the borrowing guards it
+/// can be moved to another thread, unless the lock is `!Sync` like `ReentrantLock`.
+pub struct OwnedReadGuard<T, L: CortexSync> {
+    cortex: Arc<Cortex<T, L>>,
+}
+
+impl<T, L: CortexSync> OwnedReadGuard<T, L> {
+    /// Wrap a read lock that is already held
+    pub(crate) fn new(cortex: Arc<Cortex<T, L>>) -> CortexResult<Self> {
+        if let Err(err) = cortex.check_data() {
+            cortex.release_read()?;
+            return Err(err);
+        }
+        Ok(Self { cortex })
+    }
+}
+
+impl<T, L: CortexSync> Deref for OwnedReadGuard<T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.cortex.ptr }
+    }
+}
+
+impl<T, L: CortexSync> Drop for OwnedReadGuard<T, L> {
+    fn drop(&mut self) {
+        if let Err(err) = self.cortex.release_read() {
+            crate::trace::error!("Error releasing read lock in Drop: {}", err);
+        }
+    }
+}
+
+/// Exclusive write access to the data of a `Cortex`, holding an `Arc` of it instead of borrowing
+/// it, see `Cortex::write_owned`. The lock is released when dropped. Unlike the borrowing guards
+/// it can be moved to another thread, unless the lock is `!Sync` like `ReentrantLock`.
+///
+/// If the guard is dropped while panicking, the data is marked as poisoned since the write might
+/// not have completed.
+pub struct OwnedWriteGuard<T, L: CortexSync> {
+    cortex: Arc<Cortex<T, L>>,
+}
+
+impl<T, L: CortexSync> OwnedWriteGuard<T, L> {
+    /// Wrap a write lock that is already held
+    pub(crate) fn new(cortex: Arc<Cortex<T, L>>) -> CortexResult<Self> {
+        if let Err(err) = cortex.check_data() {
+            cortex.release_write()?;
+            return Err(err);
+        }
+        cortex.begin_write();
+        Ok(Self { cortex })
+    }
+}
+
+impl<T, L: CortexSync> Deref for OwnedWriteGuard<T, L> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.cortex.ptr }
+    }
+}
+
+impl<T, L: CortexSync> DerefMut for OwnedWriteGuard<T, L> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cortex.ptr }
+    }
+}
+
+impl<T, L: CortexSync> Drop for OwnedWriteGuard<T, L> {
+    fn drop(&mut self) {
+        // Leave the generation odd when panicking, which poisons the data
+        if !std::thread::panicking() {
+            self.cortex.end_write();
+        }
+        if let Err(err) = self.cortex.release_write() {
+            crate::trace::error!("Error releasing write lock in Drop: {}", err);
+        }
+    }
+}
+
+/// Kind of lock held by a `HeldLock`
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Access {
+    Read,
+    Write,
+}
+
+/// Lock held by a `Cortex` for the duration of a single operation. Released when dropped, so that
+/// a panic or early return can't leave the lock acquired.
+pub(crate) struct HeldLock<'a, T, L: CortexSync> {
+    cortex: &'a Cortex<T, L>,
+    access: Access,
+}
+
+impl<'a, T, L: CortexSync> HeldLock<'a, T, L> {
+    /// Wrap a lock that is already held
+    pub(crate) fn new(cortex: &'a Cortex<T, L>, access: Access) -> Self {
+        Self { cortex, access }
+    }
+    /// Release the lock, returning any error instead of only logging it like `Drop` does
+    pub(crate) fn release(self) -> CortexResult<()> {
+        let result = self.release_access();
+        std::mem::forget(self);
+        result
+    }
+    fn release_access(&self) -> CortexResult<()> {
+        match self.access {
+            Access::Read => self.cortex.release_read(),
+            Access::Write => self.cortex.release_write(),
+        }
+    }
+}
+
+impl<T, L: CortexSync> Drop for HeldLock<'_, T, L> {
+    fn drop(&mut self) {
+        if let Err(err) = self.release_access() {
+            crate::trace::error!("Error releasing lock in Drop: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, CortexError, SpinLock};
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn release_on_panic() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            cortex.with_read(|_| panic!("Interrupted read")).unwrap();
+        }));
+        assert!(result.is_err());
+        // Reads don't poison the data
+        assert_eq!(cortex.with_read(|value| *value + 1).unwrap(), 1);
+
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            cortex.with_write(|_| panic!("Interrupted write")).unwrap();
+        }));
+        assert!(result.is_err());
+        // Would spin forever if the lock was still held
+        assert!(matches!(cortex.read(), Err(CortexError::Poisoned)));
+        assert!(cortex.clear_poison().unwrap());
+
+        let mut guard = cortex.read_upgradable().unwrap().upgrade().unwrap();
+        let result = catch_unwind(AssertUnwindSafe(move || {
+            *guard = 1;
+            panic!("Interrupted write");
+        }));
+        assert!(result.is_err());
+        assert!(matches!(cortex.read(), Err(CortexError::Poisoned)));
+    }
+
+    #[test]
+    fn owned_guards_move_across_threads() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Arc<Cortex<u64, SpinLock>> =
+            Arc::new(Cortex::new(Some(key), 0, false, None).unwrap());
+
+        let mut guard = cortex.write_owned().unwrap();
+        let writer = thread::spawn(move || {
+            *guard += 1;
+        });
+        writer.join().expect("Thread panicked");
+
+        let guard = cortex.read_owned().unwrap();
+        let reader = thread::spawn(move || *guard);
+        assert_eq!(reader.join().expect("Thread panicked"), 1);
+        // Both guards released the lock when dropped
+        assert_eq!(cortex.read().unwrap(), 1);
+    }
+}
@@ -0,0 +1,347 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    CortexResult,
+};
+use std::mem::{align_of, offset_of, size_of};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
+
+/// Stored at offset 0 of every segment, the bytes spell `NCTX` on little-endian hosts
+pub const LAYOUT_MAGIC: u32 = 0x5854_434E;
+/// Version of the layout of the header in front of the data of every `Cortex` segment, bumped
+/// whenever the meaning or position of a header field changes.
+///
+/// The layout is stable within a `LAYOUT_VERSION`, so that peers written in other languages can
+/// find the payload without knowing how Rust lays out types. All fields are `#[repr(C)]`, naturally
+/// aligned and ordered so that the compiler never inserts padding, with any padding spelled out as
+/// reserved fields that are always zero. Integers are stored in the byte order of the host, since
+/// segments never leave the host and the fields have to be usable with native atomics. A peer on
+/// the wrong byte order sees `LAYOUT_MAGIC` byte-swapped.
+///
+/// | Offset | Size | Field            |
+/// |--------|------|------------------|
+/// | 0      | 4    | `magic`          |
+/// | 4      | 4    | `version`        |
+/// | 8      | 4    | `header_size`    |
+/// | 12     | 4    | `data_offset`    |
+/// | 16     | 8    | `data_size`      |
+/// | 24     | 8    | `heartbeat`      |
+/// | 32     | 4    | `owner_pid`      |
+/// | 36     | 4    | `ref_count`      |
+/// | 40     | 4    | `flags`          |
+/// | 44     | 4    | `checksum`       |
+/// | 48     | 8    | `generation`     |
+/// | 56     | 8    | `lock_holder`    |
+/// | 64     | 8    | `lock_acquired`  |
+/// | 72     | 8    | `last_write`     |
+/// | 80     | 8    | `created_at`     |
+/// | 88     | 4    | `schema_version` |
+/// | 92     | 4    | `type_hash`      |
+///
+/// The payload starts at `data_offset`, which is the header size rounded up to the alignment of
+/// the stored type, and is `data_size` bytes long. `checksum` is the CRC32 (IEEE) of the payload,
+/// only maintained if `flags & 2` is set. Once `flags & 4` is set the payload is sealed and must
+/// not be written anymore. `schema_version` is the version of the payload chosen by the
+/// application, see `CortexBuilder::schema_version`. `type_hash` is a fingerprint of the Rust type
+/// of the payload, see `type_hash`, and zero if the creator didn't record one, in which case only
+/// the size of the payload is checked.
+///
+/// `magic` is zero until the creator has written the rest of the header and the initial data, and
+/// is stored last with release ordering, so peers should load it with acquire ordering and treat
+/// zero as not created yet.
+///
+/// Fields are only ever added at the end, so peers should find the payload through `data_offset`
+/// rather than assuming `header_size`.
+pub const LAYOUT_VERSION: u32 = 1;
+
+/// The segment is removed once the last attached `Cortex` is dropped
+pub(crate) const FLAG_REF_COUNTED: u32 = 1;
+/// `checksum` is updated on every write and verified on every read
+pub(crate) const FLAG_CHECKSUM: u32 = 2;
+/// The payload is immutable, see `Cortex::seal`
+pub(crate) const FLAG_SEALED: u32 = 4;
+
+/// Bookkeeping stored at the start of every segment, in front of the user data
+#[repr(C)]
+pub(crate) struct Header {
+    /// Zero until the creator has written the header and the initial data, see `publish`
+    pub(crate) magic: AtomicU32,
+    pub(crate) version: u32,
+    pub(crate) header_size: u32,
+    pub(crate) data_offset: u32,
+    pub(crate) data_size: u64,
+    /// Last heartbeat written by the owner, in milliseconds since the unix epoch. Zero means that
+    /// no heartbeat has been written yet.
+    pub(crate) heartbeat: AtomicU64,
+    /// Pid of the process responsible for cleaning up the segment. Zero means that ownership has
+    /// been given up and is waiting to be adopted.
+    pub(crate) owner_pid: AtomicI32,
+    /// Number of attached instances, only maintained for ref counted segments
+    pub(crate) ref_count: AtomicU32,
+    pub(crate) flags: AtomicU32,
+    /// CRC32 of the payload as of the last write, see `FLAG_CHECKSUM`
+    pub(crate) checksum: AtomicU32,
+    /// Incremented before and after every write, meaning an odd value signals that a write is in
+    /// progress or was interrupted
+    pub(crate) generation: AtomicU64,
+    /// Pid and thread id of the last thread to acquire the lock, packed into one value. Zero while
+    /// the lock is not held.
+    pub(crate) lock_holder: AtomicU64,
+    /// When the lock was acquired by `lock_holder`, in milliseconds since the unix epoch
+    pub(crate) lock_acquired: AtomicU64,
+    /// When the last write completed, in nanoseconds since the unix epoch
+    pub(crate) last_write: AtomicU64,
+    /// When the segment was created, in nanoseconds since the unix epoch
+    pub(crate) created_at: u64,
+    /// Application defined version of the payload, raised by migrations
+    pub(crate) schema_version: AtomicU32,
+    /// Fingerprint of the stored type, see `type_hash`. Zero if unknown.
+    pub(crate) type_hash: u32,
+}
+
+const _: () = {
+    assert!(offset_of!(Header, magic) == 0);
+    assert!(offset_of!(Header, version) == 4);
+    assert!(offset_of!(Header, header_size) == 8);
+    assert!(offset_of!(Header, data_offset) == 12);
+    assert!(offset_of!(Header, data_size) == 16);
+    assert!(offset_of!(Header, heartbeat) == 24);
+    assert!(offset_of!(Header, owner_pid) == 32);
+    assert!(offset_of!(Header, ref_count) == 36);
+    assert!(offset_of!(Header, flags) == 40);
+    assert!(offset_of!(Header, checksum) == 44);
+    assert!(offset_of!(Header, generation) == 48);
+    assert!(offset_of!(Header, lock_holder) == 56);
+    assert!(offset_of!(Header, lock_acquired) == 64);
+    assert!(offset_of!(Header, last_write) == 72);
+    assert!(offset_of!(Header, created_at) == 80);
+    assert!(offset_of!(Header, schema_version) == 88);
+    assert!(offset_of!(Header, type_hash) == 92);
+    assert!(size_of::<Header>() == 96);
+    assert!(align_of::<Header>() == 8);
+};
+
+impl Header {
+    pub(crate) fn new<T>(owner_pid: i32, flags: u32, schema_version: u32) -> Self {
+        let now = unix_nanos();
+        Self {
+            magic: AtomicU32::new(0),
+            version: LAYOUT_VERSION,
+            header_size: size_of::<Header>() as u32,
+            data_offset: data_offset::<T>() as u32,
+            data_size: size_of::<T>() as u64,
+            heartbeat: AtomicU64::new(0),
+            owner_pid: AtomicI32::new(owner_pid),
+            ref_count: AtomicU32::new(1),
+            flags: AtomicU32::new(flags),
+            checksum: AtomicU32::new(0),
+            generation: AtomicU64::new(0),
+            lock_holder: AtomicU64::new(0),
+            lock_acquired: AtomicU64::new(0),
+            last_write: AtomicU64::new(now),
+            created_at: now,
+            schema_version: AtomicU32::new(schema_version),
+            type_hash: type_hash::<T>(),
+        }
+    }
+    /// Make the segment available to attaching processes, once the header and the initial data are
+    /// written
+    pub(crate) fn publish(&self) {
+        self.magic.store(LAYOUT_MAGIC, Ordering::Release);
+    }
+    /// Check that the segment was laid out by a compatible version of the crate, and for a `T`
+    pub(crate) fn validate<T>(&self, key: i32) -> CortexResult<()> {
+        // Pairs with `publish`, making the initial data visible
+        let magic = self.magic.load(Ordering::Acquire);
+        if magic == 0 {
+            // Created, but the creator hasn't written the header yet
+            return Err(CortexError::new_logic(
+                ErrorKind::NotFound,
+                format!("Segment with key: {} is not initialized yet", key),
+            ));
+        }
+        if magic != LAYOUT_MAGIC || self.version != LAYOUT_VERSION {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                "Segment with key: {} has magic {:#x} and layout version {}, expected {:#x} and {}",
+                key, magic, self.version, LAYOUT_MAGIC, LAYOUT_VERSION
+            ),
+            ));
+        }
+        if self.data_offset as usize != data_offset::<T>()
+            || self.data_size != size_of::<T>() as u64
+        {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                "Segment with key: {} holds {} bytes at offset {}, but {} bytes at offset {} were \
+                 expected",
+                key,
+                self.data_size,
+                self.data_offset,
+                size_of::<T>(),
+                data_offset::<T>()
+            ),
+            ));
+        }
+        if self.type_hash != 0 && self.type_hash != type_hash::<T>() {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Segment with key: {} holds a type of the same size other than {}",
+                    key,
+                    std::any::type_name::<T>()
+                ),
+            ));
+        }
+        Ok(())
+    }
+    /// Store the checksum of `payload` as it is now, if checksums are enabled
+    pub(crate) fn update_checksum(&self, payload: &[u8]) {
+        if self.has_flag(FLAG_CHECKSUM) {
+            self.checksum
+                .store(crc32fast::hash(payload), Ordering::Release);
+        }
+    }
+    /// Check `payload` against the checksum of the last write, if checksums are enabled
+    pub(crate) fn verify_checksum(&self, payload: &[u8]) -> CortexResult<()> {
+        if self.has_flag(FLAG_CHECKSUM)
+            && crc32fast::hash(payload) != self.checksum.load(Ordering::Acquire)
+        {
+            return Err(CortexError::Corrupted);
+        }
+        Ok(())
+    }
+    pub(crate) fn has_flag(&self, flag: u32) -> bool {
+        self.flags.load(Ordering::Acquire) & flag != 0
+    }
+    pub(crate) fn set_flag(&self, flag: u32) {
+        self.flags.fetch_or(flag, Ordering::AcqRel);
+    }
+    /// Mark the start of a write, leaving the generation odd until `end_write`
+    pub(crate) fn begin_write(&self) {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+    /// Mark the end of a write of `payload` and record when it happened. `payload` is `None` if
+    /// the bytes of the data can't be read, in which case the segment keeps no checksum.
+    pub(crate) fn end_write(&self, payload: Option<&[u8]>) {
+        if let Some(payload) = payload {
+            self.update_checksum(payload);
+        }
+        self.last_write.store(unix_nanos(), Ordering::Relaxed);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+fn unix_nanos() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Offset of the user data from the start of the segment, respecting the alignment of `T`
+pub(crate) const fn data_offset<T>() -> usize {
+    let header = size_of::<Header>();
+    let align = align_of::<T>();
+    header.div_ceil(align) * align
+}
+
+/// Fingerprint of the name and layout of `T`, so that attaching with another type of the same size
+/// fails instead of reinterpreting the payload. Type names are only the same for processes built
+/// from the same definition of the type. Never zero, which marks segments without a fingerprint.
+pub(crate) fn type_hash<T>() -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(std::any::type_name::<T>().as_bytes());
+    hasher.update(&(size_of::<T>() as u64).to_ne_bytes());
+    hasher.update(&(align_of::<T>() as u64).to_ne_bytes());
+    hasher.finalize().max(1)
+}
+
+/// Total number of bytes to allocate for a segment holding a `T`
+pub(crate) const fn segment_size<T>() -> usize {
+    data_offset::<T>() + size_of::<T>()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        Cortex, ErrorKind, MemBackend, NoLock, RawCortex, SpinLock, LAYOUT_MAGIC, LAYOUT_VERSION,
+    };
+
+    #[test]
+    fn stable_layout() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, NoLock> =
+            unsafe { Cortex::new_unsynchronized(Some(key), 7u16) }.unwrap();
+
+        // Read the header the way a peer in another language would
+        let bytes = unsafe { std::slice::from_raw_parts(cortex.header as *const u8, 98) };
+        let u32_at =
+            |offset: usize| u32::from_ne_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        assert_eq!(u32_at(0), LAYOUT_MAGIC);
+        assert_eq!(u32_at(4), LAYOUT_VERSION);
+        assert_eq!((u32_at(8), u32_at(12)), (96, 96));
+        assert_eq!(u64::from_ne_bytes(bytes[16..24].try_into().unwrap()), 2);
+        assert_eq!(u16::from_ne_bytes(bytes[96..98].try_into().unwrap()), 7);
+
+        let err = unsafe { Cortex::<u32, NoLock>::attach_unsynchronized(key) }
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn type_fingerprint() {
+        let key = rand::random::<i32>().abs();
+        let _cortex: Cortex<_, SpinLock> = Cortex::new(Some(key), 7u32, false, None).unwrap();
+
+        // Same size and alignment, but another type
+        let err = Cortex::<i32, SpinLock>::attach(key).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        let err = Cortex::<f32, SpinLock>::attach(key).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+        assert_eq!(
+            Cortex::<u32, SpinLock>::attach(key)
+                .unwrap()
+                .read()
+                .unwrap(),
+            7
+        );
+    }
+
+    #[test]
+    fn truncated_segment() {
+        // A valid header for a u64, in a segment too short to hold the u64 itself
+        let raw: RawCortex<MemBackend> = RawCortex::new(None, 100, None).unwrap();
+        {
+            let mut bytes = raw.as_mut_slice().unwrap();
+            bytes[0..4].copy_from_slice(&LAYOUT_MAGIC.to_ne_bytes());
+            bytes[4..8].copy_from_slice(&LAYOUT_VERSION.to_ne_bytes());
+            bytes[8..16].copy_from_slice(&[96, 0, 0, 0, 96, 0, 0, 0]);
+            bytes[16..24].copy_from_slice(&8u64.to_ne_bytes());
+        }
+        let err = Cortex::<u64, MemBackend>::attach(raw.key()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn published_after_initial_write() {
+        for _ in 0..200 {
+            let key = rand::random::<i32>().abs();
+            std::thread::scope(|scope| {
+                let attacher = scope.spawn(|| loop {
+                    // Without the lock, anything seen right after attaching was there before
+                    if let Ok(cortex) =
+                        unsafe { Cortex::<[u64; 16384], NoLock>::attach_unsynchronized(key) }
+                    {
+                        return cortex.read().unwrap();
+                    }
+                });
+                let _cortex: Cortex<_, NoLock> =
+                    unsafe { Cortex::new_unsynchronized(Some(key), [7u64; 16384]) }.unwrap();
+                assert!(attacher.join().unwrap().iter().all(|value| *value == 7));
+            });
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    no_lock, Cortex, CortexResult, NoLock,
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Maximum number of bucket boundaries
+const MAX_BOUNDS: usize = 64;
+
+struct HistogramState {
+    len: usize,
+    /// Inclusive upper bounds of the buckets, in increasing order
+    bounds: [u64; MAX_BOUNDS],
+    /// One counter per bound, plus one for values above the last bound
+    counts: [AtomicU64; MAX_BOUNDS + 1],
+    sum: AtomicU64,
+}
+
+/// Point-in-time copy of a `CortexHistogram`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// Inclusive upper bounds of the buckets
+    pub bounds: Vec<u64>,
+    /// Number of recorded values per bucket, with one extra bucket at the end for values above the
+    /// last bound
+    pub counts: Vec<u64>,
+    /// Sum of all recorded values
+    pub sum: u64,
+}
+
+impl HistogramSnapshot {
+    /// Total number of recorded values
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+    pub fn mean(&self) -> Option<f64> {
+        match self.count() {
+            0 => None,
+            count => Some(self.sum as f64 / count as f64),
+        }
+    }
+    /// Upper bound of the bucket containing the given percentile, between 0 and 100, or `None` if
+    /// nothing was recorded. Values above the last bound are reported as the last bound.
+    pub fn percentile(&self, percentile: f64) -> Option<u64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percentile.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, bucket_count) in self.counts.iter().enumerate() {
+            seen += bucket_count;
+            if seen >= rank {
+                return self.bounds.get(bucket).or(self.bounds.last()).copied();
+            }
+        }
+        self.bounds.last().copied()
+    }
+}
+
+/// Process-shared histogram with fixed bucket boundaries, e.g. for latencies in microseconds.
+///
+/// Every bucket is a separate atomic counter, so any number of processes can record values and
+/// read percentiles at the same time without taking a lock.
+pub struct CortexHistogram {
+    cortex: Cortex<HistogramState, NoLock>,
+}
+
+impl CortexHistogram {
+    /// Create a new histogram on `key` with the given inclusive bucket upper bounds, which must be
+    /// strictly increasing. Values above the last bound are counted in an extra bucket.
+    pub fn new(key: i32, bounds: &[u64]) -> CortexResult<Self> {
+        if bounds.is_empty()
+            || bounds.len() > MAX_BOUNDS
+            || bounds.windows(2).any(|pair| pair[0] >= pair[1])
+        {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Histogram bounds must be strictly increasing, with between 1 and {} of them",
+                    MAX_BOUNDS
+                ),
+            ));
+        }
+        let mut state = HistogramState {
+            len: bounds.len(),
+            bounds: [0; MAX_BOUNDS],
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum: AtomicU64::new(0),
+        };
+        state.bounds[..bounds.len()].copy_from_slice(bounds);
+        let cortex = Cortex::new(Some(key), state, false, Some(no_lock::INTERNAL))?;
+        Ok(Self { cortex })
+    }
+    /// Attempt to attach to an already existing histogram
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let cortex = Cortex::attach_with_lock(key, no_lock::INTERNAL)?;
+        Ok(Self { cortex })
+    }
+    fn state(&self) -> &HistogramState {
+        unsafe { &*self.cortex.ptr }
+    }
+    fn bounds(&self) -> &[u64] {
+        let state = self.state();
+        &state.bounds[..state.len.min(MAX_BOUNDS)]
+    }
+    pub fn record(&self, value: u64) {
+        let state = self.state();
+        let bucket = self.bounds().partition_point(|bound| *bound < value);
+        state.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        state.sum.fetch_add(value, Ordering::Relaxed);
+    }
+    /// Copy out the current counts. Values recorded concurrently may or may not be included.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let state = self.state();
+        let bounds = self.bounds().to_vec();
+        let counts = state.counts[..=bounds.len()]
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        HistogramSnapshot {
+            bounds,
+            counts,
+            sum: state.sum.load(Ordering::Relaxed),
+        }
+    }
+    /// Reset all counts to zero, e.g. after a scraper has read them
+    pub fn reset(&self) {
+        let state = self.state();
+        for count in &state.counts {
+            count.store(0, Ordering::Relaxed);
+        }
+        state.sum.store(0, Ordering::Relaxed);
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::histogram::CortexHistogram;
+    use crate::ErrorKind;
+
+    #[test]
+    fn percentiles() {
+        let key = rand::random::<i32>().abs();
+        let histogram = CortexHistogram::new(key, &[10, 100, 1000]).unwrap();
+        let attached = CortexHistogram::attach(key).unwrap();
+
+        for value in 1..=90 {
+            histogram.record(value % 10 + 1);
+        }
+        for _ in 0..9 {
+            attached.record(50);
+        }
+        attached.record(5000);
+
+        let snapshot = attached.snapshot();
+        assert_eq!(snapshot.counts, [90, 9, 0, 1]);
+        assert_eq!(snapshot.count(), 100);
+        assert_eq!(snapshot.percentile(50.0), Some(10));
+        assert_eq!(snapshot.percentile(95.0), Some(100));
+        assert_eq!(snapshot.percentile(100.0), Some(1000));
+
+        histogram.reset();
+        assert_eq!(attached.snapshot().percentile(50.0), None);
+
+        let err = CortexHistogram::new(key, &[5, 5]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,69 @@
+/// Whether to back a segment with huge pages, set through `CortexBuilder::huge_pages`.
+///
+/// Huge pages reduce TLB pressure for large segments, but have to be reserved up front (see
+/// `/proc/sys/vm/nr_hugepages`) and the process needs permission to use them. Only supported on
+/// Linux.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum HugePages {
+    /// Use regular pages
+    #[default]
+    Never,
+    /// Use huge pages if possible, falling back to regular pages with a warning otherwise
+    Preferred,
+    /// Fail with an error if huge pages can't be used
+    Required,
+}
+
+/// Size of a huge page, as reported by the kernel
+#[cfg(target_os = "linux")]
+pub(crate) fn huge_page_size() -> usize {
+    const DEFAULT: usize = 2 * 1024 * 1024;
+    let Ok(meminfo) = std::fs::read_to_string("/proc/meminfo") else {
+        return DEFAULT;
+    };
+    meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Hugepagesize:"))
+        .and_then(|size| size.trim().strip_suffix("kB"))
+        .and_then(|size| size.trim().parse::<usize>().ok())
+        .map(|kilobytes| kilobytes * 1024)
+        .unwrap_or(DEFAULT)
+}
+
+/// Flag to pass to `shmget` for huge pages, along with the size rounded up to a whole number of
+/// huge pages. `None` if huge pages aren't supported on this platform.
+pub(crate) fn shm_flags(size: usize) -> Option<(libc::c_int, usize)> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            Some((libc::SHM_HUGETLB, size.next_multiple_of(huge_page_size())))
+        } else {
+            let _ = size;
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::huge_pages::HugePages;
+    use crate::{CortexBuilder, SpinLock};
+
+    #[test]
+    fn preferred_falls_back() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new([7u8; 4096])
+            .key(key)
+            .huge_pages(HugePages::Preferred)
+            .with_default_lock::<SpinLock>()
+            .unwrap();
+        assert_eq!(cortex.read().unwrap()[4095], 7);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn rounded_to_huge_pages() {
+        let (flag, size) = crate::huge_pages::shm_flags(1).unwrap();
+        assert_eq!(flag, libc::SHM_HUGETLB);
+        assert_eq!(size, crate::huge_pages::huge_page_size());
+    }
+}
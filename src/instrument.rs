@@ -0,0 +1,96 @@
+//! Metrics emitted through the `metrics` facade with the `metrics` feature, labeled with the key
+//! of the segment. Everything here compiles to nothing without the feature.
+
+use crate::{guard::Access, CortexError, CortexResult};
+use std::time::Duration;
+
+/// Whether anything is recorded, to skip measuring what would be thrown away
+pub(crate) const ENABLED: bool = cfg!(feature = "metrics");
+
+/// Record the outcome of waiting `wait` for a lock
+#[cfg(feature = "metrics")]
+pub(crate) fn lock_acquired(key: i32, wait: Duration, acquired: &CortexResult<bool>) {
+    match acquired {
+        Ok(true) => {
+            metrics::histogram!("neocortex_lock_wait_seconds", "key" => key.to_string())
+                .record(wait.as_secs_f64());
+        }
+        Ok(false) => {
+            metrics::counter!("neocortex_lock_timeouts_total", "key" => key.to_string())
+                .increment(1);
+        }
+        Err(err) => error(key, err),
+    }
+}
+
+/// Count an access to the data, once the lock for it is held
+#[cfg(feature = "metrics")]
+pub(crate) fn access(key: i32, access: Access) {
+    let name = match access {
+        Access::Read => "neocortex_reads_total",
+        Access::Write => "neocortex_writes_total",
+    };
+    metrics::counter!(name, "key" => key.to_string()).increment(1);
+}
+
+#[cfg(feature = "metrics")]
+pub(crate) fn error(key: i32, err: &CortexError) {
+    metrics::counter!(
+        "neocortex_errors_total",
+        "key" => key.to_string(),
+        "kind" => format!("{:?}", err.kind())
+    )
+    .increment(1);
+}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn lock_acquired(_key: i32, _wait: Duration, _acquired: &CortexResult<bool>) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn access(_key: i32, _access: Access) {}
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) fn error(_key: i32, _err: &CortexError) {}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use crate::{Cortex, SpinLock};
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn reads_and_writes() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let key = rand::random::<i32>().abs();
+        metrics::with_local_recorder(&recorder, || {
+            let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+            cortex.write(1).unwrap();
+            cortex.write(2).unwrap();
+            assert_eq!(cortex.read().unwrap(), 2);
+        });
+
+        let label = key.to_string();
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |name: &str| {
+            snapshot
+                .iter()
+                .find(|(composite, ..)| {
+                    let key = composite.key();
+                    key.name() == name && key.labels().any(|l| l.value() == label)
+                })
+                .map(|(.., value)| value)
+        };
+        assert_eq!(
+            value("neocortex_writes_total"),
+            Some(&DebugValue::Counter(2))
+        );
+        assert_eq!(
+            value("neocortex_reads_total"),
+            Some(&DebugValue::Counter(1))
+        );
+        assert!(matches!(
+            value("neocortex_lock_wait_seconds"),
+            Some(DebugValue::Histogram(waits)) if waits.len() == 3
+        ));
+    }
+}
@@ -0,0 +1,264 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    process_alive, unix_millis, Cortex, CortexResult, CortexSync,
+};
+use std::mem::MaybeUninit;
+use std::time::Duration;
+
+const FREE: u8 = 0;
+const PENDING: u8 = 1;
+const IN_FLIGHT: u8 = 2;
+
+/// Identifies a single claim of a job, see `CortexJobQueue::claim`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct JobId {
+    index: u32,
+    /// Sequence number of the job, distinguishes jobs that reuse the same slot
+    seq: u64,
+    /// Claim attempt, so that a claim that timed out can't ack a job claimed again since
+    attempt: u32,
+}
+
+/// Job handed out by `CortexJobQueue::claim`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClaimedJob<T> {
+    pub id: JobId,
+    pub job: T,
+    /// Number of times the job has been claimed, including this one. Anything above 1 means that
+    /// an earlier claimant crashed, timed out or gave the job back.
+    pub attempts: u32,
+}
+
+struct JobSlot<T> {
+    state: u8,
+    seq: u64,
+    claimant_pid: i32,
+    /// Unix millis after which an in-flight job can be claimed again
+    deadline: u64,
+    attempts: u32,
+    job: MaybeUninit<T>,
+}
+
+impl<T: Copy> Clone for JobSlot<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Copy> Copy for JobSlot<T> {}
+
+impl<T> JobSlot<T> {
+    fn claimable(&self, now: u64) -> bool {
+        match self.state {
+            PENDING => true,
+            IN_FLIGHT => now > self.deadline || !process_alive(self.claimant_pid),
+            _ => false,
+        }
+    }
+    fn claimed_by(&self, id: JobId) -> bool {
+        self.state == IN_FLIGHT && self.seq == id.seq && self.attempts == id.attempt
+    }
+}
+
+struct QueueState<T, const N: usize> {
+    slots: [JobSlot<T>; N],
+    next_seq: u64,
+}
+
+impl<T: Copy, const N: usize> QueueState<T, N> {
+    fn claimed(&mut self, id: JobId) -> Option<&mut JobSlot<T>> {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.claimed_by(id))
+    }
+}
+
+/// Process-shared work queue of up to `N` jobs with acknowledgement, protected by `L`.
+///
+/// Consumers `claim` jobs, which marks them in flight with the pid of the claimant and a deadline,
+/// and `ack` them once processed. Jobs whose claimant dies or misses the deadline become
+/// claimable again, so no job is lost to a crashed consumer, although a job can be processed more
+/// than once if a slow consumer misses its deadline. Jobs are handed out in the order they were
+/// pushed. Jobs are copied in and out of shared memory, so they have to be `Copy`.
+pub struct CortexJobQueue<T: Copy, L: CortexSync, const N: usize> {
+    cortex: Cortex<QueueState<T, N>, L>,
+}
+
+impl<T: Copy, L: CortexSync, const N: usize> CortexJobQueue<T, L, N> {
+    /// Create a new empty queue on `key`
+    pub fn new(key: i32) -> CortexResult<Self> {
+        let state = QueueState {
+            slots: [JobSlot {
+                state: FREE,
+                seq: 0,
+                claimant_pid: 0,
+                deadline: 0,
+                attempts: 0,
+                job: MaybeUninit::uninit(),
+            }; N],
+            next_seq: 0,
+        };
+        let cortex = Cortex::new(Some(key), state, false, None)?;
+        Ok(Self { cortex })
+    }
+    /// Attempt to attach to an already existing queue
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let cortex = Cortex::attach(key)?;
+        Ok(Self { cortex })
+    }
+    /// Add a job to the back of the queue. Fails if the queue is full.
+    pub fn push(&self, job: T) -> CortexResult<()> {
+        self.cortex.with_write(|state| {
+            let Some(slot) = state.slots.iter_mut().find(|slot| slot.state == FREE) else {
+                return Err(CortexError::new_logic(
+                    ErrorKind::NoSpace,
+                    format!(
+                        "All {} job slots of queue with key: {} are taken",
+                        N,
+                        self.key()
+                    ),
+                ));
+            };
+            *slot = JobSlot {
+                state: PENDING,
+                seq: state.next_seq,
+                claimant_pid: 0,
+                deadline: 0,
+                attempts: 0,
+                job: MaybeUninit::new(job),
+            };
+            state.next_seq += 1;
+            Ok(())
+        })?
+    }
+    /// Claim the oldest job that is pending, or whose claimant died or missed its deadline. The
+    /// job must be acked within `timeout`, otherwise it is handed out again. Returns `None` if
+    /// there is nothing to claim.
+    pub fn claim(&self, timeout: Duration) -> CortexResult<Option<ClaimedJob<T>>> {
+        let pid = unsafe { libc::getpid() };
+        self.cortex.with_write(|state| {
+            let now = unix_millis();
+            let (index, slot) = state
+                .slots
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, slot)| slot.claimable(now))
+                .min_by_key(|(_, slot)| slot.seq)?;
+            if slot.state == IN_FLIGHT {
+                crate::trace::warning!(
+                    "Reclaiming job {} from process {} after a timeout or crash",
+                    slot.seq,
+                    slot.claimant_pid
+                );
+            }
+            slot.state = IN_FLIGHT;
+            slot.claimant_pid = pid;
+            slot.deadline = now.saturating_add(timeout.as_millis() as u64);
+            slot.attempts += 1;
+            Some(ClaimedJob {
+                id: JobId {
+                    index: index as u32,
+                    seq: slot.seq,
+                    attempt: slot.attempts,
+                },
+                job: unsafe { slot.job.assume_init() },
+                attempts: slot.attempts,
+            })
+        })
+    }
+    /// Acknowledge that a claimed job was processed, removing it from the queue. Returns `false`
+    /// if the claim is no longer valid because it timed out, in which case the job might have been
+    /// handed to someone else.
+    pub fn ack(&self, id: JobId) -> CortexResult<bool> {
+        self.cortex.with_write(|state| match state.claimed(id) {
+            Some(slot) => {
+                slot.state = FREE;
+                true
+            }
+            None => false,
+        })
+    }
+    /// Give a claimed job back without processing it, making it claimable again right away.
+    /// Returns `false` if the claim is no longer valid.
+    pub fn nack(&self, id: JobId) -> CortexResult<bool> {
+        self.cortex.with_write(|state| match state.claimed(id) {
+            Some(slot) => {
+                slot.state = PENDING;
+                true
+            }
+            None => false,
+        })
+    }
+    /// Push back the deadline of a claimed job to `timeout` from now, for jobs that take longer
+    /// than expected. Returns `false` if the claim is no longer valid.
+    pub fn extend(&self, id: JobId, timeout: Duration) -> CortexResult<bool> {
+        self.cortex.with_write(|state| match state.claimed(id) {
+            Some(slot) => {
+                slot.deadline = unix_millis().saturating_add(timeout.as_millis() as u64);
+                true
+            }
+            None => false,
+        })
+    }
+    /// Number of jobs in the queue, both pending and in flight
+    pub fn len(&self) -> CortexResult<usize> {
+        self.cortex
+            .with_read(|state| state.slots.iter().filter(|slot| slot.state != FREE).count())
+    }
+    pub fn is_empty(&self) -> CortexResult<bool> {
+        Ok(self.len()? == 0)
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::job_queue::CortexJobQueue;
+    use crate::{ErrorKind, SpinLock};
+    use std::time::Duration;
+
+    #[test]
+    fn claim_and_ack() {
+        let key = rand::random::<i32>().abs();
+        let queue: CortexJobQueue<u32, SpinLock, 2> = CortexJobQueue::new(key).unwrap();
+        let attached: CortexJobQueue<u32, SpinLock, 2> = CortexJobQueue::attach(key).unwrap();
+
+        queue.push(1).unwrap();
+        queue.push(2).unwrap();
+        assert_eq!(queue.push(3).unwrap_err().kind(), ErrorKind::NoSpace);
+
+        let first = attached.claim(Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!((first.job, first.attempts), (1, 1));
+        let second = attached.claim(Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!(second.job, 2);
+        assert_eq!(attached.claim(Duration::from_secs(60)).unwrap(), None);
+
+        assert!(attached.ack(first.id).unwrap());
+        assert!(!attached.ack(first.id).unwrap());
+        assert!(attached.nack(second.id).unwrap());
+        let again = queue.claim(Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!((again.job, again.attempts), (2, 2));
+        assert!(!queue.ack(second.id).unwrap());
+        assert!(queue.ack(again.id).unwrap());
+        assert!(queue.is_empty().unwrap());
+    }
+
+    #[test]
+    fn reclaim_after_timeout() {
+        let key = rand::random::<i32>().abs();
+        let queue: CortexJobQueue<u32, SpinLock, 4> = CortexJobQueue::new(key).unwrap();
+        queue.push(7).unwrap();
+
+        let stale = queue.claim(Duration::ZERO).unwrap().unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        let reclaimed = queue.claim(Duration::from_secs(60)).unwrap().unwrap();
+        assert_eq!((reclaimed.job, reclaimed.attempts), (7, 2));
+
+        // The timed out claim can no longer ack or extend the job
+        assert!(!queue.ack(stale.id).unwrap());
+        assert!(!queue.extend(stale.id, Duration::from_secs(60)).unwrap());
+        assert!(queue.ack(reclaimed.id).unwrap());
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{process_alive, unix_millis, Cortex, CortexResult, CortexSync, DropPolicy};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// Per-process counter used to tell apart multiple handles living in the same process
+static NEXT_HANDLE: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Debug, Clone, Copy)]
+struct LeaderState {
+    /// Pid of the current leader, 0 if there is none
+    pid: i32,
+    /// Identifies the leading handle within its process
+    token: u64,
+    /// Last heartbeat of the leader in milliseconds since the unix epoch
+    heartbeat: u64,
+    /// Incremented every time leadership changes hands
+    term: u64,
+}
+
+/// Leader election between processes attached to the same key.
+///
+/// Every participant calls `Leader::join` on a well-known key, one of them wins and becomes the
+/// leader while the others become followers. The leader is expected to call `beat` periodically,
+/// and followers call `try_elect` to take over once the leader process has died or its heartbeat
+/// is older than the configured timeout.
+///
+/// The underlying segment is reference counted, so it lives until the last participant leaves,
+/// no matter which one created it.
+#[derive(Debug)]
+pub struct Leader<L: CortexSync> {
+    cortex: Cortex<LeaderState, L>,
+    pid: i32,
+    token: u64,
+    timeout: Duration,
+}
+
+impl<L: CortexSync> Leader<L> {
+    /// Join the election on `key`, becoming leader immediately if there is no live leader.
+    /// `timeout` is the maximum heartbeat age before a leader is considered dead.
+    pub fn join(key: i32, timeout: Duration) -> CortexResult<Self> {
+        let cortex =
+            Cortex::attach_or_create_with(key, DropPolicy::RefCounted, None, || LeaderState {
+                pid: 0,
+                token: 0,
+                heartbeat: 0,
+                term: 0,
+            })?;
+        let pid = unsafe { libc::getpid() };
+        let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+        let leader = Self {
+            cortex,
+            pid,
+            token: ((pid as u64) << 32) | handle as u64,
+            timeout,
+        };
+        leader.try_elect()?;
+        Ok(leader)
+    }
+    /// Attempt to become leader, succeeds if there is no leader or the current one is dead.
+    /// Returns `true` if this handle is the leader afterwards.
+    pub fn try_elect(&self) -> CortexResult<bool> {
+        let timeout = self.timeout.as_millis() as u64;
+        self.cortex.with_write(|state| {
+            if state.token == self.token {
+                return true;
+            }
+            let now = unix_millis();
+            let expired = now.saturating_sub(state.heartbeat) > timeout;
+            if state.pid == 0 || expired || !process_alive(state.pid) {
+                state.pid = self.pid;
+                state.token = self.token;
+                state.heartbeat = now;
+                state.term += 1;
+                return true;
+            }
+            false
+        })
+    }
+    /// Refresh the leader heartbeat. Returns `false` if leadership has been lost in the meantime.
+    pub fn beat(&self) -> CortexResult<bool> {
+        self.cortex.with_write(|state| {
+            if state.token != self.token {
+                return false;
+            }
+            state.heartbeat = unix_millis();
+            true
+        })
+    }
+    /// Give up leadership, letting another participant take over on its next `try_elect`
+    pub fn resign(&self) -> CortexResult<()> {
+        self.cortex.with_write(|state| {
+            if state.token == self.token {
+                state.pid = 0;
+                state.token = 0;
+            }
+        })
+    }
+    pub fn is_leader(&self) -> CortexResult<bool> {
+        Ok(self.cortex.read()?.token == self.token)
+    }
+    /// Pid of the current leader, if any
+    pub fn leader_pid(&self) -> CortexResult<Option<i32>> {
+        let pid = self.cortex.read()?.pid;
+        Ok(if pid == 0 { None } else { Some(pid) })
+    }
+    /// Number of times leadership has changed hands
+    pub fn term(&self) -> CortexResult<u64> {
+        Ok(self.cortex.read()?.term)
+    }
+}
+
+impl<L: CortexSync> Drop for Leader<L> {
+    fn drop(&mut self) {
+        // Resign so that followers don't have to wait for the heartbeat to expire
+        if let Err(err) = self.resign() {
+            crate::trace::error!("Error resigning leadership in Drop: {}", err);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "semaphore"))]
+mod tests {
+    use crate::leader::Leader;
+    use crate::semaphore::Semaphore;
+    use std::time::Duration;
+
+    #[test]
+    fn single_leader() {
+        let key = rand::random::<i32>().abs();
+        let timeout = Duration::from_secs(10);
+        let first: Leader<Semaphore> = Leader::join(key, timeout).unwrap();
+        let second: Leader<Semaphore> = Leader::join(key, timeout).unwrap();
+
+        assert!(first.is_leader().unwrap());
+        assert!(!second.is_leader().unwrap());
+        assert!(!second.try_elect().unwrap());
+        assert!(first.beat().unwrap());
+        assert!(!second.beat().unwrap());
+        assert_eq!(first.leader_pid().unwrap(), Some(unsafe { libc::getpid() }));
+    }
+
+    #[test]
+    fn reelect_after_resign_and_timeout() {
+        let key = rand::random::<i32>().abs();
+        let first: Leader<Semaphore> = Leader::join(key, Duration::from_millis(20)).unwrap();
+        let second: Leader<Semaphore> = Leader::join(key, Duration::from_millis(20)).unwrap();
+
+        first.resign().unwrap();
+        assert!(second.try_elect().unwrap());
+        assert_eq!(second.term().unwrap(), 2);
+
+        // Heartbeat expires
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(first.try_elect().unwrap());
+        assert!(!second.beat().unwrap());
+    }
+
+    #[test]
+    fn group_outlives_first_participant() {
+        let key = rand::random::<i32>().abs();
+        let timeout = Duration::from_secs(10);
+        let first: Leader<Semaphore> = Leader::join(key, timeout).unwrap();
+        let second: Leader<Semaphore> = Leader::join(key, timeout).unwrap();
+        first.resign().unwrap();
+        assert!(second.try_elect().unwrap());
+
+        // The creator leaving doesn't reset the group for later joiners
+        drop(first);
+        let third: Leader<Semaphore> = Leader::join(key, timeout).unwrap();
+        assert!(!third.is_leader().unwrap());
+        assert!(second.is_leader().unwrap());
+        assert_eq!(third.term().unwrap(), 2);
+    }
+}
@@ -1,20 +1,158 @@
+mod arena;
+mod atomic;
+mod barrier;
+mod blob;
 mod builder;
+mod cleanup;
+mod coalesce;
+mod cortex_struct;
+mod counter;
 mod crash;
+mod diagnostics;
+mod dump;
+mod endian;
+mod event;
+mod file_lock;
+mod fixed;
+mod guard;
+mod header;
+mod histogram;
+mod huge_pages;
+mod instrument;
+mod job_queue;
+mod leader;
+mod limits;
+mod log;
+mod map;
+mod mem;
+mod namespace;
+mod no_lock;
+mod once;
+mod ops;
+mod pod;
+mod raw;
+mod rcu;
+mod read_many;
+mod read_only;
+mod reentrant;
+mod retry;
+mod rwlock;
+mod segment;
+mod sequence;
+mod shared_state;
+mod slab;
+mod spin;
+mod stamped;
+mod ticket;
+mod trace;
+mod transaction;
+mod triple_buffer;
+mod vec;
+mod versioned;
+mod watcher;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "semaphore")] {
         mod semaphore;
-        pub use semaphore::{Semaphore, SemaphorePermission, SemaphoreSettings};
+        pub use semaphore::{Semaphore, SemaphorePermission, SemaphorePermit, SemaphoreSettings};
     }
 }
 
-pub use builder::CortexBuilder;
-pub use crash::CortexError;
-use errno;
+#[cfg(feature = "numa")]
+mod numa;
+
+#[cfg(all(test, feature = "loom"))]
+mod model;
+
+#[cfg(test)]
+mod miri;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "testing")] {
+        mod fault;
+        pub use fault::{Fault, FaultPlan, FaultSettings, FaultyLock, Trigger};
+    }
+}
+
+#[cfg(feature = "cleanup")]
+pub use cleanup::{cleanup_all, cleanup_on_signal, owned_resources, OwnedResource};
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "testing")]
+pub mod conformance;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "encryption")] {
+        mod encrypted;
+        pub use encrypted::EncryptedCortex;
+    }
+}
+
+pub use arena::{ArenaVec, ArenaView, CortexArena, ShmBox};
+pub use barrier::CortexBarrier;
+pub use blob::CortexBlob;
+pub use builder::{CortexBuilder, DropPolicy};
+use builder::{CortexOptions, DEFAULT_PERMISSIONS};
+use cleanup::Registration;
+pub use coalesce::CoalescedWriter;
+pub use cortex_struct::{CortexStruct, CortexStructBuilder};
+pub use counter::ShardedCounter;
+pub use crash::{CortexError, ErrorKind, SegmentInfo};
+use diagnostics::LockStatsCollector;
+pub use diagnostics::{LockHolderInfo, LockStats};
+pub use dump::CrashDump;
+pub use endian::{Le, LittleEndian};
+pub use event::Event;
+pub use file_lock::{FileLock, FileLockSettings};
+pub use fixed::{ShmString, ShmVec};
+use guard::{Access, HeldLock};
+pub use guard::{OwnedReadGuard, OwnedWriteGuard, UpgradableReadGuard, WriteGuard};
+pub use header::{LAYOUT_MAGIC, LAYOUT_VERSION};
+pub use histogram::{CortexHistogram, HistogramSnapshot};
+pub use huge_pages::HugePages;
+pub use job_queue::{ClaimedJob, CortexJobQueue, JobId};
+pub use leader::Leader;
+pub use limits::{max_segment_size, set_max_segment_size, DEFAULT_MAX_SEGMENT_SIZE};
+pub use log::{CortexLog, LogBatch, LogConsumer};
+pub use map::{CortexMap, MapSettings};
+pub use mem::MemBackend;
+pub use namespace::{ipc_key, namespace, set_namespace};
+pub use no_lock::{NoLock, Unsynchronized};
+pub use once::CortexOnce;
+pub use ops::CortexOps;
+pub use pod::Pod;
+pub use raw::{RawCortex, RawReadGuard, RawWriteGuard};
+pub use rcu::{CortexRcu, RcuReader};
+pub use read_many::{read_many, ReadMany};
+pub use read_only::ReadOnlyCortex;
+pub use reentrant::ReentrantLock;
+pub use retry::RetryPolicy;
+pub use rwlock::{RwLock, RwLockPolicy, RwLockSettings};
+pub use sequence::{CortexSequence, FilePersistence, SequencePersistence};
+pub use slab::{CortexSlab, SlabSettings, SlotId};
+pub use spin::{
+    BackoffSettings, HybridLock, HybridLockSettings, SpinLock, SpinLockSettings, YieldStrategy,
+};
+pub use stamped::Stamped;
+pub use ticket::TicketLock;
+pub use transaction::Transaction;
+pub use triple_buffer::{CortexTripleBuffer, TripleBufferReader, TripleBufferWriter};
+pub use vec::CortexVec;
+pub use versioned::Versioned;
+pub use watcher::{WatchHandle, WatchSettings, Watcher};
+
+use header::{data_offset, segment_size, Header, FLAG_CHECKSUM, FLAG_REF_COUNTED, FLAG_SEALED};
+use retry::is_interrupted;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Attempt to detach process from shared memory
 fn detach(id: i32, ptr: *const libc::c_void) -> CortexResult<()> {
-    if unsafe { libc::shmdt(ptr) } == -1 {
+    if unsafe { mem::shmdt(id, ptr) } == -1 {
         return Err(CortexError::new_dirty(format!(
             "Failed to detach from shared memory with id: {}",
             id
@@ -25,7 +163,7 @@ fn detach(id: i32, ptr: *const libc::c_void) -> CortexResult<()> {
 
 /// Attempt to mark shared memory segment for deletion
 fn mark_for_deletion(id: i32) -> CortexResult<()> {
-    if unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) } == -1 {
+    if unsafe { mem::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) } == -1 {
         return Err(CortexError::new_dirty(format!(
             "Error cleaning up shared memory with id: {}",
             id
@@ -34,32 +172,305 @@ fn mark_for_deletion(id: i32) -> CortexResult<()> {
     Ok(())
 }
 
+/// Pin the pages of a segment in RAM, so that they are never swapped out
+fn lock_memory(id: i32, ptr: *const libc::c_void, size: usize) -> CortexResult<()> {
+    cfg_if::cfg_if! {
+        if #[cfg(target_os = "linux")] {
+            let _ = ptr;
+            let result = unsafe { mem::shmctl(id, libc::SHM_LOCK, std::ptr::null_mut()) };
+        } else {
+            let result = unsafe { libc::mlock(ptr, size) };
+        }
+    }
+    if result == -1 {
+        let errno = errno::errno();
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        unsafe { libc::getrlimit(libc::RLIMIT_MEMLOCK, &mut limit) };
+        errno::set_errno(errno);
+        return Err(CortexError::new_clean(format!(
+            "Error locking {} bytes of shared memory with id: {} in RAM, this requires \
+             CAP_IPC_LOCK or an RLIMIT_MEMLOCK of at least that size (currently {} bytes)",
+            size, id, limit.rlim_cur
+        )));
+    }
+    Ok(())
+}
+
+/// Poll `done` with an exponentially increasing sleep in between, capped at one millisecond
+fn wait_until(mut done: impl FnMut() -> CortexResult<bool>) -> CortexResult<()> {
+    let mut backoff = Duration::from_micros(1);
+    while !done()? {
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+/// Attempt to read the `IPC_STAT` info of a segment
+fn stat(id: i32) -> CortexResult<libc::shmid_ds> {
+    let mut info: libc::shmid_ds = unsafe { std::mem::zeroed() };
+    if unsafe { mem::shmctl(id, libc::IPC_STAT, &mut info) } == -1 {
+        return Err(CortexError::new_clean(format!(
+            "Error during shmctl IPC_STAT for id: {}",
+            id
+        )));
+    }
+    Ok(info)
+}
+
+/// `IPC_STAT` info of the segment on `key`, if there is one and it can be read
+fn existing_segment(in_process: bool, key: i32) -> Option<SegmentInfo> {
+    let id = unsafe { mem::shmget(in_process, ipc_key(key), 0, 0) };
+    if id == -1 {
+        return None;
+    }
+    stat(id).ok().map(|info| SegmentInfo::from_stat(id, &info))
+}
+
+/// Error for a failed `shmget` creating `size` bytes on `key`. A segment already on the key is the
+/// usual cause of `EEXIST` and `EINVAL`, so its `IPC_STAT` info is included.
+fn shmget_error(in_process: bool, key: i32, size: usize) -> CortexError {
+    let errno = errno::errno();
+    let existing = match errno.0 {
+        libc::EEXIST | libc::EINVAL => existing_segment(in_process, key),
+        _ => None,
+    };
+    errno::set_errno(errno);
+    let message = match existing {
+        Some(segment) if segment.size != size => format!(
+            "Size mismatch for key: {}, existing is {}B, requested {}B",
+            key, segment.size, size
+        ),
+        _ => format!(
+            "Error during shmget for key: {}, requested {} bytes",
+            key, size
+        ),
+    };
+    let err = CortexError::new_clean(message);
+    match existing {
+        Some(segment) => err.with_segment(segment),
+        None => err,
+    }
+}
+
+/// Call `try_lock` with an exponentially increasing sleep in between until it succeeds or the
+/// timeout expires
+fn poll_lock(timeout: Duration, try_lock: impl Fn() -> CortexResult<bool>) -> CortexResult<bool> {
+    let deadline = Instant::now() + timeout;
+    let mut backoff = Duration::from_micros(1);
+    loop {
+        if try_lock()? {
+            return Ok(true);
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(false);
+        }
+        std::thread::sleep(backoff.min(deadline - now));
+        backoff = (backoff * 2).min(Duration::from_millis(1));
+    }
+}
+
+/// Milliseconds since the unix epoch, comparable across processes on the same host
+fn unix_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Generate a random key in the valid range for SysV keys, excluding `IPC_PRIVATE`.
+///
+/// Seeded from the OS entropy source through `RandomState`, so processes starting at the same
+/// time don't end up with the same sequence of keys.
+fn random_key() -> i32 {
+    use std::hash::{BuildHasher, Hasher};
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0),
+    );
+    hasher.write_u64(thread_token());
+    let key = (hasher.finish() & i32::MAX as u64) as i32;
+    key.max(1)
+}
+
+/// Kernel id of the calling thread where available, otherwise a per-process counter
+pub(crate) fn thread_id() -> u32 {
+    cfg_if::cfg_if! {
+        if #[cfg(all(target_os = "linux", not(miri)))] {
+            unsafe { libc::gettid() as u32 }
+        } else {
+            use std::sync::atomic::AtomicU32;
+            static NEXT_THREAD: AtomicU32 = AtomicU32::new(1);
+            thread_local! {
+                static THREAD_ID: u32 = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+            }
+            THREAD_ID.with(|thread| *thread)
+        }
+    }
+}
+
+/// Identifies the calling thread across processes, never 0
+pub(crate) fn thread_token() -> u64 {
+    let pid = unsafe { libc::getpid() } as u64;
+    (pid << 32) | thread_id() as u64
+}
+
+/// Check whether a process with the given pid is still running
+fn process_alive(pid: i32) -> bool {
+    // Miri runs a single process, which created every segment it can see
+    if cfg!(miri) {
+        return pid == unsafe { libc::getpid() };
+    }
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return true;
+    }
+    // The process exists but belongs to another user
+    errno::errno().0 == libc::EPERM
+}
+
 pub type CortexResult<T> = std::result::Result<T, CortexError>;
 
 pub trait CortexSync: Sized {
     type Settings;
+    /// Whether the lock only works within the current process, in which case segments are
+    /// emulated on the heap instead of being allocated as System V shared memory, see
+    /// `MemBackend`
+    const IN_PROCESS: bool = false;
 
     fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self>;
-    fn attach(cortex_key: i32) -> CortexResult<Self>;
+    fn attach(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self>;
     fn force_ownership(&mut self);
+    /// Give up ownership, meaning this instance should no longer clean up the lock when dropped
+    fn relinquish_ownership(&mut self);
+    /// Called in a child created through `fork` on the lock it inherited, which never owns the
+    /// lock in the child. Defaults to `relinquish_ownership`, override it for locks tied to the
+    /// process that opened them, which have to be opened again. Only called through
+    /// `Cortex::after_fork_child`, so a child that skips it keeps using the lock of its parent.
+    fn after_fork_child(&mut self) -> CortexResult<()> {
+        self.relinquish_ownership();
+        Ok(())
+    }
     fn read_lock(&self) -> CortexResult<()>;
     fn write_lock(&self) -> CortexResult<()>;
     fn release(&self) -> CortexResult<()>;
+    /// Release a lock acquired through `read_lock`, `try_read_lock` or `timed_read_lock`. Defaults
+    /// to `release`, override it for locks that need to tell readers and writers apart.
+    fn release_read(&self) -> CortexResult<()> {
+        self.release()
+    }
+    /// Release a lock acquired through `write_lock`, `try_write_lock` or `timed_write_lock`.
+    /// Defaults to `release`, override it for locks that need to tell readers and writers apart.
+    fn release_write(&self) -> CortexResult<()> {
+        self.release()
+    }
+    /// Attempt to acquire the read lock without blocking, returns `false` if it is held elsewhere.
+    /// Returns an error by default, for locks without a non-blocking path.
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        Err(CortexError::new_logic(
+            ErrorKind::Unsupported,
+            "try_read_lock is not supported by this lock",
+        ))
+    }
+    /// Attempt to acquire the write lock without blocking, returns `false` if it is held
+    /// elsewhere. Returns an error by default, for locks without a non-blocking path.
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        Err(CortexError::new_logic(
+            ErrorKind::Unsupported,
+            "try_write_lock is not supported by this lock",
+        ))
+    }
+    /// Attempt to acquire the read lock, giving up after `timeout`. Defaults to polling
+    /// `try_read_lock` until the timeout expires.
+    fn timed_read_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        poll_lock(timeout, || self.try_read_lock())
+    }
+    /// Attempt to acquire the write lock, giving up after `timeout`. Defaults to polling
+    /// `try_write_lock` until the timeout expires.
+    fn timed_write_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        poll_lock(timeout, || self.try_write_lock())
+    }
+    /// Acquire a read lock that can later be upgraded to a write lock through `upgrade`, without
+    /// letting another writer in between. Only one upgradable reader can exist at a time. Defaults
+    /// to `write_lock`, which trivially satisfies the guarantee at the cost of excluding readers.
+    fn upgradable_read_lock(&self) -> CortexResult<()> {
+        self.write_lock()
+    }
+    /// Attempt to acquire an upgradable read lock without blocking. Defaults to `try_write_lock`,
+    /// matching `upgradable_read_lock`.
+    fn try_upgradable_read_lock(&self) -> CortexResult<bool> {
+        self.try_write_lock()
+    }
+    /// Attempt to acquire an upgradable read lock, giving up after `timeout`. Defaults to
+    /// `timed_write_lock`, matching `upgradable_read_lock`.
+    fn timed_upgradable_read_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        self.timed_write_lock(timeout)
+    }
+    /// Upgrade a held upgradable read lock to a write lock, the lock is then released through
+    /// `release_write`. Does nothing by default, matching `upgradable_read_lock`.
+    fn upgrade(&self) -> CortexResult<()> {
+        Ok(())
+    }
+    /// Release an upgradable read lock that was never upgraded. Defaults to `release_write`,
+    /// matching `upgradable_read_lock`.
+    fn release_upgradable(&self) -> CortexResult<()> {
+        self.release_write()
+    }
+    /// Open another handle to the same lock, which doesn't clean it up when dropped. Used by
+    /// `Cortex::try_clone`, and returns an error by default for locks that can't be reopened
+    /// without their settings.
+    fn try_clone(&self) -> CortexResult<Self> {
+        Err(CortexError::new_logic(
+            ErrorKind::Unsupported,
+            "try_clone is not supported by this lock",
+        ))
+    }
+    /// Whether the current write should be left unfinished, see `Fault::PartialWrite`
+    #[cfg(feature = "testing")]
+    #[doc(hidden)]
+    fn tear_write(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
-pub struct Cortex<T, L> {
+pub struct Cortex<T, L: CortexSync> {
     key: i32,
     id: i32,
-    #[allow(dead_code)]
     size: usize,
     is_owner: bool,
+    registration: Registration,
     lock: L,
+    lock_warning: Option<Duration>,
+    /// Number of random keys that were already taken before finding a free one
+    key_collisions: u32,
+    stats: Option<LockStatsCollector>,
+    /// Mapped with `SHM_RDONLY`, so nothing may be written to the header or the data
+    read_only: bool,
+    /// Process that took the reference this handle holds on a ref counted segment, 0 if it holds
+    /// none. A copy inherited through `fork` never took one, so it must not give it back.
+    counted_by: libc::pid_t,
+    /// Mapping with `SHM_RDONLY` that `ptr` points into once the segment is sealed, see `seal`
+    sealed: Option<*mut libc::c_void>,
+    /// `T` is known to be `Pod`, so the bytes of the data can be read, which checksums need. Only
+    /// set through APIs bounded on `Pod`, see `CortexBuilder::checksum`.
+    pod: bool,
+    header: *mut Header,
     ptr: *mut T,
 }
 
-unsafe impl<T, L> Send for Cortex<T, L> {}
-unsafe impl<T, L> Sync for Cortex<T, L> {}
+// Every handle on a segment aliases the same `T`, and readers share it under the read lock, so a
+// handle behaves like an `Arc<RwLock<T>>`: moving it to another thread or sharing it between threads
+// hands out `&T` and `&mut T` there, which requires `T: Send + Sync` either way. The raw pointers
+// into the segment are only dereferenced while holding the lock.
+unsafe impl<T: Send + Sync, L: CortexSync + Send> Send for Cortex<T, L> {}
+unsafe impl<T: Send + Sync, L: CortexSync + Sync> Sync for Cortex<T, L> {}
 
 impl<T, L: CortexSync> Cortex<T, L> {
     /// Allocate a new segment of shared memory
@@ -69,16 +480,66 @@ impl<T, L: CortexSync> Cortex<T, L> {
         force_ownership: bool,
         lock_settings: Option<&L::Settings>,
     ) -> CortexResult<Self> {
-        let mut key = if let Some(key) = init_key {
-            key
-        } else {
-            unsafe { libc::rand() }
+        let options = CortexOptions {
+            force_ownership,
+            ..Default::default()
         };
+        Self::create(init_key, data, &options, lock_settings)
+    }
+    pub(crate) fn create(
+        init_key: Option<i32>,
+        data: T,
+        options: &CortexOptions,
+        lock_settings: Option<&L::Settings>,
+    ) -> CortexResult<Self> {
+        let mut key = init_key.unwrap_or_else(random_key);
+        let mut key_collisions = 0;
 
         // Allocate memory
         let size = std::mem::size_of::<T>();
-        let permissions = libc::IPC_CREAT | libc::IPC_EXCL | 0o666;
-        let mut id = unsafe { libc::shmget(key, size, permissions) };
+        let mut segment_size = segment_size::<T>();
+        limits::check_size(segment_size, options.max_size)?;
+        let mode = options.permissions.unwrap_or(DEFAULT_PERMISSIONS);
+        if mode & !0o777 != 0 {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!("Invalid segment permissions: {:o}", mode),
+            ));
+        }
+        let mut permissions = libc::IPC_CREAT | libc::IPC_EXCL | mode as i32;
+        let mut huge = false;
+        if options.huge_pages != HugePages::Never {
+            match huge_pages::shm_flags(segment_size) {
+                Some((flag, huge_size)) => {
+                    huge = true;
+                    permissions |= flag;
+                    segment_size = huge_size;
+                }
+                None if options.huge_pages == HugePages::Required => {
+                    return Err(CortexError::new_logic(
+                        ErrorKind::Unsupported,
+                        "Huge pages are not supported on this platform",
+                    ));
+                }
+                None => crate::trace::warning!("Huge pages are not supported, using regular pages"),
+            }
+        }
+        let mut id = unsafe { mem::shmget(L::IN_PROCESS, ipc_key(key), segment_size, permissions) };
+
+        // Any error other than the key being taken means that huge pages are unavailable
+        if id == -1 && huge && errno::errno().0 != libc::EEXIST {
+            if options.huge_pages == HugePages::Required {
+                return Err(CortexError::new_clean(
+                    "Error during shmget with huge pages, make sure that enough huge pages are \
+                     reserved through /proc/sys/vm/nr_hugepages and that the process is allowed \
+                     to use them",
+                ));
+            }
+            crate::trace::warning!("Huge pages are unavailable, using regular pages");
+            permissions = libc::IPC_CREAT | libc::IPC_EXCL | mode as i32;
+            segment_size = header::segment_size::<T>();
+            id = unsafe { mem::shmget(L::IN_PROCESS, ipc_key(key), segment_size, permissions) };
+        }
 
         if id == -1 {
             let mut errno = errno::errno();
@@ -86,26 +547,53 @@ impl<T, L: CortexSync> Cortex<T, L> {
             // If key already exists
             if errno.0 == libc::EEXIST {
                 match init_key {
-                    Some(key) if force_ownership => {
+                    Some(key) if options.attach_or_create => {
+                        return Cortex::attach_or_reclaim(key, data, options, lock_settings);
+                    }
+                    Some(key) if options.force_ownership => {
                         // Attach and set `is_owner` to true
-                        let mut attached = Cortex::attach(key)?;
-                        attached.force_ownership();
-                        return Ok(attached);
+                        let attached = options.retry.run(is_interrupted, || {
+                            Cortex::attach_with(key, lock_settings, options.checksum)
+                        });
+                        return match attached {
+                            Ok(mut attached) => {
+                                attached.force_ownership();
+                                Ok(attached)
+                            }
+                            Err(err)
+                                if options.force_ownership_reinit
+                                    && err.kind() == ErrorKind::TypeMismatch =>
+                            {
+                                crate::trace::warning!(
+                                    "Recreating shared memory on key: {} after mismatch: {}",
+                                    key,
+                                    err
+                                );
+                                let id =
+                                    unsafe { mem::shmget(L::IN_PROCESS, ipc_key(key), 0, 0o666) };
+                                Cortex::recreate(id, key, data, options, lock_settings)
+                            }
+                            Err(err) => Err(err),
+                        };
                     }
                     Some(_) => {
                         // Do nothing
                     }
                     None => {
-                        // Loop and retry for new key up to 20 times
-                        let mut counter = 0;
-                        while counter < 20 && id == -1 && errno.0 == libc::EEXIST {
-                            key = unsafe { libc::rand() };
-                            id = unsafe { libc::shmget(key, size, permissions) };
+                        // Loop and retry for a new key as many times as the retry policy allows
+                        while key_collisions < options.retry.max_retries
+                            && id == -1
+                            && errno.0 == libc::EEXIST
+                        {
+                            key_collisions += 1;
+                            key = random_key();
+                            id = unsafe {
+                                mem::shmget(L::IN_PROCESS, ipc_key(key), segment_size, permissions)
+                            };
                             if id != -1 {
                                 break;
                             }
                             errno = errno::errno();
-                            counter += 1;
                         }
                     }
                 }
@@ -113,42 +601,141 @@ impl<T, L: CortexSync> Cortex<T, L> {
         }
 
         if id == -1 {
-            return Err(CortexError::new_clean("Error during shmget"));
+            return Err(shmget_error(L::IN_PROCESS, key, segment_size));
         }
-        tracing::trace!("Allocated {} bytes with id: {}", size, id);
+        crate::trace::trace!("Allocated {} bytes with id: {}", segment_size, id);
 
         // Attach memory to current process and get a pointer
-        let ptr = unsafe { libc::shmat(id, std::ptr::null_mut(), 0) as *mut T };
-        if ptr as isize == -1 {
+        let header = unsafe { mem::shmat(id, std::ptr::null_mut(), 0) as *mut Header };
+        if header as isize == -1 {
             mark_for_deletion(id)?;
             return Err(CortexError::new_clean(format!(
                 "Error during shmat for id: {}",
                 id
             )));
         }
-        tracing::trace!("Successfully attached to shared memory");
+        crate::trace::trace!("Successfully attached to shared memory");
 
-        unsafe {
-            ptr.write(data);
+        #[cfg(feature = "numa")]
+        if let Some(node) = options.numa_node {
+            // Before touching any pages, so that they are allocated on the right node to begin with
+            if let Err(err) = numa::bind(header as *mut libc::c_void, segment_size, node) {
+                detach(id, header as *const libc::c_void)?;
+                mark_for_deletion(id)?;
+                return Err(err);
+            }
+        }
+        if options.lock_memory {
+            if let Err(err) = lock_memory(id, header as *const libc::c_void, segment_size) {
+                detach(id, header as *const libc::c_void)?;
+                mark_for_deletion(id)?;
+                return Err(err);
+            }
         }
 
-        let lock = L::new(key, lock_settings)?;
+        let ptr = unsafe { (header as *mut u8).add(data_offset::<T>()) as *mut T };
+        let (owner_pid, mut flags) = if options.drop_policy == DropPolicy::RefCounted {
+            (0, FLAG_REF_COUNTED)
+        } else {
+            (unsafe { libc::getpid() }, 0)
+        };
+        if options.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        // Create the lock first and write the initial data under it, attaching processes can't get
+        // past `Header::validate` until it is published
+        let initialized = options
+            .retry
+            .run(is_interrupted, || L::new(key, lock_settings))
+            .and_then(|lock| {
+                lock.write_lock()?;
+                unsafe {
+                    header.write(Header::new::<T>(owner_pid, flags, options.schema_version));
+                    ptr.write(data);
+                    if options.checksum {
+                        (*header).update_checksum(std::slice::from_raw_parts(
+                            ptr as *const u8,
+                            std::mem::size_of::<T>(),
+                        ));
+                    }
+                    (*header).publish();
+                }
+                lock.release_write()?;
+                Ok(lock)
+            });
+        let mut lock = match initialized {
+            Ok(lock) => lock,
+            Err(err) => {
+                detach(id, header as *const libc::c_void)?;
+                mark_for_deletion(id)?;
+                return Err(err);
+            }
+        };
+        if options.drop_policy == DropPolicy::RefCounted {
+            // Cleaned up by whoever drops the last reference instead
+            lock.relinquish_ownership();
+        }
 
+        let is_owner = options.drop_policy != DropPolicy::RefCounted;
+        // The count starts out at one for the creator
+        let counted_by = match is_owner {
+            true => 0,
+            false => unsafe { libc::getpid() },
+        };
+        let registration = match is_owner {
+            true => cleanup::owned(|| cleanup::OwnedResource::Segment { key, id }),
+            false => Registration::default(),
+        };
         Ok(Self {
             id,
             key,
             size,
-            is_owner: true,
+            is_owner,
+            registration,
             lock,
+            lock_warning: None,
+            key_collisions,
+            stats: None,
+            read_only: false,
+            counted_by,
+            sealed: None,
+            pod: options.checksum,
+            header,
             ptr,
         })
     }
-    /// Attempt to attach to an already existing segment of shared memory
+    /// Attempt to attach to an already existing segment of shared memory. Segments that keep a
+    /// checksum can only be attached to through `CortexBuilder::checksum`.
     pub fn attach(key: i32) -> CortexResult<Self> {
-        let lock = L::attach(key)?;
+        Self::attach_with(key, None, false)
+    }
+    /// Attempt to attach to an already existing segment of shared memory, passing settings to the
+    /// lock implementation
+    pub fn attach_with_lock(key: i32, lock_settings: &L::Settings) -> CortexResult<Self> {
+        Self::attach_with(key, Some(lock_settings), false)
+    }
+    /// Attach to an existing segment mapped with `SHM_RDONLY`, so that the returned handle can only
+    /// read, see `ReadOnlyCortex`. Only read permission on the segment is required. Fails for
+    /// segments that keep a checksum, like `attach`.
+    pub fn attach_read_only(key: i32) -> CortexResult<ReadOnlyCortex<T, L>> {
+        Self::attach_mapped(key, None, true, false).map(ReadOnlyCortex::new)
+    }
+    /// Attach with `lock_settings`, where `pod` tells that `T` is known to be `Pod`, which is
+    /// required for segments that keep a checksum
+    fn attach_with(key: i32, lock_settings: Option<&L::Settings>, pod: bool) -> CortexResult<Self> {
+        Self::attach_mapped(key, lock_settings, false, pod)
+    }
+    fn attach_mapped(
+        key: i32,
+        lock_settings: Option<&L::Settings>,
+        read_only: bool,
+        pod: bool,
+    ) -> CortexResult<Self> {
+        let lock = L::attach(key, lock_settings)?;
 
+        let mode = if read_only { 0o444 } else { 0o666 };
         let id = unsafe {
-            libc::shmget(key, 0, 0o666) // Size is 0 since we're not creating the segment
+            mem::shmget(L::IN_PROCESS, ipc_key(key), 0, mode) // Size is 0 since we're not creating the segment
         };
         if id == -1 {
             return Err(CortexError::new_clean(format!(
@@ -156,65 +743,1154 @@ impl<T, L: CortexSync> Cortex<T, L> {
                 key,
             )));
         } else {
-            tracing::trace!("Found shared memory with id: {}", id);
+            crate::trace::trace!("Found shared memory with id: {}", id);
         }
-
-        let ptr = unsafe { libc::shmat(id, std::ptr::null_mut(), 0) as *mut T };
-        if ptr as isize == -1 {
+        Self::attach_id(key, id, lock, read_only, pod)
+    }
+    fn attach_id(key: i32, id: i32, lock: L, read_only: bool, pod: bool) -> CortexResult<Self> {
+        // Anyone with access to the key could have created the segment, so don't read a header
+        // or payload that isn't there
+        let info = SegmentInfo::from_stat(id, &stat(id)?);
+        if info.size < segment_size::<T>() {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Size mismatch for key: {}, existing segment is {} bytes but at least {} were \
+                 expected",
+                    key,
+                    info.size,
+                    segment_size::<T>()
+                ),
+            )
+            .with_segment(info));
+        }
+        let flags = if read_only { libc::SHM_RDONLY } else { 0 };
+        let header = unsafe { mem::shmat(id, std::ptr::null_mut(), flags) as *mut Header };
+        if header as isize == -1 {
             return Err(CortexError::new_clean("Error during shmat"));
         } else {
-            tracing::trace!("Successfully attached to shared memory");
+            crate::trace::trace!("Successfully attached to shared memory");
         }
+        let ptr = unsafe { (header as *mut u8).add(data_offset::<T>()) as *mut T };
 
-        Ok(Self {
+        let header_ref = unsafe { &*header };
+        if let Err(err) = header_ref.validate::<T>(key) {
+            detach(id, header as *const libc::c_void)?;
+            return Err(err);
+        }
+        // Hashing the data reads every byte of it, which is only defined for types without padding
+        if header_ref.has_flag(FLAG_CHECKSUM) && !pod {
+            detach(id, header as *const libc::c_void)?;
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Shared memory with key: {} keeps a checksum, attach through \
+                     `CortexBuilder::checksum` with a type that implements `Pod`",
+                    key
+                ),
+            ));
+        }
+        // Read-only handles can't take part in the reference count, the mapping keeps the segment
+        // alive after it is removed anyway
+        let mut counted_by = 0;
+        if header_ref.has_flag(FLAG_REF_COUNTED) && !read_only {
+            // A count of zero means the last instance is tearing the segment down
+            let incremented =
+                header_ref
+                    .ref_count
+                    .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                        (count > 0).then_some(count + 1)
+                    });
+            if incremented.is_err() {
+                detach(id, header as *const libc::c_void)?;
+                return Err(CortexError::new_logic(
+                    ErrorKind::NotFound,
+                    format!("Shared memory with key: {} is being removed", key),
+                ));
+            }
+            counted_by = unsafe { libc::getpid() };
+        }
+
+        let mut cortex = Self {
             id,
             key,
             size: std::mem::size_of::<T>(),
             is_owner: false,
+            registration: Registration::default(),
             lock,
+            lock_warning: None,
+            key_collisions: 0,
+            stats: None,
+            read_only,
+            counted_by,
+            sealed: None,
+            pod,
+            header,
             ptr,
-        })
+        };
+        // Writes are rejected through the flag either way, but read sealed data through a
+        // read-only mapping like the handle that sealed it
+        if header_ref.has_flag(FLAG_SEALED) {
+            cortex.map_sealed()?;
+        }
+        Ok(cortex)
+    }
+    /// Attach a second handle to the same segment and lock, without having to pass the lock
+    /// settings again. The new handle is never the owner, but takes part in the reference count of
+    /// ref counted segments, and inherits the lock warning and whether lock stats are collected.
+    pub fn try_clone(&self) -> CortexResult<Self> {
+        let lock = self.lock.try_clone()?;
+        let mut cortex = Self::attach_id(self.key, self.id, lock, self.read_only, self.pod)?;
+        cortex.set_lock_warning(self.lock_warning);
+        cortex.set_lock_stats(self.stats.is_some());
+        Ok(cortex)
+    }
+    /// Attach to an existing segment on `key`, unless it turns out to be orphaned in which case it is
+    /// destroyed and recreated with `data`
+    fn attach_or_reclaim(
+        key: i32,
+        data: T,
+        options: &CortexOptions,
+        lock_settings: Option<&L::Settings>,
+    ) -> CortexResult<Self> {
+        let id = unsafe { mem::shmget(L::IN_PROCESS, ipc_key(key), 0, 0o666) };
+        if id == -1 {
+            // Removed in between, start over
+            return Cortex::create(Some(key), data, options, lock_settings);
+        }
+        let info = stat(id)?;
+
+        let mut orphaned = info.shm_nattch == 0 && !process_alive(info.shm_cpid);
+        if let Some(max_age) = options.stale_after {
+            if info.shm_nattch == 0 && !orphaned {
+                let header = unsafe { mem::shmat(id, std::ptr::null_mut(), 0) as *mut Header };
+                if header as isize == -1 {
+                    return Err(CortexError::new_clean("Error during shmat"));
+                }
+                let heartbeat = unsafe { (*header).heartbeat.load(Ordering::Acquire) };
+                detach(id, header as *const libc::c_void)?;
+                orphaned = unix_millis().saturating_sub(heartbeat) > max_age.as_millis() as u64;
+            }
+        }
+
+        if orphaned {
+            crate::trace::warning!("Reclaiming orphaned shared memory on key: {}", key);
+            return Cortex::recreate(id, key, data, options, lock_settings);
+        }
+
+        // Segments backed by huge pages are rounded up to a whole number of pages
+        let huge_size = huge_pages::shm_flags(segment_size::<T>()).map(|(_, size)| size);
+        if info.shm_segsz != segment_size::<T>() && Some(info.shm_segsz) != huge_size {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Size mismatch for key: {}, existing segment is {} bytes but {} were expected",
+                    key,
+                    info.shm_segsz,
+                    segment_size::<T>()
+                ),
+            )
+            .with_segment(SegmentInfo::from_stat(id, &info)));
+        }
+        // The segment might be removed, or its creator might not have created the lock yet
+        options.retry.run(
+            |err| is_interrupted(err) || err.kind() == ErrorKind::NotFound,
+            || Cortex::attach_with(key, lock_settings, options.checksum),
+        )
+    }
+    /// Remove the segment `id` on `key` along with its lock and create a new one with `data`.
+    /// Processes still attached to the old segment keep using it until they attach again.
+    fn recreate(
+        id: i32,
+        key: i32,
+        data: T,
+        options: &CortexOptions,
+        lock_settings: Option<&L::Settings>,
+    ) -> CortexResult<Self> {
+        // Another process might be removing the same segment, so failures here are fine
+        if id != -1 {
+            unsafe { mem::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+        }
+        // Take ownership of any stale lock so that it gets cleaned up when dropped
+        if let Ok(mut stale_lock) = L::attach(key, lock_settings) {
+            stale_lock.force_ownership();
+        }
+        Cortex::create(Some(key), data, options, lock_settings)
+    }
+    /// Attach to the segment on `key`, creating it with the value from `init` if it doesn't exist.
+    /// Retries with the default `RetryPolicy` when racing against other processes doing the same.
+    pub(crate) fn attach_or_create(key: i32, init: impl Fn() -> T) -> CortexResult<Self> {
+        Self::attach_or_create_with(key, DropPolicy::Owner, None, init)
+    }
+    /// Same as `attach_or_create`, creating the segment with `drop_policy` and passing
+    /// `lock_settings` to the lock implementation
+    pub(crate) fn attach_or_create_with(
+        key: i32,
+        drop_policy: DropPolicy,
+        lock_settings: Option<&L::Settings>,
+        init: impl Fn() -> T,
+    ) -> CortexResult<Self> {
+        let options = CortexOptions {
+            drop_policy,
+            ..Default::default()
+        };
+        // Any failure might mean that another process created the segment in between, so always
+        // try attaching again
+        RetryPolicy::default().run(
+            |_| true,
+            || {
+                Cortex::attach_with(key, lock_settings, false)
+                    .or_else(|_| Cortex::create(Some(key), init(), &options, lock_settings))
+            },
+        )
     }
     /// Read from shared memory
     pub fn read(&self) -> CortexResult<T> {
-        unsafe {
-            self.lock.read_lock()?;
-            let data = self.ptr.read();
-            self.lock.release()?;
-            Ok(data)
+        let _span = trace::cortex_span!("cortex_read", self);
+        let held = self.acquire_read()?;
+        self.read_and_release(held)
+    }
+    /// Read from shared memory without blocking, returns `None` if the lock is held elsewhere
+    pub fn try_read(&self) -> CortexResult<Option<T>> {
+        if !self.acquire_with(|| self.lock.try_read_lock(), || self.lock.try_read_lock())? {
+            return Ok(None);
+        }
+        let held = self.mark_held(Access::Read);
+        self.read_and_release(held).map(Some)
+    }
+    /// Read from shared memory, returns `None` if the lock could not be acquired within `timeout`
+    pub fn read_timeout(&self, timeout: Duration) -> CortexResult<Option<T>> {
+        if !self.acquire_with(
+            || self.lock.try_read_lock(),
+            || self.lock.timed_read_lock(timeout),
+        )? {
+            return Ok(None);
+        }
+        let held = self.mark_held(Access::Read);
+        self.read_and_release(held).map(Some)
+    }
+    /// Read from shared memory along with the version and time of the write that produced the
+    /// value, e.g. to skip values that were already processed or to measure end-to-end latency
+    pub fn read_versioned(&self) -> CortexResult<Versioned<T>> {
+        let header = unsafe { &*self.header };
+        let held = self.acquire_read()?;
+        if let Err(err) = self.check_data() {
+            held.release()?;
+            return Err(err);
+        }
+        let value = unsafe { self.ptr.read() };
+        let generation = header.generation.load(Ordering::Acquire);
+        let written_at = header.last_write.load(Ordering::Relaxed);
+        held.release()?;
+        Ok(Versioned {
+            value,
+            version: generation / 2,
+            written_at: std::time::UNIX_EPOCH + Duration::from_nanos(written_at),
+        })
+    }
+    /// Number of writes completed since the segment was created, the same as `Versioned::version`
+    /// but without taking the lock. Cheap enough to poll for changes, see `Watcher`.
+    pub fn version(&self) -> u64 {
+        let header = unsafe { &*self.header };
+        header.generation.load(Ordering::Acquire) / 2
+    }
+    /// Read the data and release the read lock
+    fn read_and_release(&self, held: HeldLock<'_, T, L>) -> CortexResult<T> {
+        if let Err(err) = self.check_data() {
+            held.release()?;
+            return Err(err);
         }
+        let data = unsafe { self.ptr.read() };
+        held.release()?;
+        Ok(data)
     }
-    /// Write to shared memory
+    /// Write to shared memory. Since this replaces the entire value, it also clears any poison
+    /// left behind by an interrupted write.
     pub fn write(&self, data: T) -> CortexResult<()> {
-        unsafe {
-            self.lock.write_lock()?;
-            self.ptr.write(data);
-            self.lock.release()?;
+        let _span = trace::cortex_span!("cortex_write", self);
+        let held = self.acquire_write()?;
+        self.write_and_release(held, data)
+    }
+    /// Write to shared memory without blocking, returns `false` if the lock is held elsewhere
+    pub fn try_write(&self, data: T) -> CortexResult<bool> {
+        if !self.acquire_with(|| self.lock.try_write_lock(), || self.lock.try_write_lock())? {
+            return Ok(false);
+        }
+        let held = self.mark_held(Access::Write);
+        self.check_writable()?;
+        self.write_and_release(held, data).map(|_| true)
+    }
+    /// Write to shared memory, returns `false` if the lock could not be acquired within `timeout`
+    pub fn write_timeout(&self, data: T, timeout: Duration) -> CortexResult<bool> {
+        if !self.acquire_with(
+            || self.lock.try_write_lock(),
+            || self.lock.timed_write_lock(timeout),
+        )? {
+            return Ok(false);
+        }
+        let held = self.mark_held(Access::Write);
+        self.check_writable()?;
+        self.write_and_release(held, data).map(|_| true)
+    }
+    /// Write the data and release the write lock
+    fn write_and_release(&self, held: HeldLock<'_, T, L>, data: T) -> CortexResult<()> {
+        if !self.is_poisoned() {
+            self.begin_write();
+        }
+        #[cfg(feature = "testing")]
+        if self.lock.tear_write() {
+            // Leave the write unfinished, as if the writer crashed halfway through
+            let half = std::mem::size_of::<T>() / 2;
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    &data as *const T as *const u8,
+                    self.ptr as *mut u8,
+                    half,
+                )
+            };
+            held.release()?;
+            return Err(CortexError::Poisoned);
+        }
+        unsafe { self.ptr.write(data) };
+        self.end_write();
+        held.release()
+    }
+    /// Acquire an upgradable read lock, returning a guard that derefs to the data and can be
+    /// upgraded to a `WriteGuard`. Useful for read-check-write sequences, since no other writer
+    /// can get in between the read and the upgrade.
+    pub fn read_upgradable(&self) -> CortexResult<UpgradableReadGuard<'_, T, L>> {
+        let _span = trace::cortex_span!("cortex_read_lock", self);
+        self.acquire_with(
+            || self.lock.try_upgradable_read_lock(),
+            || {
+                match self.lock_warning {
+                    Some(after) => {
+                        self.wait_for_lock(|| self.lock.timed_upgradable_read_lock(after))?
+                    }
+                    None => self.lock.upgradable_read_lock()?,
+                }
+                Ok(true)
+            },
+        )?;
+        // Released through the guard rather than `HeldLock`, since it takes `release_upgradable`
+        instrument::access(self.key, Access::Read);
+        self.mark_holder();
+        UpgradableReadGuard::new(self)
+    }
+    /// Acquire the read lock, returning a guard that derefs to the data and holds an `Arc` of the
+    /// `Cortex` rather than borrowing it, so that it can be moved into spawned tasks or stored in
+    /// structs. Locks that track the holding thread, like `ReentrantLock`, must be released on the
+    /// thread that acquired them.
+    pub fn read_owned(self: &Arc<Self>) -> CortexResult<OwnedReadGuard<T, L>> {
+        // Ownership of the lock moves on to the guard
+        std::mem::forget(self.acquire_read()?);
+        OwnedReadGuard::new(Arc::clone(self))
+    }
+    /// Acquire the write lock, returning a guard that derefs to the data and holds an `Arc` of
+    /// the `Cortex`, see `read_owned`
+    pub fn write_owned(self: &Arc<Self>) -> CortexResult<OwnedWriteGuard<T, L>> {
+        std::mem::forget(self.acquire_write()?);
+        OwnedWriteGuard::new(Arc::clone(self))
+    }
+    /// Acquire the read lock, warning periodically while waiting if `lock_warning` is set
+    fn acquire_read(&self) -> CortexResult<HeldLock<'_, T, L>> {
+        // Covers only the wait for the lock, so its duration is the lock wait time
+        let _span = trace::cortex_span!("cortex_read_lock", self);
+        self.acquire_with(
+            || self.lock.try_read_lock(),
+            || {
+                match self.lock_warning {
+                    Some(after) => self.wait_for_lock(|| self.lock.timed_read_lock(after))?,
+                    None => self.lock.read_lock()?,
+                }
+                Ok(true)
+            },
+        )?;
+        Ok(self.mark_held(Access::Read))
+    }
+    /// Acquire the write lock, warning periodically while waiting if `lock_warning` is set
+    fn acquire_write(&self) -> CortexResult<HeldLock<'_, T, L>> {
+        let _span = trace::cortex_span!("cortex_write_lock", self);
+        self.acquire_with(
+            || self.lock.try_write_lock(),
+            || {
+                match self.lock_warning {
+                    Some(after) => self.wait_for_lock(|| self.lock.timed_write_lock(after))?,
+                    None => self.lock.write_lock()?,
+                }
+                Ok(true)
+            },
+        )?;
+        let held = self.mark_held(Access::Write);
+        self.check_writable()?;
+        Ok(held)
+    }
+    /// Acquire the lock through `lock`, recording stats if enabled. When collecting stats
+    /// `try_lock` is attempted first, to find out whether the acquisition was contended.
+    fn acquire_with(
+        &self,
+        try_lock: impl FnOnce() -> CortexResult<bool>,
+        lock: impl FnOnce() -> CortexResult<bool>,
+    ) -> CortexResult<bool> {
+        let Some(stats) = &self.stats else {
+            if !instrument::ENABLED {
+                return lock();
+            }
+            let start = Instant::now();
+            let acquired = lock();
+            instrument::lock_acquired(self.key, start.elapsed(), &acquired);
+            return acquired;
+        };
+        let start = Instant::now();
+        let attempt = try_lock();
+        let acquired = match attempt {
+            Ok(true) => Ok(true),
+            _ => lock(),
+        };
+        instrument::lock_acquired(self.key, start.elapsed(), &acquired);
+        if !acquired? {
+            return Ok(false);
+        }
+        stats.record_acquisition(start.elapsed(), matches!(attempt, Ok(false)));
+        Ok(true)
+    }
+    fn wait_for_lock(
+        &self,
+        mut timed_lock: impl FnMut() -> CortexResult<bool>,
+    ) -> CortexResult<()> {
+        let start = Instant::now();
+        while !timed_lock()? {
+            crate::trace::warning!(
+                "Waited {:?} for the lock of shared memory with key: {}, currently held by: {:?}",
+                start.elapsed(),
+                self.key,
+                self.lock_holder()
+            );
+        }
+        Ok(())
+    }
+    /// Take responsibility for releasing a lock that was just acquired, returning a guard that
+    /// releases it when dropped
+    fn mark_held(&self, access: Access) -> HeldLock<'_, T, L> {
+        instrument::access(self.key, access);
+        match access {
+            Access::Read => self.mark_holder(),
+            Access::Write => self.mark_write_holder(),
+        }
+        HeldLock::new(self, access)
+    }
+    /// Record the calling thread as the holder of the lock, which must already be held
+    fn mark_holder(&self) {
+        if self.read_only {
+            return;
+        }
+        let header = unsafe { &*self.header };
+        header.lock_acquired.store(unix_millis(), Ordering::Relaxed);
+        header.lock_holder.store(thread_token(), Ordering::Release);
+    }
+    /// Like `mark_holder`, but also starts measuring the hold time of the write lock
+    fn mark_write_holder(&self) {
+        self.mark_holder();
+        if let Some(stats) = &self.stats {
+            stats.record_write_acquired();
+        }
+    }
+    /// Clear the recorded holder if it is the calling thread, another reader might have replaced it
+    fn mark_released(&self) {
+        if self.read_only {
+            return;
+        }
+        let header = unsafe { &*self.header };
+        let _ = header.lock_holder.compare_exchange(
+            thread_token(),
+            0,
+            Ordering::Release,
+            Ordering::Relaxed,
+        );
+    }
+    fn release_read(&self) -> CortexResult<()> {
+        self.mark_released();
+        self.lock.release_read()
+    }
+    fn release_write(&self) -> CortexResult<()> {
+        if let Some(stats) = &self.stats {
+            stats.record_write_released();
+        }
+        self.mark_released();
+        self.lock.release_write()
+    }
+    fn release_upgradable(&self) -> CortexResult<()> {
+        self.mark_released();
+        self.lock.release_upgradable()
+    }
+    /// Access the value in place while holding the read lock, e.g. to inspect a large value
+    /// without copying it out. The lock is released once `f` returns, or if it panics.
+    pub fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> CortexResult<R> {
+        let _span = trace::cortex_span!("cortex_read", self);
+        let held = self.acquire_read()?;
+        if let Err(err) = self.check_data() {
+            held.release()?;
+            return Err(err);
+        }
+        let result = f(unsafe { &*self.ptr });
+        held.release()?;
+        Ok(result)
+    }
+    /// Modify the value in place while holding the write lock, e.g. to update several fields at
+    /// once without copying in the whole value. If `f` panics the lock is still released, but the
+    /// data is left poisoned.
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut T) -> R) -> CortexResult<R> {
+        let _span = trace::cortex_span!("cortex_write", self);
+        let held = self.acquire_write()?;
+        if let Err(err) = self.check_data() {
+            held.release()?;
+            return Err(err);
+        }
+        self.begin_write();
+        let result = f(unsafe { &mut *self.ptr });
+        self.end_write();
+        held.release()?;
+        Ok(result)
+    }
+    /// Replace the value, returning the previous one. Both happen under a single lock acquisition,
+    /// so no other write can get in between.
+    pub fn swap(&self, data: T) -> CortexResult<T> {
+        self.with_write(|current| unsafe { std::ptr::replace(current, data) })
+    }
+    /// Take the value, leaving `T::default()` in its place under a single lock acquisition
+    pub fn take(&self) -> CortexResult<T>
+    where
+        T: Default,
+    {
+        self.swap(T::default())
+    }
+    /// Replace the value with `new` if it is equal to `expected`, under a single lock acquisition.
+    /// Returns the previous value in `Ok` if it was replaced, otherwise the current value in `Err`.
+    pub fn compare_exchange(&self, expected: T, new: T) -> CortexResult<Result<T, T>>
+    where
+        T: PartialEq,
+    {
+        let held = self.acquire_write()?;
+        if let Err(err) = self.check_data() {
+            held.release()?;
+            return Err(err);
+        }
+        let result = if unsafe { *self.ptr == expected } {
+            self.begin_write();
+            let previous = unsafe { std::ptr::replace(self.ptr, new) };
+            self.end_write();
+            Ok(previous)
+        } else {
+            Err(unsafe { self.ptr.read() })
+        };
+        held.release()?;
+        Ok(result)
+    }
+    /// Write a single field of the value, selected by `field`, without copying in the whole value.
+    /// The previous value of the field is overwritten without being dropped, like with `write`.
+    pub fn write_field<F>(
+        &self,
+        field: impl FnOnce(&mut T) -> &mut F,
+        value: F,
+    ) -> CortexResult<()> {
+        self.with_write(|data| unsafe { std::ptr::write(field(data), value) })
+    }
+    /// Read a single field of the value, selected by `field`, without copying out the whole value
+    pub fn read_field<F: Copy>(&self, field: impl FnOnce(&T) -> &F) -> CortexResult<F> {
+        self.with_read(|data| *field(data))
+    }
+    /// Check whether a writer died or panicked halfway through a write, leaving the data in an
+    /// unknown state. Only reliable while holding the lock.
+    fn is_poisoned(&self) -> bool {
+        let header = unsafe { &*self.header };
+        header.generation.load(Ordering::Acquire) % 2 == 1
+    }
+    /// The data as raw bytes, for checksums, or `None` unless `T` is known to be `Pod`. Borrowed
+    /// through `ptr`, since a reference to the header is only valid for the header itself and not
+    /// for the data behind it.
+    fn payload(&self) -> Option<&[u8]> {
+        self.pod.then(|| unsafe {
+            std::slice::from_raw_parts(self.ptr as *const u8, std::mem::size_of::<T>())
+        })
+    }
+    /// Mark the start of a write, which poisons the data until `end_write`
+    fn begin_write(&self) {
+        let header = unsafe { &*self.header };
+        header.begin_write();
+    }
+    /// Mark the end of a write, updating the checksum of the data
+    fn end_write(&self) {
+        let header = unsafe { &*self.header };
+        header.end_write(self.payload());
+    }
+    /// Check that the data can be read, i.e. that it is neither poisoned nor corrupted. Only
+    /// reliable while holding the lock.
+    fn check_data(&self) -> CortexResult<()> {
+        if self.is_poisoned() {
+            instrument::error(self.key, &CortexError::Poisoned);
+            return Err(CortexError::Poisoned);
+        }
+        let Some(payload) = self.payload() else {
+            // Attaching made sure that the segment keeps no checksum
+            return Ok(());
+        };
+        let header = unsafe { &*self.header };
+        header
+            .verify_checksum(payload)
+            .inspect_err(|err| instrument::error(self.key, err))
+    }
+    /// Copy the value into a new segment on a random key, owned by the returned handle, e.g. for
+    /// analytics that work through a consistent copy without holding up writers. Only the copy
+    /// itself happens under the read lock, the new segment is created after releasing it. The
+    /// snapshot keeps the schema version and checksum setting of this segment, and gets a new
+    /// lock with the default settings of `L`. Other processes can attach to it through its `key`.
+    pub fn fork_snapshot(&self) -> CortexResult<Self> {
+        let header = unsafe { &*self.header };
+        let options = CortexOptions {
+            checksum: self.pod && header.has_flag(FLAG_CHECKSUM),
+            schema_version: self.schema_version(),
+            ..Default::default()
+        };
+        let data = self.read()?;
+        let snapshot = Self::create(None, data, &options, None)?;
+        crate::trace::info!(
+            "Forked snapshot of shared memory with key: {} to key: {}",
+            self.key,
+            snapshot.key
+        );
+        Ok(snapshot)
+    }
+    /// Clear the poisoned state after an interrupted write, accepting the data as it currently is.
+    /// Returns `true` if the data was poisoned.
+    pub fn clear_poison(&self) -> CortexResult<bool> {
+        let held = self.acquire_write()?;
+        let poisoned = self.is_poisoned();
+        if poisoned {
+            self.end_write();
+            crate::trace::warning!("Cleared poison of shared memory with id: {}", self.id);
+        }
+        held.release()?;
+        Ok(poisoned)
+    }
+    /// Make the value immutable, e.g. to publish a lookup table to a pool of workers once it is
+    /// initialized. Every handle, in any process, fails to write from then on with
+    /// `ErrorKind::PermissionDenied`, and this handle reads through a mapping with `SHM_RDONLY`,
+    /// so that even a stray write through a raw pointer faults. Handles attaching later map the
+    /// data read-only as well. Sealing can't be undone, and sealing a sealed segment does nothing.
+    pub fn seal(&mut self) -> CortexResult<()> {
+        let header = unsafe { &*self.header };
+        if !header.has_flag(FLAG_SEALED) {
+            match self.acquire_write() {
+                Ok(held) => {
+                    // Don't freeze a torn or corrupted value
+                    if let Err(err) = self.check_data() {
+                        held.release()?;
+                        return Err(err);
+                    }
+                    header.set_flag(FLAG_SEALED);
+                    held.release()?;
+                    crate::trace::info!("Sealed shared memory with key: {}", self.key);
+                }
+                // Sealed by another handle in between
+                Err(_) if header.has_flag(FLAG_SEALED) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        self.map_sealed()
+    }
+    /// Whether the value was made immutable through `seal`
+    pub fn is_sealed(&self) -> bool {
+        let header = unsafe { &*self.header };
+        header.has_flag(FLAG_SEALED)
+    }
+    /// Read the data through a mapping with `SHM_RDONLY` from now on
+    fn map_sealed(&mut self) -> CortexResult<()> {
+        if self.read_only || self.sealed.is_some() {
+            return Ok(());
+        }
+        let mapping = unsafe { mem::shmat(self.id, std::ptr::null_mut(), libc::SHM_RDONLY) };
+        if mapping as isize == -1 {
+            return Err(CortexError::new_clean(format!(
+                "Error during read-only shmat for id: {}",
+                self.id
+            )));
+        }
+        self.ptr = unsafe { (mapping as *mut u8).add(data_offset::<T>()) as *mut T };
+        self.sealed = Some(mapping);
+        Ok(())
+    }
+    /// Fail if the value was sealed, must be checked after acquiring the write lock
+    fn check_writable(&self) -> CortexResult<()> {
+        if self.is_sealed() {
+            return Err(CortexError::new_logic(
+                ErrorKind::PermissionDenied,
+                format!("Shared memory with key: {} is sealed", self.key),
+            ));
         }
         Ok(())
     }
     pub fn key(&self) -> i32 {
         self.key
     }
+    /// Id of the underlying System V segment, as listed by `ipcs -m`. Negative for segments
+    /// emulated on the heap by `MemBackend`.
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+    /// Size of the stored value in bytes, not counting the header in front of it
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    /// Whether this instance is responsible for removing the segment when dropped. Always `false`
+    /// for ref counted segments, where the last instance to be dropped cleans up instead.
+    pub fn is_owner(&self) -> bool {
+        self.is_owner && !self.registration.inherited()
+    }
+    /// Number of processes currently attached to the segment, as reported by the kernel. A process
+    /// holding several instances is counted once per instance.
+    pub fn attach_count(&self) -> CortexResult<usize> {
+        Ok(stat(self.id)?.shm_nattch as usize)
+    }
+    /// Application defined version of the stored value, see `CortexBuilder::schema_version`
+    pub fn schema_version(&self) -> u32 {
+        let header = unsafe { &*self.header };
+        header.schema_version.load(Ordering::Acquire)
+    }
+    /// When the segment was created
+    pub fn created_at(&self) -> std::time::SystemTime {
+        let header = unsafe { &*self.header };
+        std::time::UNIX_EPOCH + Duration::from_nanos(header.created_at)
+    }
+    /// Number of randomly generated keys that were already taken before a free one was found,
+    /// always 0 when the key was chosen explicitly or the segment was attached to
+    pub fn key_collisions(&self) -> u32 {
+        self.key_collisions
+    }
+    /// The process and thread that last acquired the lock through a `Cortex`, or `None` if it is
+    /// not held. Meant for diagnosing stuck processes, with concurrent readers only one of them is
+    /// reported.
+    pub fn lock_holder(&self) -> Option<LockHolderInfo> {
+        let header = unsafe { &*self.header };
+        let holder = header.lock_holder.load(Ordering::Acquire);
+        if holder == 0 {
+            return None;
+        }
+        let acquired = header.lock_acquired.load(Ordering::Relaxed);
+        Some(LockHolderInfo {
+            pid: (holder >> 32) as i32,
+            thread_id: holder as u32,
+            acquired_at: std::time::UNIX_EPOCH + Duration::from_millis(acquired),
+        })
+    }
+    /// Log a warning every `after` while blocked waiting for the lock, including who currently
+    /// holds it. Pass `None` to turn the warning off again. Requires a lock implementation with
+    /// timed locking support.
+    pub fn set_lock_warning(&mut self, after: Option<Duration>) {
+        self.lock_warning = after;
+    }
+    /// Start or stop collecting lock stats for this instance, see `lock_stats`. Stopping discards
+    /// the stats collected so far.
+    pub fn set_lock_stats(&mut self, enabled: bool) {
+        self.stats = enabled.then(LockStatsCollector::new);
+    }
+    /// Lock contention stats of this instance since stats were enabled or last reset, `None` if
+    /// they are not being collected. Only covers the lock operations of this instance, not those
+    /// of other processes attached to the same segment.
+    pub fn lock_stats(&self) -> Option<LockStats> {
+        self.stats.as_ref().map(LockStatsCollector::snapshot)
+    }
+    /// Reset all lock stats of this instance to zero
+    pub fn reset_lock_stats(&self) {
+        if let Some(stats) = &self.stats {
+            stats.reset();
+        }
+    }
+    /// Access the underlying lock implementation, e.g. to query backend specific state
+    pub fn lock_backend(&self) -> &L {
+        &self.lock
+    }
+    /// Record a heartbeat in the segment header. Meant to be called periodically by the owner so
+    /// that attached processes can tell whether the data is still being maintained.
+    pub fn beat(&self) {
+        let header = unsafe { &*self.header };
+        header.heartbeat.store(unix_millis(), Ordering::Release);
+    }
+    /// Time of the last heartbeat, `None` if no heartbeat has been recorded yet
+    pub fn last_beat(&self) -> Option<std::time::SystemTime> {
+        let header = unsafe { &*self.header };
+        match header.heartbeat.load(Ordering::Acquire) {
+            0 => None,
+            millis => Some(std::time::UNIX_EPOCH + std::time::Duration::from_millis(millis)),
+        }
+    }
+    /// Check whether the owner has recorded a heartbeat within `max_age`
+    pub fn owner_alive(&self, max_age: std::time::Duration) -> bool {
+        let header = unsafe { &*self.header };
+        match header.heartbeat.load(Ordering::Acquire) {
+            0 => false,
+            millis => unix_millis().saturating_sub(millis) <= max_age.as_millis() as u64,
+        }
+    }
+    /// Give up responsibility for cleaning up the segment, so that another process can `adopt` it.
+    /// This instance remains usable, but will only detach when dropped.
+    ///
+    /// If no other process adopts the segment it will be left behind in the system, in the same
+    /// way as if the owner had crashed.
+    pub fn transfer_ownership(&mut self) -> CortexResult<()> {
+        if !self.is_owner {
+            return Err(CortexError::new_logic(
+                ErrorKind::PermissionDenied,
+                format!(
+                    "Cannot transfer ownership of key: {} without being the owner",
+                    self.key
+                ),
+            ));
+        }
+        let header = unsafe { &*self.header };
+        header.owner_pid.store(0, Ordering::Release);
+        self.is_owner = false;
+        self.lock.relinquish_ownership();
+        cleanup::released(self.registration);
+        self.registration = Registration::default();
+        crate::trace::trace!(
+            "Transferred ownership of shared memory with id: {}",
+            self.id
+        );
+        Ok(())
+    }
+    /// Take over ownership of a segment previously given up through `transfer_ownership`.
+    /// Returns `false` if the segment is still owned by someone else.
+    pub fn adopt(&mut self) -> bool {
+        if self.is_owner {
+            return true;
+        }
+        let header = unsafe { &*self.header };
+        if header.has_flag(FLAG_REF_COUNTED) {
+            return false;
+        }
+        let pid = unsafe { libc::getpid() };
+        if header
+            .owner_pid
+            .compare_exchange(0, pid, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return false;
+        }
+        self.is_owner = true;
+        self.lock.force_ownership();
+        self.registration = cleanup::owned(|| self.resource());
+        crate::trace::trace!("Adopted shared memory with id: {}", self.id);
+        true
+    }
+    /// Prepare a handle inherited through `fork` for use in the child, which must be done exactly
+    /// once before using it there. The child never owns the segment, locks that can't be shared
+    /// across `fork` are opened again, e.g. `FileLock`, whose lock would otherwise be held by
+    /// parent and child at the same time, and the child counts as another reference to ref
+    /// counted segments.
+    ///
+    /// Nothing checks that this was called. Dropping a handle that wasn't prepared is safe, since
+    /// it notices that it was inherited and neither removes the segment nor gives back a
+    /// reference the child never took. Using one is not: its `FileLock` is shared with the parent,
+    /// so both can hold the write lock at once, and a ref counted segment can be removed while the
+    /// child is still using it, since the child doesn't count as a reference.
+    pub fn after_fork_child(&mut self) -> CortexResult<()> {
+        self.lock.after_fork_child()?;
+        let header = unsafe { &*self.header };
+        if header.has_flag(FLAG_REF_COUNTED) && !self.read_only {
+            header.ref_count.fetch_add(1, Ordering::AcqRel);
+            self.counted_by = unsafe { libc::getpid() };
+        }
+        self.is_owner = false;
+        self.registration = Registration::default();
+        crate::trace::trace!("Prepared shared memory with id: {} after fork", self.id);
+        Ok(())
+    }
+    /// Pid of the process currently responsible for cleaning up the segment, `None` if ownership
+    /// has been given up and not yet adopted
+    pub fn owner_pid(&self) -> Option<i32> {
+        let header = unsafe { &*self.header };
+        match header.owner_pid.load(Ordering::Acquire) {
+            0 => None,
+            pid => Some(pid),
+        }
+    }
     fn force_ownership(&mut self) {
+        let header = unsafe { &*self.header };
+        header
+            .owner_pid
+            .store(unsafe { libc::getpid() }, Ordering::Release);
+        if !self.is_owner {
+            self.registration = cleanup::owned(|| self.resource());
+        }
         self.is_owner = true;
         self.lock.force_ownership();
     }
+    fn resource(&self) -> cleanup::OwnedResource {
+        cleanup::OwnedResource::Segment {
+            key: self.key,
+            id: self.id,
+        }
+    }
+}
+
+// Dumps hold the bytes of the data, including any padding, and restoring one turns arbitrary bytes
+// back into a `T`
+impl<T: Pod, L: CortexSync> Cortex<T, L> {
+    /// Dump the segment, header included, to the file at `path` under the read lock, e.g. to
+    /// persist state across restarts of the whole system or to attach it to a bug report. The
+    /// data is dumped as is, even if it is poisoned. Requires `T: Pod`, since every byte of the
+    /// data ends up in the file.
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> CortexResult<()> {
+        let held = self.acquire_read()?;
+        let segment =
+            unsafe { std::slice::from_raw_parts(self.header as *const u8, segment_size::<T>()) }
+                .to_vec();
+        held.release()?;
+        dump::write_dump(path.as_ref(), &segment)
+    }
+    /// Replace the value with one dumped by `snapshot_to`, under the write lock. Fails with
+    /// `ErrorKind::TypeMismatch` unless the dump was taken of a segment holding the same type
+    /// with the same layout and schema version, and with `ErrorKind::Poisoned` if it was taken
+    /// halfway through a write, see `restore_poisoned_from`. Dumps of a segment that keeps a
+    /// checksum are verified against it first, failing with `ErrorKind::Corrupted` if the data
+    /// was modified since. Like `write`, this clears any poison.
+    /// Requires `T: Pod`, since nothing guarantees that the bytes in the file are a valid `T`
+    /// otherwise.
+    pub fn restore_from(&self, path: impl AsRef<Path>) -> CortexResult<()> {
+        self.restore(path.as_ref(), false)
+    }
+    /// Like `restore_from`, but also accepts a dump taken halfway through a write, such as one
+    /// written by `crash_dump_on_panic`. The restored data might be partially written, so the
+    /// segment is left poisoned until it is dealt with through `clear_poison` or `write`.
+    pub fn restore_poisoned_from(&self, path: impl AsRef<Path>) -> CortexResult<()> {
+        self.restore(path.as_ref(), true)
+    }
+    fn restore(&self, path: &Path, allow_poisoned: bool) -> CortexResult<()> {
+        let (dumped, bytes) = dump::read_dump::<T>(path, self.key)?;
+        let schema_version = dumped.schema_version.load(Ordering::Relaxed);
+        if schema_version != self.schema_version() {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                    "Dump {} has schema version {}, but the segment has {}",
+                    path.display(),
+                    schema_version,
+                    self.schema_version()
+                ),
+            ));
+        }
+        let poisoned = dumped.generation.load(Ordering::Relaxed) % 2 == 1;
+        if poisoned && !allow_poisoned {
+            return Err(CortexError::new_logic(
+                ErrorKind::Poisoned,
+                format!(
+                    "Dump {} was taken during a write, restore it through restore_poisoned_from",
+                    path.display()
+                ),
+            ));
+        }
+        // The checksum in the dump is the one of the last completed write, so it can't match a dump
+        // taken during a write
+        let payload = &bytes[data_offset::<T>()..];
+        if !poisoned && dumped.verify_checksum(payload).is_err() {
+            return Err(CortexError::new_logic(
+                ErrorKind::Corrupted,
+                format!("Dump {} doesn't match its checksum", path.display()),
+            ));
+        }
+        let held = self.acquire_write()?;
+        if !self.is_poisoned() {
+            self.begin_write();
+        }
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                payload.as_ptr(),
+                self.ptr as *mut u8,
+                std::mem::size_of::<T>(),
+            )
+        };
+        if poisoned {
+            crate::trace::warning!(
+                "Restored shared memory with id: {} from a dump taken during a write, leaving it \
+                 poisoned",
+                self.id
+            );
+        } else {
+            self.end_write();
+        }
+        held.release()
+    }
+    /// Write a dump of the segment to `dir` if the current thread panics before the returned guard
+    /// is dropped, for post-mortem debugging of shared state. Besides the segment itself, which
+    /// can be loaded through `restore_poisoned_from`, a text file describes the state of the lock.
+    /// The dump is taken without the lock, since the panicking thread might hold it. Requires
+    /// unwinding panics, nothing is written with `panic = "abort"`.
+    pub fn crash_dump_on_panic(&self, dir: impl Into<PathBuf>) -> CrashDump<'_, T, L> {
+        CrashDump::new(self, dir.into())
+    }
+}
+
+impl<const N: usize, L: CortexSync> Cortex<[u8; N], L> {
+    fn check_range(&self, offset: usize, len: usize) -> CortexResult<()> {
+        if offset.checked_add(len).is_none_or(|end| end > N) {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Byte range {}..{} is out of bounds for a buffer of {} bytes",
+                    offset,
+                    offset.saturating_add(len),
+                    N
+                ),
+            ));
+        }
+        Ok(())
+    }
+    /// Read `len` bytes starting at `offset`, without copying out the whole buffer
+    pub fn read_bytes(&self, offset: usize, len: usize) -> CortexResult<Vec<u8>> {
+        self.check_range(offset, len)?;
+        self.with_read(|data| data[offset..offset + len].to_vec())
+    }
+    /// Write `bytes` starting at `offset`, without copying in the whole buffer
+    pub fn write_bytes(&self, offset: usize, bytes: &[u8]) -> CortexResult<()> {
+        self.check_range(offset, bytes.len())?;
+        self.with_write(|data| data[offset..offset + bytes.len()].copy_from_slice(bytes))
+    }
 }
 
 /// Drop a segment of shared memory
-impl<T, L> Drop for Cortex<T, L> {
+impl<T, L: CortexSync> Drop for Cortex<T, L> {
     fn drop(&mut self) {
-        tracing::trace!("Dropping shared memory with id: {}", self.id);
+        crate::trace::trace!("Dropping shared memory with id: {}", self.id);
 
-        if let Err(err) = detach(self.id, self.ptr as *const libc::c_void) {
-            tracing::error!("Error during detach in Drop: {}", err)
+        let header = unsafe { &*self.header };
+        // Only give back a reference taken by this process, not one inherited through `fork`
+        if self.counted_by == unsafe { libc::getpid() }
+            && header.ref_count.fetch_sub(1, Ordering::AcqRel) == 1
+        {
+            // Last one out cleans up, including the lock
+            self.is_owner = true;
+            self.lock.force_ownership();
         }
-        if !self.is_owner {
+        if let Err(err) = detach(self.id, self.header as *const libc::c_void) {
+            crate::trace::error!("Error during detach in Drop: {}", err)
+        }
+        if let Some(mapping) = self.sealed {
+            if let Err(err) = detach(self.id, mapping) {
+                crate::trace::error!("Error during detach in Drop: {}", err)
+            }
+        }
+        // Already removed if `cleanup_all` got to it first
+        if !self.is_owner || !cleanup::released(self.registration) {
             return;
         }
         if let Err(err) = mark_for_deletion(self.id) {
-            tracing::error!("Error during mark_for_deletion in Drop: {}", err)
+            crate::trace::error!("Error during mark_for_deletion in Drop: {}", err)
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, SpinLock};
+
+    #[test]
+    fn random_keys() {
+        let first: Cortex<_, SpinLock> = Cortex::new(None, 0u64, false, None).unwrap();
+        let second: Cortex<_, SpinLock> = Cortex::new(None, 0u64, false, None).unwrap();
+        assert!(first.key() > 0);
+        assert_ne!(first.key(), second.key());
+        assert_eq!(first.key_collisions(), 0);
+    }
+
+    #[test]
+    fn field_projection() {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        struct Quote {
+            price: f64,
+            volume: u64,
+        }
+        let key = rand::random::<i32>().abs();
+        let quote = Quote {
+            price: 1.0,
+            volume: 10,
+        };
+        let cortex: Cortex<_, SpinLock> = Cortex::new(Some(key), quote, false, None).unwrap();
+
+        cortex.write_field(|quote| &mut quote.price, 42.0).unwrap();
+        assert_eq!(cortex.read_field(|quote| &quote.price).unwrap(), 42.0);
+        cortex
+            .with_write(|quote| {
+                quote.price += 1.0;
+                quote.volume += 1;
+            })
+            .unwrap();
+        assert_eq!(
+            cortex.read().unwrap(),
+            Quote {
+                price: 43.0,
+                volume: 11
+            }
+        );
+    }
+
+    #[test]
+    fn byte_ranges() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, SpinLock> = Cortex::new(Some(key), [0u8; 64], false, None).unwrap();
+
+        cortex.write_bytes(60, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(cortex.read_bytes(59, 5).unwrap(), vec![0, 1, 2, 3, 4]);
+        assert!(cortex.write_bytes(61, &[1, 2, 3, 4]).is_err());
+        assert!(cortex.read_bytes(usize::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn swap_and_take() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, SpinLock> = Cortex::new(Some(key), 1u64, false, None).unwrap();
+
+        assert_eq!(cortex.swap(2).unwrap(), 1);
+        assert_eq!(cortex.take().unwrap(), 2);
+        assert_eq!(cortex.read().unwrap(), 0);
+    }
+
+    #[test]
+    fn compare_exchange() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, SpinLock> = Cortex::new(Some(key), 1u64, false, None).unwrap();
+
+        assert_eq!(cortex.compare_exchange(1, 2).unwrap(), Ok(1));
+        assert_eq!(cortex.compare_exchange(1, 3).unwrap(), Err(2));
+        assert_eq!(cortex.read().unwrap(), 2);
+    }
+
+    #[test]
+    fn versioned_reads() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, SpinLock> = Cortex::new(Some(key), 1u64, false, None).unwrap();
+
+        let initial = cortex.read_versioned().unwrap();
+        assert_eq!((initial.value, initial.version), (1, 0));
+        cortex.write(2).unwrap();
+        let written = cortex.read_versioned().unwrap();
+        assert_eq!((written.value, written.version), (2, 1));
+        assert!(written.written_at >= initial.written_at);
+        // Failed exchanges don't count as writes
+        assert!(cortex.compare_exchange(1, 3).unwrap().is_err());
+        assert_eq!(cortex.read_versioned().unwrap().version, 1);
+    }
+
+    #[test]
+    fn segment_metadata() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, SpinLock> = Cortex::new(Some(key), [0u32; 16], false, None).unwrap();
+        let attached: Cortex<[u32; 16], SpinLock> = Cortex::attach(key).unwrap();
+
+        assert_eq!(cortex.size(), 64);
+        assert_eq!(cortex.id(), attached.id());
+        assert!(cortex.is_owner() && !attached.is_owner());
+        assert_eq!(attached.attach_count().unwrap(), 2);
+        assert_eq!(cortex.created_at(), attached.created_at());
+        assert!(cortex.created_at() <= std::time::SystemTime::now());
+    }
+}
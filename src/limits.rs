@@ -0,0 +1,57 @@
+//! Upper bound on the size of new segments, so that a mistyped const generic or capacity fails
+//! early instead of reserving gigabytes of shared memory, see `set_max_segment_size`.
+
+use crate::{
+    crash::{CortexError, ErrorKind},
+    CortexResult,
+};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Limit on the size of new segments unless changed through `set_max_segment_size`
+pub const DEFAULT_MAX_SEGMENT_SIZE: usize = 1 << 30;
+
+static MAX_SEGMENT_SIZE: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_SEGMENT_SIZE);
+
+/// Limit the size in bytes of every segment created by this process, including the segments
+/// behind collections as they grow. Creating a larger one fails with `ErrorKind::TooLarge`
+/// before anything is allocated. Pass `usize::MAX` to lift the limit, or override it for a
+/// single segment through `CortexBuilder::max_size`.
+pub fn set_max_segment_size(bytes: usize) {
+    MAX_SEGMENT_SIZE.store(bytes, Ordering::Relaxed);
+}
+
+/// The limit set through `set_max_segment_size`, `DEFAULT_MAX_SEGMENT_SIZE` unless changed
+pub fn max_segment_size() -> usize {
+    MAX_SEGMENT_SIZE.load(Ordering::Relaxed)
+}
+
+/// Fail with `ErrorKind::TooLarge` if a segment of `size` bytes exceeds `limit`, or the global
+/// limit if `None`
+pub(crate) fn check_size(size: usize, limit: Option<usize>) -> CortexResult<()> {
+    let limit = limit.unwrap_or_else(max_segment_size);
+    if size > limit {
+        return Err(CortexError::new_logic(
+            ErrorKind::TooLarge,
+            format!(
+                "Segment of {} bytes exceeds the limit of {} bytes, see set_max_segment_size",
+                size, limit
+            ),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::limits::{check_size, max_segment_size};
+    use crate::ErrorKind;
+
+    #[test]
+    fn global_limit() {
+        let limit = max_segment_size();
+        assert!(check_size(limit, None).is_ok());
+        let err = check_size(limit + 1, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TooLarge);
+        assert!(check_size(limit + 1, Some(usize::MAX)).is_ok());
+    }
+}
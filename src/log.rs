@@ -0,0 +1,355 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    mark_for_deletion,
+    segment::Segment,
+    Cortex, CortexResult, CortexSync, ShmString,
+};
+use std::ops::Range;
+
+/// Maximum number of consumers that can be registered on a log at the same time
+const MAX_CONSUMERS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct ConsumerSlot {
+    name: ShmString<32>,
+    /// Offset of the next record the consumer has yet to process
+    committed: u64,
+    in_use: bool,
+}
+
+#[derive(Debug)]
+struct LogState {
+    /// Id of the segment holding the records
+    data_id: i32,
+    capacity: usize,
+    /// Size of a record, to catch attaching with a different record type
+    record_size: usize,
+    /// Offset the next record is appended at, records are stored at their offset modulo capacity
+    head: u64,
+    consumers: [ConsumerSlot; MAX_CONSUMERS],
+}
+
+impl LogState {
+    /// Offset of the oldest record that hasn't been overwritten yet
+    fn tail(&self) -> u64 {
+        self.head.saturating_sub(self.capacity as u64)
+    }
+    fn find(&self, name: &str) -> Option<usize> {
+        self.consumers
+            .iter()
+            .position(|slot| slot.in_use && slot.name == name)
+    }
+}
+
+/// Records read by `LogConsumer::poll`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogBatch<T> {
+    /// Offset of the first record in `records`
+    pub offset: u64,
+    pub records: Vec<T>,
+    /// Number of records that were overwritten before the consumer got to them, and are lost
+    pub missed: u64,
+}
+
+/// Process-shared append-only log, protected by `L`.
+///
+/// Records are stored in a ring of fixed capacity, and every record gets a monotonically
+/// increasing offset. Producers never block on slow consumers, once the ring is full the oldest
+/// records are overwritten. Consumers register under a name and commit the offset they have
+/// processed up to in the segment, so that they can pick up where they left off after a restart
+/// and so that their lag can be monitored. Records are copied in and out of shared memory, so
+/// they have to be `Copy`.
+#[derive(Debug)]
+pub struct CortexLog<T: Copy, L: CortexSync> {
+    state: Cortex<LogState, L>,
+    records: Segment<T>,
+}
+
+unsafe impl<T: Copy + Send + Sync, L: CortexSync + Send> Send for CortexLog<T, L> {}
+unsafe impl<T: Copy + Send + Sync, L: CortexSync + Sync> Sync for CortexLog<T, L> {}
+
+impl<T: Copy, L: CortexSync> CortexLog<T, L> {
+    /// Create a new empty log on `key` that retains the last `capacity` records
+    pub fn new(key: i32, capacity: usize) -> CortexResult<Self> {
+        if capacity == 0 {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                "Log capacity must be at least 1",
+            ));
+        }
+        let records = Segment::create(capacity, L::IN_PROCESS)?;
+        let state = LogState {
+            data_id: records.id,
+            capacity,
+            record_size: std::mem::size_of::<T>(),
+            head: 0,
+            consumers: [ConsumerSlot {
+                name: ShmString::new(),
+                committed: 0,
+                in_use: false,
+            }; MAX_CONSUMERS],
+        };
+        let state = Cortex::new(Some(key), state, false, None).inspect_err(|_| {
+            if let Err(err) = mark_for_deletion(records.id) {
+                crate::trace::error!("Error cleaning up after failed create: {}", err)
+            }
+        })?;
+        Ok(Self { state, records })
+    }
+    /// Attempt to attach to an already existing log
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let state: Cortex<LogState, L> = Cortex::attach(key)?;
+        let (data_id, record_size) = state.with_read(|state| (state.data_id, state.record_size))?;
+        if record_size != std::mem::size_of::<T>() {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                "Record size mismatch for key: {}, existing log has {} byte records but {} were \
+                 expected",
+                key,
+                record_size,
+                std::mem::size_of::<T>()
+            ),
+            ));
+        }
+        let records = Segment::attach(data_id)?;
+        Ok(Self { state, records })
+    }
+    /// Append a record, returning its offset
+    pub fn append(&self, record: T) -> CortexResult<u64> {
+        self.append_all(&[record])
+    }
+    /// Append all of `records` under a single lock acquisition, returning the offset of the first
+    pub fn append_all(&self, records: &[T]) -> CortexResult<u64> {
+        self.state.with_write(|state| {
+            let offset = state.head;
+            for record in records {
+                let index = (state.head % state.capacity as u64) as usize;
+                unsafe { self.records.ptr.add(index).write(*record) };
+                state.head += 1;
+            }
+            offset
+        })
+    }
+    /// Register a consumer under `name`, or resume an existing one from its committed offset. New
+    /// consumers start at the end of the log, so they only see records appended from now on. Up to
+    /// 16 consumers can be registered at the same time.
+    pub fn consumer(&self, name: &str) -> CortexResult<LogConsumer<'_, T, L>> {
+        let name = ShmString::try_from(name)?;
+        let position = self.state.with_write(|state| {
+            if let Some(slot) = state.find(&name) {
+                return Ok(state.consumers[slot].committed);
+            }
+            let Some(slot) = state.consumers.iter_mut().find(|slot| !slot.in_use) else {
+                return Err(CortexError::new_logic(
+                    ErrorKind::NoSpace,
+                    format!(
+                        "All {} consumer slots of log with key: {} are in use",
+                        MAX_CONSUMERS,
+                        self.key()
+                    ),
+                ));
+            };
+            *slot = ConsumerSlot {
+                name,
+                committed: state.head,
+                in_use: true,
+            };
+            Ok(state.head)
+        })??;
+        Ok(LogConsumer {
+            log: self,
+            name,
+            position,
+        })
+    }
+    /// Unregister the consumer `name`, freeing its slot. Returns `false` if there was none.
+    pub fn remove_consumer(&self, name: &str) -> CortexResult<bool> {
+        self.state.with_write(|state| match state.find(name) {
+            Some(slot) => {
+                state.consumers[slot].in_use = false;
+                true
+            }
+            None => false,
+        })
+    }
+    /// Number of records appended but not yet committed by the consumer `name`, `None` if there
+    /// is no such consumer
+    pub fn lag(&self, name: &str) -> CortexResult<Option<u64>> {
+        self.state.with_read(|state| {
+            let slot = state.find(name)?;
+            Some(state.head - state.consumers[slot].committed)
+        })
+    }
+    /// Copy out the records in `offsets`, which must not have been overwritten yet
+    fn read_records(&self, state: &LogState, offsets: Range<u64>) -> Vec<T> {
+        offsets
+            .map(|offset| {
+                let index = (offset % state.capacity as u64) as usize;
+                unsafe { self.records.ptr.add(index).read() }
+            })
+            .collect()
+    }
+    /// Copy out every record that hasn't been overwritten yet under a single lock acquisition,
+    /// without touching the position of any consumer
+    pub fn snapshot(&self) -> CortexResult<LogBatch<T>> {
+        self.state.with_read(|state| {
+            let offset = state.tail();
+            let records = self.read_records(state, offset..state.head);
+            LogBatch {
+                offset,
+                records,
+                missed: 0,
+            }
+        })
+    }
+    /// Offset the next record will be appended at, which is also the total number of records
+    /// appended so far
+    pub fn head(&self) -> CortexResult<u64> {
+        self.state.read_field(|state| &state.head)
+    }
+    /// Number of records retained before the oldest ones are overwritten
+    pub fn capacity(&self) -> usize {
+        // Never changes after creation, so there is nothing to lock
+        unsafe { (*self.state.ptr).capacity }
+    }
+    pub fn key(&self) -> i32 {
+        self.state.key()
+    }
+}
+
+/// Remove the record segment along with the log
+impl<T: Copy, L: CortexSync> Drop for CortexLog<T, L> {
+    fn drop(&mut self) {
+        if !self.state.is_owner() {
+            return;
+        }
+        if let Err(err) = mark_for_deletion(self.records.id) {
+            crate::trace::error!("Error during mark_for_deletion in Drop: {}", err)
+        }
+    }
+}
+
+/// Named reader of a `CortexLog`, see `CortexLog::consumer`.
+///
+/// Keeps its own read position, which is only stored in the log once it is committed.
+#[derive(Debug)]
+pub struct LogConsumer<'a, T: Copy, L: CortexSync> {
+    log: &'a CortexLog<T, L>,
+    name: ShmString<32>,
+    /// Offset of the next record to read
+    position: u64,
+}
+
+impl<T: Copy, L: CortexSync> LogConsumer<'_, T, L> {
+    /// Read up to `max` records following the previously read ones, without committing them.
+    /// Records overwritten before they could be read are skipped and counted in `missed`.
+    pub fn poll(&mut self, max: usize) -> CortexResult<LogBatch<T>> {
+        let batch = self.log.state.with_read(|state| {
+            let offset = self.position.max(state.tail());
+            let end = state.head.min(offset.saturating_add(max as u64));
+            let records = self.log.read_records(state, offset..end);
+            LogBatch {
+                offset,
+                records,
+                missed: offset - self.position,
+            }
+        })?;
+        self.position = batch.offset + batch.records.len() as u64;
+        if batch.missed > 0 {
+            crate::trace::warning!(
+                "Consumer {} of log with key: {} missed {} overwritten records",
+                self.name,
+                self.log.key(),
+                batch.missed
+            );
+        }
+        Ok(batch)
+    }
+    /// Store the current read position in the log, marking everything polled so far as processed
+    pub fn commit(&self) -> CortexResult<()> {
+        self.commit_offset(self.position)
+    }
+    /// Store `offset` as the next record to process, e.g. to commit only part of a batch
+    pub fn commit_offset(&self, offset: u64) -> CortexResult<()> {
+        self.log.state.with_write(|state| {
+            let Some(slot) = state.find(&self.name) else {
+                return Err(CortexError::new_logic(
+                    ErrorKind::NotFound,
+                    format!(
+                        "Consumer {} of log with key: {} was removed",
+                        self.name,
+                        self.log.key()
+                    ),
+                ));
+            };
+            state.consumers[slot].committed = offset.min(state.head);
+            Ok(())
+        })?
+    }
+    /// Move the read position, e.g. back to the committed offset to process records again
+    pub fn seek(&mut self, offset: u64) {
+        self.position = offset;
+    }
+    /// Offset of the next record to read
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+    /// Number of records appended that this consumer hasn't read yet
+    pub fn lag(&self) -> CortexResult<u64> {
+        let head = self.log.head()?;
+        Ok(head.saturating_sub(self.position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::log::{CortexLog, LogBatch};
+    use crate::SpinLock;
+
+    #[test]
+    fn consumers_resume_from_commit() {
+        let key = rand::random::<i32>().abs();
+        let log: CortexLog<u32, SpinLock> = CortexLog::new(key, 8).unwrap();
+        let mut consumer = log.consumer("indexer").unwrap();
+        log.append_all(&[1, 2, 3]).unwrap();
+
+        let attached: CortexLog<u32, SpinLock> = CortexLog::attach(key).unwrap();
+        assert_eq!(attached.lag("indexer").unwrap(), Some(3));
+        let batch = consumer.poll(2).unwrap();
+        assert_eq!(
+            batch,
+            LogBatch {
+                offset: 0,
+                records: vec![1, 2],
+                missed: 0
+            }
+        );
+        consumer.commit().unwrap();
+        assert_eq!(attached.lag("indexer").unwrap(), Some(1));
+
+        // A restarted consumer picks up after the last commit
+        let mut resumed = attached.consumer("indexer").unwrap();
+        assert_eq!(resumed.poll(10).unwrap().records, [3]);
+        assert_eq!(resumed.lag().unwrap(), 0);
+        assert!(attached.remove_consumer("indexer").unwrap());
+        assert!(resumed.commit().is_err());
+    }
+
+    #[test]
+    fn overwrite_detection() {
+        let key = rand::random::<i32>().abs();
+        let log: CortexLog<u64, SpinLock> = CortexLog::new(key, 4).unwrap();
+        let mut consumer = log.consumer("slow").unwrap();
+        for record in 0..10 {
+            log.append(record).unwrap();
+        }
+        let batch = consumer.poll(10).unwrap();
+        assert_eq!(batch.offset, 6);
+        assert_eq!(batch.records, [6, 7, 8, 9]);
+        assert_eq!(batch.missed, 6);
+        let snapshot = log.snapshot().unwrap();
+        assert_eq!((snapshot.offset, snapshot.records), (6, vec![6, 7, 8, 9]));
+        assert_eq!(consumer.position(), 10);
+    }
+}
@@ -0,0 +1,469 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    mark_for_deletion, no_lock,
+    segment::Segment,
+    spin::Backoff,
+    Cortex, CortexResult, NoLock,
+};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+const EMPTY: u32 = 0;
+const OCCUPIED: u32 = 1;
+/// Removed entry, probing has to continue past it
+const TOMBSTONE: u32 = 2;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+
+/// Upper bound for the number of stripes, and the number of buckets per stripe below which the
+/// buckets aren't split up any further
+const MAX_STRIPES: usize = 64;
+
+/// FNV-1a, since the hash has to be the same in every process while `RandomState` is seeded per
+/// process
+struct FnvHasher(u64);
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn hash<K: Hash>(key: &K) -> u64 {
+    let mut hasher = FnvHasher(0xcbf29ce484222325);
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Settings of `CortexMap::with_settings`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MapSettings {
+    /// Number of stripes the buckets are split into, between 1 and 64 and at most the number of
+    /// buckets. `None` picks one stripe per 64 buckets, up to 64 stripes. More stripes let more
+    /// writers proceed in parallel, at the cost of fuller stripes when keys aren't spread evenly.
+    pub stripes: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MapState {
+    stripes_id: i32,
+    buckets_id: i32,
+    stripes: usize,
+    stripe_len: usize,
+    /// Size of a bucket, to catch attaching with different key or value types
+    bucket_size: usize,
+}
+
+/// Group of consecutive buckets sharing a lock. Zeroed memory is an unlocked empty stripe.
+#[derive(Debug)]
+#[repr(C)]
+struct Stripe {
+    lock: AtomicU32,
+    len: AtomicUsize,
+}
+
+/// Zeroed memory is an empty bucket
+#[derive(Debug)]
+#[repr(C)]
+struct Bucket<K, V> {
+    state: u32,
+    key: MaybeUninit<K>,
+    value: MaybeUninit<V>,
+}
+
+/// Process-shared hash map with a fixed number of buckets.
+///
+/// The buckets are split into stripes, each protected by its own spin lock, so that operations on
+/// keys in different stripes don't contend. Collisions are resolved through linear probing within
+/// the stripe of the key, which means inserting fails once that stripe is full, so leave plenty
+/// of headroom. Keys are hashed with FNV-1a, which gives the same result in every process as long
+/// as `K` hashes the same way everywhere. Keys and values are copied in and out of shared memory,
+/// so they have to be `Copy`.
+///
+/// A process that dies while holding the lock of a stripe leaves that stripe locked forever.
+#[derive(Debug)]
+pub struct CortexMap<K: Copy + Hash + Eq, V: Copy> {
+    state: Cortex<MapState, NoLock>,
+    stripes: Segment<Stripe>,
+    buckets: Segment<Bucket<K, V>>,
+    info: MapState,
+}
+
+unsafe impl<K: Copy + Hash + Eq + Send + Sync, V: Copy + Send + Sync> Send for CortexMap<K, V> {}
+unsafe impl<K: Copy + Hash + Eq + Send + Sync, V: Copy + Send + Sync> Sync for CortexMap<K, V> {}
+
+/// Buckets of a stripe, only accessible while holding its lock
+struct StripeGuard<'a, K, V> {
+    stripe: &'a Stripe,
+    buckets: &'a mut [Bucket<K, V>],
+}
+
+impl<K: Copy + Eq, V: Copy> StripeGuard<'_, K, V> {
+    /// Index of the bucket holding `key`, starting the search at `home`
+    fn find(&self, home: usize, key: &K) -> Option<usize> {
+        for probe in 0..self.buckets.len() {
+            let index = (home + probe) % self.buckets.len();
+            let bucket = &self.buckets[index];
+            match bucket.state {
+                EMPTY => return None,
+                OCCUPIED if unsafe { bucket.key.assume_init_ref() } == key => return Some(index),
+                _ => {}
+            }
+        }
+        None
+    }
+    /// Index of the first bucket that `key` could be inserted into, starting the search at `home`
+    fn vacant(&self, home: usize) -> Option<usize> {
+        (0..self.buckets.len())
+            .map(|probe| (home + probe) % self.buckets.len())
+            .find(|index| self.buckets[*index].state != OCCUPIED)
+    }
+}
+
+impl<K, V> Drop for StripeGuard<'_, K, V> {
+    fn drop(&mut self) {
+        self.stripe.lock.store(UNLOCKED, Ordering::Release);
+    }
+}
+
+impl<K: Copy + Hash + Eq, V: Copy> CortexMap<K, V> {
+    /// Create a new empty map on `key` with room for `buckets` entries, rounded up to split evenly
+    /// into stripes
+    pub fn new(key: i32, buckets: usize) -> CortexResult<Self> {
+        Self::with_settings(key, buckets, &MapSettings::default())
+    }
+    /// Create a new empty map on `key` with room for `buckets` entries, split into
+    /// `settings.stripes` stripes
+    pub fn with_settings(key: i32, buckets: usize, settings: &MapSettings) -> CortexResult<Self> {
+        let limit = MAX_STRIPES.min(buckets.max(1));
+        let stripes = match settings.stripes {
+            Some(stripes) if stripes == 0 || stripes > limit => {
+                return Err(CortexError::new_logic(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Invalid number of stripes: {}, it must be between 1 and {} for {} buckets",
+                        stripes, limit, buckets
+                    ),
+                ));
+            }
+            Some(stripes) => stripes,
+            None => (buckets / MAX_STRIPES).clamp(1, MAX_STRIPES),
+        };
+        let stripe_len = buckets.div_ceil(stripes).max(1);
+        let stripe_segment = Segment::<Stripe>::create(stripes, false)?;
+        let bucket_segment = match Segment::<Bucket<K, V>>::create(stripes * stripe_len, false) {
+            Ok(segment) => segment,
+            Err(err) => {
+                if let Err(err) = mark_for_deletion(stripe_segment.id) {
+                    crate::trace::error!("Error cleaning up after failed create: {}", err)
+                }
+                return Err(err);
+            }
+        };
+        let info = MapState {
+            stripes_id: stripe_segment.id,
+            buckets_id: bucket_segment.id,
+            stripes,
+            stripe_len,
+            bucket_size: std::mem::size_of::<Bucket<K, V>>(),
+        };
+        let state =
+            Cortex::new(Some(key), info, false, Some(no_lock::INTERNAL)).inspect_err(|_| {
+                for id in [stripe_segment.id, bucket_segment.id] {
+                    if let Err(err) = mark_for_deletion(id) {
+                        crate::trace::error!("Error cleaning up after failed create: {}", err)
+                    }
+                }
+            })?;
+        Ok(Self {
+            state,
+            stripes: stripe_segment,
+            buckets: bucket_segment,
+            info,
+        })
+    }
+    /// Attempt to attach to an already existing map
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let state: Cortex<MapState, NoLock> = Cortex::attach_with_lock(key, no_lock::INTERNAL)?;
+        // Never changes after creation, so there is nothing to lock
+        let info = state.read()?;
+        if info.bucket_size != std::mem::size_of::<Bucket<K, V>>() {
+            return Err(CortexError::new_logic(
+                ErrorKind::TypeMismatch,
+                format!(
+                "Bucket size mismatch for key: {}, existing map has {} byte buckets but {} were \
+                 expected",
+                key,
+                info.bucket_size,
+                std::mem::size_of::<Bucket<K, V>>()
+            ),
+            ));
+        }
+        Ok(Self {
+            stripes: Segment::attach(info.stripes_id)?,
+            buckets: Segment::attach(info.buckets_id)?,
+            state,
+            info,
+        })
+    }
+    /// Lock the stripe that `key` belongs to, returning it along with the bucket to start probing
+    /// from
+    fn lock(&self, key: &K) -> (StripeGuard<'_, K, V>, usize) {
+        let hash = hash(key) as usize;
+        let (index, home) = (hash % self.info.stripes, hash / self.info.stripes);
+        (self.lock_stripe(index), home % self.info.stripe_len)
+    }
+    fn lock_stripe(&self, index: usize) -> StripeGuard<'_, K, V> {
+        let stripe = unsafe { &*self.stripes.ptr.add(index) };
+        let mut backoff = Backoff::new();
+        while stripe
+            .lock
+            .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+        let buckets = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.buckets.ptr.add(index * self.info.stripe_len),
+                self.info.stripe_len,
+            )
+        };
+        StripeGuard { stripe, buckets }
+    }
+    /// Insert a value, returning the previous value for the key if there was one. Fails if the
+    /// stripe of the key is full.
+    pub fn insert(&self, key: K, value: V) -> CortexResult<Option<V>> {
+        let (stripe, home) = self.lock(&key);
+        if let Some(index) = stripe.find(home, &key) {
+            let bucket = &mut stripe.buckets[index];
+            return Ok(Some(unsafe {
+                std::mem::replace(bucket.value.assume_init_mut(), value)
+            }));
+        }
+        let Some(index) = stripe.vacant(home) else {
+            return Err(CortexError::new_logic(
+                ErrorKind::NoSpace,
+                format!(
+                "Map with key: {} has no free bucket left for the key, {} of {} entries are in use",
+                self.key(),
+                self.len(),
+                self.capacity()
+            ),
+            ));
+        };
+        let bucket = &mut stripe.buckets[index];
+        bucket.key.write(key);
+        bucket.value.write(value);
+        bucket.state = OCCUPIED;
+        stripe.stripe.len.fetch_add(1, Ordering::Relaxed);
+        Ok(None)
+    }
+    pub fn get(&self, key: &K) -> Option<V> {
+        let (stripe, home) = self.lock(key);
+        let index = stripe.find(home, key)?;
+        Some(unsafe { stripe.buckets[index].value.assume_init() })
+    }
+    pub fn contains_key(&self, key: &K) -> bool {
+        let (stripe, home) = self.lock(key);
+        stripe.find(home, key).is_some()
+    }
+    /// Remove the entry for `key`, returning its value if there was one
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let (stripe, home) = self.lock(key);
+        let index = stripe.find(home, key)?;
+        let bucket = &mut stripe.buckets[index];
+        bucket.state = TOMBSTONE;
+        stripe.stripe.len.fetch_sub(1, Ordering::Relaxed);
+        Some(unsafe { bucket.value.assume_init() })
+    }
+    /// Modify the value for `key` in place while holding the lock of its stripe, returning `None`
+    /// if there is no entry for the key
+    pub fn update<R>(&self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        let (stripe, home) = self.lock(key);
+        let index = stripe.find(home, key)?;
+        Some(f(unsafe { stripe.buckets[index].value.assume_init_mut() }))
+    }
+    /// Copy out all entries, locking one stripe at a time. Entries changed concurrently in other
+    /// stripes may or may not be included.
+    pub fn entries(&self) -> Vec<(K, V)> {
+        let mut entries = Vec::new();
+        for index in 0..self.info.stripes {
+            let stripe = self.lock_stripe(index);
+            entries.extend(
+                stripe
+                    .buckets
+                    .iter()
+                    .filter(|bucket| bucket.state == OCCUPIED)
+                    .map(|bucket| unsafe {
+                        (bucket.key.assume_init(), bucket.value.assume_init())
+                    }),
+            );
+        }
+        entries
+    }
+    /// Copy out a consistent view of the whole map. All stripes are locked together, but only for
+    /// as long as it takes to copy the raw buckets, the copy is turned into a `HashMap` after
+    /// releasing them.
+    pub fn snapshot(&self) -> HashMap<K, V> {
+        let capacity = self.capacity();
+        let mut buckets: Vec<Bucket<K, V>> = Vec::with_capacity(capacity);
+        {
+            // Always locked in the same order, and everyone else only locks one stripe at a time
+            let _stripes: Vec<_> = (0..self.info.stripes)
+                .map(|index| self.lock_stripe(index))
+                .collect();
+            unsafe {
+                std::ptr::copy_nonoverlapping(self.buckets.ptr, buckets.as_mut_ptr(), capacity);
+                buckets.set_len(capacity);
+            }
+        }
+        buckets
+            .iter()
+            .filter(|bucket| bucket.state == OCCUPIED)
+            .map(|bucket| unsafe { (bucket.key.assume_init(), bucket.value.assume_init()) })
+            .collect()
+    }
+    pub fn len(&self) -> usize {
+        (0..self.info.stripes)
+            .map(|index| unsafe { &*self.stripes.ptr.add(index) })
+            .map(|stripe| stripe.len.load(Ordering::Relaxed))
+            .sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Number of stripes the buckets are split into, see `MapSettings`
+    pub fn stripes(&self) -> usize {
+        self.info.stripes
+    }
+    /// Total number of buckets
+    pub fn capacity(&self) -> usize {
+        self.info.stripes * self.info.stripe_len
+    }
+    pub fn key(&self) -> i32 {
+        self.state.key()
+    }
+}
+
+/// Remove the stripe and bucket segments along with the map
+impl<K: Copy + Hash + Eq, V: Copy> Drop for CortexMap<K, V> {
+    fn drop(&mut self) {
+        if !self.state.is_owner() {
+            return;
+        }
+        for id in [self.stripes.id, self.buckets.id] {
+            if let Err(err) = mark_for_deletion(id) {
+                crate::trace::error!("Error during mark_for_deletion in Drop: {}", err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::map::{CortexMap, MapSettings};
+    use crate::ErrorKind;
+    use std::thread;
+
+    #[test]
+    fn insert_get_remove() {
+        let key = rand::random::<i32>().abs();
+        let map: CortexMap<u64, u32> = CortexMap::new(key, 256).unwrap();
+        let attached: CortexMap<u64, u32> = CortexMap::attach(key).unwrap();
+
+        assert_eq!(map.insert(1, 10).unwrap(), None);
+        assert_eq!(map.insert(1, 11).unwrap(), Some(10));
+        assert_eq!(attached.get(&1), Some(11));
+        assert_eq!(attached.update(&1, |value| *value += 1), Some(()));
+        assert_eq!(map.remove(&1), Some(12));
+        assert!(!attached.contains_key(&1));
+        assert!(map.is_empty());
+
+        let err = CortexMap::<u8, u8>::attach(key).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TypeMismatch);
+    }
+
+    #[test]
+    fn full_stripe() {
+        let key = rand::random::<i32>().abs();
+        let map: CortexMap<u32, u32> = CortexMap::new(key, 4).unwrap();
+        for value in 0..4 {
+            map.insert(value, value).unwrap();
+        }
+        let err = map.insert(4, 4).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoSpace);
+
+        // Removed buckets are reused
+        map.remove(&0);
+        map.insert(4, 4).unwrap();
+        assert_eq!(map.len(), 4);
+    }
+
+    #[test]
+    fn concurrent_sessions() {
+        let key = rand::random::<i32>().abs();
+        let map: CortexMap<u32, u64> = CortexMap::new(key, 4096).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread| {
+                thread::spawn(move || {
+                    let attached: CortexMap<u32, u64> = CortexMap::attach(key).unwrap();
+                    for session in 0..200 {
+                        attached.insert(thread * 1000 + session, 0).unwrap();
+                        attached.update(&(thread * 1000 + session), |hits| *hits += 1);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+
+        let entries = map.entries();
+        assert_eq!(entries.len(), 1600);
+        assert_eq!(map.len(), 1600);
+        assert!(entries.iter().all(|(_, hits)| *hits == 1));
+
+        let snapshot = map.snapshot();
+        assert_eq!(snapshot.len(), 1600);
+        assert_eq!(snapshot.get(&7003), Some(&1));
+    }
+
+    #[test]
+    fn configured_stripes() {
+        let key = rand::random::<i32>().abs();
+        let settings = MapSettings { stripes: Some(16) };
+        let map: CortexMap<u32, u32> = CortexMap::with_settings(key, 64, &settings).unwrap();
+        let attached: CortexMap<u32, u32> = CortexMap::attach(key).unwrap();
+        assert_eq!(attached.stripes(), 16);
+        assert_eq!(
+            CortexMap::<u32, u32>::new(rand::random::<i32>().abs(), 64)
+                .unwrap()
+                .stripes(),
+            1
+        );
+
+        map.insert(3, 30).unwrap();
+        assert_eq!(attached.get(&3), Some(30));
+
+        for stripes in [0, 65] {
+            let settings = MapSettings {
+                stripes: Some(stripes),
+            };
+            let err = CortexMap::<u32, u32>::with_settings(key, 4096, &settings).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+}
@@ -0,0 +1,393 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    CortexResult, CortexSync,
+};
+use std::alloc::Layout;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Segments emulated on the heap are page aligned, the same as System V segments
+const PAGE_SIZE: usize = 4096;
+
+/// Emulated segments get negative ids, so that they can never be mistaken for System V ones
+static NEXT_ID: AtomicI32 = AtomicI32::new(-2);
+static SEGMENTS: Mutex<BTreeMap<i32, HeapSegment>> = Mutex::new(BTreeMap::new());
+static LOCKS: Mutex<BTreeMap<i32, Arc<MemRwLock>>> = Mutex::new(BTreeMap::new());
+
+/// Heap allocation standing in for a System V segment, with the same lifecycle: it is freed once
+/// it has been removed and the last mapping is detached
+struct HeapSegment {
+    /// `None` for private segments, and for removed ones so that the key can be reused
+    key: Option<i32>,
+    /// Address of the allocation, stored as an integer to keep the registry `Send`
+    addr: usize,
+    size: usize,
+    attached: usize,
+    removed: bool,
+}
+
+impl HeapSegment {
+    fn layout(&self) -> Layout {
+        Layout::from_size_align(self.size, PAGE_SIZE).expect("Validated when allocating")
+    }
+}
+
+fn segments() -> MutexGuard<'static, BTreeMap<i32, HeapSegment>> {
+    SEGMENTS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+fn fail<R>(errno: i32, value: R) -> R {
+    errno::set_errno(errno::Errno(errno));
+    value
+}
+
+pub(crate) fn is_heap(id: i32) -> bool {
+    id < -1
+}
+
+/// Free a removed segment once nothing is attached to it anymore
+fn release_if_unused(segments: &mut BTreeMap<i32, HeapSegment>, id: i32) {
+    if let Some(segment) = segments.get(&id) {
+        if segment.removed && segment.attached == 0 {
+            let segment = segments.remove(&id).expect("Checked above");
+            unsafe { std::alloc::dealloc(segment.addr as *mut u8, segment.layout()) };
+        }
+    }
+}
+
+fn heap_get(key: i32, size: usize, flags: i32) -> i32 {
+    let mut segments = segments();
+    if key != libc::IPC_PRIVATE {
+        let existing = segments
+            .iter()
+            .find(|(_, segment)| segment.key == Some(key));
+        if let Some((id, segment)) = existing {
+            if flags & libc::IPC_CREAT != 0 && flags & libc::IPC_EXCL != 0 {
+                return fail(libc::EEXIST, -1);
+            }
+            if size > segment.size {
+                return fail(libc::EINVAL, -1);
+            }
+            return *id;
+        }
+        if flags & libc::IPC_CREAT == 0 {
+            return fail(libc::ENOENT, -1);
+        }
+    }
+    let Ok(layout) = Layout::from_size_align(size, PAGE_SIZE) else {
+        return fail(libc::EINVAL, -1);
+    };
+    if size == 0 {
+        return fail(libc::EINVAL, -1);
+    }
+    let addr = unsafe { std::alloc::alloc_zeroed(layout) };
+    if addr.is_null() {
+        return fail(libc::ENOMEM, -1);
+    }
+    let id = NEXT_ID.fetch_sub(1, Ordering::Relaxed);
+    let segment = HeapSegment {
+        key: (key != libc::IPC_PRIVATE).then_some(key),
+        addr: addr as usize,
+        size,
+        attached: 0,
+        removed: false,
+    };
+    segments.insert(id, segment);
+    id
+}
+
+/// `libc::shmget`, emulated on the heap for locks that only work within one process, and for
+/// every segment under Miri, which can't call into System V IPC
+///
+/// # Safety
+///
+/// Same as `libc::shmget`.
+pub(crate) unsafe fn shmget(in_process: bool, key: i32, size: usize, flags: i32) -> i32 {
+    if in_process || cfg!(miri) {
+        return heap_get(key, size, flags);
+    }
+    unsafe { libc::shmget(key, size, flags) }
+}
+
+/// `libc::shmat`, for both System V and emulated segments
+///
+/// # Safety
+///
+/// Same as `libc::shmat`.
+pub(crate) unsafe fn shmat(id: i32, addr: *const libc::c_void, flags: i32) -> *mut libc::c_void {
+    if !is_heap(id) {
+        return unsafe { libc::shmat(id, addr, flags) };
+    }
+    match segments().get_mut(&id) {
+        Some(segment) => {
+            segment.attached += 1;
+            segment.addr as *mut libc::c_void
+        }
+        None => fail(libc::EINVAL, -1isize as *mut libc::c_void),
+    }
+}
+
+/// `libc::shmdt` for a mapping of the segment `id`, for both System V and emulated segments
+///
+/// # Safety
+///
+/// Same as `libc::shmdt`.
+pub(crate) unsafe fn shmdt(id: i32, addr: *const libc::c_void) -> i32 {
+    if !is_heap(id) {
+        return unsafe { libc::shmdt(addr) };
+    }
+    let mut segments = segments();
+    match segments.get_mut(&id) {
+        Some(segment) if segment.attached > 0 && segment.addr == addr as usize => {
+            segment.attached -= 1;
+            release_if_unused(&mut segments, id);
+            0
+        }
+        _ => fail(libc::EINVAL, -1),
+    }
+}
+
+/// `libc::shmctl`, for both System V and emulated segments. Emulated segments support
+/// `IPC_RMID` and `IPC_STAT`, and ignore anything else.
+///
+/// # Safety
+///
+/// Same as `libc::shmctl`.
+pub(crate) unsafe fn shmctl(id: i32, cmd: i32, buf: *mut libc::shmid_ds) -> i32 {
+    if !is_heap(id) {
+        return unsafe { libc::shmctl(id, cmd, buf) };
+    }
+    let mut segments = segments();
+    let Some(segment) = segments.get_mut(&id) else {
+        return fail(libc::EINVAL, -1);
+    };
+    match cmd {
+        libc::IPC_RMID => {
+            segment.removed = true;
+            segment.key = None;
+            release_if_unused(&mut segments, id);
+        }
+        libc::IPC_STAT => {
+            let info = unsafe { &mut *buf };
+            info.shm_segsz = segment.size;
+            info.shm_nattch = segment.attached as _;
+            info.shm_perm.uid = unsafe { libc::getuid() };
+            // Never orphaned, since the creating process is this one
+            info.shm_cpid = unsafe { libc::getpid() };
+        }
+        _ => {}
+    }
+    0
+}
+
+/// Readers and writers of a `MemBackend`, -1 while a writer holds the lock
+#[derive(Debug, Default)]
+struct MemRwLock {
+    state: Mutex<i64>,
+    released: Condvar,
+}
+
+impl MemRwLock {
+    fn state(&self) -> MutexGuard<'_, i64> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+    /// Wait until `free` holds for the state and apply `take`, or give up after `timeout`
+    fn acquire(
+        &self,
+        timeout: Option<Duration>,
+        free: impl Fn(i64) -> bool,
+        take: impl FnOnce(&mut i64),
+    ) -> bool {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut state = self.state();
+        while !free(*state) {
+            state = match deadline {
+                None => self
+                    .released
+                    .wait(state)
+                    .unwrap_or_else(|err| err.into_inner()),
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return false;
+                    }
+                    self.released
+                        .wait_timeout(state, left)
+                        .unwrap_or_else(|err| err.into_inner())
+                        .0
+                }
+            };
+        }
+        take(&mut state);
+        true
+    }
+    fn read(&self, timeout: Option<Duration>) -> bool {
+        self.acquire(timeout, |state| state >= 0, |state| *state += 1)
+    }
+    fn write(&self, timeout: Option<Duration>) -> bool {
+        self.acquire(timeout, |state| state == 0, |state| *state = -1)
+    }
+    fn release(&self) -> CortexResult<()> {
+        let mut state = self.state();
+        match *state {
+            0 => {
+                return Err(CortexError::new_logic(
+                    ErrorKind::InvalidInput,
+                    "Released an in-process lock that isn't held",
+                ))
+            }
+            -1 => *state = 0,
+            _ => *state -= 1,
+        }
+        self.released.notify_all();
+        Ok(())
+    }
+}
+
+/// Backend that keeps the segment in a plain heap allocation and protects it with an in-process
+/// reader-writer lock, so that code built on `Cortex` can run its unit tests where System V IPC is
+/// unavailable, e.g. in some containers and sandboxes.
+///
+/// Select it like any other lock, as in `Cortex<T, MemBackend>`. Everything behaves like it does
+/// with shared memory, including attaching through the key, except that segments are only visible
+/// within the current process and `Cortex::id` is negative. Segments of types that allocate more
+/// memory at runtime, like `CortexVec`, are emulated as well.
+#[derive(Debug)]
+pub struct MemBackend {
+    key: i32,
+    lock: Arc<MemRwLock>,
+    is_owner: bool,
+}
+
+fn locks() -> MutexGuard<'static, BTreeMap<i32, Arc<MemRwLock>>> {
+    LOCKS.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+impl CortexSync for MemBackend {
+    type Settings = ();
+    const IN_PROCESS: bool = true;
+
+    fn new(cortex_key: i32, _settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let lock = Arc::new(MemRwLock::default());
+        locks().insert(cortex_key, Arc::clone(&lock));
+        Ok(Self {
+            key: cortex_key,
+            lock,
+            is_owner: true,
+        })
+    }
+    fn attach(cortex_key: i32, _settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let Some(lock) = locks().get(&cortex_key).cloned() else {
+            return Err(CortexError::new_logic(
+                ErrorKind::NotFound,
+                format!("No in-process lock for key: {}", cortex_key),
+            ));
+        };
+        Ok(Self {
+            key: cortex_key,
+            lock,
+            is_owner: false,
+        })
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        Ok(Self {
+            key: self.key,
+            lock: Arc::clone(&self.lock),
+            is_owner: false,
+        })
+    }
+    fn force_ownership(&mut self) {
+        self.is_owner = true;
+    }
+    fn relinquish_ownership(&mut self) {
+        self.is_owner = false;
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.lock.read(None);
+        Ok(())
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.lock.write(None);
+        Ok(())
+    }
+    fn release(&self) -> CortexResult<()> {
+        self.lock.release()
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        Ok(self.lock.read(Some(Duration::ZERO)))
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        Ok(self.lock.write(Some(Duration::ZERO)))
+    }
+    fn timed_read_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        Ok(self.lock.read(Some(timeout)))
+    }
+    fn timed_write_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        Ok(self.lock.write(Some(timeout)))
+    }
+}
+
+/// Unregister the lock if this instance owns it, unless it was replaced in the meantime
+impl Drop for MemBackend {
+    fn drop(&mut self) {
+        if !self.is_owner {
+            return;
+        }
+        let mut locks = locks();
+        if locks
+            .get(&self.key)
+            .is_some_and(|lock| Arc::ptr_eq(lock, &self.lock))
+        {
+            locks.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mem::MemBackend;
+    use crate::{Cortex, CortexVec, ErrorKind};
+    use std::thread;
+
+    #[test]
+    fn cortex_on_the_heap() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, MemBackend> = Cortex::new(Some(key), 1, false, None).unwrap();
+        assert!(cortex.id() < -1);
+        let attached: Cortex<u64, MemBackend> = Cortex::attach(key).unwrap();
+        assert_eq!(attached.attach_count().unwrap(), 2);
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let cortex: Cortex<u64, MemBackend> = Cortex::attach(key).unwrap();
+                    for _ in 0..100 {
+                        cortex.with_write(|value| *value += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(attached.read().unwrap(), 401);
+
+        drop(attached);
+        drop(cortex);
+        let err = Cortex::<u64, MemBackend>::attach(key).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn growing_vec() {
+        let key = rand::random::<i32>().abs();
+        let vec: CortexVec<u32, MemBackend> = CortexVec::with_capacity(key, 1).unwrap();
+        let attached: CortexVec<u32, MemBackend> = CortexVec::attach(key).unwrap();
+        for value in 0..10 {
+            vec.push(value).unwrap();
+        }
+        assert_eq!(attached.to_vec().unwrap(), (0..10).collect::<Vec<_>>());
+    }
+}
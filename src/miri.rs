@@ -0,0 +1,150 @@
+//! Value semantics of `Cortex` on segments emulated on the heap, so that they can run under Miri
+//! and catch undefined behavior in the unsafe code behind the safe API: reads and writes through
+//! the data pointer, guards, field projections, poisoning and checksums. Under Miri every segment
+//! is emulated through `mem`, and `MemBackend` keeps the lock on the heap as well. Run them with
+//! `MIRIFLAGS=-Zmiri-disable-isolation cargo +nightly miri test --lib miri`, isolation has to be
+//! disabled for the timestamps in the header.
+//!
+//! Only `Copy` payloads are used, since `write` overwrites the previous value without dropping it,
+//! which Miri would report as a leak.
+
+use crate::{Cortex, CortexBuilder, CortexError, DropPolicy, ErrorKind, MemBackend};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Reading {
+    sensor: u16,
+    samples: [u8; 13],
+    value: f64,
+}
+
+const READING: Reading = Reading {
+    sensor: 1,
+    samples: [0; 13],
+    value: 0.5,
+};
+
+fn new<T>(data: T) -> Cortex<T, MemBackend> {
+    Cortex::new(None, data, false, None).unwrap()
+}
+
+#[test]
+fn reads_and_writes() {
+    let cortex = new(READING);
+    let attached: Cortex<Reading, MemBackend> = Cortex::attach(cortex.key()).unwrap();
+    assert_eq!(attached.read().unwrap(), READING);
+
+    let written = Reading {
+        value: 1.5,
+        ..READING
+    };
+    attached.write(written).unwrap();
+    assert_eq!(cortex.read().unwrap(), written);
+    assert_eq!(cortex.swap(READING).unwrap(), written);
+    assert_eq!(
+        cortex.compare_exchange(written, READING).unwrap(),
+        Err(READING)
+    );
+    assert_eq!(
+        cortex.compare_exchange(READING, written).unwrap(),
+        Ok(READING)
+    );
+    assert_eq!(attached.read_versioned().unwrap().version, 3);
+}
+
+#[test]
+fn guards() {
+    let cortex = Arc::new(new(0u64));
+    let guard = cortex.read_upgradable().unwrap();
+    assert_eq!(*guard, 0);
+    let mut guard = guard.upgrade().unwrap();
+    *guard += 1;
+    drop(guard);
+
+    *cortex.write_owned().unwrap() += 1;
+    let first = cortex.read_owned().unwrap();
+    let second = cortex.read_owned().unwrap();
+    assert_eq!((*first, *second), (2, 2));
+    drop((first, second));
+
+    let attached: Cortex<u64, MemBackend> = Cortex::attach(cortex.key()).unwrap();
+    std::thread::scope(|scope| {
+        for _ in 0..2 {
+            scope.spawn(|| {
+                for _ in 0..5 {
+                    attached.with_write(|value| *value += 1).unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(cortex.read().unwrap(), 12);
+}
+
+#[test]
+fn poisoned_by_panic() {
+    let cortex = Arc::new(new(1u32));
+    let panicked = std::panic::catch_unwind(|| {
+        let mut guard = cortex.write_owned().unwrap();
+        *guard = 2;
+        panic!("Writer died");
+    });
+    assert!(panicked.is_err());
+    assert!(matches!(cortex.read(), Err(CortexError::Poisoned)));
+    assert!(cortex.clear_poison().unwrap());
+    assert_eq!(cortex.read().unwrap(), 2);
+}
+
+#[test]
+fn projections() {
+    let cortex = new(READING);
+    cortex
+        .write_field(|reading| &mut reading.value, 2.5)
+        .unwrap();
+    cortex
+        .write_field(|reading| &mut reading.samples[12], 7)
+        .unwrap();
+    assert_eq!(cortex.read_field(|reading| &reading.value).unwrap(), 2.5);
+    assert_eq!(
+        cortex.read_field(|reading| &reading.samples).unwrap()[12],
+        7
+    );
+
+    let buffer = new([0u8; 32]);
+    buffer.write_bytes(30, &[1, 2]).unwrap();
+    assert_eq!(buffer.read_bytes(29, 3).unwrap(), [0, 1, 2]);
+    let err = buffer.read_bytes(31, 2).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+fn checksums() {
+    let cortex = CortexBuilder::new([1u32; 8])
+        .random_key()
+        .checksum()
+        .with_default_lock::<MemBackend>()
+        .unwrap();
+    cortex.with_write(|data| data[7] = 2).unwrap();
+    assert_eq!(cortex.read().unwrap()[7], 2);
+
+    // Modified without going through the lock
+    unsafe { (*cortex.ptr)[0] = 0 };
+    assert!(matches!(cortex.read(), Err(CortexError::Corrupted)));
+}
+
+#[test]
+fn ref_counted_lifecycle() {
+    let cortex = CortexBuilder::new(READING)
+        .random_key()
+        .drop_policy(DropPolicy::RefCounted)
+        .with_default_lock::<MemBackend>()
+        .unwrap();
+    let key = cortex.key();
+    let clone = cortex.try_clone().unwrap();
+    drop(cortex);
+    assert_eq!(clone.read().unwrap(), READING);
+
+    // The last handle frees the segment
+    drop(clone);
+    let err = Cortex::<Reading, MemBackend>::attach(key).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
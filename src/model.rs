@@ -0,0 +1,99 @@
+//! Model checks of the lock state machines under `loom`, which runs every interleaving of the
+//! atomic operations allowed by the memory model. Run with `cargo test --release --features loom
+//! model`.
+
+use crate::rwlock::word;
+use crate::ticket::TicketState;
+use loom::cell::UnsafeCell;
+use loom::sync::atomic::AtomicU32;
+use loom::sync::Arc;
+use loom::thread;
+
+/// Run `f` on two threads sharing `lock`, each incrementing a counter under the lock that loom
+/// checks for unsynchronized access
+fn two_writers<S: Send + Sync + 'static>(
+    lock: S,
+    f: impl Fn(&S, &UnsafeCell<u32>) + Send + Sync + 'static,
+) {
+    let shared = Arc::new((lock, UnsafeCell::new(0), f));
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || (shared.2)(&shared.0, &shared.1))
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(shared.1.with(|value| unsafe { *value }), 2);
+}
+
+fn increment(counter: &UnsafeCell<u32>) {
+    counter.with_mut(|value| unsafe { *value += 1 });
+}
+
+#[test]
+fn ticket_lock() {
+    loom::model(|| {
+        two_writers(TicketState::<AtomicU32>::default(), |lock, counter| {
+            lock.lock(thread::yield_now);
+            increment(counter);
+            lock.release();
+        });
+    });
+}
+
+#[test]
+fn ticket_try_lock() {
+    loom::model(|| {
+        two_writers(TicketState::<AtomicU32>::default(), |lock, counter| {
+            while !lock.try_lock() {
+                thread::yield_now();
+            }
+            increment(counter);
+            lock.release();
+        });
+    });
+}
+
+#[test]
+fn rwlock_readers_and_writer() {
+    loom::model(|| {
+        let shared = Arc::new((AtomicU32::new(0), UnsafeCell::new(0u32)));
+        let writer = {
+            let shared = Arc::clone(&shared);
+            thread::spawn(move || {
+                while !word::try_write(&shared.0) {
+                    thread::yield_now();
+                }
+                increment(&shared.1);
+                word::release_write(&shared.0);
+            })
+        };
+        // Readers only ever see the value before or after the write, never a torn one
+        while !word::try_read(&shared.0) {
+            thread::yield_now();
+        }
+        let seen = shared.1.with(|value| unsafe { *value });
+        assert!(seen <= 1);
+        word::release_read(&shared.0);
+        writer.join().unwrap();
+    });
+}
+
+#[test]
+fn rwlock_upgrade() {
+    loom::model(|| {
+        two_writers(AtomicU32::new(0), |lock, counter| {
+            while !word::try_upgradable(lock) {
+                thread::yield_now();
+            }
+            let seen = counter.with(|value| unsafe { *value });
+            while !word::try_upgrade(lock) {
+                thread::yield_now();
+            }
+            counter.with_mut(|value| unsafe { *value = seen + 1 });
+            word::release_write(lock);
+        });
+    });
+}
@@ -0,0 +1,89 @@
+//! Application namespace mixed into the System V key of every segment and the name of every lock
+//! object, lock file, semaphore and event, see `set_namespace`.
+
+use crate::{
+    crash::{CortexError, ErrorKind},
+    CortexResult,
+};
+use std::sync::OnceLock;
+
+static NAMESPACE: OnceLock<String> = OnceLock::new();
+
+/// Isolate this application from unrelated ones using the crate on the same host, so that they
+/// can't collide even when they pick the same keys. Every process sharing segments has to set the
+/// same namespace, before creating or attaching anything.
+///
+/// The namespace can only be set once per process, setting it again to another value fails with
+/// `ErrorKind::AlreadyExists`. It must be non-empty and can't contain `/`.
+pub fn set_namespace(namespace: impl Into<String>) -> CortexResult<()> {
+    let namespace = namespace.into();
+    if namespace.is_empty() || namespace.contains(['/', '\0']) {
+        return Err(CortexError::new_logic(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid namespace: {:?}, it must be non-empty and can't contain '/'",
+                namespace
+            ),
+        ));
+    }
+    let current = NAMESPACE.get_or_init(|| namespace.clone());
+    if *current != namespace {
+        return Err(CortexError::new_logic(
+            ErrorKind::AlreadyExists,
+            format!("Namespace is already set to: {:?}", current),
+        ));
+    }
+    crate::trace::info!("Using namespace: {}", namespace);
+    Ok(())
+}
+
+/// The namespace set through `set_namespace`, if any
+pub fn namespace() -> Option<&'static str> {
+    NAMESPACE.get().map(String::as_str)
+}
+
+/// The System V key used for segments on `key`, which differs from `key` once a namespace is set.
+/// Processes that don't go through this crate, e.g. a C peer of a `RawCortex`, have to `shmget`
+/// this key instead.
+pub fn ipc_key(key: i32) -> i32 {
+    match namespace() {
+        Some(namespace) => derive(namespace, key),
+        None => key,
+    }
+}
+
+/// FNV-1a over the namespace and the key, folded into the range of keys that `random_key`
+/// produces as well
+fn derive(namespace: &str, key: i32) -> i32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in namespace.bytes().chain(key.to_le_bytes()) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    match (hash & i32::MAX as u32) as i32 {
+        libc::IPC_PRIVATE => 1,
+        key => key,
+    }
+}
+
+/// Prefix `name` with the namespace, for the names of lock objects, lock files and events
+pub(crate) fn qualify(name: String) -> String {
+    match namespace() {
+        Some(namespace) => format!("{}_{}", namespace, name),
+        None => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::namespace::derive;
+
+    #[test]
+    fn derived_keys() {
+        let key = rand::random::<i32>().abs();
+        assert_eq!(derive("app", key), derive("app", key));
+        assert_ne!(derive("app", key), derive("other", key));
+        assert_ne!(derive("app", key), derive("app", key.wrapping_add(1)));
+        assert!((0..1000).all(|key| derive("app", key) > 0));
+    }
+}
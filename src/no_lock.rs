@@ -0,0 +1,138 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    Cortex, CortexResult, CortexSync,
+};
+use std::time::Duration;
+
+/// Lock implementation that does no locking at all.
+///
+/// Every lock operation is a no-op, which gives maximum throughput but leaves all synchronization
+/// up to the application. Only use this when access is already coordinated externally, e.g. a
+/// strict ping-pong protocol between two processes, or when the data is a single atomic value.
+///
+/// Concurrent writes, or a read concurrent with a write, through a `Cortex` using `NoLock` are
+/// data races. Creating or attaching one therefore takes `Unsynchronized` as its settings, which
+/// can only be obtained through an `unsafe` constructor, see `Cortex::new_unsynchronized` and
+/// `Cortex::attach_unsynchronized`. Without it both fail with `ErrorKind::InvalidInput`.
+#[derive(Debug)]
+pub struct NoLock;
+
+/// Settings of `NoLock`, standing for the promise that every access is synchronized externally
+#[derive(Debug, Clone, Copy)]
+pub struct Unsynchronized(());
+
+/// For the crate's own uses of `NoLock`, which synchronize access to the segment themselves
+pub(crate) const INTERNAL: &Unsynchronized = &Unsynchronized(());
+
+impl Unsynchronized {
+    /// # Safety
+    ///
+    /// For as long as any handle created or attached with these settings lives, no write through
+    /// it may run concurrently with another read or write of the same segment, from any thread or
+    /// process, through any handle.
+    pub unsafe fn new() -> Self {
+        Self(())
+    }
+}
+
+fn check_settings(settings: Option<&Unsynchronized>) -> CortexResult<()> {
+    if settings.is_none() {
+        return Err(CortexError::new_logic(
+            ErrorKind::InvalidInput,
+            "NoLock doesn't synchronize access, use Cortex::new_unsynchronized or \
+             Cortex::attach_unsynchronized",
+        ));
+    }
+    Ok(())
+}
+
+impl<T> Cortex<T, NoLock> {
+    /// Allocate a new segment of shared memory without any locking, see `NoLock`
+    ///
+    /// # Safety
+    ///
+    /// See `Unsynchronized::new`
+    pub unsafe fn new_unsynchronized(init_key: Option<i32>, data: T) -> CortexResult<Self> {
+        Self::new(init_key, data, false, Some(&Unsynchronized::new()))
+    }
+    /// Attach to an existing segment of shared memory without any locking, see `NoLock`
+    ///
+    /// # Safety
+    ///
+    /// See `Unsynchronized::new`
+    pub unsafe fn attach_unsynchronized(key: i32) -> CortexResult<Self> {
+        Self::attach_with_lock(key, &Unsynchronized::new())
+    }
+}
+
+impl CortexSync for NoLock {
+    type Settings = Unsynchronized;
+
+    fn new(_cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        check_settings(settings)?;
+        Ok(Self)
+    }
+    fn attach(_cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        check_settings(settings)?;
+        Ok(Self)
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        Ok(Self)
+    }
+    fn force_ownership(&mut self) {}
+    fn relinquish_ownership(&mut self) {}
+    fn read_lock(&self) -> CortexResult<()> {
+        Ok(())
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        Ok(())
+    }
+    fn release(&self) -> CortexResult<()> {
+        Ok(())
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        Ok(true)
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        Ok(true)
+    }
+    fn timed_read_lock(&self, _timeout: Duration) -> CortexResult<bool> {
+        Ok(true)
+    }
+    fn timed_write_lock(&self, _timeout: Duration) -> CortexResult<bool> {
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::no_lock::NoLock;
+    use crate::{Cortex, CortexBuilder, ErrorKind};
+
+    #[test]
+    fn read_write_without_lock() {
+        let key = rand::random::<i32>().abs();
+        let cortex = unsafe { Cortex::new_unsynchronized(Some(key), 42u64) }.unwrap();
+        let attached: Cortex<u64, NoLock> = unsafe { Cortex::attach_unsynchronized(key) }.unwrap();
+
+        cortex.write(7).unwrap();
+        assert_eq!(attached.read().unwrap(), 7);
+        assert_eq!(attached.try_read().unwrap(), Some(7));
+    }
+
+    #[test]
+    fn requires_unsafe_construction() {
+        let key = rand::random::<i32>().abs();
+        let err = Cortex::<u64, NoLock>::new(Some(key), 0, false, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        let err = CortexBuilder::new(0u64)
+            .random_key()
+            .with_default_lock::<NoLock>()
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        let _cortex = unsafe { Cortex::new_unsynchronized(Some(key), 0u64) }.unwrap();
+        let err = Cortex::<u64, NoLock>::attach(key).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
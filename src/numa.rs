@@ -0,0 +1,70 @@
+use crate::{crash::CortexError, CortexResult};
+
+/// Bind the pages of a segment to NUMA node `node`, moving any pages that were already allocated
+/// elsewhere
+#[cfg(target_os = "linux")]
+pub(crate) fn bind(ptr: *mut libc::c_void, size: usize, node: u32) -> CortexResult<()> {
+    const MPOL_MF_MOVE: libc::c_uint = 1 << 1;
+    let bits = libc::c_ulong::BITS as usize;
+    let mut mask = vec![0 as libc::c_ulong; node as usize / bits + 1];
+    mask[node as usize / bits] |= 1 << (node as usize % bits);
+    // The kernel expects one more than the number of bits in the mask
+    let max_node = mask.len() * bits + 1;
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_mbind,
+            ptr,
+            size,
+            libc::MPOL_BIND,
+            mask.as_ptr(),
+            max_node,
+            MPOL_MF_MOVE,
+        )
+    };
+    if result == -1 {
+        return Err(CortexError::new_clean(format!(
+            "Error during mbind to NUMA node: {}",
+            node
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn bind(_ptr: *mut libc::c_void, _size: usize, node: u32) -> CortexResult<()> {
+    Err(CortexError::new_logic(
+        crate::ErrorKind::Unsupported,
+        format!("Binding to NUMA node: {} is only supported on Linux", node),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CortexBuilder, ErrorKind, SpinLock};
+
+    #[test]
+    fn bind_to_node() {
+        let key = rand::random::<i32>().abs();
+        // Node 0 exists on every NUMA capable system
+        let cortex = CortexBuilder::new([1u64; 1024])
+            .key(key)
+            .numa_node(0)
+            .with_default_lock::<SpinLock>();
+        match cortex {
+            Ok(cortex) => assert_eq!(cortex.read().unwrap()[1023], 1),
+            // Kernels built without NUMA support
+            Err(err) => assert_eq!(err.raw_os_error(), Some(libc::ENOSYS)),
+        }
+
+        let key = rand::random::<i32>().abs();
+        let err = CortexBuilder::new(0u64)
+            .key(key)
+            .numa_node(u16::MAX as u32)
+            .with_default_lock::<SpinLock>()
+            .unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::InvalidInput | ErrorKind::Unsupported
+        ));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::{wait_until, Cortex, CortexResult, CortexSync};
+use std::mem::MaybeUninit;
+
+const UNINITIALIZED: u8 = 0;
+const RUNNING: u8 = 1;
+const DONE: u8 = 2;
+
+struct OnceState<T> {
+    state: u8,
+    value: MaybeUninit<T>,
+}
+
+/// Resets the state if the initializer panics, so that another participant can take over
+struct ResetOnUnwind<'a, T, L: CortexSync> {
+    cortex: &'a Cortex<OnceState<T>, L>,
+    armed: bool,
+}
+
+impl<T, L: CortexSync> Drop for ResetOnUnwind<'_, T, L> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        if let Err(err) = self.cortex.with_write(|once| once.state = UNINITIALIZED) {
+            crate::trace::error!("Error resetting CortexOnce after panic: {}", err);
+        }
+    }
+}
+
+/// Cross-process equivalent of `OnceCell`.
+///
+/// The first participant to call `get_or_init` runs the initializer and stores the value in shared
+/// memory, everyone else blocks until the value is available and then reads it. If the
+/// initializer panics, the cell is reset and the next waiting participant runs its own initializer
+/// instead. A process that dies mid-initialization will however leave waiters blocked. The value
+/// is copied in and out of shared memory, so it has to be `Copy`.
+pub struct CortexOnce<T: Copy, L: CortexSync> {
+    cortex: Cortex<OnceState<T>, L>,
+}
+
+impl<T: Copy, L: CortexSync> CortexOnce<T, L> {
+    /// Attach to the cell on `key`, creating it if it doesn't exist yet
+    pub fn new(key: i32) -> CortexResult<Self> {
+        let cortex = Cortex::attach_or_create(key, || OnceState {
+            state: UNINITIALIZED,
+            value: MaybeUninit::uninit(),
+        })?;
+        Ok(Self { cortex })
+    }
+    /// Read the value if it has been initialized
+    pub fn get(&self) -> CortexResult<Option<T>> {
+        let once = self.cortex.read()?;
+        if once.state == DONE {
+            Ok(Some(unsafe { once.value.assume_init() }))
+        } else {
+            Ok(None)
+        }
+    }
+    /// Read the value, running `init` first if no participant has initialized it yet
+    pub fn get_or_init(&self, init: impl FnOnce() -> T) -> CortexResult<T> {
+        let should_init = self.cortex.with_write(|once| {
+            if once.state == UNINITIALIZED {
+                once.state = RUNNING;
+                true
+            } else {
+                false
+            }
+        })?;
+        if should_init {
+            let mut guard = ResetOnUnwind {
+                cortex: &self.cortex,
+                armed: true,
+            };
+            let value = init();
+            guard.armed = false;
+            self.cortex.with_write(|once| {
+                once.value.write(value);
+                once.state = DONE;
+            })?;
+        } else {
+            let mut retry = false;
+            wait_until(|| {
+                let state = self.cortex.read()?.state;
+                retry = state == UNINITIALIZED;
+                Ok(state != RUNNING)
+            })?;
+            if retry {
+                // Previous initializer panicked
+                return self.get_or_init(init);
+            }
+        }
+        Ok(self
+            .get()?
+            .expect("CortexOnce value should be initialized at this point"))
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+#[cfg(all(test, feature = "semaphore"))]
+mod tests {
+    use crate::once::CortexOnce;
+    use crate::semaphore::Semaphore;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn initializes_once() {
+        let key = rand::random::<i32>().abs();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let once: CortexOnce<u64, Semaphore> = CortexOnce::new(key).unwrap();
+        assert_eq!(once.get().unwrap(), None);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let calls = calls.clone();
+                thread::spawn(move || {
+                    let once: CortexOnce<u64, Semaphore> = CortexOnce::new(key).unwrap();
+                    once.get_or_init(|| {
+                        calls.fetch_add(1, Ordering::SeqCst);
+                        42
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            assert_eq!(handle.join().expect("Thread panicked"), 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert_eq!(once.get().unwrap(), Some(42));
+    }
+}
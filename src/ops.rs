@@ -0,0 +1,137 @@
+use crate::{Cortex, CortexResult, CortexSync};
+use std::time::Duration;
+
+/// Object safe subset of the operations of a `Cortex`, so that application code can depend on
+/// `Box<dyn CortexOps<T>>` rather than being generic over the lock, and substitute a mock in
+/// tests. `with_read` and `with_write` are available as `inspect` and `update`, which take
+/// `dyn` closures instead.
+pub trait CortexOps<T> {
+    /// See `Cortex::read`
+    fn read(&self) -> CortexResult<T>;
+    /// See `Cortex::try_read`
+    fn try_read(&self) -> CortexResult<Option<T>>;
+    /// See `Cortex::read_timeout`
+    fn read_timeout(&self, timeout: Duration) -> CortexResult<Option<T>>;
+    /// See `Cortex::write`
+    fn write(&self, data: T) -> CortexResult<()>;
+    /// See `Cortex::try_write`
+    fn try_write(&self, data: T) -> CortexResult<bool>;
+    /// See `Cortex::write_timeout`
+    fn write_timeout(&self, data: T, timeout: Duration) -> CortexResult<bool>;
+    /// See `Cortex::swap`
+    fn swap(&self, data: T) -> CortexResult<T>;
+    /// Access the value in place while holding the read lock, see `Cortex::with_read`
+    fn inspect(&self, f: &mut dyn FnMut(&T)) -> CortexResult<()>;
+    /// Modify the value in place while holding the write lock, see `Cortex::with_write`
+    fn update(&self, f: &mut dyn FnMut(&mut T)) -> CortexResult<()>;
+    fn key(&self) -> i32;
+    fn is_owner(&self) -> bool;
+}
+
+impl<T, L: CortexSync> CortexOps<T> for Cortex<T, L> {
+    fn read(&self) -> CortexResult<T> {
+        Cortex::read(self)
+    }
+    fn try_read(&self) -> CortexResult<Option<T>> {
+        Cortex::try_read(self)
+    }
+    fn read_timeout(&self, timeout: Duration) -> CortexResult<Option<T>> {
+        Cortex::read_timeout(self, timeout)
+    }
+    fn write(&self, data: T) -> CortexResult<()> {
+        Cortex::write(self, data)
+    }
+    fn try_write(&self, data: T) -> CortexResult<bool> {
+        Cortex::try_write(self, data)
+    }
+    fn write_timeout(&self, data: T, timeout: Duration) -> CortexResult<bool> {
+        Cortex::write_timeout(self, data, timeout)
+    }
+    fn swap(&self, data: T) -> CortexResult<T> {
+        Cortex::swap(self, data)
+    }
+    fn inspect(&self, f: &mut dyn FnMut(&T)) -> CortexResult<()> {
+        self.with_read(f)
+    }
+    fn update(&self, f: &mut dyn FnMut(&mut T)) -> CortexResult<()> {
+        self.with_write(f)
+    }
+    fn key(&self) -> i32 {
+        Cortex::key(self)
+    }
+    fn is_owner(&self) -> bool {
+        Cortex::is_owner(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ops::CortexOps;
+    use crate::{Cortex, CortexResult, SpinLock};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Counter that only knows about `CortexOps`
+    fn bump(counter: &dyn CortexOps<u64>) -> CortexResult<u64> {
+        counter.update(&mut |value| *value += 1)?;
+        counter.read()
+    }
+
+    struct Mock(Mutex<u64>);
+
+    impl CortexOps<u64> for Mock {
+        fn read(&self) -> CortexResult<u64> {
+            Ok(*self.0.lock().unwrap())
+        }
+        fn try_read(&self) -> CortexResult<Option<u64>> {
+            self.read().map(Some)
+        }
+        fn read_timeout(&self, _timeout: Duration) -> CortexResult<Option<u64>> {
+            self.read().map(Some)
+        }
+        fn write(&self, data: u64) -> CortexResult<()> {
+            *self.0.lock().unwrap() = data;
+            Ok(())
+        }
+        fn try_write(&self, data: u64) -> CortexResult<bool> {
+            self.write(data).map(|_| true)
+        }
+        fn write_timeout(&self, data: u64, _timeout: Duration) -> CortexResult<bool> {
+            self.write(data).map(|_| true)
+        }
+        fn swap(&self, data: u64) -> CortexResult<u64> {
+            Ok(std::mem::replace(&mut *self.0.lock().unwrap(), data))
+        }
+        fn inspect(&self, f: &mut dyn FnMut(&u64)) -> CortexResult<()> {
+            f(&self.0.lock().unwrap());
+            Ok(())
+        }
+        fn update(&self, f: &mut dyn FnMut(&mut u64)) -> CortexResult<()> {
+            f(&mut self.0.lock().unwrap());
+            Ok(())
+        }
+        fn key(&self) -> i32 {
+            0
+        }
+        fn is_owner(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn cortex_and_mock() {
+        let key = rand::random::<i32>().abs();
+        let counters: Vec<Box<dyn CortexOps<u64>>> = vec![
+            Box::new(Cortex::<u64, SpinLock>::new(Some(key), 1, false, None).unwrap()),
+            Box::new(Mock(Mutex::new(1))),
+        ];
+        for counter in &counters {
+            assert_eq!(bump(counter.as_ref()).unwrap(), 2);
+            assert_eq!(counter.swap(5).unwrap(), 2);
+            let mut seen = 0;
+            counter.inspect(&mut |value| seen = *value).unwrap();
+            assert_eq!(seen, 5);
+        }
+        assert_eq!(counters[0].key(), key);
+    }
+}
@@ -0,0 +1,43 @@
+use crate::endian::{Le, LittleEndian};
+
+/// Plain old data: types without padding for which every bit pattern is a valid value.
+///
+/// Some APIs look at the bytes of a value rather than the value itself, e.g. to checksum it, or
+/// reinterpret bytes written by another process as a value. That is only sound for types where
+/// every byte is initialized and no byte pattern is invalid, which the compiler can't check, so
+/// those APIs take `T: Pod`. It is implemented for the primitive integer and float types, arrays
+/// of `Pod` types and `Le`.
+///
+/// # Safety
+///
+/// The type must be `#[repr(C)]`, `#[repr(transparent)]` or a primitive, contain no padding
+/// bytes, and only consist of fields that are `Pod` themselves. That excludes `bool`, `char`,
+/// enums, references and pointers, for which not every bit pattern is a valid value, or which
+/// mean nothing in another process.
+///
+/// ```ignore
+/// #[derive(Clone, Copy)]
+/// #[repr(C)]
+/// struct Quote {
+///     price: f64,
+///     volume: u64,
+/// }
+///
+/// unsafe impl Pod for Quote {}
+/// ```
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($ty:ty),*) => {
+        $(
+            unsafe impl Pod for $ty {}
+        )*
+    };
+}
+
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+// Stored as its byte array, which has no padding
+unsafe impl<T: LittleEndian + 'static> Pod for Le<T> where T::Bytes: Pod {}
@@ -0,0 +1,231 @@
+use crate::{
+    cleanup::{self, OwnedResource, Registration},
+    crash::{CortexError, ErrorKind},
+    ipc_key, limits, mark_for_deletion, mem, random_key,
+    segment::Segment,
+    shmget_error, stat, CortexResult, CortexSync, RetryPolicy,
+};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+/// Untyped segment of `size` bytes protected by `L`, for sharing memory with processes written in
+/// other languages, where the peer owns the layout.
+///
+/// Unlike `Cortex`, the segment holds nothing but the payload, so a C process can `shmget` the same
+/// key and use the memory from offset zero. The lock is found through the key in the same way as
+/// for a `Cortex`, e.g. the named semaphore `cortex_semaphore_<key>` for `Semaphore`. New segments
+/// are zero-initialized.
+#[derive(Debug)]
+pub struct RawCortex<L: CortexSync> {
+    key: i32,
+    size: usize,
+    is_owner: bool,
+    registration: Registration,
+    lock: L,
+    segment: Segment<u8>,
+}
+
+unsafe impl<L: CortexSync + Send> Send for RawCortex<L> {}
+unsafe impl<L: CortexSync + Sync> Sync for RawCortex<L> {}
+
+impl<L: CortexSync> RawCortex<L> {
+    /// Allocate a new segment of `size` bytes on `init_key`, or on a random key if `None`
+    pub fn new(
+        init_key: Option<i32>,
+        size: usize,
+        lock_settings: Option<&L::Settings>,
+    ) -> CortexResult<Self> {
+        if size == 0 {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                "Raw segments can't be empty",
+            ));
+        }
+        limits::check_size(size, None)?;
+        let flags = libc::IPC_CREAT | libc::IPC_EXCL | 0o666;
+        let mut key = init_key.unwrap_or_else(random_key);
+        let mut id = unsafe { mem::shmget(L::IN_PROCESS, ipc_key(key), size, flags) };
+        let mut collisions = 0;
+        while id == -1
+            && init_key.is_none()
+            && errno::errno().0 == libc::EEXIST
+            && collisions < RetryPolicy::default().max_retries
+        {
+            collisions += 1;
+            key = random_key();
+            id = unsafe { mem::shmget(L::IN_PROCESS, ipc_key(key), size, flags) };
+        }
+        if id == -1 {
+            return Err(shmget_error(L::IN_PROCESS, key, size));
+        }
+        crate::trace::trace!("Allocated {} raw bytes with id: {}", size, id);
+
+        let created = Segment::attach(id).and_then(|segment| {
+            let lock = L::new(key, lock_settings)?;
+            Ok((segment, lock))
+        });
+        let (segment, lock) = created.inspect_err(|_| {
+            if let Err(err) = mark_for_deletion(id) {
+                crate::trace::error!("Error cleaning up after failed create: {}", err)
+            }
+        })?;
+        Ok(Self {
+            key,
+            size,
+            is_owner: true,
+            registration: cleanup::owned(|| OwnedResource::Segment { key, id }),
+            lock,
+            segment,
+        })
+    }
+    /// Attempt to attach to an already existing segment, which may have been created by a process
+    /// in another language. The size is taken from the segment itself.
+    pub fn attach(key: i32, lock_settings: Option<&L::Settings>) -> CortexResult<Self> {
+        let lock = L::attach(key, lock_settings)?;
+        let id = unsafe { mem::shmget(L::IN_PROCESS, ipc_key(key), 0, 0o666) };
+        if id == -1 {
+            return Err(CortexError::new_clean(format!(
+                "Error during shmget for key: {}",
+                key
+            )));
+        }
+        let size = stat(id)?.shm_segsz;
+        Ok(Self {
+            key,
+            size,
+            is_owner: false,
+            registration: Registration::default(),
+            lock,
+            segment: Segment::attach(id)?,
+        })
+    }
+    /// Acquire the read lock, returning a guard that derefs to the bytes of the segment
+    pub fn as_slice(&self) -> CortexResult<RawReadGuard<'_, L>> {
+        self.lock.read_lock()?;
+        Ok(RawReadGuard {
+            raw: self,
+            _not_send: PhantomData,
+        })
+    }
+    /// Acquire the write lock, returning a guard that derefs to the bytes of the segment
+    pub fn as_mut_slice(&self) -> CortexResult<RawWriteGuard<'_, L>> {
+        self.lock.write_lock()?;
+        Ok(RawWriteGuard {
+            raw: self,
+            _not_send: PhantomData,
+        })
+    }
+    /// Size of the segment in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn key(&self) -> i32 {
+        self.key
+    }
+    /// Id of the underlying System V segment
+    pub fn id(&self) -> i32 {
+        self.segment.id
+    }
+    /// Start of the mapping, for types that lock parts of the segment themselves
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.segment.ptr
+    }
+    pub fn is_owner(&self) -> bool {
+        self.is_owner && !self.registration.inherited()
+    }
+    /// Prepare an instance inherited through `fork` for use in the child, see
+    /// `Cortex::after_fork_child`
+    pub fn after_fork_child(&mut self) -> CortexResult<()> {
+        self.lock.after_fork_child()?;
+        self.is_owner = false;
+        self.registration = Registration::default();
+        Ok(())
+    }
+}
+
+/// Remove the segment if this instance created it, the mapping itself is detached by `Segment`
+impl<L: CortexSync> Drop for RawCortex<L> {
+    fn drop(&mut self) {
+        if !self.is_owner || !cleanup::released(self.registration) {
+            return;
+        }
+        if let Err(err) = mark_for_deletion(self.segment.id) {
+            crate::trace::error!("Error during mark_for_deletion in Drop: {}", err)
+        }
+    }
+}
+
+/// Shared read access to the bytes of a `RawCortex`. The lock is released when dropped.
+pub struct RawReadGuard<'a, L: CortexSync> {
+    raw: &'a RawCortex<L>,
+    /// Locks are released by the thread that acquired them, e.g. for `ReentrantLock`
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<L: CortexSync> Deref for RawReadGuard<'_, L> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.raw.segment.ptr, self.raw.size) }
+    }
+}
+
+impl<L: CortexSync> Drop for RawReadGuard<'_, L> {
+    fn drop(&mut self) {
+        if let Err(err) = self.raw.lock.release_read() {
+            crate::trace::error!("Error releasing read lock in Drop: {}", err);
+        }
+    }
+}
+
+/// Exclusive write access to the bytes of a `RawCortex`. The lock is released when dropped.
+pub struct RawWriteGuard<'a, L: CortexSync> {
+    raw: &'a RawCortex<L>,
+    /// Locks are released by the thread that acquired them, e.g. for `ReentrantLock`
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<L: CortexSync> Deref for RawWriteGuard<'_, L> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.raw.segment.ptr, self.raw.size) }
+    }
+}
+
+impl<L: CortexSync> DerefMut for RawWriteGuard<'_, L> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.raw.segment.ptr, self.raw.size) }
+    }
+}
+
+impl<L: CortexSync> Drop for RawWriteGuard<'_, L> {
+    fn drop(&mut self) {
+        if let Err(err) = self.raw.lock.release_write() {
+            crate::trace::error!("Error releasing write lock in Drop: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::raw::RawCortex;
+    use crate::{ErrorKind, SpinLock};
+
+    #[test]
+    fn bytes_across_instances() {
+        let raw: RawCortex<SpinLock> = RawCortex::new(None, 100, None).unwrap();
+        let attached: RawCortex<SpinLock> = RawCortex::attach(raw.key(), None).unwrap();
+        // Reported exactly, even though the kernel allocates whole pages
+        assert_eq!(attached.size(), 100);
+
+        attached.as_mut_slice().unwrap()[..4].copy_from_slice(&7u32.to_le_bytes());
+        let bytes = raw.as_slice().unwrap();
+        assert_eq!(bytes[..4], 7u32.to_le_bytes());
+        assert!(bytes[4..].iter().all(|byte| *byte == 0));
+        drop(bytes);
+
+        let err = RawCortex::<SpinLock>::new(None, 0, None).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,361 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    no_lock, process_alive,
+    spin::Backoff,
+    Cortex, CortexResult, NoLock,
+};
+use std::cell::{Cell, UnsafeCell};
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
+
+/// Maximum number of `RcuReader`s on a segment at the same time
+const MAX_READERS: usize = 64;
+
+const FREE: u32 = 0;
+/// The version readers are directed to
+const CURRENT: u32 = 1;
+/// Replaced, freed once no reader can still be looking at it
+const RETIRED: u32 = 2;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+
+/// Padded to a cache line, so that readers announcing themselves never touch the same line
+#[repr(C, align(64))]
+struct ReaderSlot {
+    /// Pid of the process the slot belongs to, zero if unclaimed
+    owner_pid: AtomicI32,
+    /// Global epoch at the start of the current read plus one, zero outside of reads
+    epoch: AtomicU64,
+}
+
+#[repr(C)]
+struct Version<T> {
+    state: AtomicU32,
+    /// Global epoch at the time the version was replaced
+    retired_at: AtomicU64,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+#[repr(C)]
+struct RcuState<T, const N: usize> {
+    /// Index of the current version
+    current: AtomicU32,
+    /// Incremented every time a version is published
+    epoch: AtomicU64,
+    writer: AtomicU32,
+    readers: [ReaderSlot; MAX_READERS],
+    versions: [Version<T>; N],
+}
+
+/// Process-shared value for large, read-mostly data, following the read-copy-update pattern.
+///
+/// The segment holds `N` versions of the value, one of which is current. Writers build a new
+/// version in a free one and then publish it by switching the current version over, while
+/// readers go through an `RcuReader` and never take a lock or wait for a writer. Every reader
+/// announces the epoch it started reading in, and a replaced version is only reused once no
+/// reader started before it was replaced, so readers never see a version being overwritten.
+///
+/// Writers are serialized by a spin lock in the segment, and wait for readers to move on when
+/// all `N` versions are still in use, so pick `N` of at least 3 to let writers continue while
+/// readers are busy with older versions. A writer that dies while publishing leaves the lock held
+/// forever, while the reader slots of processes that have died are taken over by new ones.
+/// Values are copied in and out of shared memory, so they have to be `Copy`.
+pub struct CortexRcu<T: Copy, const N: usize> {
+    cortex: Cortex<RcuState<T, N>, NoLock>,
+}
+
+unsafe impl<T: Copy + Send + Sync, const N: usize> Send for CortexRcu<T, N> {}
+unsafe impl<T: Copy + Send + Sync, const N: usize> Sync for CortexRcu<T, N> {}
+
+/// Writer lock of a `CortexRcu`, released when dropped
+struct WriterLock<'a>(&'a AtomicU32);
+
+impl Drop for WriterLock<'_> {
+    fn drop(&mut self) {
+        self.0.store(UNLOCKED, Ordering::Release);
+    }
+}
+
+impl<T: Copy, const N: usize> CortexRcu<T, N> {
+    /// Create a new segment on `key` with `value` as the current version
+    pub fn new(key: i32, value: T) -> CortexResult<Self> {
+        if N < 2 || N > u32::MAX as usize {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!("Invalid number of versions: {}, at least 2 are needed", N),
+            ));
+        }
+        let state = RcuState {
+            current: AtomicU32::new(0),
+            epoch: AtomicU64::new(0),
+            writer: AtomicU32::new(UNLOCKED),
+            readers: std::array::from_fn(|_| ReaderSlot {
+                owner_pid: AtomicI32::new(0),
+                epoch: AtomicU64::new(0),
+            }),
+            versions: std::array::from_fn(|index| Version {
+                state: AtomicU32::new(if index == 0 { CURRENT } else { FREE }),
+                retired_at: AtomicU64::new(0),
+                value: UnsafeCell::new(match index {
+                    0 => MaybeUninit::new(value),
+                    _ => MaybeUninit::uninit(),
+                }),
+            }),
+        };
+        let cortex = Cortex::new(Some(key), state, false, Some(no_lock::INTERNAL))?;
+        Ok(Self { cortex })
+    }
+    /// Attempt to attach to an already existing segment
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let cortex = Cortex::attach_with_lock(key, no_lock::INTERNAL)?;
+        Ok(Self { cortex })
+    }
+    fn state(&self) -> &RcuState<T, N> {
+        unsafe { &*self.cortex.ptr }
+    }
+    /// Claim a reader slot, failing with `ErrorKind::NoSpace` if all 64 are taken. Slots of
+    /// processes that have died are taken over.
+    pub fn reader(&self) -> CortexResult<RcuReader<'_, T, N>> {
+        let pid = unsafe { libc::getpid() };
+        for (index, slot) in self.state().readers.iter().enumerate() {
+            let owner = slot.owner_pid.load(Ordering::Relaxed);
+            if (owner == 0 || (owner != pid && !process_alive(owner)))
+                && slot
+                    .owner_pid
+                    .compare_exchange(owner, pid, Ordering::Relaxed, Ordering::Relaxed)
+                    .is_ok()
+            {
+                slot.epoch.store(0, Ordering::SeqCst);
+                return Ok(RcuReader {
+                    rcu: self,
+                    slot: index,
+                    depth: Cell::new(0),
+                });
+            }
+        }
+        Err(CortexError::new_logic(
+            ErrorKind::NoSpace,
+            format!(
+                "All {} reader slots of segment with key: {} are taken",
+                MAX_READERS,
+                self.key()
+            ),
+        ))
+    }
+    /// Publish `value` as the new current version
+    pub fn publish(&self, value: T) -> CortexResult<()> {
+        self.update(|next| *next = value)
+    }
+    /// Publish a new version built by `f` from a copy of the current one. Readers keep seeing
+    /// the previous version until `f` returns.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> CortexResult<R> {
+        let state = self.state();
+        let _writer = self.lock_writer();
+        let current = state.current.load(Ordering::Relaxed) as usize;
+        let next = self.free_version(current);
+        let result = unsafe {
+            let value = &mut *state.versions[next].value.get();
+            value.write((*state.versions[current].value.get()).assume_init());
+            f(value.assume_init_mut())
+        };
+        state.versions[next].state.store(CURRENT, Ordering::Relaxed);
+        // Readers announcing themselves from here on find the new version
+        state.current.store(next as u32, Ordering::SeqCst);
+        let epoch = state.epoch.load(Ordering::Relaxed);
+        let retired = &state.versions[current];
+        retired.retired_at.store(epoch, Ordering::Relaxed);
+        retired.state.store(RETIRED, Ordering::Relaxed);
+        state.epoch.store(epoch + 1, Ordering::SeqCst);
+        crate::trace::trace!("Published version {} on key: {}", epoch + 1, self.key());
+        Ok(result)
+    }
+    fn lock_writer(&self) -> WriterLock<'_> {
+        let lock = &self.state().writer;
+        let mut backoff = Backoff::new();
+        while lock
+            .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+        WriterLock(lock)
+    }
+    /// Find a version other than `current` to build the next one in, reclaiming retired versions
+    /// that no reader can be looking at anymore, and waiting for readers if there is none. Must
+    /// be called while holding the writer lock.
+    fn free_version(&self, current: usize) -> usize {
+        let state = self.state();
+        let mut backoff = Backoff::new();
+        loop {
+            // Epoch of the oldest read in progress, a version retired before it is unreachable
+            let oldest = state
+                .readers
+                .iter()
+                .filter_map(|slot| match slot.epoch.load(Ordering::SeqCst) {
+                    0 => None,
+                    _ if !process_alive(slot.owner_pid.load(Ordering::Relaxed)) => None,
+                    epoch => Some(epoch - 1),
+                })
+                .min()
+                .unwrap_or(u64::MAX);
+            for (index, version) in state.versions.iter().enumerate() {
+                if index == current {
+                    continue;
+                }
+                match version.state.load(Ordering::Relaxed) {
+                    FREE => return index,
+                    RETIRED if version.retired_at.load(Ordering::Relaxed) < oldest => {
+                        version.state.store(FREE, Ordering::Relaxed);
+                        return index;
+                    }
+                    _ => {}
+                }
+            }
+            backoff.snooze();
+        }
+    }
+    /// Number of versions published since the segment was created
+    pub fn epoch(&self) -> u64 {
+        self.state().epoch.load(Ordering::Relaxed)
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+    pub fn is_owner(&self) -> bool {
+        self.cortex.is_owner()
+    }
+}
+
+/// Lock-free reader of a `CortexRcu`, holding one of its reader slots until dropped. Every thread
+/// reading concurrently needs a reader of its own.
+pub struct RcuReader<'a, T: Copy, const N: usize> {
+    rcu: &'a CortexRcu<T, N>,
+    slot: usize,
+    /// Number of nested reads in progress, only the outermost one announces itself
+    depth: Cell<u32>,
+}
+
+impl<T: Copy, const N: usize> RcuReader<'_, T, N> {
+    /// Access the current version in place. Writers publishing meanwhile don't affect the version
+    /// seen by `f`, but have to wait for it to return before they can reuse the version.
+    pub fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let state = self.rcu.state();
+        let slot = &state.readers[self.slot];
+        if self.depth.get() == 0 {
+            let epoch = state.epoch.load(Ordering::SeqCst);
+            slot.epoch.store(epoch + 1, Ordering::SeqCst);
+        }
+        self.depth.set(self.depth.get() + 1);
+        // Leave the read even if `f` panics
+        struct Leave<'a>(&'a Cell<u32>, &'a AtomicU64);
+        impl Drop for Leave<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() - 1);
+                if self.0.get() == 0 {
+                    self.1.store(0, Ordering::Release);
+                }
+            }
+        }
+        let _leave = Leave(&self.depth, &slot.epoch);
+        let current = state.current.load(Ordering::SeqCst) as usize;
+        f(unsafe { (*state.versions[current].value.get()).assume_init_ref() })
+    }
+    /// Copy out the current version
+    pub fn read(&self) -> T {
+        self.with_read(|value| *value)
+    }
+}
+
+impl<T: Copy, const N: usize> Drop for RcuReader<'_, T, N> {
+    fn drop(&mut self) {
+        let slot = &self.rcu.state().readers[self.slot];
+        slot.epoch.store(0, Ordering::Release);
+        slot.owner_pid.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rcu::CortexRcu;
+    use crate::ErrorKind;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn readers_see_whole_versions() {
+        let key = rand::random::<i32>().abs();
+        let rcu: CortexRcu<[u64; 64], 3> = CortexRcu::new(key, [0; 64]).unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let stop = stop.clone();
+                thread::spawn(move || {
+                    let rcu: CortexRcu<[u64; 64], 3> = CortexRcu::attach(key).unwrap();
+                    let reader = rcu.reader().unwrap();
+                    let mut last = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        let version = reader.read();
+                        assert!(version.iter().all(|value| *value == version[0]));
+                        assert!(version[0] >= last);
+                        last = version[0];
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..2000 {
+            rcu.update(|version| version.iter_mut().for_each(|value| *value += 1))
+                .unwrap();
+        }
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().expect("Thread panicked");
+        }
+        assert_eq!(rcu.reader().unwrap().read(), [2000; 64]);
+        assert_eq!(rcu.epoch(), 2000);
+    }
+
+    #[test]
+    fn writer_waits_for_old_readers() {
+        let key = rand::random::<i32>().abs();
+        let rcu: CortexRcu<u64, 2> = CortexRcu::new(key, 1).unwrap();
+        let reader = rcu.reader().unwrap();
+
+        reader
+            .with_read(|value| {
+                assert_eq!(*value, 1);
+                // The first publish takes the free version, the second has to reuse the one being read
+                let writer = thread::spawn(move || {
+                    let rcu: CortexRcu<u64, 2> = CortexRcu::attach(key).unwrap();
+                    rcu.publish(2).unwrap();
+                    rcu.publish(3).unwrap();
+                });
+                thread::sleep(Duration::from_millis(50));
+                assert!(!writer.is_finished());
+                // Nested reads see the latest version, and keep the outer read announced
+                assert_eq!(reader.read(), 2);
+                assert_eq!(*value, 1);
+                writer
+            })
+            .join()
+            .expect("Thread panicked");
+        assert_eq!(reader.read(), 3);
+
+        let err = CortexRcu::<u64, 1>::new(key + 1, 0).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn reader_slots() {
+        let key = rand::random::<i32>().abs();
+        let rcu: CortexRcu<u32, 3> = CortexRcu::new(key, 0).unwrap();
+        let readers: Vec<_> = (0..64).map(|_| rcu.reader().unwrap()).collect();
+        let err = rcu.reader().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NoSpace);
+        drop(readers);
+        assert_eq!(rcu.reader().unwrap().read(), 0);
+    }
+}
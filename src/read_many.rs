@@ -0,0 +1,167 @@
+//! Consistent reads of several segments at once, see `read_many`.
+
+use crate::{guard::HeldLock, Cortex, CortexResult, CortexSync};
+
+/// Read the values of several cortices at once, holding all of their read locks at the same time
+/// so that the values are consistent with each other, e.g.
+/// `let (config, stats) = read_many((&config, &stats))?`.
+///
+/// The locks are acquired in order of key, so concurrent `read_many` calls on overlapping sets of
+/// cortices can't deadlock, no matter the order they are passed in. Code taking several of these
+/// locks by other means has to acquire them in order of key as well. Cortices sharing a key are
+/// only locked once. Takes tuples of up to 8 references, which may differ in type and lock.
+pub fn read_many<C: ReadMany>(cortices: C) -> CortexResult<C::Output> {
+    cortices.read_many()
+}
+
+/// Tuples of `&Cortex` that can be read through `read_many`
+pub trait ReadMany {
+    /// Tuple of the values, in the same order as the cortices
+    type Output;
+    fn read_many(self) -> CortexResult<Self::Output>;
+}
+
+/// Lock held by `read_many` or a `Transaction`, with the type of the cortex erased
+pub(crate) trait Held {
+    fn release(self: Box<Self>) -> CortexResult<()>;
+}
+
+impl<T, L: CortexSync> Held for HeldLock<'_, T, L> {
+    fn release(self: Box<Self>) -> CortexResult<()> {
+        HeldLock::release(*self)
+    }
+}
+
+/// Cortex taking part in `read_many`, with its type erased
+trait Source {
+    fn key(&self) -> i32;
+    fn lock(&self) -> CortexResult<Box<dyn Held + '_>>;
+}
+
+impl<T, L: CortexSync> Source for Cortex<T, L> {
+    fn key(&self) -> i32 {
+        self.key
+    }
+    fn lock(&self) -> CortexResult<Box<dyn Held + '_>> {
+        Ok(Box::new(self.acquire_read()?))
+    }
+}
+
+impl<T, L: CortexSync> Cortex<T, L> {
+    /// Copy out the value while the read lock is held by `read_many`
+    fn read_locked(&self) -> CortexResult<T> {
+        self.check_data()?;
+        Ok(unsafe { self.ptr.read() })
+    }
+}
+
+/// Acquire the read locks of `sources` in order of key, once per key. If acquiring one fails the
+/// locks acquired so far are released on drop.
+fn lock_sorted<'a>(sources: &[&'a dyn Source]) -> CortexResult<Vec<Box<dyn Held + 'a>>> {
+    let mut sorted = sources.to_vec();
+    sorted.sort_by_key(|source| source.key());
+    sorted.dedup_by_key(|source| source.key());
+    sorted.into_iter().map(|source| source.lock()).collect()
+}
+
+/// Release the locks in the reverse order of acquiring them, returning the first error
+pub(crate) fn release(held: Vec<Box<dyn Held + '_>>) -> CortexResult<()> {
+    let mut result = Ok(());
+    for held in held.into_iter().rev() {
+        let released = held.release();
+        if result.is_ok() {
+            result = released;
+        }
+    }
+    result
+}
+
+macro_rules! impl_read_many {
+    ($($cortex:ident: $value:ident, $lock:ident),+) => {
+        impl<'a, $($value, $lock: CortexSync),+> ReadMany for ($(&'a Cortex<$value, $lock>,)+) {
+            type Output = ($($value,)+);
+
+            fn read_many(self) -> CortexResult<Self::Output> {
+                let ($($cortex,)+) = self;
+                let held = lock_sorted(&[$($cortex as &dyn Source),+])?;
+                let values = ($($cortex.read_locked()?,)+);
+                release(held)?;
+                Ok(values)
+            }
+        }
+    };
+}
+
+impl_read_many!(a: A, La);
+impl_read_many!(a: A, La, b: B, Lb);
+impl_read_many!(a: A, La, b: B, Lb, c: C, Lc);
+impl_read_many!(a: A, La, b: B, Lb, c: C, Lc, d: D, Ld);
+impl_read_many!(a: A, La, b: B, Lb, c: C, Lc, d: D, Ld, e: E, Le);
+impl_read_many!(a: A, La, b: B, Lb, c: C, Lc, d: D, Ld, e: E, Le, f: F, Lf);
+impl_read_many!(a: A, La, b: B, Lb, c: C, Lc, d: D, Ld, e: E, Le, f: F, Lf, g: G, Lg);
+impl_read_many!(a: A, La, b: B, Lb, c: C, Lc, d: D, Ld, e: E, Le, f: F, Lf, g: G, Lg, h: H, Lh);
+
+#[cfg(test)]
+mod tests {
+    use crate::read_many::read_many;
+    use crate::{Cortex, CortexError, SpinLock, TicketLock};
+    use std::thread;
+
+    #[test]
+    fn consistent_values() {
+        let key = rand::random::<i32>().abs() / 2;
+        let first: Cortex<u64, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        let second: Cortex<u64, TicketLock> = Cortex::new(Some(key + 1), 0, false, None).unwrap();
+
+        // Writers incrementing both, locking in order of key
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let first: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+                    let second: Cortex<u64, TicketLock> = Cortex::attach(key + 1).unwrap();
+                    for _ in 0..500 {
+                        first
+                            .with_write(|first| {
+                                second.with_write(|second| *second += 1).unwrap();
+                                *first += 1;
+                            })
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..500 {
+            // Passed in the reverse order of the keys
+            let (second, first) = read_many((&second, &first)).unwrap();
+            assert_eq!(first, second);
+        }
+        for writer in writers {
+            writer.join().expect("Thread panicked");
+        }
+        assert_eq!(read_many((&first, &second)).unwrap(), (2000, 2000));
+
+        // The same segment is only locked once
+        let attached: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+        assert_eq!(read_many((&first, &attached)).unwrap(), (2000, 2000));
+        assert_eq!(read_many((&first,)).unwrap(), (2000,));
+    }
+
+    #[test]
+    fn releases_on_error() {
+        let key = rand::random::<i32>().abs() / 2;
+        let healthy: Cortex<u64, SpinLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+        let poisoned: Cortex<u64, SpinLock> = Cortex::new(Some(key + 1), 2, false, None).unwrap();
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            poisoned
+                .with_write(|_| panic!("Interrupted write"))
+                .unwrap();
+        }));
+
+        let result = read_many((&poisoned, &healthy));
+        assert!(matches!(result, Err(CortexError::Poisoned)));
+        // Would spin forever if the locks were still held
+        assert_eq!(healthy.read().unwrap(), 1);
+        assert!(poisoned.clear_poison().unwrap());
+        assert_eq!(read_many((&healthy, &poisoned)).unwrap(), (1, 2));
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{Cortex, CortexResult, CortexSync, LockHolderInfo, Pod, Versioned};
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// Handle on a segment mapped with `SHM_RDONLY`, returned by `Cortex::attach_read_only`.
+///
+/// Meant for consumer processes that should never modify the data of the producer: there is no
+/// way to write through this handle, and even a stray write through a raw pointer faults instead
+/// of corrupting the segment. Reads take the read lock like any other handle, but don't record
+/// themselves as the lock holder. A read-only handle is never the owner and doesn't count towards
+/// the reference count of ref counted segments.
+#[derive(Debug)]
+pub struct ReadOnlyCortex<T, L: CortexSync> {
+    cortex: Cortex<T, L>,
+}
+
+impl<T, L: CortexSync> ReadOnlyCortex<T, L> {
+    pub(crate) fn new(cortex: Cortex<T, L>) -> Self {
+        Self { cortex }
+    }
+    /// Read from shared memory
+    pub fn read(&self) -> CortexResult<T> {
+        self.cortex.read()
+    }
+    /// Read from shared memory without blocking, returns `None` if the lock is held elsewhere
+    pub fn try_read(&self) -> CortexResult<Option<T>> {
+        self.cortex.try_read()
+    }
+    /// Read from shared memory, returns `None` if the lock could not be acquired within `timeout`
+    pub fn read_timeout(&self, timeout: Duration) -> CortexResult<Option<T>> {
+        self.cortex.read_timeout(timeout)
+    }
+    /// Read from shared memory along with the version and time of the write that produced it
+    pub fn read_versioned(&self) -> CortexResult<Versioned<T>> {
+        self.cortex.read_versioned()
+    }
+    /// Access the value in place while holding the read lock
+    pub fn with_read<R>(&self, f: impl FnOnce(&T) -> R) -> CortexResult<R> {
+        self.cortex.with_read(f)
+    }
+    /// Read a single field of the value, selected by `field`
+    pub fn read_field<F: Copy>(&self, field: impl FnOnce(&T) -> &F) -> CortexResult<F> {
+        self.cortex.read_field(field)
+    }
+    /// Attach a second read-only handle to the same segment and lock
+    pub fn try_clone(&self) -> CortexResult<Self> {
+        self.cortex.try_clone().map(Self::new)
+    }
+    /// Prepare a handle inherited through `fork` for use in the child, see
+    /// `Cortex::after_fork_child`
+    pub fn after_fork_child(&mut self) -> CortexResult<()> {
+        self.cortex.after_fork_child()
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+    /// Id of the underlying System V segment, as listed by `ipcs -m`
+    pub fn id(&self) -> i32 {
+        self.cortex.id()
+    }
+    /// Size of the stored value in bytes, not counting the header in front of it
+    pub fn size(&self) -> usize {
+        self.cortex.size()
+    }
+    /// Number of processes currently attached to the segment, including this one
+    pub fn attach_count(&self) -> CortexResult<usize> {
+        self.cortex.attach_count()
+    }
+    pub fn schema_version(&self) -> u32 {
+        self.cortex.schema_version()
+    }
+    pub fn created_at(&self) -> SystemTime {
+        self.cortex.created_at()
+    }
+    /// The thread currently holding the lock, as recorded by handles that can write
+    pub fn lock_holder(&self) -> Option<LockHolderInfo> {
+        self.cortex.lock_holder()
+    }
+    /// Time of the last heartbeat of the owner, see `Cortex::beat`
+    pub fn last_beat(&self) -> Option<SystemTime> {
+        self.cortex.last_beat()
+    }
+    /// Check whether the owner has recorded a heartbeat within `max_age`
+    pub fn owner_alive(&self, max_age: Duration) -> bool {
+        self.cortex.owner_alive(max_age)
+    }
+    /// Pid of the process currently responsible for cleaning up the segment
+    pub fn owner_pid(&self) -> Option<i32> {
+        self.cortex.owner_pid()
+    }
+}
+
+impl<T: Pod, L: CortexSync> ReadOnlyCortex<T, L> {
+    /// Dump the segment to the file at `path` under the read lock, see `Cortex::snapshot_to`
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> CortexResult<()> {
+        self.cortex.snapshot_to(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, CortexBuilder, CortexResult, ErrorKind, ReadOnlyCortex, SpinLock};
+
+    #[test]
+    fn reads_without_writing() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new([1u64; 4])
+            .key(key)
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        let read_only: ReadOnlyCortex<[u64; 4], SpinLock> = Cortex::attach_read_only(key).unwrap();
+
+        assert_eq!(read_only.read().unwrap(), [1; 4]);
+        cortex.write([2; 4]).unwrap();
+        assert_eq!(read_only.read_field(|data| &data[3]).unwrap(), 2);
+        assert_eq!(read_only.read_versioned().unwrap().version, 1);
+        assert_eq!(read_only.try_clone().unwrap().read().unwrap(), [2; 4]);
+        assert_eq!(read_only.attach_count().unwrap(), 2);
+        assert_eq!(read_only.owner_pid(), cortex.owner_pid());
+
+        // The mapping itself refuses writes
+        let header = read_only.cortex.header as *mut libc::c_void;
+        let result = unsafe { libc::mprotect(header, 1, libc::PROT_READ | libc::PROT_WRITE) };
+        assert_eq!(result, -1);
+        assert_eq!(errno::errno().0, libc::EACCES);
+    }
+
+    #[test]
+    fn ref_count_unaffected() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new(7u32)
+            .key(key)
+            .ref_counted()
+            .default_lock::<SpinLock>()
+            .create()
+            .unwrap();
+        let read_only: ReadOnlyCortex<u32, SpinLock> = Cortex::attach_read_only(key).unwrap();
+        drop(cortex);
+        // The segment is removed, but stays mapped until the read-only handle is dropped
+        assert!(Cortex::<u32, SpinLock>::attach(key).is_err());
+        assert_eq!(read_only.read().unwrap(), 7);
+    }
+
+    #[test]
+    fn sealed_segment() {
+        let key = rand::random::<i32>().abs();
+        let mut cortex: Cortex<[u32; 8], SpinLock> =
+            Cortex::new(Some(key), [0; 8], false, None).unwrap();
+        let attached: Cortex<[u32; 8], SpinLock> = Cortex::attach(key).unwrap();
+        cortex.write([7; 8]).unwrap();
+        cortex.seal().unwrap();
+        cortex.seal().unwrap();
+        assert!(attached.is_sealed());
+
+        let denied = |result: CortexResult<()>| {
+            assert_eq!(result.unwrap_err().kind(), ErrorKind::PermissionDenied)
+        };
+        denied(cortex.write([1; 8]));
+        denied(attached.write([1; 8]));
+        denied(attached.try_write([1; 8]).map(|_| ()));
+        denied(attached.with_write(|data| data[0] = 1));
+        denied(attached.read_upgradable().unwrap().upgrade().map(|_| ()));
+        // The lock was released after every attempt
+        assert_eq!(cortex.read().unwrap(), [7; 8]);
+        assert_eq!(attached.try_clone().unwrap().read().unwrap(), [7; 8]);
+
+        // The data is mapped read-only in the sealing process as well
+        let mapping = cortex.sealed.unwrap();
+        let result = unsafe { libc::mprotect(mapping, 1, libc::PROT_READ | libc::PROT_WRITE) };
+        assert_eq!(result, -1);
+        cortex.beat();
+        assert!(cortex.owner_alive(std::time::Duration::from_secs(60)));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    shared_state::SharedState,
+    spin::Backoff,
+    thread_token, CortexResult, CortexSync,
+};
+use std::cell::Cell;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+const READ: u32 = 1;
+const WRITE: u32 = 2;
+
+#[derive(Debug)]
+struct ReentrantState {
+    /// Id of the thread holding the lock, 0 if it is free
+    holder: AtomicU64,
+    /// Number of times the holder has acquired the lock
+    depth: AtomicU32,
+    /// Whether the holder acquired the lock for reading or writing
+    mode: AtomicU32,
+}
+
+/// Reentrant lock stored in a small named shared memory object.
+///
+/// The thread holding a read lock can acquire it for reading again without deadlocking, e.g.
+/// calling `read` from a `with_read` callback. Every acquisition must be matched by a release,
+/// and the lock is only handed to other threads once the outermost one is released. Acquiring
+/// the lock again while holding it for writing, or for writing while holding it for reading,
+/// fails with `ErrorKind::WouldBlock`, since it would hand out a mutable reference to the value
+/// alongside another one. Readers and writers are both given exclusive access, and waiting is
+/// done by spinning with backoff.
+///
+/// The lock is held by a thread rather than a handle, so handles are `!Sync` and every thread
+/// attaches its own. This keeps guards from being released on another thread than the one that
+/// acquired them, which would leave the lock held.
+#[derive(Debug)]
+pub struct ReentrantLock {
+    state: SharedState<ReentrantState>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl ReentrantLock {
+    fn lock(&self, mode: u32) -> CortexResult<()> {
+        let mut backoff = Backoff::new();
+        while !self.try_lock(mode)? {
+            backoff.snooze();
+        }
+        Ok(())
+    }
+    fn try_lock(&self, mode: u32) -> CortexResult<bool> {
+        let state = self.state.get();
+        let id = thread_token();
+        if state.holder.load(Ordering::Relaxed) == id {
+            if mode != READ || state.mode.load(Ordering::Relaxed) != READ {
+                return Err(CortexError::new_logic(
+                    ErrorKind::WouldBlock,
+                    "Reentrant lock can only be re-entered for reading while held for reading",
+                ));
+            }
+            state.depth.fetch_add(1, Ordering::Relaxed);
+            return Ok(true);
+        }
+        if state
+            .holder
+            .compare_exchange(0, id, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Ok(false);
+        }
+        state.depth.store(1, Ordering::Relaxed);
+        state.mode.store(mode, Ordering::Relaxed);
+        Ok(true)
+    }
+}
+
+impl CortexSync for ReentrantLock {
+    type Settings = ();
+
+    fn new(cortex_key: i32, _settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::create("cortex_reentrantlock", cortex_key)?;
+        Ok(Self {
+            state,
+            _not_sync: PhantomData,
+        })
+    }
+    fn attach(cortex_key: i32, _settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::open("cortex_reentrantlock", cortex_key)?;
+        Ok(Self {
+            state,
+            _not_sync: PhantomData,
+        })
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        let state = self.state.try_clone()?;
+        Ok(Self {
+            state,
+            _not_sync: PhantomData,
+        })
+    }
+    fn force_ownership(&mut self) {
+        self.state.force_ownership()
+    }
+    fn relinquish_ownership(&mut self) {
+        self.state.relinquish_ownership()
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.lock(READ)
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.lock(WRITE)
+    }
+    fn release(&self) -> CortexResult<()> {
+        let state = self.state.get();
+        if state.holder.load(Ordering::Relaxed) != thread_token() {
+            return Err(CortexError::new_logic(
+                ErrorKind::PermissionDenied,
+                "Reentrant lock released by a thread that doesn't hold it",
+            ));
+        }
+        if state.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            state.holder.store(0, Ordering::Release);
+        }
+        Ok(())
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        self.try_lock(READ)
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        self.try_lock(WRITE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reentrant::ReentrantLock;
+    use crate::{Cortex, CortexSync, ErrorKind};
+    use std::thread;
+
+    #[test]
+    fn nested_acquisition() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, ReentrantLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+
+        // Would deadlock with a non-reentrant lock
+        let seen = cortex.with_read(|_| cortex.read().unwrap()).unwrap();
+        assert_eq!(seen, 1);
+
+        // Re-entering in any other mode would alias the value behind a mutable reference
+        let nested = cortex.with_read(|_| cortex.write(2)).unwrap();
+        assert_eq!(nested.unwrap_err().kind(), ErrorKind::WouldBlock);
+        let nested = cortex.with_write(|_| cortex.read()).unwrap();
+        assert_eq!(nested.unwrap_err().kind(), ErrorKind::WouldBlock);
+        let nested = cortex.with_write(|_| cortex.write(2)).unwrap();
+        assert_eq!(nested.unwrap_err().kind(), ErrorKind::WouldBlock);
+        assert_eq!(cortex.read().unwrap(), 1);
+
+        cortex.lock.write_lock().unwrap();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let attached: Cortex<u64, ReentrantLock> = Cortex::attach(key).unwrap();
+                assert_eq!(attached.try_read().unwrap(), None);
+                assert!(attached.lock.release().is_err());
+            });
+        });
+        cortex.lock.release().unwrap();
+        assert!(cortex.lock.release().is_err());
+    }
+
+    #[test]
+    fn mutual_exclusion() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, ReentrantLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached: Cortex<u64, ReentrantLock> = Cortex::attach(key).unwrap();
+                    for _ in 0..1000 {
+                        attached.with_write(|value| *value += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(cortex.read().unwrap(), 8000);
+    }
+}
@@ -0,0 +1,85 @@
+use crate::{CortexError, CortexResult, ErrorKind};
+use std::time::Duration;
+
+/// How segment creation and attachment retry transient failures, set through
+/// `CortexBuilder::retry_policy`.
+///
+/// Covers interrupted system calls, key collisions when generating a random key, and races
+/// against other processes creating or removing the same segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of retries after the first attempt
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for every following one
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between retries
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 20,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(50),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+    /// Delay before retry number `retry`, counting from zero
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+    /// Run `f` until it succeeds, fails with an error that `is_transient` rejects, or the
+    /// retries run out
+    pub(crate) fn run<R>(
+        &self,
+        is_transient: impl Fn(&CortexError) -> bool,
+        mut f: impl FnMut() -> CortexResult<R>,
+    ) -> CortexResult<R> {
+        let mut retry = 0;
+        loop {
+            match f() {
+                Err(err) if retry < self.max_retries && is_transient(&err) => {
+                    crate::trace::trace!("Retrying after transient error: {}", err);
+                    std::thread::sleep(self.backoff(retry));
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Errors that are worth retrying in any context
+pub(crate) fn is_interrupted(err: &CortexError) -> bool {
+    matches!(err.kind(), ErrorKind::Interrupted | ErrorKind::WouldBlock)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::retry::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(5),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(1));
+        assert_eq!(policy.backoff(2), Duration::from_millis(4));
+        assert_eq!(policy.backoff(40), Duration::from_millis(5));
+    }
+}
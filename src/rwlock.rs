@@ -0,0 +1,395 @@
+use crate::{poll_lock, shared_state::SharedState, spin::Backoff, CortexResult, CortexSync};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+const WRITER: u32 = 1 << 31;
+const UPGRADABLE: u32 = 1 << 30;
+const READERS: u32 = UPGRADABLE - 1;
+
+/// Who gets the lock first when readers and writers are both waiting for it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RwLockPolicy {
+    /// New readers get in as long as no writer holds the lock, which gives the most read
+    /// throughput but lets a steady stream of readers starve writers
+    #[default]
+    ReaderPreferred,
+    /// New readers wait while a writer is waiting, so writers only wait for the readers already
+    /// holding the lock. A steady stream of writers starves readers instead.
+    WriterPreferred,
+    /// Readers and writers get the lock in the order they asked for it, with consecutive readers
+    /// sharing it. Nobody starves, at the cost of some read throughput.
+    Fair,
+}
+
+impl RwLockPolicy {
+    fn from_raw(raw: u32) -> Self {
+        match raw {
+            1 => Self::WriterPreferred,
+            2 => Self::Fair,
+            _ => Self::ReaderPreferred,
+        }
+    }
+    fn to_raw(self) -> u32 {
+        match self {
+            Self::ReaderPreferred => 0,
+            Self::WriterPreferred => 1,
+            Self::Fair => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RwLockSettings {
+    /// Fairness between readers and writers. Stored along with the lock by the creator, so the
+    /// settings passed when attaching are ignored.
+    pub policy: RwLockPolicy,
+}
+
+/// Zeroed memory is an unlocked, reader-preferred lock
+#[derive(Debug)]
+#[repr(C)]
+struct State {
+    word: AtomicU32,
+    policy: AtomicU32,
+    /// Writers, and upgradable readers waiting to upgrade, that block new readers
+    waiting_writers: AtomicU32,
+    /// Ticket of the next process in line, for the fair policy
+    next_ticket: AtomicU32,
+    /// Ticket of the process whose turn it is to acquire the lock, for the fair policy
+    now_serving: AtomicU32,
+}
+
+/// Reader-writer lock stored in a small named shared memory object.
+///
+/// Any number of readers can hold the lock at the same time, while writers get exclusive access.
+/// Also supports a single upgradable reader, which shares access with regular readers but can be
+/// atomically promoted to a writer. Waiting is done by spinning with backoff. Whether waiting
+/// readers or writers go first is decided by the `RwLockPolicy` in `RwLockSettings`.
+///
+/// With `RwLockPolicy::WriterPreferred` and `RwLockPolicy::Fair`, a thread taking the read lock
+/// again while already holding it can deadlock against a waiting writer.
+#[derive(Debug)]
+pub struct RwLock {
+    state: SharedState<State>,
+}
+
+/// State machine of the lock over a single word holding the `WRITER` and `UPGRADABLE` bits and
+/// the number of readers
+pub(crate) mod word {
+    use super::{READERS, UPGRADABLE, WRITER};
+    use crate::atomic::Atomic32;
+    use std::sync::atomic::Ordering;
+
+    pub(crate) fn try_read(state: &impl Atomic32) -> bool {
+        let current = state.load(Ordering::Relaxed);
+        current & WRITER == 0
+            && current & READERS != READERS
+            && state
+                .compare_exchange_weak(current, current + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
+    pub(crate) fn try_write(state: &impl Atomic32) -> bool {
+        state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+    pub(crate) fn try_upgradable(state: &impl Atomic32) -> bool {
+        let current = state.load(Ordering::Relaxed);
+        current & (WRITER | UPGRADABLE) == 0
+            && state
+                .compare_exchange_weak(
+                    current,
+                    current | UPGRADABLE,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+    }
+    pub(crate) fn try_upgrade(state: &impl Atomic32) -> bool {
+        state
+            .compare_exchange(UPGRADABLE, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+    pub(crate) fn release_read(state: &impl Atomic32) {
+        state.fetch_sub(1, Ordering::Release);
+    }
+    pub(crate) fn release_write(state: &impl Atomic32) {
+        state.fetch_and(!WRITER, Ordering::Release);
+    }
+    pub(crate) fn release_upgradable(state: &impl Atomic32) {
+        state.fetch_and(!UPGRADABLE, Ordering::Release);
+    }
+}
+
+fn spin(mut done: impl FnMut() -> bool) {
+    let mut backoff = Backoff::new();
+    while !done() {
+        backoff.snooze();
+    }
+}
+
+impl RwLock {
+    /// The policy the lock was created with
+    pub fn policy(&self) -> RwLockPolicy {
+        RwLockPolicy::from_raw(self.state.get().policy.load(Ordering::Relaxed))
+    }
+    /// Block until `try_acquire` succeeds on the lock word, in the order given by the policy.
+    /// `writer` is set for the write lock, readers and upgradable readers wait behind writers.
+    fn acquire(&self, writer: bool, try_acquire: impl Fn(&AtomicU32) -> bool) {
+        let state = self.state.get();
+        let no_waiting_writers = || state.waiting_writers.load(Ordering::Relaxed) == 0;
+        match self.policy() {
+            RwLockPolicy::ReaderPreferred => spin(|| try_acquire(&state.word)),
+            RwLockPolicy::WriterPreferred if writer => {
+                state.waiting_writers.fetch_add(1, Ordering::Relaxed);
+                spin(|| try_acquire(&state.word));
+                state.waiting_writers.fetch_sub(1, Ordering::Relaxed);
+            }
+            RwLockPolicy::WriterPreferred => {
+                spin(|| no_waiting_writers() && try_acquire(&state.word))
+            }
+            RwLockPolicy::Fair => {
+                let ticket = state.next_ticket.fetch_add(1, Ordering::Relaxed);
+                spin(|| state.now_serving.load(Ordering::Acquire) == ticket);
+                // Only an upgrading reader can be waiting here, which already holds the lock
+                spin(|| (writer || no_waiting_writers()) && try_acquire(&state.word));
+                // Let the next in line try, which only gets in once a writer is done
+                state.now_serving.fetch_add(1, Ordering::Release);
+            }
+        }
+    }
+    /// Attempt `try_acquire` without blocking, failing while others are ahead in line
+    fn try_acquire(&self, writer: bool, try_acquire: impl Fn(&AtomicU32) -> bool) -> bool {
+        let state = self.state.get();
+        let ahead = match self.policy() {
+            RwLockPolicy::ReaderPreferred => false,
+            RwLockPolicy::WriterPreferred => {
+                !writer && state.waiting_writers.load(Ordering::Relaxed) != 0
+            }
+            RwLockPolicy::Fair => {
+                state.next_ticket.load(Ordering::Relaxed)
+                    != state.now_serving.load(Ordering::Relaxed)
+            }
+        };
+        !ahead && try_acquire(&state.word)
+    }
+}
+
+impl CortexSync for RwLock {
+    type Settings = RwLockSettings;
+
+    fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::<State>::create("cortex_rwlock", cortex_key)?;
+        let policy = settings.copied().unwrap_or_default().policy;
+        state.get().policy.store(policy.to_raw(), Ordering::Relaxed);
+        Ok(Self { state })
+    }
+    fn attach(cortex_key: i32, _settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::open("cortex_rwlock", cortex_key)?;
+        Ok(Self { state })
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        let state = self.state.try_clone()?;
+        Ok(Self { state })
+    }
+    fn force_ownership(&mut self) {
+        self.state.force_ownership()
+    }
+    fn relinquish_ownership(&mut self) {
+        self.state.relinquish_ownership()
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.acquire(false, word::try_read);
+        Ok(())
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.acquire(true, word::try_write);
+        Ok(())
+    }
+    /// Releases whichever lock is held, prefer `release_read`, `release_write` and
+    /// `release_upgradable`. Only releases the upgradable read lock if no plain reader is left,
+    /// since it shares the word with them.
+    fn release(&self) -> CortexResult<()> {
+        let current = self.state.get().word.load(Ordering::Relaxed);
+        if current & WRITER != 0 {
+            self.release_write()
+        } else if current & READERS == 0 && current & UPGRADABLE != 0 {
+            self.release_upgradable()
+        } else {
+            self.release_read()
+        }
+    }
+    fn release_read(&self) -> CortexResult<()> {
+        word::release_read(&self.state.get().word);
+        Ok(())
+    }
+    fn release_write(&self) -> CortexResult<()> {
+        word::release_write(&self.state.get().word);
+        Ok(())
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_acquire(false, word::try_read))
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_acquire(true, word::try_write))
+    }
+    fn upgradable_read_lock(&self) -> CortexResult<()> {
+        self.acquire(false, word::try_upgradable);
+        Ok(())
+    }
+    fn try_upgradable_read_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_acquire(false, word::try_upgradable))
+    }
+    fn timed_upgradable_read_lock(&self, timeout: Duration) -> CortexResult<bool> {
+        poll_lock(timeout, || self.try_upgradable_read_lock())
+    }
+    fn upgrade(&self) -> CortexResult<()> {
+        // Wait for the remaining readers to drain, which are kept out unless readers are preferred
+        let state = self.state.get();
+        let policy = self.policy();
+        if policy != RwLockPolicy::ReaderPreferred {
+            state.waiting_writers.fetch_add(1, Ordering::Relaxed);
+        }
+        spin(|| word::try_upgrade(&state.word));
+        if policy != RwLockPolicy::ReaderPreferred {
+            state.waiting_writers.fetch_sub(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+    fn release_upgradable(&self) -> CortexResult<()> {
+        word::release_upgradable(&self.state.get().word);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rwlock::{RwLock, RwLockPolicy, RwLockSettings};
+    use crate::{Cortex, CortexSync};
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_readers() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, RwLock> = Cortex::new(Some(key), 42, false, None).unwrap();
+        let attached: Cortex<u64, RwLock> = Cortex::attach(key).unwrap();
+
+        cortex.lock.read_lock().unwrap();
+        assert_eq!(attached.try_read().unwrap(), Some(42));
+        assert!(!attached.try_write(1).unwrap());
+        cortex.lock.release_read().unwrap();
+        assert!(attached.try_write(1).unwrap());
+    }
+
+    #[test]
+    fn release_next_to_upgradable() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, RwLock> = Cortex::new(Some(key), 42, false, None).unwrap();
+        let attached: Cortex<u64, RwLock> = Cortex::attach(key).unwrap();
+
+        // Releases the plain reader, leaving the upgradable one
+        cortex.lock.upgradable_read_lock().unwrap();
+        cortex.lock.read_lock().unwrap();
+        cortex.lock.release().unwrap();
+        assert!(!attached.lock.try_upgradable_read_lock().unwrap());
+        assert_eq!(attached.try_read().unwrap(), Some(42));
+
+        // Releases the upgradable reader once it is the only one left
+        cortex.lock.release().unwrap();
+        assert!(attached.try_write(1).unwrap());
+    }
+
+    #[test]
+    fn upgradable_read_records_stats() {
+        let key = rand::random::<i32>().abs();
+        let mut cortex: Cortex<u64, RwLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        cortex.set_lock_stats(true);
+        drop(cortex.read_upgradable().unwrap());
+        *cortex.read_upgradable().unwrap().upgrade().unwrap() = 1;
+        assert_eq!(cortex.lock_stats().unwrap().acquisitions, 2);
+        assert_eq!(cortex.read().unwrap(), 1);
+    }
+
+    #[test]
+    fn upgrade_without_lost_updates() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, RwLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached: Cortex<u64, RwLock> = Cortex::attach(key).unwrap();
+                    for _ in 0..500 {
+                        let guard = attached.read_upgradable().unwrap();
+                        let seen = *guard;
+                        let mut guard = guard.upgrade().unwrap();
+                        *guard = seen + 1;
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(cortex.read().unwrap(), 4000);
+    }
+
+    /// Whether a new reader gets in while a writer waits for the current reader to leave
+    fn reader_overtakes(policy: RwLockPolicy) -> bool {
+        let key = rand::random::<i32>().abs();
+        let settings = RwLockSettings { policy };
+        let cortex: Cortex<u64, RwLock> =
+            Cortex::new(Some(key), 0, false, Some(&settings)).unwrap();
+        let attached: Cortex<u64, RwLock> = Cortex::attach(key).unwrap();
+        assert_eq!(attached.lock.policy(), policy);
+
+        cortex.lock.read_lock().unwrap();
+        let writer = thread::spawn(move || attached.write(1).unwrap());
+        thread::sleep(Duration::from_millis(50));
+        let overtakes = cortex.lock.try_read_lock().unwrap();
+        if overtakes {
+            assert!(!writer.is_finished());
+            cortex.lock.release_read().unwrap();
+        }
+        cortex.lock.release_read().unwrap();
+        writer.join().expect("Thread panicked");
+        assert_eq!(cortex.read().unwrap(), 1);
+        overtakes
+    }
+
+    #[test]
+    fn policies() {
+        assert!(reader_overtakes(RwLockPolicy::ReaderPreferred));
+        assert!(!reader_overtakes(RwLockPolicy::WriterPreferred));
+        assert!(!reader_overtakes(RwLockPolicy::Fair));
+    }
+
+    #[test]
+    fn fair_without_lost_updates() {
+        let key = rand::random::<i32>().abs();
+        let settings = RwLockSettings {
+            policy: RwLockPolicy::Fair,
+        };
+        let cortex: Cortex<u64, RwLock> =
+            Cortex::new(Some(key), 0, false, Some(&settings)).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread| {
+                thread::spawn(move || {
+                    let attached: Cortex<u64, RwLock> = Cortex::attach(key).unwrap();
+                    for _ in 0..500 {
+                        match thread % 2 {
+                            0 => attached.with_write(|value| *value += 1).unwrap(),
+                            _ => assert!(attached.read().unwrap() <= 2000),
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(cortex.read().unwrap(), 2000);
+    }
+}
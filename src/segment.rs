@@ -0,0 +1,61 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    detach, limits, mark_for_deletion, mem, CortexResult,
+};
+
+/// Mapping of a private segment holding an array of `T` into the current process, for types
+/// whose size is only known at runtime. Other processes find the segment through its id, which
+/// has to be published in a regular `Cortex`.
+#[derive(Debug)]
+pub(crate) struct Segment<T> {
+    pub(crate) id: i32,
+    pub(crate) ptr: *mut T,
+}
+
+impl<T> Segment<T> {
+    /// Allocate a private segment with room for `capacity` elements, only reachable through its id.
+    /// The segment is emulated on the heap if `in_process` is set, see `CortexSync::IN_PROCESS`.
+    pub(crate) fn create(capacity: usize, in_process: bool) -> CortexResult<Self> {
+        let Some(size) = capacity.checked_mul(std::mem::size_of::<T>()) else {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!("Capacity overflow for {} elements", capacity),
+            ));
+        };
+        // Segments can't be empty
+        let size = size.max(1);
+        limits::check_size(size, None)?;
+        let id =
+            unsafe { mem::shmget(in_process, libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o666) };
+        if id == -1 {
+            return Err(CortexError::new_clean("Error during shmget"));
+        }
+        crate::trace::trace!("Allocated {} bytes with id: {}", size, id);
+        Self::attach(id).inspect_err(|_| {
+            if let Err(err) = mark_for_deletion(id) {
+                crate::trace::error!("Error cleaning up after failed shmat: {}", err)
+            }
+        })
+    }
+    pub(crate) fn attach(id: i32) -> CortexResult<Self> {
+        let ptr = unsafe { mem::shmat(id, std::ptr::null_mut(), 0) };
+        if ptr as isize == -1 {
+            return Err(CortexError::new_clean(format!(
+                "Error during shmat for id: {}",
+                id
+            )));
+        }
+        Ok(Self {
+            id,
+            ptr: ptr as *mut T,
+        })
+    }
+}
+
+impl<T> Drop for Segment<T> {
+    fn drop(&mut self) {
+        if let Err(err) = detach(self.id, self.ptr as *const libc::c_void) {
+            crate::trace::error!("Error during detach in Drop: {}", err)
+        }
+    }
+}
@@ -1,9 +1,16 @@
-use crate::{crash::CortexError, CortexResult, CortexSync};
+use crate::{
+    cleanup::{self, OwnedResource, Registration},
+    crash::{CortexError, ErrorKind},
+    namespace, CortexResult, CortexSync,
+};
 use std::ffi::{CString, NulError};
 
-fn get_name(shmem_key: i32) -> Result<CString, NulError> {
-    let name = CString::new(format!("cortex_semaphore_{}", shmem_key))?;
-    Ok(name)
+fn get_name(settings: Option<&SemaphoreSettings>, shmem_key: i32) -> Result<CString, NulError> {
+    let name = match settings.and_then(|settings| settings.namespace.as_deref()) {
+        Some(namespace) => format!("{}_semaphore_{}", namespace, shmem_key),
+        None => namespace::qualify(format!("cortex_semaphore_{}", shmem_key)),
+    };
+    CString::new(name)
 }
 
 #[allow(dead_code)]
@@ -40,52 +47,147 @@ pub struct Semaphore {
     semaphore: *mut libc::sem_t,
     name: CString,
     is_owner: bool,
+    registration: Registration,
+    interruptible: bool,
 }
 
+// POSIX semaphores can be used from any thread
+unsafe impl Send for Semaphore {}
+unsafe impl Sync for Semaphore {}
+
 pub struct SemaphoreSettings {
     pub mode: SemaphorePermission,
+    /// Initial number of permits. Defaults to 1, meaning the semaphore acts as a mutex, which is
+    /// the only value accepted for the lock of a `Cortex`. Semaphores created through
+    /// `Semaphore::create` can start with any number of permits, shared by that many holders.
+    pub initial_value: u32,
+    /// Prefix of the semaphore name, replacing `cortex` and the namespace passed to
+    /// `set_namespace` for this semaphore only. Isolates the semaphores of unrelated applications
+    /// that happen to use the same keys, and must be the same when creating and attaching.
+    pub namespace: Option<String>,
+    /// Return an error with `ErrorKind::Interrupted` when a signal arrives while waiting, instead
+    /// of transparently resuming the wait. Defaults to `false`.
+    pub interruptible: bool,
+}
+
+impl Default for SemaphoreSettings {
+    fn default() -> Self {
+        Self {
+            // Use most restrictive mode as default
+            mode: SemaphorePermission::OwnerOnly,
+            initial_value: 1,
+            namespace: None,
+            interruptible: false,
+        }
+    }
+}
+
+/// Check that a semaphore used as the lock of a `Cortex` acts as a mutex. Reads and writes both
+/// take a single permit, so more permits would let several writers in at once, and none would
+/// block the creation of the segment forever.
+fn check_mutex(settings: &SemaphoreSettings) -> CortexResult<()> {
+    if settings.initial_value != 1 {
+        return Err(CortexError::new_logic(
+            ErrorKind::InvalidInput,
+            format!(
+                "A semaphore used as the lock of a cortex needs an initial value of 1, got {}",
+                settings.initial_value
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Maximum value a semaphore can hold on this system
+fn max_value() -> u32 {
+    match unsafe { libc::sysconf(libc::_SC_SEM_VALUE_MAX) } {
+        -1 => i16::MAX as u32, // Lowest maximum allowed by POSIX
+        max => max.min(u32::MAX as libc::c_long) as u32,
+    }
 }
 
 impl Drop for Semaphore {
     fn drop(&mut self) {
-        tracing::trace!("Dropping semaphore: {:?}", self.name);
+        crate::trace::trace!("Dropping semaphore: {:?}", self.name);
 
         // Mark semaphore as done by current process, decreasing its reference count but does not
         // remove it from the system
         if unsafe { libc::sem_close(self.semaphore) } == -1 {
-            tracing::error!("Error during sem_close");
+            crate::trace::error!("Error during sem_close");
         };
-        if !self.is_owner {
+        if !self.is_owner || !cleanup::released(self.registration) {
             return;
         }
         // Delete the semaphore from the system
         if unsafe { libc::sem_unlink(self.name.as_ptr()) } == -1 {
-            tracing::error!("Error during sem_unlink");
+            crate::trace::error!("Error during sem_unlink");
         }
     }
 }
 
-impl CortexSync for Semaphore {
-    type Settings = SemaphoreSettings;
-
-    fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
-        let permission = if let Some(settings) = settings {
-            settings.mode.as_mode()
-        } else {
-            // Use most restrictive mode as default
-            SemaphorePermission::OwnerOnly.as_mode()
-        };
-        let name = match get_name(cortex_key) {
-            Ok(name) => name,
-            Err(_) => return Err(CortexError::new_clean("CString NulError")),
-        };
+impl Semaphore {
+    /// Current number of available permits. Not supported on macOS.
+    pub fn get_value(&self) -> CortexResult<i32> {
+        let mut value = 0;
+        if unsafe { libc::sem_getvalue(self.semaphore, &mut value) } == -1 {
+            return Err(CortexError::new_clean("Error during sem_getvalue"));
+        }
+        Ok(value)
+    }
+    /// Create a new named semaphore on `key`, for use on its own rather than as the lock of a
+    /// `Cortex`. Fails if one already exists.
+    pub fn create(key: i32, settings: &SemaphoreSettings) -> CortexResult<Self> {
+        Self::open_new(key, settings)
+    }
+    /// Attempt to open an already existing named semaphore
+    pub fn open(key: i32, settings: Option<&SemaphoreSettings>) -> CortexResult<Self> {
+        Self::open_existing(key, settings)
+    }
+    /// Block until a permit is available, the permit is returned when the guard is dropped
+    pub fn acquire(&self) -> CortexResult<SemaphorePermit<'_>> {
+        self.wait()?;
+        Ok(SemaphorePermit { semaphore: self })
+    }
+    /// Take a permit if one is available without blocking
+    pub fn try_acquire(&self) -> CortexResult<Option<SemaphorePermit<'_>>> {
+        Ok(self
+            .try_wait()?
+            .then(|| SemaphorePermit { semaphore: self }))
+    }
+    /// Block until a permit is available or the timeout expires, returns `None` on timeout
+    pub fn acquire_timeout(
+        &self,
+        timeout: std::time::Duration,
+    ) -> CortexResult<Option<SemaphorePermit<'_>>> {
+        Ok(self
+            .timed_write_lock(timeout)?
+            .then(|| SemaphorePermit { semaphore: self }))
+    }
+    /// Add a permit to the semaphore, e.g. one that was previously taken with
+    /// `SemaphorePermit::forget` or by another process
+    pub fn release(&self) -> CortexResult<()> {
+        self.post()
+    }
+    fn open_new(cortex_key: i32, settings: &SemaphoreSettings) -> CortexResult<Self> {
+        let permission = settings.mode.as_mode();
+        if settings.initial_value > max_value() {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Initial semaphore value {} exceeds the maximum of {}",
+                    settings.initial_value,
+                    max_value()
+                ),
+            ));
+        }
+        let name = get_name(Some(settings), cortex_key)?;
         let name_ptr = name.as_ptr();
         let semaphore = unsafe {
             libc::sem_open(
                 name_ptr,
                 libc::O_EXCL | libc::O_CREAT,
                 permission as libc::c_uint,
-                1,
+                settings.initial_value as libc::c_uint,
             )
         };
         if semaphore == libc::SEM_FAILED {
@@ -93,15 +195,14 @@ impl CortexSync for Semaphore {
         }
         Ok(Self {
             semaphore,
+            registration: cleanup::owned(|| OwnedResource::Semaphore(name.clone())),
             name,
             is_owner: true,
+            interruptible: settings.interruptible,
         })
     }
-    fn attach(cortex_key: i32) -> CortexResult<Self> {
-        let name = match get_name(cortex_key) {
-            Ok(name) => name,
-            Err(_) => return Err(CortexError::new_clean("CString NulError")),
-        };
+    fn open_existing(cortex_key: i32, settings: Option<&SemaphoreSettings>) -> CortexResult<Self> {
+        let name = get_name(settings, cortex_key)?;
         let name_ptr = name.as_ptr();
         let semaphore = unsafe { libc::sem_open(name_ptr, 0, 0 as libc::c_uint, 0) };
         if semaphore == libc::SEM_FAILED {
@@ -111,40 +212,161 @@ impl CortexSync for Semaphore {
             semaphore,
             name,
             is_owner: false,
+            registration: Registration::default(),
+            interruptible: settings.is_some_and(|settings| settings.interruptible),
         })
     }
-    fn read_lock(&self) -> CortexResult<()> {
-        if unsafe { libc::sem_wait(self.semaphore) } == -1 {
-            Err(CortexError::new_clean("Error during sem_wait"))
-        } else {
-            Ok(())
-        }
+    /// Whether a failed call should be retried because it was interrupted by a signal
+    fn should_retry(&self) -> bool {
+        !self.interruptible && errno::errno().0 == libc::EINTR
     }
-    fn write_lock(&self) -> CortexResult<()> {
-        if unsafe { libc::sem_wait(self.semaphore) } == -1 {
-            Err(CortexError::new_clean("Error during sem_wait"))
-        } else {
-            Ok(())
+    fn wait(&self) -> CortexResult<()> {
+        while unsafe { libc::sem_wait(self.semaphore) } == -1 {
+            if !self.should_retry() {
+                return Err(CortexError::new_clean("Error during sem_wait"));
+            }
         }
+        Ok(())
     }
-    fn release(&self) -> CortexResult<()> {
+    fn post(&self) -> CortexResult<()> {
         if unsafe { libc::sem_post(self.semaphore) } == -1 {
             Err(CortexError::new_clean("Error during sem_release"))
         } else {
             Ok(())
         }
     }
+    fn try_wait(&self) -> CortexResult<bool> {
+        while unsafe { libc::sem_trywait(self.semaphore) } == -1 {
+            if errno::errno().0 == libc::EAGAIN {
+                return Ok(false);
+            }
+            if !self.should_retry() {
+                return Err(CortexError::new_clean("Error during sem_trywait"));
+            }
+        }
+        Ok(true)
+    }
+    // macOS doesn't implement `sem_timedwait`, so it falls back to polling `sem_trywait`
+    #[cfg(not(target_os = "macos"))]
+    fn timed_wait(&self, timeout: std::time::Duration) -> CortexResult<bool> {
+        let mut deadline = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe { libc::clock_gettime(libc::CLOCK_REALTIME, &mut deadline) };
+        let nanos = deadline.tv_nsec as u64 + timeout.subsec_nanos() as u64;
+        deadline.tv_sec +=
+            timeout.as_secs() as libc::time_t + (nanos / 1_000_000_000) as libc::time_t;
+        deadline.tv_nsec = (nanos % 1_000_000_000) as _;
+
+        // The deadline is absolute, so retrying after a signal doesn't extend the timeout
+        while unsafe { libc::sem_timedwait(self.semaphore, &deadline) } == -1 {
+            if errno::errno().0 == libc::ETIMEDOUT {
+                return Ok(false);
+            }
+            if !self.should_retry() {
+                return Err(CortexError::new_clean("Error during sem_timedwait"));
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Permit taken from a `Semaphore`, which is given back when dropped
+#[derive(Debug)]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl SemaphorePermit<'_> {
+    /// Keep the permit taken without giving it back, e.g. to hand it over to another process
+    /// which will call `Semaphore::release` later
+    pub fn forget(self) {
+        std::mem::forget(self)
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        if let Err(err) = self.semaphore.post() {
+            crate::trace::error!("Error releasing semaphore permit in Drop: {}", err);
+        }
+    }
+}
+
+impl CortexSync for Semaphore {
+    type Settings = SemaphoreSettings;
+
+    fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let default_settings = SemaphoreSettings::default();
+        let settings = settings.unwrap_or(&default_settings);
+        check_mutex(settings)?;
+        Self::open_new(cortex_key, settings)
+    }
+    fn attach(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        if let Some(settings) = settings {
+            check_mutex(settings)?;
+        }
+        Self::open_existing(cortex_key, settings)
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        let semaphore = unsafe { libc::sem_open(self.name.as_ptr(), 0, 0 as libc::c_uint, 0) };
+        if semaphore == libc::SEM_FAILED {
+            return Err(CortexError::new_clean("Error during sem_open"));
+        }
+        Ok(Self {
+            semaphore,
+            name: self.name.clone(),
+            is_owner: false,
+            registration: Registration::default(),
+            interruptible: self.interruptible,
+        })
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.wait()
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.wait()
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        self.try_wait()
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        self.try_wait()
+    }
+    #[cfg(not(target_os = "macos"))]
+    fn timed_read_lock(&self, timeout: std::time::Duration) -> CortexResult<bool> {
+        self.timed_wait(timeout)
+    }
+    #[cfg(not(target_os = "macos"))]
+    fn timed_write_lock(&self, timeout: std::time::Duration) -> CortexResult<bool> {
+        self.timed_wait(timeout)
+    }
+    fn release(&self) -> CortexResult<()> {
+        self.post()
+    }
     fn force_ownership(&mut self) {
+        if !self.is_owner {
+            self.registration = cleanup::owned(|| OwnedResource::Semaphore(self.name.clone()));
+        }
         self.is_owner = true
     }
+    fn relinquish_ownership(&mut self) {
+        if self.is_owner {
+            cleanup::released(self.registration);
+            self.registration = Registration::default();
+        }
+        self.is_owner = false
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::semaphore::Semaphore;
-    use crate::Cortex;
+    use crate::semaphore::{Semaphore, SemaphoreSettings};
+    use crate::{Cortex, CortexBuilder, CortexError, CortexSync, ErrorKind};
     use std::sync::{Arc, Barrier};
     use std::thread;
+    use std::time::Duration;
 
     #[test]
     fn create_shared_mem() {
@@ -213,4 +435,284 @@ mod tests {
 
         thread::spawn(move || cortex.read());
     }
+
+    #[test]
+    fn heartbeat() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, Semaphore> = Cortex::new(Some(key), 42.0, false, None).unwrap();
+        let attached: Cortex<f64, Semaphore> = Cortex::attach(key).unwrap();
+        assert!(attached.last_beat().is_none());
+        assert!(!attached.owner_alive(Duration::from_secs(1)));
+
+        cortex.beat();
+        assert!(attached.last_beat().is_some());
+        assert!(attached.owner_alive(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn attach_or_create() {
+        let key = rand::random::<i32>().abs();
+        let cortex = CortexBuilder::new(42.0)
+            .key(key)
+            .attach_or_create()
+            .with_default_lock::<Semaphore>()
+            .unwrap();
+        let attached = CortexBuilder::new(0.0)
+            .key(key)
+            .attach_or_create()
+            .with_default_lock::<Semaphore>()
+            .unwrap();
+        assert_eq!(attached.read().unwrap(), 42.0);
+        drop(cortex);
+    }
+
+    #[test]
+    fn reclaim_stale_segment() {
+        let key = rand::random::<i32>().abs();
+        // Leave a segment behind without any attachers or heartbeat
+        let size = crate::header::segment_size::<f64>();
+        let id = unsafe { libc::shmget(key, size, libc::IPC_CREAT | 0o666) };
+        assert_ne!(id, -1);
+
+        let cortex = CortexBuilder::new(42.0)
+            .key(key)
+            .attach_or_create()
+            .stale_after(Duration::from_millis(1))
+            .with_default_lock::<Semaphore>()
+            .unwrap();
+        assert_eq!(cortex.read().unwrap(), 42.0);
+    }
+
+    #[test]
+    fn transfer_ownership() {
+        let key = rand::random::<i32>().abs();
+        let mut bootstrap: Cortex<_, Semaphore> =
+            Cortex::new(Some(key), 42.0, false, None).unwrap();
+        let mut daemon: Cortex<f64, Semaphore> = Cortex::attach(key).unwrap();
+        assert!(!daemon.adopt());
+
+        bootstrap.transfer_ownership().unwrap();
+        assert_eq!(daemon.owner_pid(), None);
+        assert!(daemon.adopt());
+        drop(bootstrap);
+
+        // Segment and lock survive the bootstrap process going away
+        let attached: Cortex<f64, Semaphore> = Cortex::attach(key).unwrap();
+        assert_eq!(attached.read().unwrap(), 42.0);
+        assert!(daemon.is_owner);
+    }
+
+    #[test]
+    fn ref_counted() {
+        let key = rand::random::<i32>().abs();
+        let creator = CortexBuilder::new(42.0)
+            .key(key)
+            .ref_counted()
+            .with_default_lock::<Semaphore>()
+            .unwrap();
+        let attached: Cortex<f64, Semaphore> = Cortex::attach(key).unwrap();
+
+        // Creator going away first no longer removes the segment
+        drop(creator);
+        let late: Cortex<f64, Semaphore> = Cortex::attach(key).unwrap();
+        assert_eq!(late.read().unwrap(), 42.0);
+
+        drop(attached);
+        drop(late);
+        assert!(Cortex::<f64, Semaphore>::attach(key).is_err());
+    }
+
+    #[test]
+    fn cloned_handles() {
+        let key = rand::random::<i32>().abs();
+        let settings = SemaphoreSettings {
+            namespace: Some("cloned".into()),
+            ..Default::default()
+        };
+        let cortex = CortexBuilder::new(0u64)
+            .key(key)
+            .with_lock::<Semaphore>(&settings)
+            .unwrap();
+
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let clone = cortex.try_clone().unwrap();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        clone.with_write(|value| *value += 1).unwrap();
+                    }
+                    clone.is_owner()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(!handle.join().expect("Thread panicked"));
+        }
+        assert_eq!(cortex.read().unwrap(), 400);
+        assert!(cortex.is_owner());
+    }
+
+    #[test]
+    fn poisoned_by_interrupted_write() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, Semaphore> = Cortex::new(Some(key), 42.0, false, None).unwrap();
+
+        // Simulate a writer dying halfway through
+        unsafe { &*cortex.header }
+            .generation
+            .fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        assert!(matches!(cortex.read(), Err(CortexError::Poisoned)));
+
+        assert!(cortex.clear_poison().unwrap());
+        assert_eq!(cortex.read().unwrap(), 42.0);
+        assert!(!cortex.clear_poison().unwrap());
+    }
+
+    #[test]
+    fn try_and_timed_locks() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<_, Semaphore> = Cortex::new(Some(key), 42.0, false, None).unwrap();
+        assert_eq!(cortex.try_read().unwrap(), Some(42.0));
+        assert!(cortex.try_write(1.0).unwrap());
+
+        cortex.lock.write_lock().unwrap();
+        assert_eq!(cortex.try_read().unwrap(), None);
+        assert!(!cortex.try_write(2.0).unwrap());
+        assert_eq!(
+            cortex.read_timeout(Duration::from_millis(10)).unwrap(),
+            None
+        );
+        cortex.lock.release_write().unwrap();
+
+        assert!(cortex
+            .write_timeout(3.0, Duration::from_millis(10))
+            .unwrap());
+        assert_eq!(
+            cortex.read_timeout(Duration::from_millis(10)).unwrap(),
+            Some(3.0)
+        );
+    }
+
+    #[test]
+    fn counting_semaphore() {
+        let key = rand::random::<i32>().abs();
+        // Several permits would let several writers in at once, and none would deadlock creation
+        for initial_value in [0, 3] {
+            let settings = SemaphoreSettings {
+                initial_value,
+                ..Default::default()
+            };
+            let err =
+                Cortex::<f64, Semaphore>::new(Some(key), 42.0, false, Some(&settings)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+            assert!(Cortex::<f64, Semaphore>::attach_with_lock(key, &settings).is_err());
+        }
+
+        // Counting is left to standalone semaphores
+        let settings = SemaphoreSettings {
+            initial_value: 3,
+            ..Default::default()
+        };
+        let semaphore = Semaphore::create(key, &settings).unwrap();
+        let _first = semaphore.acquire().unwrap();
+        let _second = semaphore.acquire().unwrap();
+        assert_eq!(semaphore.get_value().unwrap(), 1);
+    }
+
+    #[test]
+    fn namespaced_semaphore() {
+        let key = rand::random::<i32>().abs();
+        let settings = SemaphoreSettings {
+            namespace: Some("neocortex_test".to_string()),
+            ..Default::default()
+        };
+        let cortex: Cortex<_, Semaphore> =
+            Cortex::new(Some(key), 42.0, false, Some(&settings)).unwrap();
+        assert_eq!(
+            cortex.lock_backend().name.to_str().unwrap(),
+            format!("neocortex_test_semaphore_{}", key)
+        );
+
+        // Attaching without the namespace looks for a different semaphore
+        assert!(Cortex::<f64, Semaphore>::attach(key).is_err());
+        let attached: Cortex<f64, Semaphore> = Cortex::attach_with_lock(key, &settings).unwrap();
+        assert_eq!(attached.read().unwrap(), 42.0);
+    }
+
+    #[test]
+    fn standalone_permits() {
+        let key = rand::random::<i32>().abs();
+        let settings = SemaphoreSettings {
+            initial_value: 2,
+            ..Default::default()
+        };
+        let semaphore = Semaphore::create(key, &settings).unwrap();
+        let opened = Semaphore::open(key, None).unwrap();
+
+        let first = semaphore.acquire().unwrap();
+        let second = opened.try_acquire().unwrap();
+        assert!(second.is_some());
+        assert!(opened.try_acquire().unwrap().is_none());
+        assert!(opened
+            .acquire_timeout(Duration::from_millis(10))
+            .unwrap()
+            .is_none());
+
+        drop(first);
+        let third = opened.try_acquire().unwrap().unwrap();
+        third.forget();
+        assert!(semaphore.try_acquire().unwrap().is_none());
+        semaphore.release().unwrap();
+        assert!(semaphore.try_acquire().unwrap().is_some());
+    }
+
+    extern "C" fn ignore_signal(_: libc::c_int) {}
+
+    /// Send a signal with a no-op handler to `thread` while it is blocked, interrupting the wait
+    fn interrupt(thread: &thread::JoinHandle<impl Send>) {
+        use std::os::unix::thread::JoinHandleExt;
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore_signal as *const () as usize;
+            libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+            thread::sleep(Duration::from_millis(20));
+            libc::pthread_kill(thread.as_pthread_t(), libc::SIGUSR1);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn retry_on_signal() {
+        let key = rand::random::<i32>().abs();
+        let semaphore = Semaphore::create(key, &SemaphoreSettings::default()).unwrap();
+        semaphore.acquire().unwrap().forget();
+
+        let waiter = thread::spawn(move || {
+            let opened = Semaphore::open(key, None).unwrap();
+            opened.acquire().map(|permit| permit.forget())
+        });
+        interrupt(&waiter);
+        assert!(!waiter.is_finished());
+        semaphore.release().unwrap();
+        waiter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn interruptible_wait() {
+        let key = rand::random::<i32>().abs();
+        let settings = SemaphoreSettings {
+            initial_value: 0,
+            interruptible: true,
+            ..Default::default()
+        };
+        let _semaphore = Semaphore::create(key, &settings).unwrap();
+
+        let waiter = thread::spawn(move || {
+            let opened = Semaphore::open(key, Some(&settings)).unwrap();
+            opened.acquire().map(|permit| permit.forget())
+        });
+        interrupt(&waiter);
+        let err = waiter.join().unwrap().unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Interrupted);
+    }
 }
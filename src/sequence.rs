@@ -0,0 +1,220 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    Cortex, CortexResult, CortexSync,
+};
+use std::io::Write;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Hook for keeping a `CortexSequence` monotonic across restarts of the whole system, when the
+/// shared memory itself is lost.
+///
+/// Rather than storing every id, the sequence reserves ids in blocks and stores the end of the
+/// reservation before handing out any id from it. After a restart the sequence continues from the
+/// stored value, which skips at most one block of unused ids but never repeats one.
+pub trait SequencePersistence {
+    /// Load the stored end of the reservation, `None` if nothing was stored yet
+    fn load(&self) -> CortexResult<Option<u64>>;
+    /// Durably store the end of the reservation
+    fn store(&self, reserved: u64) -> CortexResult<()>;
+}
+
+/// Stores the reservation of a `CortexSequence` in a file, replacing it atomically on every store
+#[derive(Debug, Clone)]
+pub struct FilePersistence {
+    pub path: PathBuf,
+}
+
+impl FilePersistence {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl SequencePersistence for FilePersistence {
+    fn load(&self) -> CortexResult<Option<u64>> {
+        let contents = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        match contents.trim().parse() {
+            Ok(reserved) => Ok(Some(reserved)),
+            Err(_) => Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!("Invalid sequence reservation in {}", self.path.display()),
+            )),
+        }
+    }
+    fn store(&self, reserved: u64) -> CortexResult<()> {
+        // Write to a temporary file first, so that a crash never leaves a partial value behind
+        let temporary = self.path.with_extension("tmp");
+        let mut file = std::fs::File::create(&temporary)?;
+        write!(file, "{}", reserved)?;
+        file.sync_all()?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+struct SequenceState {
+    next: AtomicU64,
+    /// End of the persisted reservation, ids at or past it must not be handed out before it is
+    /// extended. Only used by instances with persistence.
+    reserved: AtomicU64,
+}
+
+/// Process-shared generator of unique, monotonically increasing ids.
+///
+/// Handing out ids is a single atomic increment, `L` is only used to coordinate extending the
+/// reservation when persistence is configured, see `SequencePersistence`.
+pub struct CortexSequence<L: CortexSync> {
+    cortex: Cortex<SequenceState, L>,
+    persistence: Option<(Box<dyn SequencePersistence + Send + Sync>, u64)>,
+}
+
+impl<L: CortexSync> CortexSequence<L> {
+    /// Attach to the sequence on `key`, creating it starting from zero if it doesn't exist yet
+    pub fn new(key: i32) -> CortexResult<Self> {
+        Self::open(key, 0, None)
+    }
+    /// Attach to the sequence on `key`, creating it if it doesn't exist yet, and persist
+    /// reservations of `block` ids through `persistence`. A newly created sequence continues from
+    /// the reservation stored by `persistence`. Every process should configure the same
+    /// persistence, since only instances with persistence wait for reservations to be stored.
+    pub fn with_persistence(
+        key: i32,
+        persistence: impl SequencePersistence + Send + Sync + 'static,
+        block: u64,
+    ) -> CortexResult<Self> {
+        let start = persistence.load()?.unwrap_or(0);
+        Self::open(key, start, Some((Box::new(persistence), block.max(1))))
+    }
+    fn open(
+        key: i32,
+        start: u64,
+        persistence: Option<(Box<dyn SequencePersistence + Send + Sync>, u64)>,
+    ) -> CortexResult<Self> {
+        let cortex = Cortex::attach_or_create(key, || SequenceState {
+            next: AtomicU64::new(start),
+            reserved: AtomicU64::new(start),
+        })?;
+        Ok(Self {
+            cortex,
+            persistence,
+        })
+    }
+    fn state(&self) -> &SequenceState {
+        unsafe { &*self.cortex.ptr }
+    }
+    /// Mint the next id
+    pub fn next(&self) -> CortexResult<u64> {
+        self.next_batch(1).map(|ids| ids.start)
+    }
+    /// Mint `n` consecutive ids at once, e.g. to hand them out locally without touching shared
+    /// memory for every id
+    pub fn next_batch(&self, n: u64) -> CortexResult<Range<u64>> {
+        // Never move `next` past the end, so that an exhausted sequence stays exhausted instead of
+        // wrapping around to ids that were already handed out
+        let start = self
+            .state()
+            .next
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next| {
+                next.checked_add(n)
+            })
+            .map_err(|_| CortexError::new_logic(ErrorKind::NoSpace, "Sequence exhausted"))?;
+        let end = start + n;
+        if let Some((persistence, block)) = &self.persistence {
+            if end > self.state().reserved.load(Ordering::Acquire) {
+                self.reserve(persistence.as_ref(), end, *block)?;
+            }
+        }
+        Ok(start..end)
+    }
+    /// Extend the reservation to cover `end`, unless another process got there first
+    fn reserve(
+        &self,
+        persistence: &(dyn SequencePersistence + Send + Sync),
+        end: u64,
+        block: u64,
+    ) -> CortexResult<()> {
+        self.cortex.with_write(|state| {
+            let reserved = state.reserved.load(Ordering::Acquire);
+            if end <= reserved {
+                return Ok(());
+            }
+            let extended = end.saturating_add(block);
+            persistence.store(extended)?;
+            crate::trace::trace!("Reserved sequence ids up to {}", extended);
+            state.reserved.store(extended, Ordering::Release);
+            Ok(())
+        })?
+    }
+    /// The id that `next` would mint, without minting it
+    pub fn current(&self) -> u64 {
+        self.state().next.load(Ordering::Relaxed)
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sequence::{CortexSequence, FilePersistence, SequencePersistence};
+    use crate::{ErrorKind, SpinLock};
+    use std::thread;
+
+    #[test]
+    fn unique_ids() {
+        let key = rand::random::<i32>().abs();
+        let sequence: CortexSequence<SpinLock> = CortexSequence::new(key).unwrap();
+        assert_eq!(sequence.next().unwrap(), 0);
+        assert_eq!(sequence.next_batch(10).unwrap(), 1..11);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached: CortexSequence<SpinLock> = CortexSequence::new(key).unwrap();
+                    (0..100)
+                        .map(|_| attached.next().unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        let mut ids: Vec<_> = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Thread panicked"))
+            .collect();
+        ids.sort_unstable();
+        assert_eq!(ids, (11..811).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn exhausted() {
+        let key = rand::random::<i32>().abs();
+        let sequence: CortexSequence<SpinLock> = CortexSequence::new(key).unwrap();
+        assert_eq!(sequence.next_batch(u64::MAX).unwrap(), 0..u64::MAX);
+        assert_eq!(sequence.next().unwrap_err().kind(), ErrorKind::NoSpace);
+        assert_eq!(sequence.next().unwrap_err().kind(), ErrorKind::NoSpace);
+        assert_eq!(sequence.current(), u64::MAX);
+    }
+
+    #[test]
+    fn continues_after_restart() {
+        let key = rand::random::<i32>().abs();
+        let path = std::env::temp_dir().join(format!("cortex_sequence_{}", key));
+        {
+            let sequence: CortexSequence<SpinLock> =
+                CortexSequence::with_persistence(key, FilePersistence::new(&path), 100).unwrap();
+            assert_eq!(sequence.next_batch(5).unwrap(), 0..5);
+            assert_eq!(FilePersistence::new(&path).load().unwrap(), Some(105));
+        }
+        // The segment is gone, so the sequence continues from the stored reservation
+        let sequence: CortexSequence<SpinLock> =
+            CortexSequence::with_persistence(key, FilePersistence::new(&path), 100).unwrap();
+        assert_eq!(sequence.next().unwrap(), 105);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use crate::{
+    cleanup::{self, OwnedResource, Registration},
+    crash::{CortexError, ErrorKind},
+    namespace, CortexResult,
+};
+use std::ffi::CString;
+use std::marker::PhantomData;
+
+/// Named POSIX shared memory object holding the state of a lock implementation.
+///
+/// The object is zero-initialized on creation, so `S` must be valid when all of its bytes are
+/// zero, which holds for the atomic integers used by the lock backends.
+#[derive(Debug)]
+pub(crate) struct SharedState<S> {
+    ptr: *mut S,
+    name: CString,
+    is_owner: bool,
+    registration: Registration,
+    state: PhantomData<S>,
+}
+
+unsafe impl<S: Sync> Send for SharedState<S> {}
+unsafe impl<S: Sync> Sync for SharedState<S> {}
+
+fn get_name(prefix: &str, key: i32) -> CortexResult<CString> {
+    let name = namespace::qualify(format!("{}_{}", prefix, key));
+    Ok(CString::new(format!("/{}", name))?)
+}
+
+fn map<S>(fd: libc::c_int) -> CortexResult<*mut S> {
+    let ptr = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            std::mem::size_of::<S>(),
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_SHARED,
+            fd,
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return Err(CortexError::new_clean("Error during mmap"));
+    }
+    Ok(ptr as *mut S)
+}
+
+impl<S> SharedState<S> {
+    /// Create a new zero-initialized state object, failing if one already exists
+    pub(crate) fn create(prefix: &str, key: i32) -> CortexResult<Self> {
+        let name = get_name(prefix, key)?;
+        let fd = unsafe {
+            libc::shm_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                0o600 as libc::c_uint,
+            )
+        };
+        if fd == -1 {
+            return Err(CortexError::new_clean(format!(
+                "Error during shm_open for {:?}",
+                name
+            )));
+        }
+        let size = std::mem::size_of::<S>() as libc::off_t;
+        let result = if unsafe { libc::ftruncate(fd, size) } == -1 {
+            Err(CortexError::new_clean("Error during ftruncate"))
+        } else {
+            map(fd)
+        };
+        unsafe { libc::close(fd) };
+        match result {
+            Ok(ptr) => Ok(Self {
+                ptr,
+                registration: cleanup::owned(|| OwnedResource::SharedMemory(name.clone())),
+                name,
+                is_owner: true,
+                state: PhantomData,
+            }),
+            Err(err) => {
+                if unsafe { libc::shm_unlink(name.as_ptr()) } == -1 {
+                    return Err(CortexError::new_dirty(format!(
+                        "Error during shm_unlink for {:?}",
+                        name
+                    )));
+                }
+                Err(err)
+            }
+        }
+    }
+    /// Attempt to open an already existing state object
+    pub(crate) fn open(prefix: &str, key: i32) -> CortexResult<Self> {
+        Self::open_name(get_name(prefix, key)?)
+    }
+    /// Open another handle to the same state object, which doesn't clean it up when dropped
+    pub(crate) fn try_clone(&self) -> CortexResult<Self> {
+        Self::open_name(self.name.clone())
+    }
+    fn open_name(name: CString) -> CortexResult<Self> {
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0 as libc::c_uint) };
+        if fd == -1 {
+            return Err(CortexError::new_clean(format!(
+                "Error during shm_open for {:?}",
+                name
+            )));
+        }
+        // The creator might not have gotten around to setting the size yet
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        let result = if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+            Err(CortexError::new_clean("Error during fstat"))
+        } else if (stat.st_size as usize) < std::mem::size_of::<S>() {
+            Err(CortexError::new_logic(
+                ErrorKind::NotFound,
+                format!("Lock state {:?} is not initialized", name),
+            ))
+        } else {
+            map(fd)
+        };
+        unsafe { libc::close(fd) };
+        Ok(Self {
+            ptr: result?,
+            name,
+            is_owner: false,
+            registration: Registration::default(),
+            state: PhantomData,
+        })
+    }
+    pub(crate) fn get(&self) -> &S {
+        unsafe { &*self.ptr }
+    }
+    pub(crate) fn force_ownership(&mut self) {
+        if !self.is_owner {
+            self.registration = cleanup::owned(|| OwnedResource::SharedMemory(self.name.clone()));
+        }
+        self.is_owner = true
+    }
+    pub(crate) fn relinquish_ownership(&mut self) {
+        if self.is_owner {
+            cleanup::released(self.registration);
+            self.registration = Registration::default();
+        }
+        self.is_owner = false
+    }
+}
+
+impl<S> Drop for SharedState<S> {
+    fn drop(&mut self) {
+        crate::trace::trace!("Dropping lock state: {:?}", self.name);
+
+        if unsafe { libc::munmap(self.ptr as *mut libc::c_void, std::mem::size_of::<S>()) } == -1 {
+            crate::trace::error!("Error during munmap");
+        }
+        if !self.is_owner || !cleanup::released(self.registration) {
+            return;
+        }
+        if unsafe { libc::shm_unlink(self.name.as_ptr()) } == -1 {
+            crate::trace::error!("Error during shm_unlink");
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    spin::Backoff,
+    thread_id, Cortex, CortexResult, CortexSync,
+};
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+
+/// Upper bound for `SlabSettings::stripes`
+const MAX_STRIPES: usize = 64;
+
+/// Settings of `CortexSlab::with_settings`
+#[derive(Debug, Clone, Copy)]
+pub struct SlabSettings {
+    /// Number of groups of slots with a lock and free list each, between 1 and 64 and at most the
+    /// number of slots. With a single stripe the whole slab is protected by `L`, otherwise every
+    /// stripe has its own spin lock in the segment, so that writers inserting and removing in
+    /// different stripes don't contend.
+    pub stripes: usize,
+}
+
+impl Default for SlabSettings {
+    fn default() -> Self {
+        Self { stripes: 1 }
+    }
+}
+
+/// Handle to a value stored in a `CortexSlab`, valid in every process attached to it.
+///
+/// Handles carry the generation of their slot, so a handle to a removed value never resolves to a
+/// value that was later inserted into the same slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SlotId {
+    index: u32,
+    generation: u32,
+}
+
+impl SlotId {
+    /// Pack the handle into a single integer, e.g. to pass it to another process
+    pub fn to_raw(self) -> u64 {
+        (self.generation as u64) << 32 | self.index as u64
+    }
+    pub fn from_raw(raw: u64) -> Self {
+        Self {
+            index: raw as u32,
+            generation: (raw >> 32) as u32,
+        }
+    }
+    pub fn index(&self) -> usize {
+        self.index as usize
+    }
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    value: MaybeUninit<T>,
+    /// Incremented every time the slot is freed
+    generation: u32,
+    occupied: bool,
+    /// Index of the next free slot plus one, zero at the end of the free list
+    next_free: u32,
+}
+
+impl<T: Copy> Clone for Slot<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Copy> Copy for Slot<T> {}
+
+/// Consecutive slots sharing a lock and a free list. Zeroed memory is an unlocked full stripe.
+#[derive(Debug)]
+#[repr(C)]
+struct Stripe {
+    /// Only used with more than one stripe, the slab is protected by `L` otherwise
+    lock: AtomicU32,
+    /// Index of the first free slot of the stripe plus one, zero if the stripe is full
+    free_head: u32,
+}
+
+#[derive(Debug)]
+struct SlabState<T, const N: usize> {
+    slots: [Slot<T>; N],
+    stripes: [Stripe; MAX_STRIPES],
+    stripe_count: u32,
+    len: AtomicU32,
+}
+
+/// Lock of a stripe, released when dropped
+struct StripeLock<'a>(&'a AtomicU32);
+
+impl Drop for StripeLock<'_> {
+    fn drop(&mut self) {
+        self.0.store(UNLOCKED, Ordering::Release);
+    }
+}
+
+/// Process-shared pool of `N` fixed-size slots, protected by `L`.
+///
+/// Inserting a value returns a `SlotId` handle that stays valid until the value is removed, which
+/// makes it easy to hand out references to shared objects between a producer and several
+/// consumers. Free slots are kept in a free list, so inserting and removing never scans the pool.
+/// Values are copied in and out of shared memory, so they have to be `Copy`.
+///
+/// The slots can be split into stripes with a lock each through `SlabSettings`, for write-heavy
+/// workloads. Each thread inserts into its own stripe first, moving on to the next one when it is
+/// full. A process that dies while holding the lock of a stripe leaves that stripe locked forever.
+#[derive(Debug)]
+pub struct CortexSlab<T: Copy, L: CortexSync, const N: usize> {
+    cortex: Cortex<SlabState<T, N>, L>,
+    stripes: usize,
+}
+
+impl<T: Copy, L: CortexSync, const N: usize> CortexSlab<T, L, N> {
+    /// Create a new empty slab on `key`
+    pub fn new(key: i32) -> CortexResult<Self> {
+        Self::with_settings(key, &SlabSettings::default())
+    }
+    /// Create a new empty slab on `key`, split into `settings.stripes` stripes
+    pub fn with_settings(key: i32, settings: &SlabSettings) -> CortexResult<Self> {
+        if N >= u32::MAX as usize {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!("Slab of {} slots is too large", N),
+            ));
+        }
+        let stripes = settings.stripes;
+        if stripes == 0 || stripes > MAX_STRIPES.min(N.max(1)) {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Invalid number of stripes: {}, it must be between 1 and {} for {} slots",
+                    stripes,
+                    MAX_STRIPES.min(N.max(1)),
+                    N
+                ),
+            ));
+        }
+        let stripe_len = N.div_ceil(stripes).max(1);
+        let mut slots = [Slot {
+            value: MaybeUninit::uninit(),
+            generation: 0,
+            occupied: false,
+            next_free: 0,
+        }; N];
+        // Every stripe starts out with a free list through all of its slots
+        for (index, slot) in slots.iter_mut().enumerate() {
+            let end = (index / stripe_len + 1) * stripe_len;
+            slot.next_free = if index + 1 < end.min(N) {
+                index as u32 + 2
+            } else {
+                0
+            };
+        }
+        let state = SlabState {
+            slots,
+            stripes: std::array::from_fn(|stripe| Stripe {
+                lock: AtomicU32::new(UNLOCKED),
+                free_head: match stripe * stripe_len {
+                    start if start < N => start as u32 + 1,
+                    _ => 0,
+                },
+            }),
+            stripe_count: stripes as u32,
+            len: AtomicU32::new(0),
+        };
+        let cortex = Cortex::new(Some(key), state, false, None)?;
+        Ok(Self { cortex, stripes })
+    }
+    /// Attempt to attach to an already existing slab
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let cortex: Cortex<SlabState<T, N>, L> = Cortex::attach(key)?;
+        // Never changes after creation
+        let stripes = cortex.read_field(|state| &state.stripe_count)? as usize;
+        Ok(Self { cortex, stripes })
+    }
+    /// Stripe holding the slot at `index`
+    fn stripe_of(&self, index: usize) -> usize {
+        index / N.div_ceil(self.stripes).max(1)
+    }
+    /// Run `f` on the state while holding the lock of `stripe`, `f` may only touch the slots and
+    /// the free list of that stripe. With a single stripe this is `L`, which is only taken for
+    /// reading unless `write` is set, and otherwise the spin lock of the stripe.
+    fn locked<R>(
+        &self,
+        stripe: usize,
+        write: bool,
+        f: impl FnOnce(*mut SlabState<T, N>) -> R,
+    ) -> CortexResult<R> {
+        if self.stripes == 1 {
+            return match write {
+                true => self.cortex.with_write(|state| f(state)),
+                false => self
+                    .cortex
+                    .with_read(|state| f(state as *const SlabState<T, N> as *mut _)),
+            };
+        }
+        let state = self.cortex.ptr;
+        let lock = unsafe { &(*state).stripes[stripe].lock };
+        let mut backoff = Backoff::new();
+        while lock
+            .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+        let _lock = StripeLock(lock);
+        Ok(f(state))
+    }
+    /// The slot behind `id` if it is still occupied by the value `id` was handed out for. Must only
+    /// be called while holding the lock of its stripe.
+    unsafe fn slot(state: *mut SlabState<T, N>, id: SlotId) -> Option<*mut Slot<T>> {
+        let slot = unsafe { std::ptr::addr_of_mut!((*state).slots[id.index()]) };
+        let valid = unsafe { (*slot).occupied && (*slot).generation == id.generation };
+        valid.then_some(slot)
+    }
+    /// Store `value` in a free slot, returning a handle to it. Fails if all slots are taken.
+    pub fn insert(&self, value: T) -> CortexResult<SlotId> {
+        // Spread threads over the stripes, so that they don't all contend on the first one
+        let first = thread_id() as usize % self.stripes;
+        for offset in 0..self.stripes {
+            let stripe = (first + offset) % self.stripes;
+            let inserted = self.locked(stripe, true, |state| unsafe {
+                let free_head = std::ptr::addr_of_mut!((*state).stripes[stripe].free_head);
+                let index = (*free_head).checked_sub(1)?;
+                let slot = &mut *std::ptr::addr_of_mut!((*state).slots[index as usize]);
+                *free_head = slot.next_free;
+                slot.value.write(value);
+                slot.occupied = true;
+                (*state).len.fetch_add(1, Ordering::Relaxed);
+                Some(SlotId {
+                    index,
+                    generation: slot.generation,
+                })
+            })?;
+            if let Some(id) = inserted {
+                return Ok(id);
+            }
+        }
+        Err(CortexError::new_logic(
+            ErrorKind::NoSpace,
+            format!("All {} slots of slab with key: {} are taken", N, self.key()),
+        ))
+    }
+    /// Read the value behind `id`, `None` if it was removed
+    pub fn get(&self, id: SlotId) -> CortexResult<Option<T>> {
+        if id.index() >= N {
+            return Ok(None);
+        }
+        self.locked(self.stripe_of(id.index()), false, |state| unsafe {
+            Self::slot(state, id).map(|slot| (*slot).value.assume_init())
+        })
+    }
+    /// Modify the value behind `id` in place, returning `None` if it was removed
+    pub fn update<R>(&self, id: SlotId, f: impl FnOnce(&mut T) -> R) -> CortexResult<Option<R>> {
+        if id.index() >= N {
+            return Ok(None);
+        }
+        self.locked(self.stripe_of(id.index()), true, |state| unsafe {
+            Self::slot(state, id).map(|slot| f((*slot).value.assume_init_mut()))
+        })
+    }
+    /// Remove the value behind `id` and return it, `None` if it was already removed. The handle,
+    /// and any copies of it, are invalid from then on.
+    pub fn remove(&self, id: SlotId) -> CortexResult<Option<T>> {
+        if id.index() >= N {
+            return Ok(None);
+        }
+        let stripe = self.stripe_of(id.index());
+        self.locked(stripe, true, |state| unsafe {
+            let slot = &mut *Self::slot(state, id)?;
+            let free_head = std::ptr::addr_of_mut!((*state).stripes[stripe].free_head);
+            slot.occupied = false;
+            slot.generation = slot.generation.wrapping_add(1);
+            slot.next_free = *free_head;
+            *free_head = id.index + 1;
+            (*state).len.fetch_sub(1, Ordering::Relaxed);
+            Some(slot.value.assume_init())
+        })
+    }
+    /// Copy out all values along with their handles. The slots are copied under a single lock
+    /// acquisition and sorted out after releasing it, so the lock is held as briefly as possible.
+    /// With several stripes, every stripe is copied under its own lock, so values inserted or
+    /// removed concurrently in other stripes may or may not be included.
+    pub fn snapshot(&self) -> CortexResult<Vec<(SlotId, T)>> {
+        let stripe_len = N.div_ceil(self.stripes).max(1);
+        let mut slots = Vec::with_capacity(N);
+        for stripe in 0..self.stripes {
+            let range = (stripe * stripe_len).min(N)..((stripe + 1) * stripe_len).min(N);
+            self.locked(stripe, false, |state| unsafe {
+                let start = std::ptr::addr_of!((*state).slots[range.start]);
+                slots.extend_from_slice(std::slice::from_raw_parts(start, range.len()));
+            })?;
+        }
+        Ok(slots
+            .into_iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.occupied)
+            .map(|(index, slot)| {
+                let id = SlotId {
+                    index: index as u32,
+                    generation: slot.generation,
+                };
+                (id, unsafe { slot.value.assume_init() })
+            })
+            .collect())
+    }
+    pub fn contains(&self, id: SlotId) -> CortexResult<bool> {
+        if id.index() >= N {
+            return Ok(false);
+        }
+        self.locked(self.stripe_of(id.index()), false, |state| unsafe {
+            Self::slot(state, id).is_some()
+        })
+    }
+    pub fn len(&self) -> CortexResult<usize> {
+        let state = self.cortex.ptr;
+        Ok(unsafe { (*state).len.load(Ordering::Relaxed) } as usize)
+    }
+    pub fn is_empty(&self) -> CortexResult<bool> {
+        Ok(self.len()? == 0)
+    }
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+    /// Number of stripes the slots are split into, see `SlabSettings`
+    pub fn stripes(&self) -> usize {
+        self.stripes
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slab::{CortexSlab, SlabSettings, SlotId};
+    use crate::{ErrorKind, SpinLock};
+    use std::thread;
+
+    #[test]
+    fn handles_across_instances() {
+        let key = rand::random::<i32>().abs();
+        let slab: CortexSlab<u64, SpinLock, 2> = CortexSlab::new(key).unwrap();
+        let attached: CortexSlab<u64, SpinLock, 2> = CortexSlab::attach(key).unwrap();
+
+        let first = slab.insert(1).unwrap();
+        let second = slab.insert(2).unwrap();
+        let err = slab.insert(3).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoSpace);
+
+        let handle = SlotId::from_raw(second.to_raw());
+        assert_eq!(attached.get(handle).unwrap(), Some(2));
+        attached.update(handle, |value| *value += 1).unwrap();
+        assert_eq!(attached.remove(handle).unwrap(), Some(3));
+
+        // The slot is reused, but the stale handle doesn't resolve to the new value
+        let third = slab.insert(4).unwrap();
+        assert_eq!(third.index(), second.index());
+        assert_eq!(slab.get(second).unwrap(), None);
+        assert_eq!(slab.remove(second).unwrap(), None);
+        assert_eq!(slab.get(third).unwrap(), Some(4));
+        assert_eq!(slab.get(first).unwrap(), Some(1));
+        assert_eq!(slab.len().unwrap(), 2);
+        assert_eq!(attached.snapshot().unwrap(), [(first, 1), (third, 4)]);
+    }
+
+    #[test]
+    fn striped_inserts() {
+        let key = rand::random::<i32>().abs();
+        let settings = SlabSettings { stripes: 8 };
+        let slab: CortexSlab<u32, SpinLock, 1000> =
+            CortexSlab::with_settings(key, &settings).unwrap();
+        assert_eq!(
+            CortexSlab::<u32, SpinLock, 1000>::attach(key)
+                .unwrap()
+                .stripes(),
+            8
+        );
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread| {
+                thread::spawn(move || {
+                    let attached: CortexSlab<u32, SpinLock, 1000> =
+                        CortexSlab::attach(key).unwrap();
+                    // Fill up beyond the stripe of the thread, so the others have to take over
+                    let ids: Vec<_> = (0..125)
+                        .map(|value| attached.insert(thread * 1000 + value).unwrap())
+                        .collect();
+                    for id in ids.iter().step_by(5) {
+                        attached.remove(*id).unwrap().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+
+        assert_eq!(slab.len().unwrap(), 800);
+        let snapshot = slab.snapshot().unwrap();
+        assert_eq!(snapshot.len(), 800);
+        assert!(snapshot
+            .iter()
+            .all(|(id, value)| slab.get(*id).unwrap() == Some(*value)));
+        for _ in 0..200 {
+            slab.insert(0).unwrap();
+        }
+        let err = slab.insert(0).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NoSpace);
+
+        let err = CortexSlab::<u32, SpinLock, 4>::with_settings(key, &settings).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,531 @@
+use crate::{shared_state::SharedState, CortexResult, CortexSync};
+use std::hint::spin_loop;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::time::Duration;
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and at least one process might be sleeping on the lock
+const CONTENDED: u32 = 2;
+
+/// Number of doublings before `Backoff` stops spinning and starts yielding the thread
+const SPIN_STEPS: u32 = 6;
+/// Most doublings `Backoff` does, beyond which a single snooze would spin for milliseconds
+/// rather than yield
+const MAX_SPIN_STEPS: u32 = 16;
+
+/// What a waiting thread does once it is done spinning
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum YieldStrategy {
+    /// Keep spinning at the longest backoff, for the lowest latency on machines with cores to spare
+    Spin,
+    /// Give up the rest of the time slice to other threads
+    #[default]
+    Yield,
+    /// Sleep between attempts, which keeps waiting threads off the CPU on machines with few cores
+    Sleep(Duration),
+}
+
+/// Tuning of the exponential backoff between attempts to acquire a contended lock.
+///
+/// The busy-wait between attempts starts at a single spin and doubles `spin_steps` times, after
+/// which `yield_strategy` takes over. With `adaptive` set, the lock keeps a moving average of how
+/// long it is held, and waiters expecting to wait longer than `park_threshold` skip spinning and
+/// go straight to yielding, or to sleeping on the futex for `HybridLock`. This costs reading the
+/// clock on every acquire and release.
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffSettings {
+    /// Number of doublings, values above 16 are treated as 16
+    pub spin_steps: u32,
+    pub yield_strategy: YieldStrategy,
+    pub adaptive: bool,
+    pub park_threshold: Duration,
+}
+
+impl Default for BackoffSettings {
+    fn default() -> Self {
+        Self {
+            spin_steps: SPIN_STEPS,
+            yield_strategy: YieldStrategy::Yield,
+            adaptive: false,
+            park_threshold: Duration::from_micros(20),
+        }
+    }
+}
+
+impl BackoffSettings {
+    /// Default backoff with `adaptive` set
+    pub fn adaptive() -> Self {
+        Self {
+            adaptive: true,
+            ..Self::default()
+        }
+    }
+}
+
+/// Exponential backoff between attempts to acquire a contended lock
+pub(crate) struct Backoff {
+    step: u32,
+    spin_steps: u32,
+    yield_strategy: YieldStrategy,
+}
+
+impl Backoff {
+    pub(crate) fn new() -> Self {
+        Self::with_settings(&BackoffSettings::default())
+    }
+    pub(crate) fn with_settings(settings: &BackoffSettings) -> Self {
+        Self {
+            step: 0,
+            spin_steps: settings.spin_steps.min(MAX_SPIN_STEPS),
+            yield_strategy: settings.yield_strategy,
+        }
+    }
+    /// Go straight to the yield strategy from the next snooze on
+    fn skip_spinning(&mut self) {
+        self.step = self.spin_steps.saturating_add(1);
+    }
+    pub(crate) fn snooze(&mut self) {
+        if self.step <= self.spin_steps {
+            for _ in 0..(1u64 << self.step) {
+                spin_loop();
+            }
+            self.step = self.step.saturating_add(1);
+            return;
+        }
+        match self.yield_strategy {
+            YieldStrategy::Spin => {
+                for _ in 0..(1u64 << self.spin_steps) {
+                    spin_loop();
+                }
+            }
+            YieldStrategy::Yield => std::thread::yield_now(),
+            YieldStrategy::Sleep(duration) => std::thread::sleep(duration),
+        }
+    }
+}
+
+/// Nanoseconds on the monotonic clock, which is shared by all processes on the host
+pub(crate) fn monotonic_nanos() -> u64 {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    now.tv_sec as u64 * 1_000_000_000 + now.tv_nsec as u64
+}
+
+/// Shared state of `SpinLock` and `HybridLock`. Zeroed memory is an unlocked lock without any
+/// recorded hold times.
+#[derive(Debug)]
+#[repr(C)]
+struct State {
+    word: AtomicU32,
+    /// When the current holder acquired the lock, only recorded by adaptive handles
+    acquired_at: AtomicU64,
+    /// Moving average of how long the lock is held in nanoseconds
+    average_hold: AtomicU64,
+}
+
+impl State {
+    /// Whether the current holder is expected to hold on to the lock for longer than `threshold`
+    fn long_wait(&self, threshold: Duration) -> bool {
+        let held = monotonic_nanos().saturating_sub(self.acquired_at.load(Ordering::Relaxed));
+        let remaining = self
+            .average_hold
+            .load(Ordering::Relaxed)
+            .saturating_sub(held);
+        remaining > threshold.as_nanos() as u64
+    }
+    fn record_acquired(&self) {
+        self.acquired_at.store(monotonic_nanos(), Ordering::Relaxed);
+    }
+    /// Fold the hold time into the average, must be called while still holding the lock
+    fn record_release(&self) {
+        let held = monotonic_nanos().saturating_sub(self.acquired_at.load(Ordering::Relaxed));
+        let average = self.average_hold.load(Ordering::Relaxed);
+        self.average_hold
+            .store(average - average / 8 + held / 8, Ordering::Relaxed);
+    }
+}
+
+/// Block until the value at `word` is no longer `expected`, or a spurious wakeup occurs
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn futex_wait(word: &AtomicU32, expected: u32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            word.as_ptr(),
+            libc::FUTEX_WAIT,
+            expected,
+            std::ptr::null::<libc::timespec>(),
+        );
+    }
+}
+
+/// Wake up one process blocked in `futex_wait` on `word`
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn futex_wake(word: &AtomicU32) {
+    unsafe {
+        libc::syscall(libc::SYS_futex, word.as_ptr(), libc::FUTEX_WAKE, 1);
+    }
+}
+
+// Platforms without futexes fall back to short sleeps
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn futex_wait(word: &AtomicU32, expected: u32) {
+    if word.load(Ordering::Relaxed) == expected {
+        std::thread::sleep(std::time::Duration::from_micros(50));
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn futex_wake(_word: &AtomicU32) {}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SpinLockSettings {
+    pub backoff: BackoffSettings,
+}
+
+/// Lock that busy-waits with exponential backoff, stored in a small named shared memory object.
+///
+/// Avoids syscalls entirely on the uncontended path, which makes it a good fit for very short
+/// critical sections. Readers and writers are both given exclusive access. The backoff is a
+/// per-process setting, and can be passed both when creating and attaching.
+#[derive(Debug)]
+pub struct SpinLock {
+    state: SharedState<State>,
+    backoff: BackoffSettings,
+}
+
+impl SpinLock {
+    fn from_state(state: SharedState<State>, settings: Option<&SpinLockSettings>) -> Self {
+        let backoff = settings.copied().unwrap_or_default().backoff;
+        Self { state, backoff }
+    }
+    fn lock(&self) {
+        let state = self.state.get();
+        let mut backoff = Backoff::with_settings(&self.backoff);
+        if self.backoff.adaptive && state.long_wait(self.backoff.park_threshold) {
+            backoff.skip_spinning();
+        }
+        loop {
+            if state
+                .word
+                .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                break;
+            }
+            // Spin on a plain load to avoid bouncing the cache line between cores
+            while state.word.load(Ordering::Relaxed) != UNLOCKED {
+                backoff.snooze();
+            }
+        }
+        if self.backoff.adaptive {
+            state.record_acquired();
+        }
+    }
+    fn try_lock(&self) -> bool {
+        let state = self.state.get();
+        let locked = state
+            .word
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked && self.backoff.adaptive {
+            state.record_acquired();
+        }
+        locked
+    }
+}
+
+impl CortexSync for SpinLock {
+    type Settings = SpinLockSettings;
+
+    fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::create("cortex_spinlock", cortex_key)?;
+        Ok(Self::from_state(state, settings))
+    }
+    fn attach(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::open("cortex_spinlock", cortex_key)?;
+        Ok(Self::from_state(state, settings))
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        Ok(Self {
+            state: self.state.try_clone()?,
+            backoff: self.backoff,
+        })
+    }
+    fn force_ownership(&mut self) {
+        self.state.force_ownership()
+    }
+    fn relinquish_ownership(&mut self) {
+        self.state.relinquish_ownership()
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.lock();
+        Ok(())
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.lock();
+        Ok(())
+    }
+    fn release(&self) -> CortexResult<()> {
+        let state = self.state.get();
+        if self.backoff.adaptive {
+            state.record_release();
+        }
+        state.word.store(UNLOCKED, Ordering::Release);
+        Ok(())
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_lock())
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_lock())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct HybridLockSettings {
+    /// Number of attempts to acquire the lock by spinning before going to sleep
+    pub spin_limit: u32,
+    /// Backoff between the spinning attempts. With `adaptive` set, waiters expecting to wait
+    /// longer than the park threshold go to sleep without spinning at all.
+    pub backoff: BackoffSettings,
+}
+
+impl Default for HybridLockSettings {
+    fn default() -> Self {
+        Self {
+            spin_limit: 100,
+            backoff: BackoffSettings::default(),
+        }
+    }
+}
+
+/// Lock that spins for a bounded number of attempts before going to sleep on a futex (or short
+/// sleeps on platforms without futexes).
+///
+/// Gets the low latency of a spinlock for short critical sections, without burning CPU when the
+/// lock is held for longer. Readers and writers are both given exclusive access. The spin limit and
+/// backoff are per-process settings, and can be passed both when creating and attaching.
+#[derive(Debug)]
+pub struct HybridLock {
+    state: SharedState<State>,
+    settings: HybridLockSettings,
+}
+
+impl HybridLock {
+    fn from_state(state: SharedState<State>, settings: Option<&HybridLockSettings>) -> Self {
+        let settings = settings.copied().unwrap_or_default();
+        Self { state, settings }
+    }
+    fn lock(&self) {
+        let state = self.state.get();
+        let backoff_settings = &self.settings.backoff;
+        let spin_limit =
+            match backoff_settings.adaptive && state.long_wait(backoff_settings.park_threshold) {
+                true => 0,
+                false => self.settings.spin_limit,
+            };
+        let mut backoff = Backoff::with_settings(backoff_settings);
+        let mut locked = false;
+        for _ in 0..spin_limit {
+            if state
+                .word
+                .compare_exchange_weak(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                locked = true;
+                break;
+            }
+            backoff.snooze();
+        }
+        // Mark the lock as contended so that the holder knows to wake us up
+        while !locked && state.word.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex_wait(&state.word, CONTENDED);
+        }
+        if backoff_settings.adaptive {
+            state.record_acquired();
+        }
+    }
+    fn try_lock(&self) -> bool {
+        let state = self.state.get();
+        let locked = state
+            .word
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok();
+        if locked && self.settings.backoff.adaptive {
+            state.record_acquired();
+        }
+        locked
+    }
+}
+
+impl CortexSync for HybridLock {
+    type Settings = HybridLockSettings;
+
+    fn new(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::create("cortex_hybridlock", cortex_key)?;
+        Ok(Self::from_state(state, settings))
+    }
+    fn attach(cortex_key: i32, settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::open("cortex_hybridlock", cortex_key)?;
+        Ok(Self::from_state(state, settings))
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        Ok(Self {
+            state: self.state.try_clone()?,
+            settings: self.settings,
+        })
+    }
+    fn force_ownership(&mut self) {
+        self.state.force_ownership()
+    }
+    fn relinquish_ownership(&mut self) {
+        self.state.relinquish_ownership()
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.lock();
+        Ok(())
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.lock();
+        Ok(())
+    }
+    fn release(&self) -> CortexResult<()> {
+        let state = self.state.get();
+        if self.settings.backoff.adaptive {
+            state.record_release();
+        }
+        if state.word.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&state.word);
+        }
+        Ok(())
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_lock())
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::spin::{
+        Backoff, BackoffSettings, HybridLock, HybridLockSettings, SpinLock, SpinLockSettings,
+        State, YieldStrategy, MAX_SPIN_STEPS,
+    };
+    use crate::{Cortex, CortexSync};
+    use std::sync::atomic::{AtomicU32, AtomicU64};
+    use std::thread;
+    use std::time::Duration;
+
+    fn increment_concurrently<L: CortexSync + 'static>(settings: Option<L::Settings>)
+    where
+        L::Settings: Copy + Send + 'static,
+    {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, L> = Cortex::new(Some(key), 0, false, settings.as_ref()).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached: Cortex<u64, L> = match settings {
+                        Some(settings) => Cortex::attach_with_lock(key, &settings).unwrap(),
+                        None => Cortex::attach(key).unwrap(),
+                    };
+                    for _ in 0..1000 {
+                        attached.with_write(|value| *value += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(cortex.read().unwrap(), 8000);
+    }
+
+    #[test]
+    fn spinlock_mutual_exclusion() {
+        increment_concurrently::<SpinLock>(None);
+    }
+
+    #[test]
+    fn hybridlock_mutual_exclusion() {
+        increment_concurrently::<HybridLock>(None);
+    }
+
+    #[test]
+    fn backoff_clamps_spin_steps() {
+        let mut backoff = Backoff::with_settings(&BackoffSettings {
+            spin_steps: u32::MAX,
+            ..Default::default()
+        });
+        assert_eq!(backoff.spin_steps, MAX_SPIN_STEPS);
+        // Done spinning after a bounded number of snoozes, and yields from then on
+        for _ in 0..=MAX_SPIN_STEPS {
+            backoff.snooze();
+        }
+        assert!(backoff.step > backoff.spin_steps);
+        backoff.snooze();
+
+        backoff.skip_spinning();
+        backoff.snooze();
+    }
+
+    #[test]
+    fn adaptive_backoff() {
+        let backoff = BackoffSettings {
+            spin_steps: 2,
+            yield_strategy: YieldStrategy::Sleep(Duration::from_micros(10)),
+            ..BackoffSettings::adaptive()
+        };
+        increment_concurrently::<SpinLock>(Some(SpinLockSettings { backoff }));
+        increment_concurrently::<HybridLock>(Some(HybridLockSettings {
+            spin_limit: 10,
+            backoff,
+        }));
+    }
+
+    #[test]
+    fn hold_times() {
+        let state = State {
+            word: AtomicU32::new(0),
+            acquired_at: AtomicU64::new(0),
+            average_hold: AtomicU64::new(0),
+        };
+        for _ in 0..32 {
+            state.record_acquired();
+            thread::sleep(Duration::from_millis(2));
+            state.record_release();
+        }
+        state.record_acquired();
+        assert!(state.long_wait(Duration::from_micros(500)));
+        let average = state
+            .average_hold
+            .load(std::sync::atomic::Ordering::Relaxed);
+        thread::sleep(Duration::from_nanos(average));
+        assert!(!state.long_wait(Duration::from_micros(500)));
+    }
+
+    #[test]
+    fn hybridlock_try_lock() {
+        let key = rand::random::<i32>().abs();
+        let settings = HybridLockSettings {
+            spin_limit: 0,
+            ..Default::default()
+        };
+        let cortex: Cortex<u64, HybridLock> =
+            Cortex::new(Some(key), 0, false, Some(&settings)).unwrap();
+        let attached: Cortex<u64, HybridLock> = Cortex::attach_with_lock(key, &settings).unwrap();
+
+        cortex.lock.write_lock().unwrap();
+        assert_eq!(attached.try_read().unwrap(), None);
+        cortex.lock.release().unwrap();
+        assert_eq!(attached.try_read().unwrap(), Some(0));
+    }
+}
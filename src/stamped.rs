@@ -0,0 +1,82 @@
+use crate::{spin::monotonic_nanos, Cortex, CortexResult, CortexSync};
+use std::time::Duration;
+
+/// Sample stamped with a sequence number and the time it was written, for sensor pipelines that
+/// need to tell fresh samples from ones they have already seen.
+///
+/// Store it in a `Cortex<Stamped<T>, L>` and write through `write_stamped`, which fills in both
+/// stamps under the write lock. `timestamp` is taken from the monotonic clock, which is shared by
+/// all processes on the host and never jumps, unlike the wall clock behind `Versioned`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct Stamped<T> {
+    /// Number of samples written through `write_stamped`, zero for the initial value
+    pub sequence: u64,
+    /// When the sample was written, in nanoseconds on the monotonic clock
+    pub timestamp: u64,
+    pub value: T,
+}
+
+impl<T> Stamped<T> {
+    /// Initial sample with sequence number zero, stamped with the current time
+    pub fn new(value: T) -> Self {
+        Self {
+            sequence: 0,
+            timestamp: monotonic_nanos(),
+            value,
+        }
+    }
+    /// Time passed since the sample was written
+    pub fn age(&self) -> Duration {
+        Duration::from_nanos(monotonic_nanos().saturating_sub(self.timestamp))
+    }
+}
+
+impl<T: Copy, L: CortexSync> Cortex<Stamped<T>, L> {
+    /// Write `value` as the next sample, returning its sequence number
+    pub fn write_stamped(&self, value: T) -> CortexResult<u64> {
+        self.with_write(|sample| {
+            sample.sequence += 1;
+            sample.timestamp = monotonic_nanos();
+            sample.value = value;
+            sample.sequence
+        })
+    }
+    /// Copy out the sample if its sequence number is past `last_sequence`, `None` if it was
+    /// already seen. Pass the sequence number of the last sample processed, or zero to get any
+    /// sample written since the initial value.
+    pub fn read_if_newer(&self, last_sequence: u64) -> CortexResult<Option<Stamped<T>>> {
+        self.with_read(|sample| (sample.sequence > last_sequence).then_some(*sample))
+    }
+    /// Sequence number of the latest sample, without copying the value
+    pub fn sequence(&self) -> CortexResult<u64> {
+        self.read_field(|sample| &sample.sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, SpinLock, Stamped};
+    use std::time::Duration;
+
+    #[test]
+    fn newer_samples() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<Stamped<[f32; 3]>, SpinLock> =
+            Cortex::new(Some(key), Stamped::new([0.0; 3]), false, None).unwrap();
+        let attached: Cortex<Stamped<[f32; 3]>, SpinLock> = Cortex::attach(key).unwrap();
+        assert_eq!(attached.read_if_newer(0).unwrap(), None);
+
+        assert_eq!(cortex.write_stamped([1.0; 3]).unwrap(), 1);
+        let first = attached.read_if_newer(0).unwrap().unwrap();
+        assert_eq!((first.sequence, first.value), (1, [1.0; 3]));
+        assert_eq!(attached.read_if_newer(first.sequence).unwrap(), None);
+
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(cortex.write_stamped([2.0; 3]).unwrap(), 2);
+        let second = attached.read_if_newer(first.sequence).unwrap().unwrap();
+        assert!(second.timestamp > first.timestamp);
+        assert!(first.age() >= Duration::from_millis(2));
+        assert_eq!(attached.sequence().unwrap(), 2);
+    }
+}
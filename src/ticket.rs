@@ -0,0 +1,139 @@
+use crate::{atomic::Atomic32, shared_state::SharedState, spin::Backoff, CortexResult, CortexSync};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[derive(Debug, Default)]
+pub(crate) struct TicketState<A = AtomicU32> {
+    /// Next ticket to hand out
+    next: A,
+    /// Ticket currently allowed to hold the lock
+    serving: A,
+}
+
+impl<A: Atomic32> TicketState<A> {
+    /// Take a ticket and call `snooze` until it is served
+    pub(crate) fn lock(&self, mut snooze: impl FnMut()) {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        while self.serving.load(Ordering::Acquire) != ticket {
+            snooze();
+        }
+    }
+    pub(crate) fn try_lock(&self) -> bool {
+        let serving = self.serving.load(Ordering::Acquire);
+        // Only take a ticket if it would be served immediately
+        self.next
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+    pub(crate) fn release(&self) {
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+/// Fair lock that grants access in strict FIFO order, stored in a small named shared memory
+/// object.
+///
+/// Every process takes a ticket and waits until that ticket is being served, which means no
+/// process can be starved by another one repeatedly re-acquiring the lock. Waiting is done by
+/// spinning with backoff, so it is best suited for short critical sections. Readers and writers
+/// are both given exclusive access.
+#[derive(Debug)]
+pub struct TicketLock {
+    state: SharedState<TicketState>,
+}
+
+impl TicketLock {
+    fn lock(&self) {
+        let mut backoff = Backoff::new();
+        self.state.get().lock(|| backoff.snooze());
+    }
+    fn try_lock(&self) -> bool {
+        self.state.get().try_lock()
+    }
+}
+
+impl CortexSync for TicketLock {
+    type Settings = ();
+
+    fn new(cortex_key: i32, _settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::create("cortex_ticketlock", cortex_key)?;
+        Ok(Self { state })
+    }
+    fn attach(cortex_key: i32, _settings: Option<&Self::Settings>) -> CortexResult<Self> {
+        let state = SharedState::open("cortex_ticketlock", cortex_key)?;
+        Ok(Self { state })
+    }
+    fn try_clone(&self) -> CortexResult<Self> {
+        let state = self.state.try_clone()?;
+        Ok(Self { state })
+    }
+    fn force_ownership(&mut self) {
+        self.state.force_ownership()
+    }
+    fn relinquish_ownership(&mut self) {
+        self.state.relinquish_ownership()
+    }
+    fn read_lock(&self) -> CortexResult<()> {
+        self.lock();
+        Ok(())
+    }
+    fn write_lock(&self) -> CortexResult<()> {
+        self.lock();
+        Ok(())
+    }
+    fn release(&self) -> CortexResult<()> {
+        self.state.get().release();
+        Ok(())
+    }
+    fn try_read_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_lock())
+    }
+    fn try_write_lock(&self) -> CortexResult<bool> {
+        Ok(self.try_lock())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ticket::TicketLock;
+    use crate::{Cortex, CortexSync};
+    use std::thread;
+
+    #[test]
+    fn mutual_exclusion() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, TicketLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                thread::spawn(move || {
+                    let attached: Cortex<u64, TicketLock> = Cortex::attach(key).unwrap();
+                    for _ in 0..1000 {
+                        attached.with_write(|value| *value += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+        assert_eq!(cortex.read().unwrap(), 8000);
+    }
+
+    #[test]
+    fn try_lock_does_not_queue() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u64, TicketLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+
+        cortex.lock.write_lock().unwrap();
+        assert!(!cortex.lock.try_write_lock().unwrap());
+        cortex.lock.release().unwrap();
+        // A failed attempt must not have left a ticket behind
+        assert!(cortex.lock.try_write_lock().unwrap());
+        cortex.lock.release().unwrap();
+    }
+}
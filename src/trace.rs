@@ -0,0 +1,61 @@
+//! Wrappers around the `tracing` macros that compile to nothing without the `tracing` feature.
+//! Arguments are still type checked, so code using them builds the same either way.
+
+#[cfg(feature = "tracing")]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        ::tracing::$level!($($arg)+)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {{
+        let _ = format_args!($($arg)+);
+    }};
+}
+
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::trace::event!(trace, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::trace::event!(info, $($arg)+) };
+}
+
+macro_rules! warning {
+    ($($arg:tt)+) => { $crate::trace::event!(warn, $($arg)+) };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::trace::event!(error, $($arg)+) };
+}
+
+/// Enter a debug level span with the identity of a `Cortex` as fields, which is exited when the
+/// returned value is dropped. Does nothing without the `tracing` feature.
+#[cfg(feature = "tracing")]
+macro_rules! cortex_span {
+    ($name:literal, $cortex:expr) => {
+        ::tracing::debug_span!(
+            $name,
+            key = $cortex.key,
+            id = $cortex.id,
+            size = $cortex.size
+        )
+        .entered()
+    };
+}
+
+/// Stands in for an entered span without the `tracing` feature
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! cortex_span {
+    ($name:literal, $cortex:expr) => {{
+        let _ = &$cortex;
+        $crate::trace::NoSpan
+    }};
+}
+
+pub(crate) use {cortex_span, error, event, info, trace, warning};
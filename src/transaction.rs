@@ -0,0 +1,218 @@
+//! Writes to several segments that become visible together, see `Transaction`.
+
+use crate::{
+    read_many::{self, Held},
+    Cortex, CortexResult, CortexSync,
+};
+
+/// Writes to several cortices, staged and then committed together under all of their write
+/// locks, so that readers holding any of the read locks, e.g. through `read_many`, never observe
+/// some of the writes without the others.
+///
+/// The write locks are acquired in order of key, the same order as `read_many`, so concurrent
+/// transactions and `read_many` calls can't deadlock. Writes are applied in the order they were
+/// staged, and nothing is applied if any of the locks can't be acquired or a cortex being updated
+/// is poisoned. A closure passed to `update` that panics leaves the writes staged before it
+/// applied and its own cortex poisoned, the same as a panic in `Cortex::with_write`.
+///
+/// ```ignore
+/// let mut transaction = Transaction::new();
+/// transaction.write(&position, new_position);
+/// transaction.update(&timestamp, |timestamp| *timestamp = now);
+/// transaction.commit()?;
+/// ```
+#[derive(Default)]
+pub struct Transaction<'a> {
+    staged: Vec<Box<dyn Stage<'a> + 'a>>,
+}
+
+impl std::fmt::Debug for Transaction<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let keys: Vec<_> = self.staged.iter().map(|stage| stage.key()).collect();
+        f.debug_struct("Transaction").field("keys", &keys).finish()
+    }
+}
+
+enum Change<'a, T> {
+    Write(T),
+    Update(Box<dyn FnOnce(&mut T) + 'a>),
+}
+
+struct Staged<'a, T, L: CortexSync> {
+    cortex: &'a Cortex<T, L>,
+    change: Change<'a, T>,
+}
+
+/// Staged write, with the type of the cortex erased
+trait Stage<'a> {
+    fn key(&self) -> i32;
+    fn lock(&self) -> CortexResult<Box<dyn Held + 'a>>;
+    /// Check that the write can be applied, while holding the write lock
+    fn check(&self) -> CortexResult<()>;
+    /// Apply the write, while holding the write lock
+    fn apply(self: Box<Self>);
+}
+
+impl<'a, T, L: CortexSync> Stage<'a> for Staged<'a, T, L> {
+    fn key(&self) -> i32 {
+        self.cortex.key
+    }
+    fn lock(&self) -> CortexResult<Box<dyn Held + 'a>> {
+        Ok(Box::new(self.cortex.acquire_write()?))
+    }
+    fn check(&self) -> CortexResult<()> {
+        match self.change {
+            // Replacing the whole value clears poison, the same as `Cortex::write`
+            Change::Write(_) => Ok(()),
+            Change::Update(_) => self.cortex.check_data(),
+        }
+    }
+    fn apply(self: Box<Self>) {
+        let cortex = self.cortex;
+        match self.change {
+            Change::Write(data) => {
+                if !cortex.is_poisoned() {
+                    cortex.begin_write();
+                }
+                unsafe { cortex.ptr.write(data) };
+            }
+            Change::Update(f) => {
+                cortex.begin_write();
+                f(unsafe { &mut *cortex.ptr });
+            }
+        }
+        cortex.end_write();
+    }
+}
+
+impl<'a> Transaction<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Stage replacing the value of `cortex` with `data`
+    pub fn write<T: 'a, L: CortexSync + 'a>(
+        &mut self,
+        cortex: &'a Cortex<T, L>,
+        data: T,
+    ) -> &mut Self {
+        self.stage(cortex, Change::Write(data))
+    }
+    /// Stage modifying the value of `cortex` in place through `f`, which runs during `commit`
+    pub fn update<T: 'a, L: CortexSync + 'a>(
+        &mut self,
+        cortex: &'a Cortex<T, L>,
+        f: impl FnOnce(&mut T) + 'a,
+    ) -> &mut Self {
+        self.stage(cortex, Change::Update(Box::new(f)))
+    }
+    fn stage<T: 'a, L: CortexSync + 'a>(
+        &mut self,
+        cortex: &'a Cortex<T, L>,
+        change: Change<'a, T>,
+    ) -> &mut Self {
+        self.staged.push(Box::new(Staged { cortex, change }));
+        self
+    }
+    /// Number of staged writes
+    pub fn len(&self) -> usize {
+        self.staged.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.staged.is_empty()
+    }
+    /// Acquire the write locks of all cortices in order of key, once per key, apply the staged
+    /// writes and release the locks again
+    pub fn commit(self) -> CortexResult<()> {
+        let mut order: Vec<_> = self.staged.iter().collect();
+        order.sort_by_key(|stage| stage.key());
+        order.dedup_by_key(|stage| stage.key());
+        let held = order
+            .into_iter()
+            .map(|stage| stage.lock())
+            .collect::<CortexResult<Vec<_>>>()?;
+        for stage in &self.staged {
+            stage.check()?;
+        }
+        crate::trace::trace!("Committing transaction of {} writes", self.staged.len());
+        for stage in self.staged {
+            stage.apply();
+        }
+        read_many::release(held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transaction::Transaction;
+    use crate::{read_many, Cortex, CortexError, RwLock, SpinLock};
+    use std::thread;
+
+    #[test]
+    fn no_partial_updates() {
+        let key = rand::random::<i32>().abs() / 2;
+        let position: Cortex<[f64; 2], RwLock> =
+            Cortex::new(Some(key + 1), [0.0; 2], false, None).unwrap();
+        let timestamp: Cortex<u64, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+
+        let writers: Vec<_> = (0..4)
+            .map(|_| {
+                thread::spawn(move || {
+                    let position: Cortex<[f64; 2], RwLock> = Cortex::attach(key + 1).unwrap();
+                    let timestamp: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+                    for _ in 0..250 {
+                        let mut transaction = Transaction::new();
+                        transaction
+                            .update(&position, |position| position[0] += 1.0)
+                            .update(&timestamp, |timestamp| *timestamp += 1);
+                        transaction.commit().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..500 {
+            let (position, timestamp) = read_many((&position, &timestamp)).unwrap();
+            assert_eq!(position[0], timestamp as f64);
+        }
+        for writer in writers {
+            writer.join().expect("Thread panicked");
+        }
+
+        let mut transaction = Transaction::new();
+        transaction
+            .write(&timestamp, 1)
+            .update(&timestamp, |timestamp| *timestamp *= 10)
+            .write(&position, [1.0, 2.0]);
+        assert_eq!(transaction.len(), 3);
+        transaction.commit().unwrap();
+        assert_eq!(
+            read_many((&position, &timestamp)).unwrap(),
+            ([1.0, 2.0], 10)
+        );
+    }
+
+    #[test]
+    fn nothing_applied_on_error() {
+        let key = rand::random::<i32>().abs() / 2;
+        let healthy: Cortex<u64, SpinLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+        let poisoned: Cortex<u64, SpinLock> = Cortex::new(Some(key + 1), 2, false, None).unwrap();
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            poisoned
+                .with_write(|_| panic!("Interrupted write"))
+                .unwrap();
+        }));
+
+        let mut transaction = Transaction::new();
+        transaction
+            .write(&healthy, 3)
+            .update(&poisoned, |value| *value += 1);
+        assert!(matches!(transaction.commit(), Err(CortexError::Poisoned)));
+        // Would spin forever if the locks were still held
+        assert_eq!(healthy.read().unwrap(), 1);
+
+        // Replacing the value clears the poison instead
+        let mut transaction = Transaction::new();
+        transaction.write(&healthy, 3).write(&poisoned, 4);
+        transaction.commit().unwrap();
+        assert_eq!(read_many((&healthy, &poisoned)).unwrap(), (3, 4));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    no_lock, process_alive, Cortex, CortexResult, NoLock,
+};
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+
+/// Set on the middle buffer when it holds a frame the reader hasn't taken yet
+const NEW_FRAME: u32 = 4;
+const INDEX: u32 = 3;
+
+/// The index out of 0, 1 and 2 that is neither `a` nor `b`, `None` if they aren't distinct indices
+fn remaining(a: u32, b: u32) -> Option<u32> {
+    (a != b && a <= 2 && b <= 2).then(|| 3 - a - b)
+}
+
+#[repr(C)]
+struct TripleState<T> {
+    /// Index of the buffer between the writer and the reader, along with `NEW_FRAME`
+    middle: AtomicU32,
+    /// Index of the buffer the writer fills, only changed by the writer
+    back: AtomicU32,
+    /// Index of the buffer the reader reads, only changed by the reader
+    front: AtomicU32,
+    /// Pid of the process holding the writer or reader role, zero if unclaimed
+    writer_pid: AtomicI32,
+    reader_pid: AtomicI32,
+    buffers: [UnsafeCell<T>; 3],
+}
+
+/// Process-shared triple buffer, passing frames from a single writer to a single reader without
+/// either side ever blocking, e.g. sensor frames shown by a UI process.
+///
+/// Of the three buffers, the writer fills one, the reader reads another, and the third holds the
+/// latest complete frame. Publishing swaps the filled buffer with the middle one, and the reader
+/// swaps its buffer with the middle one whenever there is a new frame, each through a single
+/// atomic operation. Frames published faster than the reader takes them are skipped, the reader
+/// always gets the latest one.
+///
+/// Each role can be claimed by one process at a time through `writer` and `reader`, and is taken
+/// over from a process that has died. Frames are copied in and out of shared memory, so they have
+/// to be `Copy`.
+pub struct CortexTripleBuffer<T: Copy> {
+    cortex: Cortex<TripleState<T>, NoLock>,
+}
+
+unsafe impl<T: Copy + Send + Sync> Send for CortexTripleBuffer<T> {}
+unsafe impl<T: Copy + Send + Sync> Sync for CortexTripleBuffer<T> {}
+
+impl<T: Copy> CortexTripleBuffer<T> {
+    /// Create a new triple buffer on `key`, with all three buffers set to `initial`
+    pub fn new(key: i32, initial: T) -> CortexResult<Self> {
+        let state = TripleState {
+            middle: AtomicU32::new(1),
+            back: AtomicU32::new(0),
+            front: AtomicU32::new(2),
+            writer_pid: AtomicI32::new(0),
+            reader_pid: AtomicI32::new(0),
+            buffers: std::array::from_fn(|_| UnsafeCell::new(initial)),
+        };
+        let cortex = Cortex::new(Some(key), state, false, Some(no_lock::INTERNAL))?;
+        Ok(Self { cortex })
+    }
+    /// Attempt to attach to an already existing triple buffer
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let cortex = Cortex::attach_with_lock(key, no_lock::INTERNAL)?;
+        Ok(Self { cortex })
+    }
+    fn state(&self) -> &TripleState<T> {
+        unsafe { &*self.cortex.ptr }
+    }
+    /// Claim the role behind `pid_slot` for the current process
+    fn claim(&self, pid_slot: &AtomicI32, role: &str) -> CortexResult<()> {
+        let pid = unsafe { libc::getpid() };
+        let owner = pid_slot.load(Ordering::Relaxed);
+        if (owner == 0 || (owner != pid && !process_alive(owner)))
+            && pid_slot
+                .compare_exchange(owner, pid, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        {
+            return Ok(());
+        }
+        Err(CortexError::new_logic(
+            ErrorKind::AlreadyExists,
+            format!(
+                "The {} of triple buffer with key: {} is already claimed by pid: {}",
+                role,
+                self.key(),
+                pid_slot.load(Ordering::Relaxed)
+            ),
+        ))
+    }
+    /// Claim the writer role, failing with `ErrorKind::AlreadyExists` while another writer exists
+    pub fn writer(&self) -> CortexResult<TripleBufferWriter<'_, T>> {
+        let state = self.state();
+        self.claim(&state.writer_pid, "writer")?;
+        // Recover the buffer of a writer that died while publishing, the three indices always
+        // being a permutation of 0, 1 and 2 otherwise
+        let middle = state.middle.load(Ordering::Acquire) & INDEX;
+        let back = remaining(middle, state.front.load(Ordering::Relaxed))
+            .unwrap_or_else(|| state.back.load(Ordering::Relaxed));
+        state.back.store(back, Ordering::Relaxed);
+        Ok(TripleBufferWriter {
+            buffer: self,
+            back: back as usize,
+        })
+    }
+    /// Claim the reader role, failing with `ErrorKind::AlreadyExists` while another reader exists
+    pub fn reader(&self) -> CortexResult<TripleBufferReader<'_, T>> {
+        let state = self.state();
+        self.claim(&state.reader_pid, "reader")?;
+        let middle = state.middle.load(Ordering::Acquire) & INDEX;
+        let front = remaining(middle, state.back.load(Ordering::Relaxed))
+            .unwrap_or_else(|| state.front.load(Ordering::Relaxed));
+        state.front.store(front, Ordering::Relaxed);
+        Ok(TripleBufferReader {
+            buffer: self,
+            front: front as usize,
+        })
+    }
+    pub fn key(&self) -> i32 {
+        self.cortex.key()
+    }
+    pub fn is_owner(&self) -> bool {
+        self.cortex.is_owner()
+    }
+}
+
+/// Writer role of a `CortexTripleBuffer`, released when dropped
+pub struct TripleBufferWriter<'a, T: Copy> {
+    buffer: &'a CortexTripleBuffer<T>,
+    back: usize,
+}
+
+impl<T: Copy> TripleBufferWriter<'_, T> {
+    /// Publish `frame` as the latest one
+    pub fn write(&mut self, frame: T) {
+        self.update(|back| *back = frame)
+    }
+    /// Fill the back buffer in place through `f` and publish it. The back buffer holds an older
+    /// frame, not necessarily the last one published, so `f` has to overwrite everything it
+    /// relies on.
+    pub fn update(&mut self, f: impl FnOnce(&mut T)) {
+        let state = self.buffer.state();
+        f(unsafe { &mut *state.buffers[self.back].get() });
+        let previous = state
+            .middle
+            .swap(self.back as u32 | NEW_FRAME, Ordering::AcqRel);
+        self.back = (previous & INDEX) as usize;
+        state.back.store(previous & INDEX, Ordering::Relaxed);
+    }
+}
+
+impl<T: Copy> Drop for TripleBufferWriter<'_, T> {
+    fn drop(&mut self) {
+        self.buffer.state().writer_pid.store(0, Ordering::Release);
+    }
+}
+
+/// Reader role of a `CortexTripleBuffer`, released when dropped
+pub struct TripleBufferReader<'a, T: Copy> {
+    buffer: &'a CortexTripleBuffer<T>,
+    front: usize,
+}
+
+impl<T: Copy> TripleBufferReader<'_, T> {
+    /// Whether a frame was published since the last one taken by this reader
+    pub fn has_new_frame(&self) -> bool {
+        self.buffer.state().middle.load(Ordering::Relaxed) & NEW_FRAME != 0
+    }
+    /// Take the latest frame if there is a new one, returning whether there was
+    fn take(&mut self) -> bool {
+        let state = self.buffer.state();
+        if !self.has_new_frame() {
+            return false;
+        }
+        let previous = state.middle.swap(self.front as u32, Ordering::AcqRel);
+        self.front = (previous & INDEX) as usize;
+        state.front.store(previous & INDEX, Ordering::Relaxed);
+        true
+    }
+    /// Access the latest frame in place, which stays untouched by the writer until the next call
+    pub fn latest(&mut self) -> &T {
+        self.take();
+        unsafe { &*self.buffer.state().buffers[self.front].get() }
+    }
+    /// Copy out the latest frame
+    pub fn read(&mut self) -> T {
+        *self.latest()
+    }
+    /// Copy out the latest frame if one was published since the last read, `None` otherwise
+    pub fn read_new(&mut self) -> Option<T> {
+        self.take()
+            .then(|| unsafe { *self.buffer.state().buffers[self.front].get() })
+    }
+}
+
+impl<T: Copy> Drop for TripleBufferReader<'_, T> {
+    fn drop(&mut self) {
+        self.buffer.state().reader_pid.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::triple_buffer::CortexTripleBuffer;
+    use crate::ErrorKind;
+    use std::thread;
+
+    #[test]
+    fn latest_frame() {
+        let key = rand::random::<i32>().abs();
+        let buffer: CortexTripleBuffer<[u32; 4]> = CortexTripleBuffer::new(key, [0; 4]).unwrap();
+        let attached: CortexTripleBuffer<[u32; 4]> = CortexTripleBuffer::attach(key).unwrap();
+        let mut writer = buffer.writer().unwrap();
+        let mut reader = attached.reader().unwrap();
+
+        assert_eq!(reader.read_new(), None);
+        assert_eq!(reader.read(), [0; 4]);
+        writer.write([1; 4]);
+        writer.write([2; 4]);
+        assert!(reader.has_new_frame());
+        // Frames the reader didn't get to are skipped
+        assert_eq!(reader.read_new(), Some([2; 4]));
+        assert_eq!(reader.read_new(), None);
+        assert_eq!(reader.latest(), &[2; 4]);
+
+        let err = attached.writer().err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert!(buffer.reader().is_err());
+
+        // A new writer continues with the right buffer
+        drop(writer);
+        let mut writer = attached.writer().unwrap();
+        writer.write([3; 4]);
+        assert_eq!(reader.read(), [3; 4]);
+    }
+
+    #[test]
+    fn frames_never_torn() {
+        let key = rand::random::<i32>().abs();
+        let buffer: CortexTripleBuffer<[u64; 32]> = CortexTripleBuffer::new(key, [0; 32]).unwrap();
+
+        let writer = thread::spawn(move || {
+            let buffer: CortexTripleBuffer<[u64; 32]> = CortexTripleBuffer::attach(key).unwrap();
+            let mut writer = buffer.writer().unwrap();
+            for frame in 1..=10_000 {
+                writer.update(|back| back.iter_mut().for_each(|value| *value = frame));
+            }
+        });
+        let mut reader = buffer.reader().unwrap();
+        let mut last = 0;
+        while last < 10_000 {
+            let frame = reader.latest();
+            assert!(frame.iter().all(|value| *value == frame[0]));
+            assert!(frame[0] >= last);
+            last = frame[0];
+        }
+        writer.join().expect("Thread panicked");
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{
+    crash::{CortexError, ErrorKind},
+    mark_for_deletion,
+    segment::Segment,
+    Cortex, CortexResult, CortexSync,
+};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Indirection header of a `CortexVec`, stored on the key of the vector
+#[derive(Debug, Clone, Copy)]
+struct VecState {
+    /// Id of the segment currently holding the elements, replaced whenever the vector grows
+    data_id: i32,
+    len: usize,
+    capacity: usize,
+}
+
+/// Process-shared growable vector, protected by `L`.
+///
+/// The elements live in a separate segment, which is replaced by a larger one when it runs out of
+/// room. The id of the current segment is published in a small segment on the key of the vector,
+/// and every instance transparently re-attaches once it notices that the segment was replaced.
+/// Elements are copied in and out of shared memory, so they have to be `Copy`.
+#[derive(Debug)]
+pub struct CortexVec<T: Copy, L: CortexSync> {
+    state: Cortex<VecState, L>,
+    /// The element segment as last seen by this instance, only used while holding the lock
+    segment: Mutex<Segment<T>>,
+}
+
+unsafe impl<T: Copy + Send + Sync, L: CortexSync + Send> Send for CortexVec<T, L> {}
+unsafe impl<T: Copy + Send + Sync, L: CortexSync + Sync> Sync for CortexVec<T, L> {}
+
+impl<T: Copy, L: CortexSync> CortexVec<T, L> {
+    /// Create a new empty vector on `key`
+    pub fn new(key: i32) -> CortexResult<Self> {
+        Self::with_capacity(key, 0)
+    }
+    /// Create a new empty vector on `key` with room for `capacity` elements before it has to grow
+    pub fn with_capacity(key: i32, capacity: usize) -> CortexResult<Self> {
+        let segment = Segment::create(capacity, L::IN_PROCESS)?;
+        let state = VecState {
+            data_id: segment.id,
+            len: 0,
+            capacity,
+        };
+        let state = Cortex::new(Some(key), state, false, None).inspect_err(|_| {
+            if let Err(err) = mark_for_deletion(segment.id) {
+                crate::trace::error!("Error cleaning up after failed create: {}", err)
+            }
+        })?;
+        Ok(Self {
+            state,
+            segment: Mutex::new(segment),
+        })
+    }
+    /// Attempt to attach to an already existing vector
+    pub fn attach(key: i32) -> CortexResult<Self> {
+        let state: Cortex<VecState, L> = Cortex::attach(key)?;
+        // Under the lock, so that the segment can't be replaced in between
+        let segment = state.with_read(|state| Segment::attach(state.data_id))??;
+        Ok(Self {
+            state,
+            segment: Mutex::new(segment),
+        })
+    }
+    /// The local mapping of the element segment, re-attaching first if it was replaced. Must only
+    /// be called while holding the lock.
+    fn segment(&self, state: &VecState) -> CortexResult<MutexGuard<'_, Segment<T>>> {
+        let mut segment = self.segment.lock().unwrap_or_else(PoisonError::into_inner);
+        if segment.id != state.data_id {
+            crate::trace::trace!("Re-attaching to grown segment with id: {}", state.data_id);
+            *segment = Segment::attach(state.data_id)?;
+        }
+        Ok(segment)
+    }
+    /// Make room for `additional` more elements, moving them to a larger segment if needed. Must
+    /// only be called while holding the write lock.
+    fn reserve_locked(
+        &self,
+        state: &mut VecState,
+        additional: usize,
+    ) -> CortexResult<MutexGuard<'_, Segment<T>>> {
+        let mut segment = self.segment(state)?;
+        let Some(required) = state.len.checked_add(additional) else {
+            return Err(CortexError::new_logic(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Capacity overflow when reserving {} more elements",
+                    additional
+                ),
+            ));
+        };
+        if required <= state.capacity {
+            return Ok(segment);
+        }
+        let capacity = required.max(state.capacity.saturating_mul(2));
+        let grown = Segment::create(capacity, L::IN_PROCESS)?;
+        unsafe { std::ptr::copy_nonoverlapping(segment.ptr, grown.ptr, state.len) };
+        crate::trace::trace!(
+            "Grew vector with key: {} to {} elements",
+            self.state.key(),
+            capacity
+        );
+
+        state.data_id = grown.id;
+        state.capacity = capacity;
+        let previous = std::mem::replace(&mut *segment, grown);
+        // Instances still attached to the previous segment keep it alive until they re-attach
+        mark_for_deletion(previous.id)?;
+        Ok(segment)
+    }
+    /// Append an element, growing the vector if it is full
+    pub fn push(&self, value: T) -> CortexResult<()> {
+        self.extend_from_slice(&[value])
+    }
+    /// Append all elements of `values` under a single lock acquisition
+    pub fn extend_from_slice(&self, values: &[T]) -> CortexResult<()> {
+        self.state.with_write(|state| {
+            let segment = self.reserve_locked(state, values.len())?;
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    values.as_ptr(),
+                    segment.ptr.add(state.len),
+                    values.len(),
+                )
+            };
+            state.len += values.len();
+            Ok(())
+        })?
+    }
+    /// Remove the last element and return it, `None` if the vector is empty
+    pub fn pop(&self) -> CortexResult<Option<T>> {
+        self.state.with_write(|state| {
+            if state.len == 0 {
+                return Ok(None);
+            }
+            let segment = self.segment(state)?;
+            state.len -= 1;
+            Ok(Some(unsafe { segment.ptr.add(state.len).read() }))
+        })?
+    }
+    /// Read the element at `index`, `None` if it is out of bounds
+    pub fn get(&self, index: usize) -> CortexResult<Option<T>> {
+        self.state.with_read(|state| {
+            if index >= state.len {
+                return Ok(None);
+            }
+            let segment = self.segment(state)?;
+            Ok(Some(unsafe { segment.ptr.add(index).read() }))
+        })?
+    }
+    /// Overwrite the element at `index`
+    pub fn set(&self, index: usize, value: T) -> CortexResult<()> {
+        self.state.with_write(|state| {
+            if index >= state.len {
+                return Err(CortexError::new_logic(
+                    ErrorKind::InvalidInput,
+                    format!(
+                        "Index {} is out of bounds for a vector of {} elements",
+                        index, state.len
+                    ),
+                ));
+            }
+            let segment = self.segment(state)?;
+            unsafe { segment.ptr.add(index).write(value) };
+            Ok(())
+        })?
+    }
+    /// Copy out all elements under a single lock acquisition
+    pub fn to_vec(&self) -> CortexResult<Vec<T>> {
+        self.state.with_read(|state| {
+            let segment = self.segment(state)?;
+            Ok(unsafe { std::slice::from_raw_parts(segment.ptr, state.len) }.to_vec())
+        })?
+    }
+    /// Make room for at least `additional` more elements without growing again
+    pub fn reserve(&self, additional: usize) -> CortexResult<()> {
+        self.state
+            .with_write(|state| self.reserve_locked(state, additional).map(|_| ()))?
+    }
+    /// Remove all elements, keeping the capacity
+    pub fn clear(&self) -> CortexResult<()> {
+        self.state.with_write(|state| state.len = 0)
+    }
+    pub fn len(&self) -> CortexResult<usize> {
+        self.state.read_field(|state| &state.len)
+    }
+    pub fn is_empty(&self) -> CortexResult<bool> {
+        Ok(self.len()? == 0)
+    }
+    /// Number of elements the vector can hold before it has to grow
+    pub fn capacity(&self) -> CortexResult<usize> {
+        self.state.read_field(|state| &state.capacity)
+    }
+    pub fn key(&self) -> i32 {
+        self.state.key()
+    }
+}
+
+/// Remove the element segment along with the vector
+impl<T: Copy, L: CortexSync> Drop for CortexVec<T, L> {
+    fn drop(&mut self) {
+        if !self.state.is_owner() {
+            return;
+        }
+        // Another instance might have replaced the segment since this one last used it, in which
+        // case the previous one was already marked for deletion
+        let data_id = unsafe { (*self.state.ptr).data_id };
+        if let Err(err) = mark_for_deletion(data_id) {
+            crate::trace::error!("Error during mark_for_deletion in Drop: {}", err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vec::CortexVec;
+    use crate::{CortexError, ErrorKind, SpinLock};
+    use std::thread;
+
+    #[test]
+    fn grows_and_reattaches() {
+        let key = rand::random::<i32>().abs();
+        let vec: CortexVec<u64, SpinLock> = CortexVec::with_capacity(key, 2).unwrap();
+        let attached: CortexVec<u64, SpinLock> = CortexVec::attach(key).unwrap();
+
+        vec.extend_from_slice(&[0, 1]).unwrap();
+        assert_eq!(attached.capacity().unwrap(), 2);
+        for value in 2..100 {
+            vec.push(value).unwrap();
+        }
+        assert!(attached.capacity().unwrap() >= 100);
+        assert_eq!(attached.get(99).unwrap(), Some(99));
+        assert_eq!(attached.get(100).unwrap(), None);
+
+        attached.set(0, 42).unwrap();
+        assert_eq!(vec.pop().unwrap(), Some(99));
+        let values = vec.to_vec().unwrap();
+        assert_eq!(values.len(), 99);
+        assert_eq!(values[0], 42);
+        assert!(matches!(
+            attached.set(99, 0),
+            Err(err @ CortexError::CleanSystem(_)) if err.kind() == ErrorKind::InvalidInput
+        ));
+
+        vec.clear().unwrap();
+        assert!(attached.is_empty().unwrap());
+        assert_eq!(attached.pop().unwrap(), None);
+    }
+
+    #[test]
+    fn concurrent_pushes() {
+        let key = rand::random::<i32>().abs();
+        let vec: CortexVec<u32, SpinLock> = CortexVec::new(key).unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread| {
+                thread::spawn(move || {
+                    let attached: CortexVec<u32, SpinLock> = CortexVec::attach(key).unwrap();
+                    for value in 0..200 {
+                        attached.push(thread * 200 + value).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Thread panicked");
+        }
+
+        let mut values = vec.to_vec().unwrap();
+        values.sort_unstable();
+        assert_eq!(values, (0..1600).collect::<Vec<_>>());
+    }
+}
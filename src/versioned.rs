@@ -0,0 +1,13 @@
+use std::time::SystemTime;
+
+/// Value read together with metadata about the write that produced it, see
+/// `Cortex::read_versioned`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Versioned<T> {
+    pub value: T,
+    /// Number of writes completed since the segment was created, which only changes when the
+    /// value is written. Useful to skip values that were already processed.
+    pub version: u64,
+    /// When the last write completed, or when the segment was created if it was never written
+    pub written_at: SystemTime,
+}
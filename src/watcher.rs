@@ -0,0 +1,212 @@
+//! Change notifications without a hand-written poll loop, see `Watcher`.
+
+use crate::{Cortex, CortexResult, CortexSync, Versioned};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub struct WatchSettings {
+    /// How often `spawn` checks for changes
+    pub interval: Duration,
+    /// Only report a change once the value has stayed unchanged for this long, so a burst of
+    /// writes is reported once with its final value. Reported right away when `None`.
+    pub debounce: Option<Duration>,
+}
+
+impl Default for WatchSettings {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(10),
+            debounce: None,
+        }
+    }
+}
+
+/// Calls back whenever a cortex is written, by polling its version, see `Cortex::version`.
+///
+/// Either drive it from an existing loop by calling `poll` at an interval of your choosing, or
+/// move it to a background thread through `spawn`, which polls every `WatchSettings::interval`.
+/// Checking the version doesn't take the lock, the value is only read when it changed. Writes
+/// made in between two polls are reported once, with the latest value.
+///
+/// ```ignore
+/// let watcher = Watcher::new(Cortex::attach(key)?, WatchSettings::default());
+/// let handle = watcher.spawn(|config| apply(config.value))?;
+/// ```
+#[derive(Debug)]
+pub struct Watcher<T, L: CortexSync> {
+    cortex: Cortex<T, L>,
+    settings: WatchSettings,
+    /// Version last passed to the callback
+    reported: u64,
+    /// Latest version seen and when it was first seen, for debouncing
+    seen: u64,
+    seen_at: Instant,
+}
+
+impl<T, L: CortexSync> Watcher<T, L> {
+    /// Watch `cortex` for writes made from now on
+    pub fn new(cortex: Cortex<T, L>, settings: WatchSettings) -> Self {
+        let version = cortex.version();
+        Self {
+            cortex,
+            settings,
+            reported: version,
+            seen: version,
+            seen_at: Instant::now(),
+        }
+    }
+    /// Check for a change once, calling `callback` with the new value if there is one to report.
+    /// Returns whether `callback` was called.
+    pub fn poll(&mut self, callback: impl FnOnce(Versioned<T>)) -> CortexResult<bool> {
+        let version = self.cortex.version();
+        if version != self.seen {
+            self.seen = version;
+            self.seen_at = Instant::now();
+        }
+        let settled = self
+            .settings
+            .debounce
+            .is_none_or(|debounce| self.seen_at.elapsed() >= debounce);
+        if self.seen == self.reported || !settled {
+            return Ok(false);
+        }
+        let value = self.cortex.read_versioned()?;
+        // Writes made since checking the version are part of the value, and not reported again
+        self.reported = value.version;
+        self.seen = self.seen.max(value.version);
+        callback(value);
+        Ok(true)
+    }
+    pub fn cortex(&self) -> &Cortex<T, L> {
+        &self.cortex
+    }
+    pub fn settings(&self) -> &WatchSettings {
+        &self.settings
+    }
+}
+
+impl<T, L> Watcher<T, L>
+where
+    T: Send + Sync + 'static,
+    L: CortexSync + Send + 'static,
+{
+    /// Poll on a background thread, calling `callback` there for every change. The thread runs
+    /// until the returned handle is stopped or dropped, or reading the value fails.
+    pub fn spawn(
+        mut self,
+        mut callback: impl FnMut(Versioned<T>) + Send + 'static,
+    ) -> CortexResult<WatchHandle> {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = Arc::clone(&stop);
+        let thread = std::thread::Builder::new()
+            .name("neocortex-watcher".to_string())
+            .spawn(move || {
+                while !stopped.load(Ordering::Relaxed) {
+                    self.poll(&mut callback)?;
+                    std::thread::sleep(self.settings.interval);
+                }
+                Ok(())
+            })?;
+        Ok(WatchHandle {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+/// Background thread started by `Watcher::spawn`, stopped when dropped
+#[derive(Debug)]
+pub struct WatchHandle {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<CortexResult<()>>>,
+}
+
+impl WatchHandle {
+    /// Whether the thread stopped, e.g. because reading the value failed
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(JoinHandle::is_finished)
+    }
+    /// Stop the thread and wait for it, returning the error it stopped on if any
+    pub fn stop(mut self) -> CortexResult<()> {
+        self.join()
+    }
+    fn join(&mut self) -> CortexResult<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result,
+            Some(Err(panic)) => std::panic::resume_unwind(panic),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            self.stop.store(true, Ordering::Relaxed);
+            return;
+        }
+        if let Err(err) = self.join() {
+            crate::trace::warning!("Watcher stopped with an error: {}", err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Cortex, SpinLock, WatchSettings, Watcher};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn callbacks_on_change() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u32, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        let settings = WatchSettings {
+            interval: Duration::from_millis(1),
+            debounce: None,
+        };
+        let (sender, receiver) = mpsc::channel();
+        let attached: Cortex<u32, SpinLock> = Cortex::attach(key).unwrap();
+        let handle = Watcher::new(attached, settings)
+            .spawn(move |value| sender.send(value.value).unwrap())
+            .unwrap();
+
+        let timeout = Duration::from_secs(5);
+        for value in 1..=3 {
+            cortex.write(value).unwrap();
+            assert_eq!(receiver.recv_timeout(timeout).unwrap(), value);
+        }
+        handle.stop().unwrap();
+        cortex.write(4).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(20)).is_err());
+    }
+
+    #[test]
+    fn debounced_polls() {
+        let key = rand::random::<i32>().abs();
+        let cortex: Cortex<u32, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+        let settings = WatchSettings {
+            debounce: Some(Duration::from_millis(30)),
+            ..Default::default()
+        };
+        let attached: Cortex<u32, SpinLock> = Cortex::attach(key).unwrap();
+        let mut watcher = Watcher::new(attached, settings);
+        let mut reported = Vec::new();
+        assert!(!watcher.poll(|value| reported.push(value.value)).unwrap());
+
+        // A burst of writes is reported once it settles, with the final value
+        for value in 1..=5 {
+            cortex.write(value).unwrap();
+            assert!(!watcher.poll(|value| reported.push(value.value)).unwrap());
+        }
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(watcher.poll(|value| reported.push(value.value)).unwrap());
+        assert!(!watcher.poll(|value| reported.push(value.value)).unwrap());
+        assert_eq!(reported, [5]);
+        assert_eq!(watcher.cortex().version(), 5);
+    }
+}
@@ -0,0 +1,29 @@
+//! Handles are only `Send` and `Sync` if the stored data may be shared between threads,
+//! read-only handles can't write, and struct fields can't be read as types that aren't `Pod`, nor
+//! checksummed
+
+#[test]
+fn send_and_sync() {
+    let cases = trybuild::TestCases::new();
+    cases.pass("tests/ui/send_sync.rs");
+    cases.compile_fail("tests/ui/rc_not_send.rs");
+    cases.compile_fail("tests/ui/cell_not_send.rs");
+}
+
+#[test]
+fn read_only_has_no_write() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/read_only_write.rs");
+}
+
+#[test]
+fn struct_fields_are_pod() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/struct_field_not_pod.rs");
+}
+
+#[test]
+fn checksums_need_pod() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/ui/checksum_not_pod.rs");
+}
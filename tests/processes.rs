@@ -0,0 +1,297 @@
+//! Tests across real processes, which threads can't stand in for: segments outliving their
+//! creator, crashes and permissions. Child processes run this test binary again, restricted to the
+//! `child` test and told what to do through environment variables.
+
+#[cfg(feature = "cleanup")]
+use neocortex::OwnedResource;
+use neocortex::{Cortex, CortexBuilder, ErrorKind, FileLock, SpinLock};
+use std::process::{Child, Command, ExitStatus};
+
+const ROLE: &str = "NEOCORTEX_TEST_ROLE";
+const KEY: &str = "NEOCORTEX_TEST_KEY";
+
+/// Run `role` with `key` in a child process
+fn spawn(role: &str, key: i32) -> Child {
+    Command::new(std::env::current_exe().unwrap())
+        .args(["child", "--exact", "--quiet", "--test-threads=1"])
+        .env(ROLE, role)
+        .env(KEY, key.to_string())
+        .spawn()
+        .unwrap()
+}
+
+/// Run `role` with `key` in a child process and wait for it to exit
+fn run(role: &str, key: i32) -> ExitStatus {
+    spawn(role, key).wait().unwrap()
+}
+
+fn random_key() -> i32 {
+    rand::random::<i32>().abs()
+}
+
+/// Entry point of the child processes, does nothing in a regular test run
+#[test]
+fn child() {
+    let (Ok(role), Ok(key)) = (std::env::var(ROLE), std::env::var(KEY)) else {
+        return;
+    };
+    let key: i32 = key.parse().unwrap();
+    match role.as_str() {
+        "attach_missing" => {
+            let err = Cortex::<u64, SpinLock>::attach(key).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+        }
+        "read_then_write" => {
+            let cortex: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+            assert_eq!(cortex.read().unwrap(), 7);
+            cortex.write(8).unwrap();
+        }
+        "create_and_exit" => {
+            let _cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+        }
+        "create_and_abort" => {
+            let _cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+            // Dies without running any destructors, like a crash
+            std::process::abort();
+        }
+        "attach_as_nobody" => {
+            unsafe {
+                assert_eq!(libc::setgid(65534), 0);
+                assert_eq!(libc::setuid(65534), 0);
+            }
+            let err = Cortex::<u64, SpinLock>::attach(key).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+        }
+        #[cfg(feature = "cleanup")]
+        "create_and_wait_for_signal" => {
+            neocortex::cleanup_on_signal(&[libc::SIGTERM]).unwrap();
+            let cortex: Cortex<u64, SpinLock> = Cortex::new(None, 1, false, None).unwrap();
+            // Tell the parent which key to check once it's done with us
+            let parent: Cortex<i32, SpinLock> = Cortex::attach(key).unwrap();
+            parent.write(cortex.key()).unwrap();
+            loop {
+                std::thread::park();
+            }
+        }
+        #[cfg(feature = "cleanup")]
+        "cleanup_all" => {
+            let cortex: Cortex<u64, FileLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+            let owned = neocortex::owned_resources();
+            assert!(owned.contains(&OwnedResource::Segment {
+                key,
+                id: cortex.id()
+            }));
+            assert!(owned
+                .iter()
+                .any(|resource| matches!(resource, OwnedResource::File(_))));
+
+            neocortex::cleanup_all().unwrap();
+            assert!(neocortex::owned_resources().is_empty());
+            assert_eq!(cortex.read().unwrap(), 1);
+            let err = Cortex::<u64, FileLock>::attach(key).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::NotFound);
+
+            // The key is taken again, which dropping the previous owner must leave alone
+            let _replacement: Cortex<u64, FileLock> =
+                Cortex::new(Some(key), 2, false, None).unwrap();
+            drop(cortex);
+            let attached: Cortex<u64, FileLock> = Cortex::attach(key).unwrap();
+            assert_eq!(attached.read().unwrap(), 2);
+        }
+        "namespaced" => {
+            neocortex::set_namespace("neocortex_test").unwrap();
+            assert_ne!(neocortex::ipc_key(key), key);
+            // The parent holds the same key outside of the namespace
+            let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 2, false, None).unwrap();
+            assert_eq!(cortex.read().unwrap(), 2);
+
+            neocortex::set_namespace("neocortex_test").unwrap();
+            let err = neocortex::set_namespace("other").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        }
+        "fork" => fork(key),
+        "fork_ref_counted" => fork_ref_counted(key),
+        role => panic!("Unknown role: {}", role),
+    }
+}
+
+fn wait_for(pid: libc::pid_t) -> libc::c_int {
+    let mut status = 0;
+    assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+    status
+}
+
+/// Forks on its own, which is only safe in a child process that doesn't run anything else
+fn fork(key: i32) {
+    let cortex: Cortex<u64, FileLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+
+    // A child that doesn't know about the fork still leaves the segment to the parent
+    match unsafe { libc::fork() } {
+        0 => {
+            assert!(!cortex.is_owner());
+            drop(cortex);
+            unsafe { libc::_exit(0) };
+        }
+        pid => assert_eq!(wait_for(pid), 0),
+    }
+    Cortex::<u64, FileLock>::attach(key).unwrap();
+
+    // A prepared child gets a lock of its own, which the parent can't take at the same time
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+    match unsafe { libc::fork() } {
+        0 => {
+            let mut cortex = cortex;
+            cortex.after_fork_child().unwrap();
+            cortex
+                .with_write(|value| {
+                    *value = 2;
+                    unsafe {
+                        libc::write(fds[1], [1u8].as_ptr() as *const libc::c_void, 1);
+                        libc::pause();
+                    }
+                })
+                .unwrap();
+        }
+        pid => {
+            unsafe { libc::close(fds[1]) };
+            let mut locked = 0u8;
+            let read =
+                unsafe { libc::read(fds[0], &mut locked as *mut u8 as *mut libc::c_void, 1) };
+            let locked_by_parent = read == 1 && cortex.try_write(3).unwrap();
+            // Before asserting anything, so that a failure doesn't leave the child behind
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+            wait_for(pid);
+            assert_eq!(read, 1);
+            assert!(!locked_by_parent);
+            assert!(cortex.try_write(3).unwrap());
+        }
+    }
+}
+
+/// Forks on its own, see `fork`
+fn fork_ref_counted(key: i32) {
+    let cortex = CortexBuilder::new(1u64)
+        .key(key)
+        .ref_counted()
+        .with_default_lock::<SpinLock>()
+        .unwrap();
+
+    // A child that doesn't know about the fork never took a reference, so it gives none back
+    for prepare in [false, true] {
+        match unsafe { libc::fork() } {
+            0 => {
+                let mut cortex = cortex;
+                if prepare {
+                    cortex.after_fork_child().unwrap();
+                }
+                drop(cortex);
+                unsafe { libc::_exit(0) };
+            }
+            pid => assert_eq!(wait_for(pid), 0),
+        }
+        let attached: Cortex<u64, SpinLock> = Cortex::attach(key).unwrap();
+        assert_eq!(attached.read().unwrap(), 1);
+    }
+
+    drop(cortex);
+    let err = Cortex::<u64, SpinLock>::attach(key).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn attach_ordering() {
+    let key = random_key();
+    assert!(run("attach_missing", key).success());
+
+    let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 7, false, None).unwrap();
+    assert!(run("read_then_write", key).success());
+    assert_eq!(cortex.read().unwrap(), 8);
+}
+
+#[test]
+fn namespaces_are_isolated() {
+    let key = random_key();
+    let cortex: Cortex<u64, SpinLock> = Cortex::new(Some(key), 1, false, None).unwrap();
+    assert!(run("namespaced", key).success());
+    assert_eq!(cortex.read().unwrap(), 1);
+}
+
+#[test]
+fn owner_exit_removes_segment() {
+    let key = random_key();
+    assert!(run("create_and_exit", key).success());
+    let err = Cortex::<u64, SpinLock>::attach(key).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+}
+
+#[test]
+fn crashed_owner_is_reclaimed() {
+    let key = random_key();
+    assert!(!run("create_and_abort", key).success());
+
+    // The segment and lock of the crashed process are still around, but orphaned
+    let cortex = CortexBuilder::new(2u64)
+        .key(key)
+        .attach_or_create()
+        .with_default_lock::<SpinLock>()
+        .unwrap();
+    assert!(cortex.is_owner());
+    assert_eq!(cortex.read().unwrap(), 2);
+}
+
+#[test]
+fn permissions_are_enforced() {
+    // Only root can switch to another user, and root itself bypasses the permissions
+    if unsafe { libc::geteuid() } != 0 {
+        return;
+    }
+    let key = random_key();
+    let _cortex = CortexBuilder::new(0u64)
+        .key(key)
+        .permissions(0o600)
+        .with_default_lock::<SpinLock>()
+        .unwrap();
+    assert!(run("attach_as_nobody", key).success());
+}
+
+#[cfg(feature = "cleanup")]
+#[test]
+fn signal_removes_owned_resources() {
+    use std::os::unix::process::ExitStatusExt;
+
+    let key = random_key();
+    let parent: Cortex<i32, SpinLock> = Cortex::new(Some(key), 0, false, None).unwrap();
+    let mut child = spawn("create_and_wait_for_signal", key);
+    let child_key = loop {
+        match parent.read().unwrap() {
+            0 => std::thread::sleep(std::time::Duration::from_millis(10)),
+            child_key => break child_key,
+        }
+    };
+
+    unsafe { libc::kill(child.id() as i32, libc::SIGTERM) };
+    let status = child.wait().unwrap();
+    assert_eq!(status.signal(), Some(libc::SIGTERM));
+    // Both the segment and the state of its lock are gone, so the key can be created again
+    let err = Cortex::<u64, SpinLock>::attach(child_key).err().unwrap();
+    assert_eq!(err.kind(), ErrorKind::NotFound);
+    let _recreated: Cortex<u64, SpinLock> = Cortex::new(Some(child_key), 1, false, None).unwrap();
+}
+
+#[cfg(feature = "cleanup")]
+#[test]
+fn cleanup_all_removes_owned_resources() {
+    // In a process of its own, since it removes everything the process owns
+    assert!(run("cleanup_all", random_key()).success());
+}
+
+#[test]
+fn forked_child_is_not_owner() {
+    assert!(run("fork", random_key()).success());
+}
+
+#[test]
+fn forked_child_keeps_ref_count() {
+    assert!(run("fork_ref_counted", random_key()).success());
+}
@@ -0,0 +1,9 @@
+use neocortex::{Cortex, SpinLock};
+use std::cell::Cell;
+
+// Another handle on the same segment could hand out a `&Cell` at the same time
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<Cortex<Cell<u64>, SpinLock>>();
+}
@@ -0,0 +1,15 @@
+use neocortex::{Cortex, CortexBuilder, SpinLock};
+
+#[derive(Clone, Copy)]
+struct Padded {
+    tag: u8,
+    value: u64,
+}
+
+fn main() {
+    let _cortex: Cortex<Padded, SpinLock> = CortexBuilder::new(Padded { tag: 1, value: 2 })
+        .random_key()
+        .checksum()
+        .with_default_lock()
+        .unwrap();
+}
@@ -0,0 +1,8 @@
+use neocortex::{Cortex, SpinLock};
+use std::rc::Rc;
+
+fn assert_send<T: Send>() {}
+
+fn main() {
+    assert_send::<Cortex<Rc<u64>, SpinLock>>();
+}
@@ -0,0 +1,6 @@
+use neocortex::{Cortex, ReadOnlyCortex, SpinLock};
+
+fn main() {
+    let cortex: ReadOnlyCortex<u64, SpinLock> = Cortex::attach_read_only(1).unwrap();
+    cortex.write(42).unwrap();
+}
@@ -0,0 +1,12 @@
+use neocortex::{Cortex, CortexMap, CortexVec, MemBackend, RawCortex, SpinLock};
+use std::sync::atomic::AtomicU64;
+
+fn assert_send_sync<T: Send + Sync>() {}
+
+fn main() {
+    assert_send_sync::<Cortex<u64, SpinLock>>();
+    assert_send_sync::<Cortex<[AtomicU64; 4], MemBackend>>();
+    assert_send_sync::<CortexVec<u32, SpinLock>>();
+    assert_send_sync::<CortexMap<u32, f64>>();
+    assert_send_sync::<RawCortex<SpinLock>>();
+}
@@ -0,0 +1,6 @@
+use neocortex::CortexStruct;
+
+fn main() {
+    let shared = CortexStruct::builder().field("flag", 0u8).create(1).unwrap();
+    let _flag: bool = shared.read("flag").unwrap();
+}